@@ -0,0 +1,556 @@
+use crate::{
+    http, numfmt,
+    overhead::Counted,
+    retry::{RetryPolicy, Retrying},
+    ProcessSample, Subject,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::{debug, info, warn};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{atomic::AtomicU64, Arc},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use sysinfo::{Pid, PidExt};
+
+/// Columns of the alerts CSV written next to the output.
+pub const HEADER: [&str; 6] = [
+    "Timestamp",
+    "PID",
+    "Process Name",
+    "Metric",
+    "Value (%)",
+    "Threshold (%)",
+];
+
+/// How long a webhook may take to answer an alert.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The limits set by `--alert-cpu` and `--alert-mem`, for how many
+/// consecutive samples `--alert-for` wants a process over one before it
+/// alerts, and how often `--alert-every` lets one process raise the same
+/// alert.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thresholds {
+    pub cpu: Option<f64>,
+    pub memory: Option<f64>,
+    pub consecutive: u32,
+    pub every: Duration,
+}
+
+impl Thresholds {
+    pub fn is_enabled(&self) -> bool {
+        self.cpu.is_some() || self.memory.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Metric {
+    Cpu,
+    Memory,
+}
+
+impl Metric {
+    fn name(self) -> &'static str {
+        match self {
+            Metric::Cpu => "cpu",
+            Metric::Memory => "memory",
+        }
+    }
+}
+
+/// What `--alert-cmd` and `--alert-webhook` do when an alert is raised.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Actions {
+    /// Run by the shell with the alert in `CHRONOLOGGER_ALERT_*` variables.
+    pub command: Option<String>,
+    /// Sent the alert as a JSON object in a POST request.
+    pub webhook: Option<http::Url>,
+}
+
+impl Actions {
+    pub fn is_empty(&self) -> bool {
+        self.command.is_none() && self.webhook.is_none()
+    }
+}
+
+/// One alert, as handed to the actions and the sinks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub timestamp: String,
+    /// Empty for a `--group-by` group.
+    pub pid: Option<u32>,
+    pub name: String,
+    pub metric: Metric,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+impl Alert {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "timestamp": self.timestamp,
+            "pid": self.pid,
+            "name": self.name,
+            "metric": self.metric.name(),
+            "value": self.value,
+            "threshold": self.threshold,
+        })
+    }
+
+    /// The variables `--alert-cmd` gets.
+    fn environment(&self) -> [(&'static str, String); 6] {
+        [
+            ("CHRONOLOGGER_ALERT_TIMESTAMP", self.timestamp.clone()),
+            (
+                "CHRONOLOGGER_ALERT_PID",
+                self.pid.map_or_else(String::new, |pid| pid.to_string()),
+            ),
+            ("CHRONOLOGGER_ALERT_NAME", self.name.clone()),
+            ("CHRONOLOGGER_ALERT_METRIC", self.metric.name().to_string()),
+            ("CHRONOLOGGER_ALERT_VALUE", format!("{:.2}", self.value)),
+            ("CHRONOLOGGER_ALERT_THRESHOLD", self.threshold.to_string()),
+        ]
+    }
+}
+
+/// Where the alerts of a capture written to `output` go, e.g.
+/// `foo.alerts.csv` for `foo.csv`.
+pub fn path_for(output: &str) -> PathBuf {
+    let output = Path::new(output);
+    let stem = output.file_stem().unwrap_or(output.as_os_str());
+    let mut name = stem.to_os_string();
+    name.push(".alerts.csv");
+    output.with_file_name(name)
+}
+
+/// Raises an alert for every process over a threshold for enough
+/// consecutive samples, at most once per `every` for each process and
+/// metric, logging it, writing it to the alerts CSV if there is one and
+/// running the actions.
+pub struct Alerts {
+    thresholds: Thresholds,
+    writer: Option<csv::Writer<BufWriter<Counted<Retrying<File>>>>>,
+    actions: Actions,
+    /// Actions still running, which the run waits for when it ends.
+    pending: Vec<JoinHandle<()>>,
+    /// For how many consecutive samples each process has been over each
+    /// threshold.
+    streaks: HashMap<(Subject, Metric), u32>,
+    /// When each process last raised each alert.
+    last: HashMap<(Subject, Metric), DateTime<Local>>,
+    raised: u64,
+    /// The alerts raised by the latest tick.
+    latest: Vec<Alert>,
+    value: String,
+}
+
+impl Alerts {
+    /// Raises alerts only in the log.
+    pub fn new(thresholds: Thresholds) -> Self {
+        Self {
+            thresholds,
+            writer: None,
+            actions: Actions::default(),
+            pending: Vec::new(),
+            streaks: HashMap::new(),
+            last: HashMap::new(),
+            raised: 0,
+            latest: Vec::new(),
+            value: String::new(),
+        }
+    }
+
+    /// Also runs `actions` for every alert.
+    pub fn with_actions(mut self, actions: Actions) -> Self {
+        self.actions = actions;
+        self
+    }
+
+    /// Also writes alerts to the CSV at `path`, adding to it if `append`
+    /// and it already has a header.
+    pub fn with_file(
+        mut self,
+        path: &Path,
+        append: bool,
+        retry: RetryPolicy,
+        written: Arc<AtomicU64>,
+    ) -> Result<Self> {
+        info!("Writing alerts to {}", path.display());
+        let file = OpenOptions::new()
+            .create(true)
+            .append(append)
+            .write(true)
+            .truncate(!append)
+            .open(path)
+            .context("Failed to create alerts file!")?;
+        let has_header = append && file.metadata()?.len() > 0;
+        let mut writer = csv::Writer::from_writer(BufWriter::new(Counted::new(
+            Retrying::new(file, retry),
+            written,
+        )));
+        if !has_header {
+            writer
+                .write_record(HEADER)
+                .context("Failed to write alerts header!")?;
+            writer.flush().context("Failed to flush alerts file!")?;
+        }
+        self.writer = Some(writer);
+        Ok(self)
+    }
+
+    /// How many alerts were raised.
+    pub fn raised(&self) -> u64 {
+        self.raised
+    }
+
+    /// The alerts the latest `check` raised.
+    pub fn latest(&self) -> &[Alert] {
+        &self.latest
+    }
+
+    /// Raises the alerts of the tick sampled at `now`.
+    pub fn check(&mut self, now: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        let every = self.thresholds.every;
+        // Processes that are no longer over a threshold, or have exited,
+        // are forgotten once they could alert again anyway. A clock that
+        // went back keeps them.
+        self.last
+            .retain(|_, last| (*now - *last).to_std().map_or(true, |since| since < every));
+        let raised = self.raised;
+        self.latest.clear();
+        // A process under a threshold, or not logged, starts its streak
+        // over.
+        let previous = std::mem::take(&mut self.streaks);
+        for sample in samples {
+            let checks = [
+                (Metric::Cpu, sample.cpu_usage as f64, self.thresholds.cpu),
+                (Metric::Memory, sample.memory_usage, self.thresholds.memory),
+            ];
+            for (metric, value, threshold) in checks {
+                let Some(threshold) = threshold.filter(|&threshold| value > threshold) else {
+                    continue;
+                };
+                let key = (sample.subject(), metric);
+                let streak = previous
+                    .get(&key)
+                    .map_or(1, |streak| streak.saturating_add(1));
+                self.streaks.insert(key.clone(), streak);
+                if streak < self.thresholds.consecutive || self.last.contains_key(&key) {
+                    continue;
+                }
+                self.last.insert(key, *now);
+                self.raised += 1;
+                match sample.pid {
+                    Some(pid) => warn!(
+                        "Alert: {} (PID {}) is at {:.2}% {}, over the threshold of {}%",
+                        sample.name,
+                        pid,
+                        value,
+                        metric.name(),
+                        threshold
+                    ),
+                    None => warn!(
+                        "Alert: group {} is at {:.2}% {}, over the threshold of {}%",
+                        sample.name,
+                        value,
+                        metric.name(),
+                        threshold
+                    ),
+                }
+                self.write(now, sample, metric, value, threshold)?;
+                let alert = Alert {
+                    timestamp: now.to_rfc3339(),
+                    pid: sample.pid.map(Pid::as_u32),
+                    name: sample.name.clone(),
+                    metric,
+                    value,
+                    threshold,
+                };
+                if !self.actions.is_empty() {
+                    self.trigger(alert.clone());
+                }
+                self.latest.push(alert);
+            }
+        }
+        match &mut self.writer {
+            Some(writer) if self.raised > raised => {
+                writer.flush().context("Failed to flush alerts file!")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Waits for the actions still running, so a webhook is not cut off
+    /// when the run exits.
+    pub fn finish(&mut self) {
+        for handle in self.pending.drain(..) {
+            let _ = handle.join();
+        }
+    }
+
+    /// Runs the actions for `alert` in the background, so a slow script or
+    /// webhook does not hold up the tick. A failing action is logged.
+    fn trigger(&mut self, alert: Alert) {
+        self.pending.retain(|handle| !handle.is_finished());
+        if let Some(command) = &self.actions.command {
+            let mut shell = shell(command);
+            shell.envs(alert.environment());
+            match shell.spawn() {
+                Ok(mut child) => self.pending.push(thread::spawn(move || match child.wait() {
+                    Ok(status) if status.success() => debug!("--alert-cmd finished"),
+                    Ok(status) => warn!("--alert-cmd failed with {}", status),
+                    Err(e) => warn!("Failed to wait for --alert-cmd: {}", e),
+                })),
+                Err(e) => warn!("Failed to run --alert-cmd: {}", e),
+            }
+        }
+        if let Some(url) = self.actions.webhook.clone() {
+            let body = alert.to_json().to_string();
+            self.pending.push(thread::spawn(move || {
+                let path = if url.path.is_empty() { "/" } else { &url.path };
+                let response = http::send(
+                    "POST",
+                    &url,
+                    path,
+                    "application/json",
+                    body.as_bytes(),
+                    WEBHOOK_TIMEOUT,
+                );
+                match response {
+                    Ok(response) if response.is_success() => debug!("Alert webhook answered"),
+                    Ok(response) => warn!(
+                        "Alert webhook returned {}: {}",
+                        response.status,
+                        response.body.trim()
+                    ),
+                    Err(e) => warn!("Failed to send alert webhook: {:#}", e),
+                }
+            }));
+        }
+    }
+
+    fn write(
+        &mut self,
+        now: &DateTime<Local>,
+        sample: &ProcessSample,
+        metric: Metric,
+        value: f64,
+        threshold: f64,
+    ) -> Result<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        self.value.clear();
+        numfmt::write_fixed(&mut self.value, value, numfmt::PERCENT_PRECISION);
+        let pid = sample
+            .pid
+            .map_or_else(String::new, |pid| pid.as_u32().to_string());
+        writer
+            .write_record([
+                now.to_rfc3339().as_str(),
+                &pid,
+                &sample.name,
+                metric.name(),
+                &self.value,
+                &threshold.to_string(),
+            ])
+            .context("Failed to write alert!")
+    }
+}
+
+/// The shell command line that runs `command`.
+fn shell(command: &str) -> Command {
+    #[cfg(windows)]
+    let mut shell = {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    };
+    #[cfg(not(windows))]
+    let mut shell = {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+/// Parses a threshold such as `80` or `12.5%`, in percent.
+pub fn parse_threshold(value: &str) -> Result<f64, String> {
+    let number = value.trim().trim_end_matches('%');
+    match number.parse::<f64>() {
+        Ok(percent) if percent.is_finite() && percent > 0.0 => Ok(percent),
+        Ok(_) => Err("The threshold must be a percentage greater than 0".to_string()),
+        Err(_) => Err(format!("Invalid percentage: {:?}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse_threshold() {
+        assert_eq!(parse_threshold("80"), Ok(80.0));
+        assert_eq!(parse_threshold("12.5%"), Ok(12.5));
+        assert_eq!(parse_threshold("250"), Ok(250.0));
+        for invalid in ["0", "-5", "NaN", "inf", "high", "%"] {
+            assert!(parse_threshold(invalid).is_err(), "{} accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn test_alerts_once_per_process_and_period() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = path_for(dir.path().join("capture.csv").to_str().unwrap());
+        let thresholds = Thresholds {
+            cpu: Some(50.0),
+            memory: Some(10.0),
+            consecutive: 1,
+            every: Duration::from_secs(10),
+        };
+        let mut alerts = Alerts::new(thresholds)
+            .with_file(&path, false, RetryPolicy::default(), Arc::default())
+            .unwrap();
+        let start = Local.timestamp_opt(1_767_225_600, 0).unwrap();
+        let samples = [
+            sample(1, "worker-1", 90.0, 1.0),
+            sample(2, "worker-2", 10.0, 20.0),
+            sample(3, "worker-3", 50.0, 10.0),
+        ];
+        for seconds in [0, 5, 10] {
+            let now = start + chrono::Duration::seconds(seconds);
+            alerts.check(&now, &samples).unwrap();
+        }
+        // Two processes over a threshold, alerting at 0 and again at 10s.
+        assert_eq!(alerts.raised(), 4);
+
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        assert_eq!(reader.headers().unwrap(), HEADER.as_slice());
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(
+            rows[0].iter().skip(1).collect::<Vec<_>>(),
+            ["1", "worker-1", "cpu", "90.00", "50"]
+        );
+        assert_eq!(
+            rows[1].iter().skip(1).collect::<Vec<_>>(),
+            ["2", "worker-2", "memory", "20.00", "10"]
+        );
+        assert_eq!(
+            &rows[2][0],
+            (start + chrono::Duration::seconds(10)).to_rfc3339()
+        );
+    }
+
+    #[test]
+    fn test_appends_to_existing_alerts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("capture.alerts.csv");
+        let thresholds = Thresholds {
+            cpu: Some(1.0),
+            memory: None,
+            consecutive: 1,
+            every: Duration::from_secs(60),
+        };
+        let now = Local::now();
+        for _ in 0..2 {
+            let mut alerts = Alerts::new(thresholds)
+                .with_file(&path, true, RetryPolicy::default(), Arc::default())
+                .unwrap();
+            alerts
+                .check(&now, &[sample(7, "worker-7", 5.0, 0.0)])
+                .unwrap();
+        }
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 3, "{}", contents);
+    }
+
+    #[test]
+    fn test_alerts_after_consecutive_samples() {
+        let thresholds = Thresholds {
+            cpu: Some(50.0),
+            memory: None,
+            consecutive: 3,
+            every: Duration::from_secs(3600),
+        };
+        let mut alerts = Alerts::new(thresholds);
+        let start = Local.timestamp_opt(1_767_225_600, 0).unwrap();
+        // Process 1 dips under the threshold, process 2 drops out of a tick.
+        let both = || {
+            vec![
+                sample(1, "worker-1", 90.0, 0.0),
+                sample(2, "worker-2", 90.0, 0.0),
+            ]
+        };
+        let ticks = [
+            both(),
+            both(),
+            vec![sample(1, "worker-1", 10.0, 0.0)],
+            both(),
+            both(),
+            both(),
+        ];
+        let mut raised = Vec::new();
+        for (second, samples) in ticks.iter().enumerate() {
+            let now = start + chrono::Duration::seconds(second as i64);
+            alerts.check(&now, samples).unwrap();
+            raised.push(alerts.raised());
+        }
+        assert_eq!(raised, [0, 0, 0, 0, 0, 2]);
+    }
+
+    #[test]
+    fn test_actions_receive_alert() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let output = dir.path().join("alert.txt");
+        #[cfg(unix)]
+        let command = format!(
+            "printf '%s %s %s %s' \"$CHRONOLOGGER_ALERT_PID\" \"$CHRONOLOGGER_ALERT_NAME\" \
+             \"$CHRONOLOGGER_ALERT_METRIC\" \"$CHRONOLOGGER_ALERT_VALUE\" > {}",
+            output.display()
+        );
+        #[cfg(windows)]
+        let command = format!(
+            "echo %CHRONOLOGGER_ALERT_PID% %CHRONOLOGGER_ALERT_NAME% \
+             %CHRONOLOGGER_ALERT_METRIC% %CHRONOLOGGER_ALERT_VALUE%> {}",
+            output.display()
+        );
+        let (url, requests) = http::testing::capture_requests(1, 200);
+        let thresholds = Thresholds {
+            cpu: None,
+            memory: Some(10.0),
+            consecutive: 1,
+            every: Duration::from_secs(60),
+        };
+        let mut alerts = Alerts::new(thresholds).with_actions(Actions {
+            command: Some(command),
+            webhook: Some(url),
+        });
+        alerts
+            .check(&Local::now(), &[sample(7, "worker-7", 0.0, 12.5)])
+            .unwrap();
+        alerts.finish();
+
+        let written = std::fs::read_to_string(&output).unwrap();
+        assert_eq!(written.trim(), "7 worker-7 memory 12.50");
+        let request = requests.join().unwrap().remove(0);
+        assert!(request.starts_with("POST / HTTP/1.1\r\n"), "{}", request);
+        let body: serde_json::Value =
+            serde_json::from_str(request.split("\r\n\r\n").nth(1).unwrap()).unwrap();
+        assert_eq!(body["pid"], 7);
+        assert_eq!(body["metric"], "memory");
+        assert_eq!(body["value"], 12.5);
+        assert_eq!(body["threshold"], 10.0);
+    }
+}
@@ -0,0 +1,480 @@
+//! `chronologger analyze`: reads back a capture and reports what each
+//! process used over it.
+
+use crate::{integrity, SCOPE_FULL};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::Path,
+};
+
+/// Epoch timestamps above this are in milliseconds rather than seconds; in
+/// seconds it lies in the year 5138.
+const EPOCH_MS_THRESHOLD: i64 = 100_000_000_000;
+
+/// Which processes to report and how.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Reports only the `top` processes with the highest mean CPU usage.
+    pub top: Option<usize>,
+    /// Reports only processes whose name contains one of these, ignoring
+    /// case, or whose PID is one of them.
+    pub processes: Vec<String>,
+    /// Prints a JSON array instead of a table.
+    pub json: bool,
+}
+
+/// One process row of a capture.
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    timestamp: Option<DateTime<Utc>>,
+    pid: u32,
+    name: String,
+    cpu_usage: f64,
+    memory_usage: f64,
+}
+
+/// Minimum, mean, 95th percentile and maximum of a series of samples.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub min: f64,
+    pub mean: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl Stats {
+    /// Computes the statistics of `values`, which must not be empty. The
+    /// percentile is by nearest rank, so it is always a sampled value.
+    fn of(values: &mut [f64]) -> Self {
+        values.sort_unstable_by(f64::total_cmp);
+        let rank = (values.len() as f64 * 0.95).ceil() as usize;
+        Self {
+            min: values[0],
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            p95: values[rank.max(1) - 1],
+            max: values[values.len() - 1],
+        }
+    }
+
+    fn to_json(self) -> Value {
+        json!({
+            "min": self.min,
+            "mean": self.mean,
+            "p95": self.p95,
+            "max": self.max,
+        })
+    }
+}
+
+/// What one process, by PID and name, used over the capture.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessStats {
+    pub pid: u32,
+    pub name: String,
+    pub samples: usize,
+    pub cpu: Stats,
+    pub memory: Stats,
+    /// When the process was first and last sampled, if the capture's
+    /// timestamps can be read back.
+    pub first_seen: Option<DateTime<Utc>>,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl ProcessStats {
+    /// The time between the first and the last sample.
+    pub fn lifetime(&self) -> Option<chrono::Duration> {
+        Some(self.last_seen? - self.first_seen?)
+    }
+
+    fn to_json(&self) -> Value {
+        let lifetime = self
+            .lifetime()
+            .map(|lifetime| lifetime.num_milliseconds() as f64 / 1000.0);
+        json!({
+            "pid": self.pid,
+            "name": self.name,
+            "samples": self.samples,
+            "first_seen": self.first_seen.map(|time| time.to_rfc3339()),
+            "last_seen": self.last_seen.map(|time| time.to_rfc3339()),
+            "lifetime_seconds": lifetime,
+            "cpu_percent": self.cpu.to_json(),
+            "memory_percent": self.memory.to_json(),
+        })
+    }
+}
+
+/// Collects the samples of each process.
+#[derive(Debug, Default)]
+struct Collector {
+    processes: HashMap<(u32, String), Samples>,
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    cpu: Vec<f64>,
+    memory: Vec<f64>,
+    first_seen: Option<DateTime<Utc>>,
+    last_seen: Option<DateTime<Utc>>,
+}
+
+impl Collector {
+    fn add(&mut self, row: Row) {
+        let samples = self.processes.entry((row.pid, row.name)).or_default();
+        samples.cpu.push(row.cpu_usage);
+        samples.memory.push(row.memory_usage);
+        if let Some(timestamp) = row.timestamp {
+            samples.first_seen = Some(samples.first_seen.map_or(timestamp, |t| t.min(timestamp)));
+            samples.last_seen = Some(samples.last_seen.map_or(timestamp, |t| t.max(timestamp)));
+        }
+    }
+
+    /// Every process, by highest mean CPU usage first and then by PID.
+    fn finish(self) -> Vec<ProcessStats> {
+        let mut stats: Vec<ProcessStats> = self
+            .processes
+            .into_iter()
+            .map(|((pid, name), mut samples)| ProcessStats {
+                pid,
+                name,
+                samples: samples.cpu.len(),
+                cpu: Stats::of(&mut samples.cpu),
+                memory: Stats::of(&mut samples.memory),
+                first_seen: samples.first_seen,
+                last_seen: samples.last_seen,
+            })
+            .collect();
+        stats.sort_unstable_by(|a, b| {
+            b.cpu
+                .mean
+                .total_cmp(&a.cpu.mean)
+                .then(a.pid.cmp(&b.pid))
+                .then(a.name.cmp(&b.name))
+        });
+        stats
+    }
+}
+
+/// Reads the capture at `path`, CSV, JSON Lines, JSON or SQLite, and
+/// returns the statistics of every process in it.
+pub fn analyze(path: &Path) -> Result<Vec<ProcessStats>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let first = reader
+        .fill_buf()
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut collector = Collector::default();
+    if first.starts_with(integrity::GZIP_MAGIC) || first.starts_with(integrity::ZSTD_MAGIC) {
+        bail!(
+            "{} is compressed; decompress it first and analyze the result",
+            path.display()
+        );
+    } else if first.starts_with(integrity::SQLITE_MAGIC) {
+        read_sqlite(path, &mut collector)?;
+    } else if first.starts_with(integrity::AVRO_MAGIC)
+        || first.starts_with(integrity::PARQUET_MAGIC)
+    {
+        bail!(
+            "Cannot analyze {}: only CSV, JSON Lines, JSON and SQLite captures can be read back",
+            path.display()
+        );
+    } else if first.first() == Some(&b'[') {
+        let records: Vec<Value> = serde_json::from_reader(reader)
+            .with_context(|| format!("{} is not a JSON array", path.display()))?;
+        records
+            .iter()
+            .filter_map(json_row)
+            .for_each(|row| collector.add(row));
+    } else if first.first() == Some(&b'{') {
+        for line in reader.lines() {
+            let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+            // A line cut short by a crash ends the capture.
+            let Ok(record) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            if let Some(row) = json_row(&record) {
+                collector.add(row);
+            }
+        }
+    } else {
+        read_csv(path, reader, &mut collector)?;
+    }
+    Ok(collector.finish())
+}
+
+/// Reads the process rows of a CSV capture, skipping its metadata and
+/// footer comments, markers and focus-tier rows.
+fn read_csv(path: &Path, reader: impl BufRead, collector: &mut Collector) -> Result<()> {
+    let mut csv = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .flexible(true)
+        .from_reader(reader);
+    let headers = csv
+        .headers()
+        .with_context(|| format!("Failed to read the header of {}", path.display()))?
+        .clone();
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let (Some(timestamp), Some(pid), Some(name), Some(cpu), Some(memory)) = (
+        column("Timestamp"),
+        column("PID"),
+        column("Process Name"),
+        column("CPU Usage (%)"),
+        column("Memory Usage (%)"),
+    ) else {
+        bail!("{} is not a capture of processes", path.display());
+    };
+    let scope = column("Scope");
+    for record in csv.records() {
+        // A line cut short by a crash ends the capture.
+        let Ok(record) = record else {
+            continue;
+        };
+        if scope
+            .and_then(|scope| record.get(scope))
+            .is_some_and(|scope| scope != SCOPE_FULL)
+        {
+            continue;
+        }
+        let (Some(pid), Some(cpu_usage), Some(memory_usage)) = (
+            record.get(pid).and_then(|pid| pid.parse().ok()),
+            record.get(cpu).and_then(|cpu| cpu.parse().ok()),
+            record.get(memory).and_then(|memory| memory.parse().ok()),
+        ) else {
+            continue;
+        };
+        collector.add(Row {
+            timestamp: record.get(timestamp).and_then(parse_timestamp),
+            pid,
+            name: record.get(name).unwrap_or_default().to_string(),
+            cpu_usage,
+            memory_usage,
+        });
+    }
+    Ok(())
+}
+
+/// The process row a JSON Lines or JSON record holds, or `None` for markers
+/// and the integrity footer.
+fn json_row(record: &Value) -> Option<Row> {
+    if record
+        .get("scope")
+        .and_then(Value::as_str)
+        .is_some_and(|scope| scope != SCOPE_FULL)
+    {
+        return None;
+    }
+    let timestamp = match record.get("timestamp")? {
+        Value::String(timestamp) => parse_timestamp(timestamp),
+        Value::Number(epoch) => epoch.as_i64().and_then(from_epoch),
+        _ => None,
+    };
+    Some(Row {
+        timestamp,
+        pid: u32::try_from(record.get("pid")?.as_u64()?).ok()?,
+        name: record.get("name")?.as_str()?.to_string(),
+        cpu_usage: record.get("cpu_percent")?.as_f64()?,
+        memory_usage: record.get("memory_percent")?.as_f64()?,
+    })
+}
+
+#[cfg(feature = "sqlite")]
+fn read_sqlite(path: &Path, collector: &mut Collector) -> Result<()> {
+    let name = path.to_str().context("Database path is not valid UTF-8")?;
+    crate::sqlite::read_samples(name, |sample| {
+        collector.add(Row {
+            timestamp: parse_timestamp(&sample.timestamp),
+            pid: sample.pid,
+            name: sample.name,
+            cpu_usage: sample.cpu_usage,
+            memory_usage: sample.memory_usage,
+        })
+    })
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn read_sqlite(path: &Path, _collector: &mut Collector) -> Result<()> {
+    bail!(
+        "Cannot analyze {}: SQLite support requires the `sqlite` feature",
+        path.display()
+    )
+}
+
+/// Reads an RFC 3339 or epoch timestamp. Other `--timestamp-format` styles
+/// cannot be read back.
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    match DateTime::parse_from_rfc3339(timestamp) {
+        Ok(timestamp) => Some(timestamp.with_timezone(&Utc)),
+        Err(_) => from_epoch(timestamp.parse().ok()?),
+    }
+}
+
+fn from_epoch(epoch: i64) -> Option<DateTime<Utc>> {
+    if epoch.abs() >= EPOCH_MS_THRESHOLD {
+        Utc.timestamp_millis_opt(epoch).single()
+    } else {
+        Utc.timestamp_opt(epoch, 0).single()
+    }
+}
+
+/// Whether `stats` is one of the processes `patterns` asks for.
+fn matches(stats: &ProcessStats, patterns: &[String]) -> bool {
+    patterns.is_empty()
+        || patterns.iter().any(|pattern| {
+            pattern.parse() == Ok(stats.pid)
+                || stats.name.to_lowercase().contains(&pattern.to_lowercase())
+        })
+}
+
+/// Analyzes the capture at `path` and prints the processes `options` asks
+/// for to `out`.
+pub fn run(path: &Path, options: &Options, mut out: impl Write) -> Result<()> {
+    let stats: Vec<ProcessStats> = analyze(path)?
+        .into_iter()
+        .filter(|stats| matches(stats, &options.processes))
+        .take(options.top.unwrap_or(usize::MAX))
+        .collect();
+    if options.json {
+        let stats: Vec<Value> = stats.iter().map(ProcessStats::to_json).collect();
+        serde_json::to_writer_pretty(&mut out, &stats)?;
+        writeln!(out)?;
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "{:>7}  {:<24} {:>7} {:>10}  {:>7} {:>7} {:>7} {:>7}  {:>7} {:>7} {:>7} {:>7}",
+        "PID",
+        "NAME",
+        "SAMPLES",
+        "LIFETIME",
+        "CPU MIN",
+        "MEAN",
+        "P95",
+        "MAX",
+        "MEM MIN",
+        "MEAN",
+        "P95",
+        "MAX"
+    )?;
+    for stats in &stats {
+        let lifetime = match stats.lifetime() {
+            Some(lifetime) => format!("{:.1}s", lifetime.num_milliseconds() as f64 / 1000.0),
+            None => "-".to_string(),
+        };
+        writeln!(
+            out,
+            "{:>7}  {:<24} {:>7} {:>10}  {:>7.2} {:>7.2} {:>7.2} {:>7.2}  {:>7.2} {:>7.2} {:>7.2} {:>7.2}",
+            stats.pid,
+            stats.name,
+            stats.samples,
+            lifetime,
+            stats.cpu.min,
+            stats.cpu.mean,
+            stats.cpu.p95,
+            stats.cpu.max,
+            stats.memory.min,
+            stats.memory.mean,
+            stats.memory.p95,
+            stats.memory.max
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn capture(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut values: Vec<f64> = (1..=20).rev().map(f64::from).collect();
+        let stats = Stats::of(&mut values);
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.mean, 10.5);
+        assert_eq!(stats.p95, 19.0);
+        assert_eq!(stats.max, 20.0);
+        assert_eq!(Stats::of(&mut [4.0]).p95, 4.0);
+    }
+
+    #[test]
+    fn test_csv() {
+        let file = capture(
+            "# chronologger: 1.0.1\n\
+             Timestamp,PID,Process Name,CPU Usage (%),Memory Usage (%),Scope\n\
+             2026-01-01T00:00:00+00:00,1,init,1.00,0.50,full\n\
+             2026-01-01T00:00:00+00:00,42,worker,10.00,2.00,full\n\
+             2026-01-01T00:00:00.500+00:00,42,worker,99.00,2.00,focus\n\
+             2026-01-01T00:00:01+00:00,,[2 ticks dropped],,,gap\n\
+             2026-01-01T00:00:02+00:00,42,worker,30.00,4.00,full\n\
+             # integrity: rows=5 bytes=1 sha256=00\n",
+        );
+        let stats = analyze(file.path()).unwrap();
+        assert_eq!(stats.len(), 2);
+        let worker = &stats[0];
+        assert_eq!((worker.pid, worker.name.as_str()), (42, "worker"));
+        assert_eq!(worker.samples, 2);
+        assert_eq!(worker.cpu.mean, 20.0);
+        assert_eq!(worker.cpu.max, 30.0);
+        assert_eq!(worker.memory.min, 2.0);
+        assert_eq!(worker.lifetime(), Some(chrono::Duration::seconds(2)));
+        assert_eq!(stats[1].name, "init");
+        assert_eq!(stats[1].lifetime(), Some(chrono::Duration::zero()));
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let file = capture(
+            "{\"timestamp\":1767225600000,\"pid\":7,\"name\":\"db\",\"cpu_percent\":5.0,\"memory_percent\":1.0}\n\
+             {\"timestamp\":1767225600000,\"heartbeat\":true}\n\
+             {\"timestamp\":1767225660000,\"pid\":7,\"name\":\"db\",\"cpu_percent\":7.0,\"memory_percent\":3.0}\n\
+             {\"Integrity\":{\"rows\":3}}\n\
+             {\"timestamp\":1767225720000,\"pid\":7,\"na",
+        );
+        let stats = analyze(file.path()).unwrap();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].samples, 2);
+        assert_eq!(stats[0].memory.mean, 2.0);
+        assert_eq!(stats[0].lifetime(), Some(chrono::Duration::minutes(1)));
+    }
+
+    #[test]
+    fn test_options() {
+        let file = capture(
+            "Timestamp,PID,Process Name,CPU Usage (%),Memory Usage (%)\n\
+             strftime,1,init,1.00,0.50\n\
+             strftime,2,Worker,5.00,0.50\n\
+             strftime,3,worker,3.00,0.50\n",
+        );
+        let options = Options {
+            top: Some(1),
+            processes: vec!["WORK".to_string()],
+            json: true,
+        };
+        let mut out = Vec::new();
+        run(file.path(), &options, &mut out).unwrap();
+        let printed: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(printed.as_array().unwrap().len(), 1);
+        assert_eq!(printed[0]["pid"], 2);
+        assert_eq!(printed[0]["lifetime_seconds"], Value::Null);
+
+        let options = Options {
+            processes: vec!["1".to_string()],
+            ..Options::default()
+        };
+        let mut out = Vec::new();
+        run(file.path(), &options, &mut out).unwrap();
+        let printed = String::from_utf8(out).unwrap();
+        assert_eq!(printed.lines().count(), 2);
+        assert!(printed.lines().nth(1).unwrap().contains("init"));
+    }
+}
@@ -0,0 +1,308 @@
+use anyhow::{bail, Context, Result};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Map, Value};
+use sha2::Sha256;
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    fs,
+    hash::{BuildHasher, Hasher},
+    path::{Path, PathBuf},
+};
+
+/// Hex digits of the keyed hash kept in a hashed name.
+const HASH_DIGITS: usize = 12;
+
+/// How `--anonymize-names` replaces process names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Mode {
+    /// A short hash of the name under a key, the same for every run given
+    /// the same key.
+    Hash,
+    /// `proc-001`, `proc-002` and so on in order of first appearance.
+    Enumerate,
+}
+
+impl Mode {
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Hash => "hash",
+            Mode::Enumerate => "enumerate",
+        }
+    }
+}
+
+/// Options of `--anonymize-names`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub mode: Mode,
+    /// The hash key given with `--anonymize-key`.
+    pub key: Option<String>,
+    /// The local file keeping the names and any generated key, if the output
+    /// is a file or `--anonymize-map` is given.
+    pub map: Option<PathBuf>,
+}
+
+/// The map file of `output`, next to it.
+pub fn map_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".names.json");
+    PathBuf::from(path)
+}
+
+/// Replaces process names the same way for the whole run. What it takes to
+/// undo that, the original names and a generated key, is only saved to the
+/// local map file, never to the output, the sidecar or a sink. A later run
+/// with the same map file picks up where this one left off.
+#[derive(Debug, Clone)]
+pub struct Anonymizer {
+    mode: Mode,
+    key: String,
+    /// Whether the key was generated and so has to be saved.
+    generated_key: bool,
+    /// Replaced names by original name.
+    names: HashMap<String, String>,
+    map: Option<PathBuf>,
+    /// Set when `names` or the key have changed since the map was saved.
+    dirty: bool,
+}
+
+impl Anonymizer {
+    pub fn open(options: &Options) -> Result<Self> {
+        let saved = match &options.map {
+            Some(path) => read_map(path)?,
+            None => None,
+        };
+        if let Some(saved) = &saved {
+            let mode = saved["mode"].as_str().unwrap_or_default();
+            if mode != options.mode.name() {
+                bail!(
+                    "{} maps names with --anonymize-names {}, not {}",
+                    options.map.as_deref().unwrap_or(Path::new("")).display(),
+                    mode,
+                    options.mode.name()
+                );
+            }
+        }
+        let saved_key = saved
+            .as_ref()
+            .and_then(|saved| saved["key"].as_str())
+            .map(str::to_string);
+        let (key, generated_key) = match (&options.key, saved_key) {
+            (Some(key), _) => (key.clone(), false),
+            (None, Some(key)) => (key, true),
+            (None, None) if options.mode == Mode::Enumerate => (String::new(), false),
+            (None, None) => {
+                if options.map.is_none() {
+                    bail!(
+                        "--anonymize-names hash needs --anonymize-key or, to keep a generated \
+                         key, --anonymize-map for output that is not a file"
+                    );
+                }
+                (generate_key(), true)
+            }
+        };
+        if options.mode == Mode::Enumerate && options.map.is_none() {
+            bail!(
+                "--anonymize-names enumerate needs --anonymize-map to keep the names for \
+                 output that is not a file"
+            );
+        }
+
+        let mut anonymizer = Self {
+            mode: options.mode,
+            key,
+            generated_key,
+            names: HashMap::new(),
+            map: options.map.clone(),
+            dirty: generated_key,
+        };
+        let saved_names = saved
+            .as_ref()
+            .and_then(|saved| saved["names"].as_object())
+            .into_iter()
+            .flatten();
+        for (name, replaced) in saved_names {
+            let Some(replaced) = replaced.as_str() else {
+                continue;
+            };
+            // Names hashed under another key would not match this run's.
+            if anonymizer.mode == Mode::Hash && anonymizer.hash(name) != replaced {
+                anonymizer.dirty = true;
+                continue;
+            }
+            anonymizer.names.insert(name.clone(), replaced.to_string());
+        }
+        Ok(anonymizer)
+    }
+
+    /// What `name` is written as.
+    pub fn name(&mut self, name: &str) -> &str {
+        if !self.names.contains_key(name) {
+            let replaced = match self.mode {
+                Mode::Hash => self.hash(name),
+                Mode::Enumerate => format!("proc-{:03}", self.names.len() + 1),
+            };
+            self.names.insert(name.to_string(), replaced);
+            self.dirty = true;
+        }
+        &self.names[name]
+    }
+
+    fn hash(&self, name: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.as_bytes())
+            .expect("HMAC takes a key of any length");
+        mac.update(name.as_bytes());
+        let hash = format!("{:x}", mac.finalize().into_bytes());
+        format!("proc-{}", &hash[..HASH_DIGITS])
+    }
+
+    /// Saves the names met so far, and a generated key, to the map file if
+    /// they changed since the last save.
+    pub fn save(&mut self) -> Result<()> {
+        let Some(path) = &self.map else {
+            return Ok(());
+        };
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut map = json!({ "mode": self.mode.name() });
+        if self.generated_key {
+            map["key"] = self.key.clone().into();
+        }
+        map["names"] = self
+            .names
+            .iter()
+            .map(|(name, replaced)| (name.clone(), Value::from(replaced.as_str())))
+            .collect::<Map<_, _>>()
+            .into();
+        write_private(path, &(serde_json::to_string_pretty(&map)? + "\n"))
+            .with_context(|| format!("Failed to write the name map {}", path.display()))?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// `name` as `anonymizer` replaces it, or unchanged without one.
+pub fn apply<'a>(anonymizer: Option<&'a mut Anonymizer>, name: &'a str) -> &'a str {
+    match anonymizer {
+        Some(anonymizer) => anonymizer.name(name),
+        None => name,
+    }
+}
+
+fn read_map(path: &Path) -> Result<Option<Value>> {
+    match fs::read_to_string(path) {
+        Ok(text) => serde_json::from_str(&text)
+            .map(Some)
+            .with_context(|| format!("Malformed name map {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// A 256-bit key as hex. The bits come from the standard library's hasher,
+/// whose keys are drawn from the OS's random number generator.
+fn generate_key() -> String {
+    (0..4u64)
+        .map(|salt| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(salt);
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+/// Writes `text` to a temporary file readable only by its owner and renames
+/// it over `path`.
+fn write_private(path: &Path, text: &str) -> std::io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    std::io::Write::write_all(&mut options.open(&temporary)?, text.as_bytes())?;
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn options(mode: Mode, key: Option<&str>, map: Option<PathBuf>) -> Options {
+        Options {
+            mode,
+            key: key.map(str::to_string),
+            map,
+        }
+    }
+
+    #[test]
+    fn test_hash_is_deterministic_with_a_key() {
+        let mut first = Anonymizer::open(&options(Mode::Hash, Some("secret"), None)).unwrap();
+        let mut second = Anonymizer::open(&options(Mode::Hash, Some("secret"), None)).unwrap();
+        let nginx = first.name("nginx").to_string();
+        assert_eq!(nginx, "proc-8bc8124e47ad");
+        assert_eq!(second.name("postgres"), first.name("postgres"));
+        assert_eq!(second.name("nginx"), nginx);
+        assert_ne!(first.name("postgres"), nginx);
+
+        let mut other = Anonymizer::open(&options(Mode::Hash, Some("other"), None)).unwrap();
+        assert_ne!(other.name("nginx"), nginx);
+
+        // Without a key or a place to keep one, the names could not be
+        // repeated.
+        assert!(Anonymizer::open(&options(Mode::Hash, None, None)).is_err());
+    }
+
+    #[test]
+    fn test_generated_key_is_kept() {
+        let dir = TempDir::new().unwrap();
+        let map = dir.path().join("capture.csv.names.json");
+        let mut first = Anonymizer::open(&options(Mode::Hash, None, Some(map.clone()))).unwrap();
+        let nginx = first.name("nginx").to_string();
+        first.save().unwrap();
+
+        let saved: Value = serde_json::from_str(&fs::read_to_string(&map).unwrap()).unwrap();
+        assert_eq!(saved["key"].as_str().unwrap().len(), 64);
+        let mut second = Anonymizer::open(&options(Mode::Hash, None, Some(map))).unwrap();
+        assert_eq!(second.name("nginx"), nginx);
+    }
+
+    #[test]
+    fn test_enumerate_map_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let map = dir.path().join("capture.csv.names.json");
+        let enumerate = || options(Mode::Enumerate, None, Some(map.clone()));
+        let mut first = Anonymizer::open(&enumerate()).unwrap();
+        assert_eq!(first.name("nginx"), "proc-001");
+        assert_eq!(first.name("postgres"), "proc-002");
+        assert_eq!(first.name("nginx"), "proc-001");
+        first.save().unwrap();
+        assert!(!dir.path().join("capture.csv.names.json.tmp").exists());
+
+        let saved: Value = serde_json::from_str(&fs::read_to_string(&map).unwrap()).unwrap();
+        assert_eq!(
+            saved,
+            json!({
+                "mode": "enumerate",
+                "names": { "nginx": "proc-001", "postgres": "proc-002" },
+            })
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&map).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let mut second = Anonymizer::open(&enumerate()).unwrap();
+        assert_eq!(second.name("postgres"), "proc-002");
+        assert_eq!(second.name("redis"), "proc-003");
+
+        let hash = options(Mode::Hash, Some("secret"), Some(map));
+        assert!(Anonymizer::open(&hash).is_err());
+    }
+}
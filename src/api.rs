@@ -0,0 +1,281 @@
+use crate::{
+    http::{self, Reply, Request},
+    sink::Sink,
+    ProcessSample,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Local};
+use log::{error, info};
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    net::{SocketAddr, TcpListener},
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, RwLock},
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+const DEFAULT_HISTORY_SECONDS: u32 = 300;
+
+#[derive(Debug, Clone)]
+pub struct ApiConfig {
+    pub listen: String,
+    pub history: usize,
+    pub interval: std::time::Duration,
+    pub output: String,
+    /// Added to every tick and to the status.
+    pub run_id: Option<String>,
+}
+
+struct Tick {
+    timestamp: DateTime<Local>,
+    body: Value,
+}
+
+#[derive(Default)]
+struct Snapshot {
+    history: VecDeque<Tick>,
+    ticks: u64,
+    samples_written: u64,
+}
+
+/// Serves the most recent ticks over HTTP. The sampler only takes the write
+/// lock once per tick to push the new snapshot.
+pub struct ApiSink {
+    snapshot: Arc<RwLock<Snapshot>>,
+    history: usize,
+    run_id: Option<String>,
+    address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl ApiSink {
+    pub fn new(config: ApiConfig) -> Result<Self> {
+        let listener = TcpListener::bind(&config.listen)
+            .with_context(|| format!("Failed to listen on {}", config.listen))?;
+        let address = listener.local_addr()?;
+        info!("Serving the HTTP API on http://{}", address);
+
+        let snapshot = Arc::new(RwLock::new(Snapshot::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let history = config.history;
+        let run_id = config.run_id.clone();
+        let handle = {
+            let snapshot = snapshot.clone();
+            let stop = stop.clone();
+            let started_at = Local::now();
+            let started = Instant::now();
+            thread::spawn(move || {
+                let result = http::serve(listener, stop, |request| {
+                    route(&config, &snapshot, started_at, started, request)
+                });
+                if let Err(e) = result {
+                    error!("HTTP API stopped: {:#}", e);
+                }
+            })
+        };
+
+        Ok(Self {
+            snapshot,
+            history,
+            run_id,
+            address,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Sink for ApiSink {
+    fn publish(&mut self, timestamp: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        let mut body = json!({
+            "timestamp": timestamp.to_rfc3339(),
+            "samples": samples.iter().map(ProcessSample::to_json).collect::<Vec<_>>(),
+        });
+        if let Some(run_id) = &self.run_id {
+            body["run_id"] = json!(run_id);
+        }
+        let tick = Tick {
+            timestamp: *timestamp,
+            body,
+        };
+
+        let mut snapshot = self.snapshot.write().unwrap();
+        if snapshot.history.len() == self.history {
+            snapshot.history.pop_front();
+        }
+        snapshot.history.push_back(tick);
+        snapshot.ticks += 1;
+        snapshot.samples_written += samples.len() as u64;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        info!("HTTP API on {} stopped", self.address);
+        Ok(())
+    }
+}
+
+fn route(
+    config: &ApiConfig,
+    snapshot: &RwLock<Snapshot>,
+    started_at: DateTime<Local>,
+    started: Instant,
+    request: &Request,
+) -> Reply {
+    if request.method != "GET" {
+        return Reply {
+            status: 405,
+            content_type: "text/plain",
+            body: "Method Not Allowed\n".to_string(),
+        };
+    }
+
+    let snapshot = snapshot.read().unwrap();
+    match request.path.as_str() {
+        "/latest" => Reply::json(
+            snapshot
+                .history
+                .back()
+                .map(|tick| tick.body.to_string())
+                .unwrap_or_else(|| "null".to_string()),
+        ),
+        "/history" => {
+            let seconds = match request.query.get("seconds") {
+                Some(seconds) => match seconds.parse::<u32>() {
+                    Ok(seconds) => seconds,
+                    Err(_) => {
+                        return Reply {
+                            status: 400,
+                            content_type: "text/plain",
+                            body: "seconds must be a whole number of seconds up to 4294967295\n"
+                                .to_string(),
+                        }
+                    }
+                },
+                None => DEFAULT_HISTORY_SECONDS,
+            };
+            let since = Local::now() - Duration::seconds(i64::from(seconds));
+            let ticks: Vec<&Value> = snapshot
+                .history
+                .iter()
+                .filter(|tick| tick.timestamp >= since)
+                .map(|tick| &tick.body)
+                .collect();
+            Reply::json(json!(ticks).to_string())
+        }
+        "/status" => Reply::json(
+            json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "started_at": started_at.to_rfc3339(),
+                "uptime_seconds": started.elapsed().as_secs_f64(),
+                "interval_seconds": config.interval.as_secs_f64(),
+                "output": config.output,
+                "run_id": config.run_id,
+                "ticks": snapshot.ticks,
+                "samples_written": snapshot.samples_written,
+            })
+            .to_string(),
+        ),
+        _ => Reply::not_found(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Url;
+    use sysinfo::{Pid, PidExt};
+
+    fn url(sink: &ApiSink) -> Url {
+        Url {
+            tls: false,
+            host: "127.0.0.1".to_string(),
+            port: sink.address().port(),
+            path: String::new(),
+        }
+    }
+
+    fn get(sink: &ApiSink, path: &str) -> Value {
+        let response = http::send(
+            "GET",
+            &url(sink),
+            path,
+            "text/plain",
+            b"",
+            std::time::Duration::from_secs(5),
+        )
+        .expect("Request failed");
+        assert_eq!(response.status, 200);
+        serde_json::from_str(&response.body).expect("Response is not valid JSON")
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let mut sink = ApiSink::new(ApiConfig {
+            listen: "127.0.0.1:0".to_string(),
+            history: 2,
+            interval: std::time::Duration::from_secs(1),
+            output: "process_usage.csv".to_string(),
+            run_id: Some("run-1".to_string()),
+        })
+        .expect("Failed to start API");
+
+        for pid in 1..=3 {
+            let samples = vec![ProcessSample {
+                pid: Some(Pid::from_u32(pid)),
+                name: "worker".to_string(),
+                cpu_usage: 1.0,
+                memory_usage: 2.0,
+                extras: Vec::new(),
+            }];
+            sink.publish(&Local::now(), &samples)
+                .expect("Failed to publish samples");
+        }
+
+        let latest = get(&sink, "/latest");
+        assert!(latest["timestamp"].is_string());
+        assert_eq!(latest["samples"][0]["pid"], 3);
+        assert_eq!(latest["samples"][0]["name"], "worker");
+        assert_eq!(latest["run_id"], "run-1");
+
+        let history = get(&sink, "/history?seconds=60");
+        assert_eq!(
+            history.as_array().unwrap().len(),
+            2,
+            "History must be bounded"
+        );
+
+        for path in ["/history?seconds=99999999999999999", "/history?seconds=-5"] {
+            let response = http::send(
+                "GET",
+                &url(&sink),
+                path,
+                "text/plain",
+                b"",
+                std::time::Duration::from_secs(5),
+            )
+            .expect("Request failed");
+            assert_eq!(response.status, 400, "{} must be refused", path);
+        }
+
+        let status = get(&sink, "/status");
+        assert_eq!(status["ticks"], 3);
+        assert_eq!(status["samples_written"], 3);
+        assert_eq!(status["run_id"], "run-1");
+        assert!(status["uptime_seconds"].is_number());
+
+        sink.finish().expect("Failed to stop API");
+    }
+}
@@ -0,0 +1,372 @@
+use crate::{
+    columns::{Extra, ExtraValue},
+    integrity::SharedDigest,
+    overhead::Counted,
+    schema::Kind,
+    Destination, ProcessSample,
+};
+use anyhow::{Context, Result};
+use apache_avro::{types::Value, Reader, Schema, Writer};
+use chrono::{DateTime, Local};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::Path,
+    sync::{Mutex, OnceLock},
+};
+use sysinfo::PidExt;
+
+/// File metadata key of the run ID.
+const RUN_ID_KEY: &str = "chronologger.run_id";
+
+/// Large enough that the writer never cuts a block on its own; blocks are
+/// written explicitly at tick or row-count boundaries.
+const MAX_BLOCK_BYTES: usize = 64 << 20;
+
+/// The record schema for rows with the optional columns `extras`, each a
+/// union with null that is null where the row has no value. `pid` is null
+/// for a `--group-by` group. A run only ever uses a few column sets, so each
+/// schema is built once and kept for the writers to borrow.
+pub fn schema(extras: &[Extra]) -> &'static Schema {
+    static SCHEMAS: OnceLock<Mutex<HashMap<Vec<Extra>, &'static Schema>>> = OnceLock::new();
+    let mut schemas = SCHEMAS.get_or_init(Default::default).lock().unwrap();
+    *schemas.entry(extras.to_vec()).or_insert_with(|| {
+        let mut fields = vec![
+            json!({
+                "name": "timestamp",
+                "type": { "type": "long", "logicalType": "timestamp-millis" }
+            }),
+            json!({ "name": "pid", "type": ["null", "int"], "default": null }),
+            json!({ "name": "name", "type": "string" }),
+            json!({ "name": "cpu", "type": "double" }),
+            json!({ "name": "mem", "type": "double" }),
+        ];
+        fields.extend(extras.iter().map(|extra| {
+            let kind = match extra.kind() {
+                Kind::Pid => "int",
+                Kind::Text => "string",
+                _ => "long",
+            };
+            json!({ "name": extra.id(), "type": ["null", kind], "default": null })
+        }));
+        let schema = json!({
+            "type": "record",
+            "name": "ProcessSample",
+            "namespace": "chronologger",
+            "fields": fields,
+        });
+        Box::leak(Box::new(
+            Schema::parse(&schema).expect("Invalid Avro schema"),
+        ))
+    })
+}
+
+/// Writes samples into an Avro object container file. Each block holds one
+/// tick, or `block_rows` rows when set.
+pub struct AvroOutput {
+    /// The destination until the writer is started with the first write,
+    /// since the schema depends on the optional columns, which are set after
+    /// the output is created.
+    file: Option<Counted<Destination>>,
+    writer: Option<Box<Writer<'static, Counted<Destination>>>>,
+    extras: Vec<Extra>,
+    /// Set before the writer is started.
+    run_id: Option<String>,
+    block_rows: Option<usize>,
+    pending_rows: usize,
+    /// Digest of a file destination, which cannot see where rows end.
+    digest: Option<SharedDigest>,
+}
+
+impl AvroOutput {
+    pub fn new(file: Counted<Destination>, block_rows: Option<usize>) -> Self {
+        let digest = file.get_ref().digest().cloned();
+        Self {
+            file: Some(file),
+            writer: None,
+            extras: Vec::new(),
+            run_id: None,
+            block_rows,
+            pending_rows: 0,
+            digest,
+        }
+    }
+
+    pub fn digest(&self) -> Option<&SharedDigest> {
+        self.digest.as_ref()
+    }
+
+    /// Sets the optional columns, before anything is written.
+    pub fn set_extras(&mut self, extras: &[Extra]) {
+        self.extras = extras.to_vec();
+    }
+
+    /// Records `run_id` in the file metadata, which is written with the
+    /// first block.
+    pub fn set_run_id(&mut self, run_id: &str) -> Result<()> {
+        match &mut self.writer {
+            Some(writer) => writer
+                .add_user_metadata(RUN_ID_KEY.to_string(), run_id)
+                .context("Failed to record the run ID"),
+            None => {
+                self.run_id = Some(run_id.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    /// The writer, started with the schema of the optional columns on first
+    /// use.
+    fn writer(&mut self) -> Result<&mut Writer<'static, Counted<Destination>>> {
+        let Some(file) = self.file.take() else {
+            return Ok(self.writer.as_mut().expect("The writer has the file"));
+        };
+        let writer = self.writer.insert(Box::new(
+            Writer::builder()
+                .schema(schema(&self.extras))
+                .writer(file)
+                .block_size(MAX_BLOCK_BYTES)
+                .build(),
+        ));
+        if let Some(run_id) = self.run_id.take() {
+            writer
+                .add_user_metadata(RUN_ID_KEY.to_string(), run_id)
+                .context("Failed to record the run ID")?;
+        }
+        Ok(writer)
+    }
+
+    pub fn write_tick(
+        &mut self,
+        timestamp: &DateTime<Local>,
+        samples: &[ProcessSample],
+    ) -> Result<()> {
+        let timestamp = timestamp.timestamp_millis();
+        for sample in samples {
+            let record = record(&self.extras, timestamp, sample);
+            self.writer()?
+                .append(record)
+                .context("Failed to write record!")?;
+            self.pending_rows += 1;
+            if let Some(digest) = &self.digest {
+                digest.lock().unwrap().add_rows(1);
+            }
+            if self
+                .block_rows
+                .is_some_and(|rows| self.pending_rows >= rows)
+            {
+                self.flush()?;
+            }
+        }
+        if self.block_rows.is_none() {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes any pending rows as a final block.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer()?.flush().context("Failed to flush writer!")?;
+        self.pending_rows = 0;
+        Ok(())
+    }
+}
+
+/// Counts the records in the Avro file at `path`.
+pub fn count_rows(path: &Path) -> Result<u64> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let reader = Reader::new(file).with_context(|| format!("{} is not Avro", path.display()))?;
+    let mut rows = 0;
+    for record in reader {
+        record.with_context(|| format!("Malformed record in {}", path.display()))?;
+        rows += 1;
+    }
+    Ok(rows)
+}
+
+fn record(extras: &[Extra], timestamp: i64, sample: &ProcessSample) -> Value {
+    let mut fields = vec![
+        ("timestamp".to_string(), Value::TimestampMillis(timestamp)),
+        (
+            "pid".to_string(),
+            nullable(sample.pid.map(|pid| Value::Int(pid.as_u32() as i32))),
+        ),
+        ("name".to_string(), Value::String(sample.name.clone())),
+        ("cpu".to_string(), Value::Double(sample.cpu_usage as f64)),
+        ("mem".to_string(), Value::Double(sample.memory_usage)),
+    ];
+    // Rows without a value, e.g. markers, write null.
+    for (column, extra) in extras.iter().enumerate() {
+        let value = sample.extras.get(column).and_then(Option::as_ref);
+        fields.push((
+            extra.id().to_string(),
+            nullable(value.map(|value| typed(*extra, value))),
+        ));
+    }
+    Value::Record(fields)
+}
+
+/// A value of a `["null", T]` union.
+fn nullable(value: Option<Value>) -> Value {
+    match value {
+        Some(value) => Value::Union(1, Box::new(value)),
+        None => Value::Union(0, Box::new(Value::Null)),
+    }
+}
+
+/// `value` as the type the schema gives `extra`.
+fn typed(extra: Extra, value: &ExtraValue) -> Value {
+    match (extra.kind(), value) {
+        (_, ExtraValue::Text(text)) => Value::String(text.to_string()),
+        (Kind::Pid, ExtraValue::Count(count)) => Value::Int(*count as i32),
+        (_, ExtraValue::Count(count)) => Value::Long(*count as i64),
+        (_, ExtraValue::Signed(value)) => Value::Long(*value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{sample, samples};
+    use crate::{
+        integrity::{Hashed, Rows},
+        retry::Retrying,
+    };
+    use tempfile::NamedTempFile;
+
+    fn read_back(file: &NamedTempFile) -> Vec<Value> {
+        let reader = Reader::new(File::open(file.path()).unwrap()).expect("Invalid container");
+        reader.map(|value| value.expect("Invalid record")).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let timestamp = Local::now();
+        let samples = samples();
+
+        let mut output = AvroOutput::new(
+            Counted::new(
+                Destination::File(Hashed::new(
+                    Retrying::new(temp_file.reopen().unwrap(), Default::default()),
+                    Rows::Counted,
+                )),
+                Default::default(),
+            ),
+            None,
+        );
+        output.set_run_id("run-1").expect("Failed to set run ID");
+        output
+            .write_tick(&timestamp, &samples)
+            .expect("Failed to write tick");
+        output
+            .write_tick(&timestamp, &samples)
+            .expect("Failed to write tick");
+        output.flush().expect("Failed to flush");
+
+        let reader = Reader::new(File::open(temp_file.path()).unwrap()).unwrap();
+        assert_eq!(
+            reader.user_metadata().get(RUN_ID_KEY).map(Vec::as_slice),
+            Some(&b"run-1"[..])
+        );
+        let records = read_back(&temp_file);
+        assert_eq!(records.len(), samples.len() * 2);
+        assert_eq!(count_rows(temp_file.path()).unwrap(), records.len() as u64);
+        let footer = output.digest().unwrap().lock().unwrap().footer();
+        assert_eq!(footer.rows, records.len() as u64);
+        assert_eq!(footer.bytes, temp_file.as_file().metadata().unwrap().len());
+        for (record, sample) in records.iter().zip(samples.iter().cycle()) {
+            assert_eq!(
+                record,
+                &super::record(&[], timestamp.timestamp_millis(), sample)
+            );
+        }
+    }
+
+    #[test]
+    fn test_round_trip_optional_columns() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let extras = [Extra::Rss, Extra::Ppid, Extra::MemoryGrowth, Extra::Status];
+        let mut process = sample(7, "worker", 1.5, 0.25);
+        process.extras = vec![
+            Some(ExtraValue::Count(4096)),
+            Some(ExtraValue::Count(1)),
+            Some(ExtraValue::Signed(-512)),
+            Some(ExtraValue::Text("Sleeping".into())),
+        ];
+        // A group has no PID, and leaves out values its processes differ in.
+        let mut group = sample(1, "nginx", 3.0, 0.5);
+        group.pid = None;
+        group.extras = vec![Some(ExtraValue::Count(8192)), None, None];
+
+        let mut output = AvroOutput::new(
+            Counted::new(
+                Destination::File(Hashed::new(
+                    Retrying::new(temp_file.reopen().unwrap(), Default::default()),
+                    Rows::Counted,
+                )),
+                Default::default(),
+            ),
+            None,
+        );
+        output.set_extras(&extras);
+        output
+            .write_tick(&Local::now(), &[process, group])
+            .expect("Failed to write tick");
+        output.flush().expect("Failed to flush");
+
+        let reader = Reader::new(File::open(temp_file.path()).unwrap()).unwrap();
+        assert_eq!(reader.writer_schema(), schema(&extras));
+        let records = read_back(&temp_file);
+        let fields = |record: &Value| match record {
+            Value::Record(fields) => fields.iter().cloned().collect::<HashMap<_, _>>(),
+            _ => panic!("Not a record: {:?}", record),
+        };
+        let null = || Value::Union(0, Box::new(Value::Null));
+        let value = |value| Value::Union(1, Box::new(value));
+
+        let process = fields(&records[0]);
+        assert_eq!(process["pid"], value(Value::Int(7)));
+        assert_eq!(process["rss_bytes"], value(Value::Long(4096)));
+        assert_eq!(process["parent_pid"], value(Value::Int(1)));
+        assert_eq!(
+            process["memory_growth_bytes_per_second"],
+            value(Value::Long(-512))
+        );
+        assert_eq!(
+            process["status"],
+            value(Value::String("Sleeping".to_string()))
+        );
+
+        let group = fields(&records[1]);
+        assert_eq!(group["pid"], null());
+        assert_eq!(group["name"], Value::String("nginx".to_string()));
+        assert_eq!(group["rss_bytes"], value(Value::Long(8192)));
+        assert_eq!(group["parent_pid"], null());
+        assert_eq!(group["status"], null());
+    }
+
+    #[test]
+    fn test_block_rows() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let mut output = AvroOutput::new(
+            Counted::new(
+                Destination::File(Hashed::new(
+                    Retrying::new(temp_file.reopen().unwrap(), Default::default()),
+                    Rows::Counted,
+                )),
+                Default::default(),
+            ),
+            Some(2),
+        );
+        output
+            .write_tick(&Local::now(), &samples())
+            .expect("Failed to write tick");
+
+        // Two full blocks are on disk before the final flush.
+        assert_eq!(read_back(&temp_file).len(), 4);
+        output.flush().expect("Failed to flush");
+        assert_eq!(read_back(&temp_file).len(), 5);
+    }
+}
@@ -0,0 +1,125 @@
+//! `--max-self-cpu`: keeps chronologger's own CPU usage within a budget by
+//! sampling less often once it exceeds it, and more often again once it has
+//! room to.
+
+use std::time::{Duration, Instant};
+
+/// How many full-table ticks in a row must leave room in the budget before
+/// sampling speeds up again.
+pub const SPEED_UP_AFTER: u32 = 10;
+
+/// What a full-table tick's CPU usage asks of the schedule.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Keep,
+    /// The usage since the previous tick, in percent, exceeded the budget.
+    SlowDown(f64),
+    /// The usage stayed within half the budget for [`SPEED_UP_AFTER`] ticks,
+    /// so sampling twice as often would still fit.
+    SpeedUp,
+}
+
+/// Measures chronologger's CPU usage between full-table ticks. It covers
+/// everything chronologger does in between, the focus ticks and background
+/// outputs included.
+pub struct CpuBudget {
+    /// Percent of one core, as the CPU column has it.
+    max: f64,
+    /// When the previous full-table tick ended and the CPU time used by then.
+    last: Option<(Instant, Duration)>,
+    /// Ticks in a row within half the budget.
+    calm: u32,
+}
+
+impl CpuBudget {
+    pub fn new(max: f64) -> Self {
+        Self {
+            max,
+            last: None,
+            calm: 0,
+        }
+    }
+
+    /// Takes the CPU time chronologger used by `now`, at the end of a
+    /// full-table tick, and judges its usage since the previous one. The
+    /// first tick only starts the measurement, as it also pays for starting
+    /// up.
+    pub fn check(&mut self, now: Instant, cpu_time: Duration) -> Verdict {
+        let Some((last, used)) = self.last.replace((now, cpu_time)) else {
+            return Verdict::Keep;
+        };
+        let wall = now.duration_since(last);
+        if wall.is_zero() {
+            return Verdict::Keep;
+        }
+        let usage = cpu_time.saturating_sub(used).as_secs_f64() / wall.as_secs_f64() * 100.0;
+        if usage > self.max {
+            self.calm = 0;
+            return Verdict::SlowDown(usage);
+        }
+        if usage > self.max / 2.0 {
+            self.calm = 0;
+            return Verdict::Keep;
+        }
+        self.calm += 1;
+        if self.calm < SPEED_UP_AFTER {
+            return Verdict::Keep;
+        }
+        self.calm = 0;
+        Verdict::SpeedUp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let start = Instant::now();
+        let mut budget = CpuBudget::new(2.0);
+        assert_eq!(
+            budget.check(start, Duration::from_millis(500)),
+            Verdict::Keep
+        );
+        // 10ms of CPU time over a second is 1%.
+        let now = start + Duration::from_secs(1);
+        assert_eq!(budget.check(now, Duration::from_millis(510)), Verdict::Keep);
+        let now = now + Duration::from_secs(1);
+        let Verdict::SlowDown(usage) = budget.check(now, Duration::from_millis(560)) else {
+            panic!("5% is over the budget");
+        };
+        assert!((usage - 5.0).abs() < 1e-9, "{}", usage);
+        // Measured from the last check, not the start.
+        let now = now + Duration::from_secs(2);
+        assert_eq!(budget.check(now, Duration::from_millis(580)), Verdict::Keep);
+    }
+
+    #[test]
+    fn test_speeds_up_after_calm_ticks() {
+        let mut now = Instant::now();
+        let mut used = Duration::ZERO;
+        let mut budget = CpuBudget::new(2.0);
+        let mut tick = |budget: &mut CpuBudget, cpu: Duration| {
+            now += Duration::from_secs(1);
+            used += cpu;
+            budget.check(now, used)
+        };
+        tick(&mut budget, Duration::ZERO);
+        // 0.5% of a second leaves room to sample twice as often.
+        for _ in 1..SPEED_UP_AFTER {
+            assert_eq!(tick(&mut budget, Duration::from_millis(5)), Verdict::Keep);
+        }
+        // 1.5% is within the budget but would not be at twice the rate, so
+        // the count starts over.
+        assert_eq!(tick(&mut budget, Duration::from_millis(15)), Verdict::Keep);
+        for _ in 1..SPEED_UP_AFTER {
+            assert_eq!(tick(&mut budget, Duration::from_millis(5)), Verdict::Keep);
+        }
+        assert_eq!(
+            tick(&mut budget, Duration::from_millis(5)),
+            Verdict::SpeedUp
+        );
+        assert_eq!(tick(&mut budget, Duration::from_millis(5)), Verdict::Keep);
+    }
+}
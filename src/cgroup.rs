@@ -0,0 +1,306 @@
+use crate::per_pid::PerPid;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Which cgroup the throttling columns describe.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Selection {
+    /// The cgroup of each process, looked up once per PID.
+    #[default]
+    PerProcess,
+    /// chronologger's own cgroup, for every row.
+    Own,
+    /// The cgroup directory at this path, for every row.
+    Path(PathBuf),
+}
+
+/// Parses `--cgroup`: `self` or a cgroup directory.
+pub fn parse_selection(value: &str) -> Result<Selection, String> {
+    match value {
+        "" => Err("Cgroup path must not be empty".to_string()),
+        "self" => Ok(Selection::Own),
+        path => Ok(Selection::Path(PathBuf::from(path))),
+    }
+}
+
+/// CPU bandwidth counters of a cgroup, or how much they grew over a tick.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuStat {
+    /// Enforcement periods that have elapsed.
+    pub nr_periods: u64,
+    /// Periods in which the cgroup used up its quota.
+    pub nr_throttled: u64,
+    /// Time the cgroup's tasks were throttled, in microseconds.
+    pub throttled_usec: u64,
+}
+
+impl CpuStat {
+    /// Parses `cpu.stat` of cgroup v2, or of the v1 cpu controller, which
+    /// reports the throttled time in nanoseconds.
+    fn parse(text: &str) -> Self {
+        let mut stat = Self::default();
+        for line in text.lines() {
+            let Some((key, value)) = line.split_once(' ') else {
+                continue;
+            };
+            let Ok(value) = value.trim().parse::<u64>() else {
+                continue;
+            };
+            match key {
+                "nr_periods" => stat.nr_periods = value,
+                "nr_throttled" => stat.nr_throttled = value,
+                "throttled_usec" => stat.throttled_usec = value,
+                "throttled_time" => stat.throttled_usec = value / 1000,
+                _ => {}
+            }
+        }
+        stat
+    }
+
+    /// Growth since `earlier`. A cgroup that was recreated in between starts
+    /// again from zero rather than going negative.
+    fn since(self, earlier: Self) -> Self {
+        Self {
+            nr_periods: self.nr_periods.saturating_sub(earlier.nr_periods),
+            nr_throttled: self.nr_throttled.saturating_sub(earlier.nr_throttled),
+            throttled_usec: self.throttled_usec.saturating_sub(earlier.throttled_usec),
+        }
+    }
+}
+
+/// Tracks CPU throttling per tick. Each cgroup's `cpu.stat` is read at most
+/// once per tick however many processes share it, and the counters are
+/// reported as their growth since the previous tick.
+pub struct Throttling {
+    root: PathBuf,
+    selection: Selection,
+    own: Option<Option<PathBuf>>,
+    /// Where the `cpu.stat` of each PID is.
+    paths: PerPid<Option<PathBuf>>,
+    previous: HashMap<PathBuf, CpuStat>,
+    current: HashMap<PathBuf, Option<CpuStat>>,
+}
+
+impl Throttling {
+    pub fn new(selection: Selection) -> Self {
+        Self::with_root(PathBuf::from("/"), selection)
+    }
+
+    /// Reads `/proc` and `/sys/fs/cgroup` below `root` instead of `/`.
+    fn with_root(root: PathBuf, selection: Selection) -> Self {
+        Self {
+            root,
+            selection,
+            own: None,
+            paths: PerPid::new(),
+            previous: HashMap::new(),
+            current: HashMap::new(),
+        }
+    }
+
+    /// Starts a new tick. PIDs and cgroups not seen in the tick that ended
+    /// are forgotten.
+    pub fn begin_tick(&mut self) {
+        self.paths.begin_tick();
+        let current = &self.current;
+        self.previous.retain(|path, _| current.contains_key(path));
+        self.current.clear();
+    }
+
+    /// Forgets the counters read so far, so that the next tick reads them
+    /// afresh rather than reporting their growth across a suspend.
+    pub fn reset(&mut self) {
+        self.previous.clear();
+        self.current.clear();
+    }
+
+    /// How much the throttling counters of the cgroup `pid` belongs to grew
+    /// since the previous tick. `None` on the first tick that reads the
+    /// cgroup, or if its `cpu.stat` cannot be read.
+    pub fn delta(&mut self, pid: u32) -> Option<CpuStat> {
+        let path = match &self.selection {
+            Selection::PerProcess => {
+                let path = match self.paths.take(pid) {
+                    Some(path) => path,
+                    None => cpu_stat_path(&self.root, &pid.to_string()),
+                };
+                self.paths.insert(pid, path.clone());
+                path?
+            }
+            Selection::Own => self
+                .own
+                .get_or_insert_with(|| cpu_stat_path(&self.root, "self"))
+                .clone()?,
+            Selection::Path(dir) => dir.join("cpu.stat"),
+        };
+        if let Some(delta) = self.current.get(&path) {
+            return *delta;
+        }
+        let delta = fs::read_to_string(&path)
+            .ok()
+            .map(|text| CpuStat::parse(&text))
+            .and_then(|stat| {
+                let earlier = self.previous.insert(path.clone(), stat)?;
+                Some(stat.since(earlier))
+            });
+        self.current.insert(path, delta);
+        delta
+    }
+}
+
+/// Finds the `cpu.stat` of the cgroup that `pid` (or `self`) belongs to. On
+/// hybrid hosts the v1 cpu controller, if mounted, takes precedence over the
+/// unified hierarchy.
+fn cpu_stat_path(root: &Path, pid: &str) -> Option<PathBuf> {
+    let membership = fs::read_to_string(root.join("proc").join(pid).join("cgroup")).ok()?;
+    let cgroup_root = root.join("sys/fs/cgroup");
+    let mut unified = None;
+    for line in membership.lines() {
+        let mut fields = line.splitn(3, ':');
+        let (Some(_), Some(controllers), Some(path)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let relative = path.trim_start_matches('/');
+        if controllers.is_empty() {
+            unified = Some(cgroup_root.join(relative).join("cpu.stat"));
+        } else if controllers.split(',').any(|controller| controller == "cpu") {
+            // The controller is mounted under its own name or together with
+            // the others it was co-mounted with, e.g. cpu,cpuacct.
+            for mount in [controllers, "cpu"] {
+                let candidate = cgroup_root.join(mount).join(relative).join("cpu.stat");
+                if candidate.is_file() {
+                    return Some(candidate);
+                }
+            }
+        }
+    }
+    unified.filter(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const V2_STAT: &str = "usage_usec 8000000\nuser_usec 6000000\nsystem_usec 2000000\n\
+        nr_periods 100\nnr_throttled 10\nthrottled_usec 50000\n";
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_parse_v1_and_v2() {
+        assert_eq!(
+            CpuStat::parse(V2_STAT),
+            CpuStat {
+                nr_periods: 100,
+                nr_throttled: 10,
+                throttled_usec: 50_000,
+            }
+        );
+        let v1 = "nr_periods 7\nnr_throttled 2\nthrottled_time 3000000\n";
+        assert_eq!(
+            CpuStat::parse(v1),
+            CpuStat {
+                nr_periods: 7,
+                nr_throttled: 2,
+                throttled_usec: 3000,
+            }
+        );
+    }
+
+    #[test]
+    fn test_finds_cgroup_of_process() {
+        let root = TempDir::new().unwrap();
+        let root = root.path();
+        write(root, "proc/10/cgroup", "0::/kubepods/pod1\n");
+        write(root, "sys/fs/cgroup/kubepods/pod1/cpu.stat", V2_STAT);
+        write(
+            root,
+            "proc/11/cgroup",
+            "4:memory:/docker/abc\n3:cpu,cpuacct:/docker/abc\n0::/\n",
+        );
+        write(
+            root,
+            "sys/fs/cgroup/cpu,cpuacct/docker/abc/cpu.stat",
+            "nr_periods 1\n",
+        );
+        write(root, "proc/12/cgroup", "0::/gone\n");
+
+        assert_eq!(
+            cpu_stat_path(root, "10").unwrap(),
+            root.join("sys/fs/cgroup/kubepods/pod1/cpu.stat")
+        );
+        assert_eq!(
+            cpu_stat_path(root, "11").unwrap(),
+            root.join("sys/fs/cgroup/cpu,cpuacct/docker/abc/cpu.stat")
+        );
+        assert_eq!(cpu_stat_path(root, "12"), None);
+        assert_eq!(cpu_stat_path(root, "13"), None);
+    }
+
+    #[test]
+    fn test_deltas_per_tick() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+        write(dir, "proc/10/cgroup", "0::/pool\n");
+        write(dir, "proc/11/cgroup", "0::/pool\n");
+        write(dir, "sys/fs/cgroup/pool/cpu.stat", V2_STAT);
+        let mut throttling = Throttling::with_root(dir.to_path_buf(), Selection::PerProcess);
+
+        throttling.begin_tick();
+        assert_eq!(throttling.delta(10), None, "No delta on the first tick");
+        assert_eq!(throttling.delta(11), None);
+
+        write(
+            dir,
+            "sys/fs/cgroup/pool/cpu.stat",
+            "nr_periods 110\nnr_throttled 13\nthrottled_usec 65000\n",
+        );
+        throttling.begin_tick();
+        let expected = Some(CpuStat {
+            nr_periods: 10,
+            nr_throttled: 3,
+            throttled_usec: 15_000,
+        });
+        // Both processes share the cgroup and see the same growth.
+        assert_eq!(throttling.delta(10), expected);
+        assert_eq!(throttling.delta(11), expected);
+        assert_eq!(throttling.delta(12), None);
+
+        throttling.begin_tick();
+        assert_eq!(
+            throttling.delta(10),
+            Some(CpuStat::default()),
+            "Nothing grew since the last tick"
+        );
+        throttling.begin_tick();
+        assert!(throttling.paths.take(11).is_none(), "Unseen PID was kept");
+    }
+
+    #[test]
+    fn test_fixed_cgroup() {
+        let root = TempDir::new().unwrap();
+        let pool = root.path().join("pool");
+        write(root.path(), "pool/cpu.stat", V2_STAT);
+        let mut throttling =
+            Throttling::with_root(root.path().to_path_buf(), Selection::Path(pool.clone()));
+        throttling.begin_tick();
+        assert_eq!(throttling.delta(1), None);
+        throttling.begin_tick();
+        assert_eq!(throttling.delta(2), Some(CpuStat::default()));
+        assert_eq!(parse_selection("self"), Ok(Selection::Own));
+        assert_eq!(
+            parse_selection(pool.to_str().unwrap()),
+            Ok(Selection::Path(pool))
+        );
+    }
+}
@@ -0,0 +1,1207 @@
+use crate::{
+    cgroup::{self, Throttling},
+    containers::Containers,
+    derived::{Derived, Usage},
+    numfmt, overhead,
+    redact::Redactor,
+    schema::Kind,
+    source::{self, Denied, ProcessDetails},
+    traffic::Traffic,
+};
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use sysinfo::Pid;
+
+#[cfg(all(target_os = "linux", feature = "gpu"))]
+use crate::gpu::Gpu;
+
+/// Optional per-process columns, written after the default ones in the
+/// order they were asked for. A value that cannot be read for a process,
+/// e.g. because it belongs to another user, is left empty, and the reads
+/// the OS denied are counted for the run summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum Extra {
+    /// Energy used by the process since it started, in nanojoules (macOS).
+    Energy,
+    /// Proportional set size in bytes: shared pages divided among the
+    /// processes sharing them (Linux).
+    Pss,
+    /// Unique set size in bytes: pages no other process maps (Linux).
+    Uss,
+    /// CFS enforcement periods of the process's cgroup during the tick
+    /// (Linux).
+    NrPeriods,
+    /// Periods of the tick in which the cgroup was throttled (Linux).
+    NrThrottled,
+    /// Time the cgroup was throttled during the tick, in microseconds
+    /// (Linux).
+    ThrottledUsec,
+    /// The container the process runs in, from its cgroup: the short
+    /// container ID, after the pod UID on Kubernetes (Linux).
+    Container,
+    /// Resident set size in bytes.
+    Rss,
+    /// Virtual memory size in bytes.
+    Vsz,
+    /// Bytes read from disk since the process started.
+    DiskRead,
+    /// Bytes written to disk since the process started.
+    DiskWrite,
+    /// Run state, e.g. Runnable, Sleeping or Zombie.
+    Status,
+    /// PID of the parent process.
+    Ppid,
+    /// When the process started, in seconds since the Unix epoch.
+    StartTime,
+    /// The full command line, with secrets masked unless
+    /// --no-redact-cmdline is given.
+    Cmdline,
+    /// Number of threads (Linux).
+    Threads,
+    /// Number of open file descriptors (Linux).
+    Fds,
+    /// Bytes sent over the process's TCP sockets since it was first
+    /// sampled (Linux).
+    NetSent,
+    /// Bytes received over the process's TCP sockets since it was first
+    /// sampled (Linux).
+    NetReceived,
+    /// CPU time in milliseconds since the process was first sampled.
+    CpuTime,
+    /// CPU time in milliseconds during the tick.
+    CpuDelta,
+    /// How fast resident memory grew since the previous tick, in bytes per
+    /// second; negative when it shrank.
+    MemoryGrowth,
+    /// Milliseconds since logging started, from a monotonic clock that
+    /// clock changes do not move.
+    Elapsed,
+    /// Percent of one GPU's time the process kept busy, summed over the
+    /// GPUs (Linux with NVIDIA GPUs, needs the gpu feature).
+    GpuUtil,
+    /// GPU memory the process holds in bytes (Linux with NVIDIA GPUs, needs
+    /// the gpu feature).
+    GpuMemory,
+    /// Number of processes in the row: 1, or the size of the group with
+    /// --group-by.
+    Instances,
+    /// CPU time in milliseconds chronologger itself used since the previous
+    /// full-table tick started: what sampling and writing it cost.
+    SamplerOverhead,
+}
+
+/// Names of the default columns for `--columns`, in the order they are
+/// written.
+pub const DEFAULT_NAMES: [&str; 5] = ["timestamp", "pid", "name", "cpu", "memory"];
+
+/// The value of an optional column for one process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtraValue {
+    Count(u64),
+    /// A value that may be negative.
+    Signed(i64),
+    /// Shared by every row of the same process or container.
+    Text(Arc<str>),
+}
+
+impl ExtraValue {
+    /// Appends the value as it is written to CSV.
+    pub fn write(&self, text: &mut String) {
+        match self {
+            ExtraValue::Count(count) => numfmt::write_int(text, *count),
+            ExtraValue::Signed(value) => numfmt::write_int(text, *value),
+            ExtraValue::Text(value) => text.push_str(value),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            ExtraValue::Count(count) => (*count).into(),
+            ExtraValue::Signed(value) => (*value).into(),
+            ExtraValue::Text(value) => value.as_ref().into(),
+        }
+    }
+
+    /// Reads a value written by [`to_json`](Self::to_json).
+    pub fn from_json(value: &serde_json::Value) -> Option<Self> {
+        match value {
+            serde_json::Value::String(text) => Some(ExtraValue::Text(text.as_str().into())),
+            value => value
+                .as_u64()
+                .map(ExtraValue::Count)
+                .or_else(|| value.as_i64().map(ExtraValue::Signed)),
+        }
+    }
+}
+
+impl From<u64> for ExtraValue {
+    fn from(count: u64) -> Self {
+        ExtraValue::Count(count)
+    }
+}
+
+impl Extra {
+    /// The column's CSV header.
+    pub fn header(self) -> &'static str {
+        match self {
+            Extra::Energy => "Energy (nJ)",
+            Extra::Pss => "PSS (bytes)",
+            Extra::Uss => "USS (bytes)",
+            Extra::NrPeriods => "CPU Periods",
+            Extra::NrThrottled => "Throttled Periods",
+            Extra::ThrottledUsec => "Throttled (us)",
+            Extra::Container => "Container",
+            Extra::Rss => "RSS (bytes)",
+            Extra::Vsz => "Virtual Memory (bytes)",
+            Extra::DiskRead => "Disk Read (bytes)",
+            Extra::DiskWrite => "Disk Written (bytes)",
+            Extra::Status => "Status",
+            Extra::Ppid => "Parent PID",
+            Extra::StartTime => "Start Time (Unix s)",
+            Extra::Cmdline => "Command Line",
+            Extra::Threads => "Threads",
+            Extra::Fds => "Open FDs",
+            Extra::NetSent => "Net Sent (bytes)",
+            Extra::NetReceived => "Net Received (bytes)",
+            Extra::CpuTime => "CPU Time (ms)",
+            Extra::CpuDelta => "CPU Delta (ms)",
+            Extra::MemoryGrowth => "Memory Growth (bytes/s)",
+            Extra::Elapsed => "Elapsed (ms)",
+            Extra::GpuUtil => "GPU Utilization (%)",
+            Extra::GpuMemory => "GPU Memory (bytes)",
+            Extra::Instances => "Instances",
+            Extra::SamplerOverhead => "Sampler Overhead (ms)",
+        }
+    }
+
+    /// The column's identifier in `--print-schema`.
+    pub fn id(self) -> &'static str {
+        match self {
+            Extra::Energy => "energy_nj",
+            Extra::Pss => "pss_bytes",
+            Extra::Uss => "uss_bytes",
+            Extra::NrPeriods => "cpu_periods",
+            Extra::NrThrottled => "throttled_periods",
+            Extra::ThrottledUsec => "throttled_us",
+            Extra::Container => "container",
+            Extra::Rss => "rss_bytes",
+            Extra::Vsz => "vsz_bytes",
+            Extra::DiskRead => "disk_read_bytes",
+            Extra::DiskWrite => "disk_write_bytes",
+            Extra::Status => "status",
+            Extra::Ppid => "parent_pid",
+            Extra::StartTime => "start_time",
+            Extra::Cmdline => "command_line",
+            Extra::Threads => "threads",
+            Extra::Fds => "open_fds",
+            Extra::NetSent => "net_sent_bytes",
+            Extra::NetReceived => "net_received_bytes",
+            Extra::CpuTime => "cpu_time_ms",
+            Extra::CpuDelta => "cpu_delta_ms",
+            Extra::MemoryGrowth => "memory_growth_bytes_per_second",
+            Extra::Elapsed => "elapsed_ms",
+            Extra::GpuUtil => "gpu_utilization_percent",
+            Extra::GpuMemory => "gpu_memory_bytes",
+            Extra::Instances => "instances",
+            Extra::SamplerOverhead => "sampler_overhead_ms",
+        }
+    }
+
+    /// What the column's values are.
+    pub fn kind(self) -> Kind {
+        match self {
+            Extra::Container | Extra::Status | Extra::Cmdline => Kind::Text,
+            Extra::Ppid => Kind::Pid,
+            Extra::MemoryGrowth => Kind::Signed,
+            _ => Kind::Count,
+        }
+    }
+
+    /// The unit of the column's values, if they are counts.
+    pub fn unit(self) -> Option<&'static str> {
+        match self {
+            Extra::Energy => Some("nanojoules"),
+            Extra::Pss
+            | Extra::Uss
+            | Extra::Rss
+            | Extra::Vsz
+            | Extra::DiskRead
+            | Extra::DiskWrite
+            | Extra::NetSent
+            | Extra::NetReceived
+            | Extra::GpuMemory => Some("bytes"),
+            Extra::NrPeriods | Extra::NrThrottled => Some("periods"),
+            Extra::ThrottledUsec => Some("microseconds"),
+            Extra::StartTime => Some("seconds"),
+            Extra::Threads => Some("threads"),
+            Extra::Fds => Some("descriptors"),
+            Extra::CpuTime | Extra::CpuDelta | Extra::Elapsed | Extra::SamplerOverhead => {
+                Some("milliseconds")
+            }
+            Extra::MemoryGrowth => Some("bytes per second"),
+            Extra::GpuUtil => Some("percent"),
+            Extra::Instances => Some("processes"),
+            Extra::Container | Extra::Status | Extra::Ppid | Extra::Cmdline => None,
+        }
+    }
+
+    /// The property the column is written as in EMF documents.
+    pub fn emf_name(self) -> &'static str {
+        match self {
+            Extra::Energy => "EnergyNanojoules",
+            Extra::Pss => "PssBytes",
+            Extra::Uss => "UssBytes",
+            Extra::NrPeriods => "CpuPeriods",
+            Extra::NrThrottled => "ThrottledPeriods",
+            Extra::ThrottledUsec => "ThrottledMicroseconds",
+            Extra::Container => "Container",
+            Extra::Rss => "RssBytes",
+            Extra::Vsz => "VirtualMemoryBytes",
+            Extra::DiskRead => "DiskReadBytes",
+            Extra::DiskWrite => "DiskWrittenBytes",
+            Extra::Status => "Status",
+            Extra::Ppid => "ParentPID",
+            Extra::StartTime => "StartTime",
+            Extra::Cmdline => "CommandLine",
+            Extra::Threads => "Threads",
+            Extra::Fds => "OpenFileDescriptors",
+            Extra::NetSent => "NetSentBytes",
+            Extra::NetReceived => "NetReceivedBytes",
+            Extra::CpuTime => "CpuTimeMilliseconds",
+            Extra::CpuDelta => "CpuDeltaMilliseconds",
+            Extra::MemoryGrowth => "MemoryGrowthBytesPerSecond",
+            Extra::Elapsed => "ElapsedMilliseconds",
+            Extra::GpuUtil => "GpuUtilizationPercent",
+            Extra::GpuMemory => "GpuMemoryBytes",
+            Extra::Instances => "Instances",
+            Extra::SamplerOverhead => "SamplerOverheadMs",
+        }
+    }
+
+    /// Whether this build can read the column on this platform.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Extra::Energy => cfg!(target_os = "macos"),
+            Extra::Pss
+            | Extra::Uss
+            | Extra::NrPeriods
+            | Extra::NrThrottled
+            | Extra::ThrottledUsec
+            | Extra::Container
+            | Extra::Threads
+            | Extra::Fds
+            | Extra::NetSent
+            | Extra::NetReceived => cfg!(target_os = "linux"),
+            Extra::Rss
+            | Extra::Vsz
+            | Extra::DiskRead
+            | Extra::DiskWrite
+            | Extra::Status
+            | Extra::Ppid
+            | Extra::StartTime
+            | Extra::Cmdline
+            | Extra::CpuTime
+            | Extra::CpuDelta
+            | Extra::MemoryGrowth
+            | Extra::Elapsed
+            | Extra::Instances
+            | Extra::SamplerOverhead => true,
+            Extra::GpuUtil | Extra::GpuMemory => cfg!(all(target_os = "linux", feature = "gpu")),
+        }
+    }
+
+    /// Whether the column describes the cgroup rather than the process.
+    pub fn is_throttling(self) -> bool {
+        matches!(
+            self,
+            Extra::NrPeriods | Extra::NrThrottled | Extra::ThrottledUsec
+        )
+    }
+
+    /// Whether reading the column costs noticeably more than sampling the
+    /// process itself.
+    pub fn is_expensive(self) -> bool {
+        matches!(
+            self,
+            Extra::Pss | Extra::Uss | Extra::NetSent | Extra::NetReceived
+        )
+    }
+
+    /// Whether the column comes from NVML.
+    pub fn is_gpu(self) -> bool {
+        matches!(self, Extra::GpuUtil | Extra::GpuMemory)
+    }
+
+    fn is_traffic(self) -> bool {
+        matches!(self, Extra::NetSent | Extra::NetReceived)
+    }
+
+    /// Whether the column is computed from the process's previous sample,
+    /// as `--derived` adds.
+    pub fn is_derived(self) -> bool {
+        matches!(self, Extra::CpuTime | Extra::CpuDelta | Extra::MemoryGrowth)
+    }
+
+    /// Reads the column's value for `pid`.
+    #[cfg(test)]
+    pub fn read(self, pid: Pid) -> Option<ExtraValue> {
+        let mut reader = Reader::new(vec![self], cgroup::Selection::default());
+        let mut values = Vec::new();
+        reader.begin_tick();
+        reader.read(pid, None, None, &mut values);
+        values.remove(0)
+    }
+}
+
+/// A column `--columns` lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Listed {
+    /// A default column, by its place in [`DEFAULT_NAMES`].
+    Default(usize),
+    Optional(Extra),
+}
+
+/// Parses one name of `--columns`. Underscores may stand in for dashes.
+pub fn parse_column(name: &str) -> Result<Listed, String> {
+    use clap::ValueEnum;
+
+    let name = name.trim().replace('_', "-");
+    if let Some(index) = DEFAULT_NAMES.iter().position(|default| *default == name) {
+        return Ok(Listed::Default(index));
+    }
+    Extra::from_str(&name, true)
+        .map(Listed::Optional)
+        .map_err(|_| {
+            let optional = Extra::value_variants()
+                .iter()
+                .filter_map(|extra| extra.to_possible_value())
+                .map(|value| value.get_name().to_string());
+            let valid: Vec<String> = DEFAULT_NAMES
+                .iter()
+                .map(ToString::to_string)
+                .chain(optional)
+                .collect();
+            format!(
+                "unknown column '{}', valid columns are: {}",
+                name,
+                valid.join(", ")
+            )
+        })
+}
+
+/// Where each column `listed` goes among the default columns followed by
+/// `extras`, which holds every optional column listed and may add more, as
+/// `--derived` does, which go last. `None` when that is the usual order,
+/// which includes a list of optional columns alone, written after every
+/// default one.
+pub fn order(listed: &[Listed], extras: &[Extra]) -> Result<Option<Vec<usize>>, String> {
+    use clap::ValueEnum;
+
+    for (i, column) in listed.iter().enumerate() {
+        if listed[..i].contains(column) {
+            let name = match column {
+                Listed::Default(index) => DEFAULT_NAMES[*index].to_string(),
+                Listed::Optional(extra) => extra
+                    .to_possible_value()
+                    .map(|value| value.get_name().to_string())
+                    .unwrap_or_default(),
+            };
+            return Err(format!("column '{}' is listed twice", name));
+        }
+    }
+    if !listed
+        .iter()
+        .any(|column| matches!(column, Listed::Default(_)))
+    {
+        return Ok(None);
+    }
+    let position = |extra: &Extra| {
+        DEFAULT_NAMES.len() + extras.iter().position(|other| other == extra).unwrap()
+    };
+    let mut order: Vec<usize> = listed
+        .iter()
+        .map(|column| match column {
+            Listed::Default(index) => *index,
+            Listed::Optional(extra) => position(extra),
+        })
+        .collect();
+    for extra in extras {
+        if !listed.contains(&Listed::Optional(*extra)) {
+            order.push(position(extra));
+        }
+    }
+    let usual = order
+        .iter()
+        .copied()
+        .eq(0..DEFAULT_NAMES.len() + extras.len());
+    Ok((!usual).then_some(order))
+}
+
+/// Reads the optional columns of every process in a tick.
+#[derive(Default)]
+pub struct Reader {
+    extras: Vec<Extra>,
+    /// Set when a throttling column was asked for.
+    throttling: Option<Throttling>,
+    /// Set when the container column was asked for.
+    containers: Option<Containers>,
+    /// Set when a network column was asked for.
+    traffic: Option<Traffic>,
+    /// Set when a derived column was asked for.
+    derived: Option<Derived>,
+    /// Set when a GPU column was asked for.
+    #[cfg(all(target_os = "linux", feature = "gpu"))]
+    gpu: Option<Gpu>,
+    /// Masks secrets in the command line column.
+    redactor: Option<Redactor>,
+    /// When the first and the current tick started.
+    started: Option<Instant>,
+    now: Option<Instant>,
+    /// Chronologger's own CPU time when the current tick started, and what
+    /// it used since the previous one in milliseconds, if the sampler
+    /// overhead column was asked for.
+    self_cpu_time: Option<Duration>,
+    overhead: Option<u64>,
+    /// How many reads of each column the OS denied.
+    denied: Vec<u64>,
+}
+
+impl Reader {
+    /// Reads `extras`, taking the throttling columns from the cgroups that
+    /// `cgroup` selects.
+    pub fn new(extras: Vec<Extra>, cgroup: cgroup::Selection) -> Self {
+        let throttling = extras
+            .iter()
+            .any(|extra| extra.is_throttling())
+            .then(|| Throttling::new(cgroup));
+        let containers = extras.contains(&Extra::Container).then(Containers::new);
+        let traffic = extras
+            .iter()
+            .any(|extra| extra.is_traffic())
+            .then(Traffic::new);
+        let derived = extras
+            .iter()
+            .any(|extra| extra.is_derived())
+            .then(Derived::new);
+        #[cfg(all(target_os = "linux", feature = "gpu"))]
+        let gpu = extras.iter().any(|extra| extra.is_gpu()).then(Gpu::new);
+        let extras_len = extras.len();
+        Self {
+            extras,
+            throttling,
+            containers,
+            traffic,
+            derived,
+            #[cfg(all(target_os = "linux", feature = "gpu"))]
+            gpu,
+            redactor: None,
+            started: None,
+            now: None,
+            self_cpu_time: None,
+            overhead: None,
+            denied: vec![0; extras_len],
+        }
+    }
+
+    /// Masks what `redactor` finds in the command line column.
+    pub fn redact_with(&mut self, redactor: Redactor) {
+        self.redactor = Some(redactor);
+    }
+
+    /// Names containers through the Docker Engine API on `socket` instead of
+    /// writing their IDs.
+    #[cfg(all(unix, feature = "containers"))]
+    pub fn resolve_containers(&mut self, socket: std::path::PathBuf) {
+        if let Some(containers) = &mut self.containers {
+            containers.resolve_with(socket);
+        }
+    }
+
+    pub fn extras(&self) -> &[Extra] {
+        &self.extras
+    }
+
+    /// The columns with reads the OS denied so far, and how many.
+    pub fn denied(&self) -> Vec<(Extra, u64)> {
+        self.extras
+            .iter()
+            .zip(&self.denied)
+            .filter(|(_, count)| **count > 0)
+            .map(|(extra, count)| (*extra, *count))
+            .collect()
+    }
+
+    /// The columns the OS does not let this user read for PID 1, which
+    /// root owns, and so will be empty for other users' processes.
+    pub fn probe_denied(&self) -> Vec<Extra> {
+        use sysinfo::PidExt;
+
+        let pid = Pid::from_u32(1);
+        self.extras
+            .iter()
+            .copied()
+            .filter(|extra| match extra {
+                Extra::Energy => energy(pid).is_err(),
+                Extra::Pss | Extra::Uss => proportional_memory(pid).is_err(),
+                Extra::Fds => open_fds(pid).is_err(),
+                Extra::DiskRead | Extra::DiskWrite => !source::may_read_private(0),
+                Extra::Cmdline => !cfg!(target_os = "linux") && !source::may_read_private(0),
+                _ => false,
+            })
+            .collect()
+    }
+
+    /// Starts a tick. Throttling, network, derived, GPU and sampler overhead
+    /// columns report what happened since the previous one, and the elapsed
+    /// column the time since the first.
+    pub fn begin_tick(&mut self) {
+        let now = Instant::now();
+        self.started.get_or_insert(now);
+        self.now = Some(now);
+        if self.extras.contains(&Extra::SamplerOverhead) {
+            let cpu_time = overhead::cpu_time();
+            self.overhead = self
+                .self_cpu_time
+                .zip(cpu_time)
+                .map(|(before, now)| now.saturating_sub(before).as_millis() as u64);
+            self.self_cpu_time = cpu_time;
+        }
+        if let Some(derived) = &mut self.derived {
+            derived.begin_tick(now);
+        }
+        if let Some(throttling) = &mut self.throttling {
+            throttling.begin_tick();
+        }
+        if let Some(traffic) = &mut self.traffic {
+            traffic.begin_tick();
+        }
+        if let Some(containers) = &mut self.containers {
+            containers.begin_tick();
+        }
+        #[cfg(all(target_os = "linux", feature = "gpu"))]
+        if let Some(gpu) = &mut self.gpu {
+            gpu.begin_tick();
+        }
+    }
+
+    /// Forgets what the throttling, network, derived and sampler overhead
+    /// columns compare the next tick with, after a suspend that the
+    /// monotonic clock did not see. Each of them starts over as on the
+    /// first tick.
+    pub fn reset(&mut self) {
+        self.self_cpu_time = None;
+        self.overhead = None;
+        if let Some(derived) = &mut self.derived {
+            derived.reset();
+        }
+        if let Some(throttling) = &mut self.throttling {
+            throttling.reset();
+        }
+        if let Some(traffic) = &mut self.traffic {
+            traffic.reset();
+        }
+    }
+
+    /// Reads every column for `pid` into `values`, reusing its capacity.
+    /// Columns that come from the same source read it only once, those
+    /// from the process table are taken from `details`, if the source has
+    /// them, and the derived ones from `usage`.
+    pub fn read(
+        &mut self,
+        pid: Pid,
+        details: Option<&ProcessDetails>,
+        usage: Option<Usage>,
+        values: &mut Vec<Option<ExtraValue>>,
+    ) {
+        use sysinfo::PidExt;
+
+        values.clear();
+        let mut footprint = None;
+        let mut throttled = None;
+        let mut traffic = None;
+        let mut derived = None;
+        let mut gpu = None;
+        for (i, extra) in self.extras.iter().enumerate() {
+            let mut denied = false;
+            let count = match extra {
+                Extra::Energy => permitted(energy(pid), &mut denied).flatten(),
+                Extra::Pss | Extra::Uss => {
+                    let footprint = *footprint.get_or_insert_with(|| proportional_memory(pid));
+                    permitted(footprint, &mut denied)
+                        .flatten()
+                        .map(|(pss, uss)| if *extra == Extra::Pss { pss } else { uss })
+                }
+                Extra::NrPeriods | Extra::NrThrottled | Extra::ThrottledUsec => {
+                    let stat = *throttled.get_or_insert_with(|| {
+                        self.throttling
+                            .as_mut()
+                            .and_then(|throttling| throttling.delta(pid.as_u32()))
+                    });
+                    stat.map(|stat| match extra {
+                        Extra::NrPeriods => stat.nr_periods,
+                        Extra::NrThrottled => stat.nr_throttled,
+                        _ => stat.throttled_usec,
+                    })
+                }
+                Extra::Container => {
+                    let label = self
+                        .containers
+                        .as_mut()
+                        .and_then(|containers| containers.label(pid.as_u32()));
+                    values.push(label.map(ExtraValue::Text));
+                    continue;
+                }
+                Extra::Rss => details.map(|details| details.resident_bytes),
+                Extra::Vsz => details.map(|details| details.virtual_bytes),
+                Extra::DiskRead => {
+                    details.and_then(|details| permitted(details.disk_read, &mut denied))
+                }
+                Extra::DiskWrite => {
+                    details.and_then(|details| permitted(details.disk_written, &mut denied))
+                }
+                Extra::Ppid => details
+                    .and_then(|details| details.parent)
+                    .map(|parent| parent.as_u32() as u64),
+                Extra::StartTime => details.map(|details| details.started),
+                Extra::Threads => thread_count(pid),
+                Extra::Elapsed => self
+                    .started
+                    .zip(self.now)
+                    .map(|(started, now)| now.duration_since(started).as_millis() as u64),
+                Extra::Fds => permitted(open_fds(pid), &mut denied).flatten(),
+                Extra::Instances => Some(1),
+                Extra::SamplerOverhead => self.overhead,
+                Extra::GpuUtil => gpu.get_or_insert_with(|| self.gpu_usage(pid)).0,
+                Extra::GpuMemory => gpu.get_or_insert_with(|| self.gpu_usage(pid)).1,
+                Extra::NetSent | Extra::NetReceived => {
+                    let bytes = *traffic.get_or_insert_with(|| {
+                        self.traffic
+                            .as_mut()
+                            .and_then(|traffic| traffic.read(pid.as_u32()))
+                    });
+                    bytes.map(|bytes| match extra {
+                        Extra::NetSent => bytes.sent,
+                        _ => bytes.received,
+                    })
+                }
+                Extra::CpuTime | Extra::CpuDelta | Extra::MemoryGrowth => {
+                    let computed = *derived.get_or_insert_with(|| {
+                        let state = self.derived.as_mut();
+                        state
+                            .zip(usage)
+                            .map(|(state, usage)| state.read(pid.as_u32(), usage))
+                    });
+                    let value = computed.and_then(|derived| match extra {
+                        Extra::CpuTime => derived.cpu_time.map(ExtraValue::Count),
+                        Extra::CpuDelta => derived.cpu_delta.map(ExtraValue::Count),
+                        _ => derived.memory_growth.map(ExtraValue::Signed),
+                    });
+                    values.push(value);
+                    continue;
+                }
+                Extra::Status => {
+                    let status = details.map(|details| details.status.to_string());
+                    values.push(status.map(|status| ExtraValue::Text(status.into())));
+                    continue;
+                }
+                Extra::Cmdline => {
+                    // Kernel threads have no command line.
+                    let line = details
+                        .and_then(|details| permitted(details.cmd, &mut denied))
+                        .filter(|cmd| !cmd.is_empty())
+                        .map(|cmd| self.command_line(cmd));
+                    values.push(line.map(ExtraValue::Text));
+                    self.denied[i] += u64::from(denied);
+                    continue;
+                }
+            };
+            values.push(count.map(ExtraValue::Count));
+            self.denied[i] += u64::from(denied);
+        }
+    }
+
+    /// The GPU utilization and memory of `pid` in this tick.
+    #[cfg(all(target_os = "linux", feature = "gpu"))]
+    fn gpu_usage(&self, pid: Pid) -> (Option<u64>, Option<u64>) {
+        use sysinfo::PidExt;
+
+        let usage = self
+            .gpu
+            .as_ref()
+            .map(|gpu| gpu.usage(pid.as_u32()))
+            .unwrap_or_default();
+        (usage.utilization, usage.memory)
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "gpu")))]
+    fn gpu_usage(&self, _pid: Pid) -> (Option<u64>, Option<u64>) {
+        (None, None)
+    }
+
+    /// `cmd` joined with spaces, as `ps` shows it, with secrets masked.
+    fn command_line(&self, cmd: &[String]) -> Arc<str> {
+        let line = cmd.join(" ");
+        match &self.redactor {
+            Some(redactor) => redactor.redact(&line).into(),
+            None => line.into(),
+        }
+    }
+}
+
+/// Prints the optional columns for `--list-columns`.
+pub fn print_list() {
+    use clap::ValueEnum;
+
+    println!(
+        "Default columns, written unless --columns leaves them out: {}",
+        DEFAULT_NAMES.join(", ")
+    );
+    println!("Optional columns for --extra-columns and --columns:");
+    for extra in Extra::value_variants() {
+        let value = extra.to_possible_value().expect("No column is hidden");
+        let mut notes = Vec::new();
+        if extra.is_expensive() {
+            notes.push("expensive");
+        }
+        if !extra.is_supported() {
+            notes.push("not available on this platform");
+        }
+        let notes = if notes.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", notes.join(", "))
+        };
+        println!(
+            "  {:<16} {:<22} {}{}",
+            value.get_name(),
+            extra.header(),
+            value
+                .get_help()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+            notes
+        );
+    }
+}
+
+/// The value of a read, if the OS allowed it, noting in `denied` if not.
+fn permitted<T>(result: Result<T, Denied>, denied: &mut bool) -> Option<T> {
+    *denied = result.is_err();
+    result.ok()
+}
+
+/// The value of a read that failed with `EACCES` or `EPERM` if the OS
+/// denied it, and nothing if it failed otherwise.
+fn unless_denied<T>(result: io::Result<T>) -> Result<Option<T>, Denied> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Err(Denied),
+        Err(_) => Ok(None),
+    }
+}
+
+/// PSS and USS of the process in bytes. Reading another user's process
+/// needs root, and is denied otherwise.
+#[cfg(target_os = "linux")]
+fn proportional_memory(pid: Pid) -> Result<Option<(u64, u64)>, Denied> {
+    use sysinfo::PidExt;
+
+    let footprint = unless_denied(crate::smaps::read(pid.as_u32()))?;
+    Ok(footprint.map(|footprint| (footprint.pss, footprint.uss)))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn proportional_memory(_pid: Pid) -> Result<Option<(u64, u64)>, Denied> {
+    Ok(None)
+}
+
+/// The number of threads of the process.
+#[cfg(target_os = "linux")]
+fn thread_count(pid: Pid) -> Option<u64> {
+    use sysinfo::PidExt;
+
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid.as_u32())).ok()?;
+    let threads = status
+        .lines()
+        .find_map(|line| line.strip_prefix("Threads:"))?;
+    threads.trim().parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn thread_count(_pid: Pid) -> Option<u64> {
+    None
+}
+
+/// The number of file descriptors the process has open. Reading another
+/// user's process needs root, and is denied otherwise.
+#[cfg(target_os = "linux")]
+fn open_fds(pid: Pid) -> Result<Option<u64>, Denied> {
+    use sysinfo::PidExt;
+
+    let entries = std::fs::read_dir(format!("/proc/{}/fd", pid.as_u32()));
+    unless_denied(entries.map(|entries| entries.count() as u64))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_fds(_pid: Pid) -> Result<Option<u64>, Denied> {
+    Ok(None)
+}
+
+/// The energy the kernel has billed to the process, as `top` and Activity
+/// Monitor use for their energy figures. Reading another user's process
+/// needs root, and is denied otherwise.
+#[cfg(target_os = "macos")]
+fn energy(pid: Pid) -> Result<Option<u64>, Denied> {
+    use sysinfo::PidExt;
+
+    // SAFETY: rusage_info_v4 is plain data, and proc_pid_rusage writes at
+    // most one of it for the RUSAGE_INFO_V4 flavor.
+    let mut info: libc::rusage_info_v4 = unsafe { std::mem::zeroed() };
+    let result = unsafe {
+        libc::proc_pid_rusage(
+            pid.as_u32() as libc::c_int,
+            libc::RUSAGE_INFO_V4,
+            &mut info as *mut libc::rusage_info_v4 as *mut libc::rusage_info_t,
+        )
+    };
+    if result == 0 {
+        return Ok(Some(info.ri_billed_energy));
+    }
+    unless_denied(Err(io::Error::last_os_error()))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn energy(_pid: Pid) -> Result<Option<u64>, Denied> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sysinfo::PidExt;
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn test_own_energy_after_busy_loop() {
+        let pid = Pid::from_u32(std::process::id());
+        let started = std::time::Instant::now();
+        let mut x = 0u64;
+        while started.elapsed() < std::time::Duration::from_millis(500) {
+            x = std::hint::black_box(x.wrapping_mul(31).wrapping_add(7));
+        }
+        let energy = Extra::Energy
+            .read(pid)
+            .expect("Own energy must be readable");
+        assert!(
+            energy != ExtraValue::Count(0),
+            "No energy billed after a busy loop"
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_reads_pss_and_uss_together() {
+        let pid = Pid::from_u32(std::process::id());
+        let mut values = Vec::new();
+        let mut reader = Reader::new(
+            vec![Extra::Uss, Extra::Energy, Extra::Pss],
+            cgroup::Selection::default(),
+        );
+        reader.read(pid, None, None, &mut values);
+        let (Some(ExtraValue::Count(uss)), Some(ExtraValue::Count(pss))) = (&values[0], &values[2])
+        else {
+            panic!("PSS and USS must be readable: {:?}", values);
+        };
+        assert!(uss <= pss);
+        assert_eq!(values[1], None);
+        // A process that does not exist leaves the fields empty.
+        let mut reader = Reader::new(vec![Extra::Pss], cgroup::Selection::default());
+        reader.read(Pid::from_u32(99_999_999), None, None, &mut values);
+        assert_eq!(values, [None]);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_reads_threads_and_fds() {
+        let pid = Pid::from_u32(std::process::id());
+        let file = tempfile::tempfile().unwrap();
+        let mut values = Vec::new();
+        let mut reader = Reader::new(
+            vec![Extra::Threads, Extra::Fds],
+            cgroup::Selection::default(),
+        );
+        reader.read(pid, None, None, &mut values);
+        let (Some(ExtraValue::Count(threads)), Some(ExtraValue::Count(fds))) =
+            (&values[0], &values[1])
+        else {
+            panic!("Threads and FDs must be readable: {:?}", values);
+        };
+        assert!(*threads >= 1);
+        // Standard streams and the temporary file at least.
+        assert!(*fds >= 4, "{} FDs", fds);
+        drop(file);
+        reader.read(Pid::from_u32(99_999_999), None, None, &mut values);
+        assert_eq!(values, [None, None]);
+    }
+
+    #[test]
+    fn test_process_table_columns() {
+        let cmd = [
+            "app".to_string(),
+            "--password=hunter2".to_string(),
+            "a, \"b\"".to_string(),
+        ];
+        let details = ProcessDetails {
+            resident_bytes: 4096,
+            virtual_bytes: 8192,
+            disk_read: Ok(10),
+            disk_written: Ok(20),
+            status: sysinfo::ProcessStatus::Sleep,
+            parent: Some(Pid::from_u32(1)),
+            started: 1_767_225_600,
+            cmd: Ok(&cmd),
+            uid: Some(0),
+        };
+        let mut reader = Reader::new(
+            vec![
+                Extra::Cmdline,
+                Extra::Rss,
+                Extra::Status,
+                Extra::Ppid,
+                Extra::DiskWrite,
+            ],
+            cgroup::Selection::default(),
+        );
+        reader.redact_with(Redactor::new(&[]).unwrap());
+        let mut values = Vec::new();
+        reader.read(Pid::from_u32(2), Some(&details), None, &mut values);
+        assert_eq!(
+            values,
+            [
+                Some(ExtraValue::Text("app --password=*** a, \"b\"".into())),
+                Some(4096.into()),
+                Some(ExtraValue::Text("Sleeping".into())),
+                Some(1.into()),
+                Some(20.into()),
+            ]
+        );
+        // A source without the details leaves them empty.
+        reader.read(Pid::from_u32(2), None, None, &mut values);
+        assert!(values.iter().all(Option::is_none), "{:?}", values);
+        assert_eq!(reader.denied(), []);
+    }
+
+    #[test]
+    fn test_denied_fields_empty_and_counted() {
+        let details = |cmd: &'static [String]| ProcessDetails {
+            resident_bytes: 4096,
+            virtual_bytes: 8192,
+            disk_read: Err(Denied),
+            disk_written: Err(Denied),
+            status: sysinfo::ProcessStatus::Sleep,
+            parent: Some(Pid::from_u32(1)),
+            started: 1_767_225_600,
+            cmd: Ok(cmd),
+            uid: Some(0),
+        };
+        let mut reader = Reader::new(
+            vec![Extra::Rss, Extra::DiskRead, Extra::Cmdline],
+            cgroup::Selection::default(),
+        );
+        let mut values = Vec::new();
+        reader.read(Pid::from_u32(2), Some(&details(&[])), None, &mut values);
+        // Empty rather than zero, and an empty command line is no denial.
+        assert_eq!(values, [Some(4096.into()), None, None]);
+        let denied = ProcessDetails {
+            cmd: Err(Denied),
+            ..details(&[])
+        };
+        reader.read(Pid::from_u32(3), Some(&denied), None, &mut values);
+        assert_eq!(values, [Some(4096.into()), None, None]);
+        assert_eq!(reader.denied(), [(Extra::DiskRead, 2), (Extra::Cmdline, 1)]);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_root_process_denied_or_read() {
+        use crate::source::{SysinfoSource, SystemSource};
+
+        let mut source = SysinfoSource::new().with_disk_usage();
+        source.refresh();
+        let init = source
+            .processes()
+            .find(|process| process.pid == Pid::from_u32(1))
+            .expect("PID 1 must exist");
+        let extras = vec![Extra::DiskRead, Extra::Cmdline, Extra::Fds];
+        let mut reader = Reader::new(extras.clone(), cgroup::Selection::default());
+        let mut values = Vec::new();
+        reader.read(init.pid, init.details.as_ref(), None, &mut values);
+        let denied = reader.probe_denied();
+        for (extra, value) in extras.iter().zip(&values) {
+            if denied.contains(extra) {
+                assert_eq!(*value, None, "{:?} of PID 1 is denied", extra);
+            }
+        }
+        // A command line is read or left empty, never written empty.
+        assert_ne!(values[1], Some(ExtraValue::Text("".into())));
+        let counted: Vec<Extra> = reader.denied().iter().map(|(extra, _)| *extra).collect();
+        assert_eq!(counted, denied);
+    }
+
+    #[test]
+    fn test_elapsed() {
+        let mut reader = Reader::new(vec![Extra::Elapsed], cgroup::Selection::default());
+        let mut values = Vec::new();
+        reader.read(Pid::from_u32(1), None, None, &mut values);
+        assert_eq!(values, [None], "No tick has started");
+
+        reader.begin_tick();
+        reader.read(Pid::from_u32(1), None, None, &mut values);
+        assert_eq!(values, [Some(0.into())]);
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        reader.begin_tick();
+        reader.read(Pid::from_u32(2), None, None, &mut values);
+        let Some(ExtraValue::Count(elapsed)) = values[0] else {
+            panic!("Elapsed must be set: {:?}", values);
+        };
+        assert!(elapsed >= 20, "{} ms", elapsed);
+    }
+
+    #[test]
+    fn test_sampler_overhead() {
+        let mut reader = Reader::new(vec![Extra::SamplerOverhead], cgroup::Selection::default());
+        let mut values = Vec::new();
+        reader.begin_tick();
+        reader.read(Pid::from_u32(1), None, None, &mut values);
+        assert_eq!(values, [None], "The first tick has nothing to measure");
+
+        // Spends some CPU time, as sampling would.
+        let start = overhead::cpu_time().unwrap();
+        while overhead::cpu_time().unwrap() - start < Duration::from_millis(20) {
+            std::hint::black_box(0u64.wrapping_add(1));
+        }
+        reader.begin_tick();
+        reader.read(Pid::from_u32(1), None, None, &mut values);
+        let Some(ExtraValue::Count(used)) = values[0] else {
+            panic!("The overhead must be set: {:?}", values);
+        };
+        assert!(used >= 20, "{} ms", used);
+    }
+
+    #[test]
+    fn test_parse_column() {
+        assert_eq!(parse_column("cpu"), Ok(Listed::Default(3)));
+        assert_eq!(
+            parse_column("disk_read"),
+            Ok(Listed::Optional(Extra::DiskRead))
+        );
+        assert_eq!(
+            parse_column("start-time"),
+            Ok(Listed::Optional(Extra::StartTime))
+        );
+        let error = parse_column("rsz").unwrap_err();
+        assert!(
+            error.contains("timestamp, pid, name, cpu, memory"),
+            "{}",
+            error
+        );
+        assert!(error.contains("cmdline"), "{}", error);
+    }
+
+    #[test]
+    fn test_order() {
+        let list = |names: &str| -> Vec<Listed> {
+            names
+                .split(',')
+                .map(|name| parse_column(name).unwrap())
+                .collect()
+        };
+        let extras = [Extra::Rss, Extra::CpuDelta];
+        assert_eq!(order(&list("rss,cpu-delta"), &extras), Ok(None));
+        assert_eq!(
+            order(
+                &list("timestamp,pid,name,cpu,memory,rss,cpu-delta"),
+                &extras
+            ),
+            Ok(None)
+        );
+        // cpu-delta was added by --derived.
+        assert_eq!(
+            order(&list("pid,rss,cpu,timestamp"), &extras),
+            Ok(Some(vec![1, 5, 3, 0, 6]))
+        );
+        assert_eq!(
+            order(&list("pid,cpu,pid"), &[]),
+            Err("column 'pid' is listed twice".to_string())
+        );
+    }
+
+    #[test]
+    fn test_throttling_of_fixed_cgroup() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let stat = dir.path().join("cpu.stat");
+        std::fs::write(
+            &stat,
+            "nr_periods 40\nnr_throttled 4\nthrottled_usec 9000\n",
+        )
+        .unwrap();
+        let mut reader = Reader::new(
+            vec![Extra::Pss, Extra::ThrottledUsec, Extra::NrThrottled],
+            cgroup::Selection::Path(dir.path().to_path_buf()),
+        );
+        let mut values = Vec::new();
+        reader.begin_tick();
+        reader.read(Pid::from_u32(99_999_999), None, None, &mut values);
+        assert_eq!(values, [None, None, None], "No deltas before a second tick");
+
+        std::fs::write(
+            &stat,
+            "nr_periods 50\nnr_throttled 6\nthrottled_usec 12500\n",
+        )
+        .unwrap();
+        reader.begin_tick();
+        for pid in [1, 99_999_999] {
+            reader.read(Pid::from_u32(pid), None, None, &mut values);
+            assert_eq!(values, [None, Some(3500.into()), Some(2.into())]);
+        }
+    }
+
+    #[test]
+    #[cfg(not(target_os = "linux"))]
+    fn test_linux_columns_unsupported() {
+        for extra in [
+            Extra::Pss,
+            Extra::Uss,
+            Extra::NrPeriods,
+            Extra::NrThrottled,
+            Extra::ThrottledUsec,
+            Extra::Container,
+            Extra::NetSent,
+            Extra::NetReceived,
+        ] {
+            assert!(!extra.is_supported(), "{:?} claims support", extra);
+        }
+        assert_eq!(Extra::Pss.read(Pid::from_u32(std::process::id())), None);
+    }
+
+    #[test]
+    fn test_gpu_columns_empty_without_gpu() {
+        // Neither the test hosts nor builds without the gpu feature have
+        // NVML to read.
+        let supported = cfg!(all(target_os = "linux", feature = "gpu"));
+        assert_eq!(Extra::GpuUtil.is_supported(), supported);
+        let pid = Pid::from_u32(std::process::id());
+        assert_eq!(Extra::GpuUtil.read(pid), None);
+        assert_eq!(Extra::GpuMemory.read(pid), None);
+    }
+
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn test_energy_unsupported() {
+        assert!(!Extra::Energy.is_supported());
+        assert_eq!(Extra::Energy.read(Pid::from_u32(1)), None);
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::Path;
+
+/// How a file output is compressed, as `--compress` or the extension of
+/// its name asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Compression {
+    /// gzip.
+    Gzip,
+    /// Zstandard, which needs the `zstd` feature.
+    Zstd,
+}
+
+impl Compression {
+    /// The compression the extension of `path` asks for: `.gz` or `.zst`.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?;
+        if extension.eq_ignore_ascii_case("gz") {
+            Some(Compression::Gzip)
+        } else if extension.eq_ignore_ascii_case("zst") {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// The extension of a file compressed this way, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+/// The zstd level output is compressed at, zstd's default, which keeps up
+/// with sampling at a fraction of the cost of the higher levels.
+#[cfg(feature = "zstd")]
+const ZSTD_LEVEL: i32 = 3;
+
+#[cfg(feature = "zstd")]
+pub type ZstdEncoder<W> = zstd::stream::write::Encoder<'static, W>;
+
+/// Starts a zstd frame written to `inner`. Every flush ends a block, so a
+/// file cut short by a crash still decompresses up to its last flush.
+#[cfg(feature = "zstd")]
+pub fn zstd_encoder<W: std::io::Write>(inner: W) -> std::io::Result<ZstdEncoder<W>> {
+    let mut encoder = ZstdEncoder::new(inner, ZSTD_LEVEL)?;
+    // The frame's checksum lets `zstd -t` check the whole file.
+    encoder.include_checksum(true)?;
+    Ok(encoder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(
+            Compression::from_path("capture.csv.gz"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_path("/tmp/CAPTURE.GZ"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_path("capture.jsonl.zst"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_path("capture.csv"), None);
+        assert_eq!(Compression::from_path("-"), None);
+    }
+}
@@ -0,0 +1,412 @@
+use anyhow::{bail, Context, Result};
+use clap::{ArgAction, Command};
+use serde::Deserialize;
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+/// Keys of a table that name an option other than `<table>-<key>`.
+const ALIASES: [(&str, &str); 11] = [
+    ("filters.names", "name"),
+    ("filters.pids", "pid"),
+    ("filters.users", "user"),
+    ("filters.excludes", "exclude"),
+    ("filters.exact", "exact"),
+    ("alerts.cpu", "alert-cpu"),
+    ("alerts.memory", "alert-mem"),
+    ("alerts.every", "alert-every"),
+    ("alerts.for", "alert-for"),
+    ("alerts.cmd", "alert-cmd"),
+    ("alerts.webhook", "alert-webhook"),
+];
+
+/// The table holding the named profiles, `[profile.<name>]`.
+const PROFILES: &str = "profile";
+
+/// Options that only make sense on the command line.
+const COMMAND_LINE_ONLY: [&str; 3] = ["config", "no-config", "profile"];
+
+/// Where the config file is read from when `--config` is not given, if
+/// there is one: `$XDG_CONFIG_HOME/chronologger/config.toml`, falling back
+/// to `~/.config`, or `%APPDATA%\chronologger\config.toml` on Windows.
+pub fn default_path() -> Option<PathBuf> {
+    location(|name| std::env::var_os(name)).filter(|path| path.is_file())
+}
+
+fn location(var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let directory = |name: &str| {
+        var(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    };
+    #[cfg(windows)]
+    let base = directory("APPDATA");
+    #[cfg(not(windows))]
+    let base =
+        directory("XDG_CONFIG_HOME").or_else(|| directory("HOME").map(|home| home.join(".config")));
+    Some(base?.join("chronologger").join("config.toml"))
+}
+
+/// One `key = value` of the file, with its dotted key inside the file or
+/// the profile it belongs to.
+#[derive(Debug)]
+struct Entry<'a> {
+    profile: Option<&'a str>,
+    key: String,
+    value: &'a Value,
+}
+
+impl Entry<'_> {
+    /// The key as written in the file.
+    fn full_key(&self) -> String {
+        match self.profile {
+            Some(profile) => format!("{}.{}.{}", PROFILES, profile, self.key),
+            None => self.key.clone(),
+        }
+    }
+}
+
+/// The options read from a `--config` file. Keys are the long names of
+/// the command-line options, with `_` accepted for `-`, so that
+/// `interval = "5s"` sets `--interval 5s`. A table groups options sharing a
+/// prefix: `[rotate]` with `size = "10MB"` sets `--rotate-size`, and
+/// `[filters]` and `[alerts]` cover `--name`, `--pid`, `--user`, `--exclude`,
+/// `--exact` and the `--alert-*` options. `[profile.<name>]` tables hold
+/// options that `--profile <name>` lays over the rest.
+#[derive(Debug, Default, Deserialize)]
+pub struct ConfigFile {
+    #[serde(skip)]
+    name: String,
+    /// The `[profile.<name>]` tables, by name.
+    #[serde(default, rename = "profile")]
+    profiles: Table,
+    /// Every other key of the file.
+    #[serde(flatten)]
+    options: Table,
+    /// The profile laid over the rest, if any.
+    #[serde(skip)]
+    profile: Option<String>,
+}
+
+impl ConfigFile {
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        Self::parse(&path.display().to_string(), &text)
+    }
+
+    /// Parses `text`, naming the file `name` in errors.
+    pub fn parse(name: &str, text: &str) -> Result<Self> {
+        let mut file: Self = toml::from_str(text)
+            .map_err(|e| anyhow::anyhow!("Invalid config file {}: {}", name, e))?;
+        file.name = name.to_string();
+        if let Some((profile, _)) = file.profiles.iter().find(|(_, table)| !table.is_table()) {
+            bail!(
+                "`{}.{}` in config file {} is not a table",
+                PROFILES,
+                profile,
+                name
+            );
+        }
+        Ok(file)
+    }
+
+    /// The names of the file's profiles, in the order they first appear.
+    pub fn profiles(&self) -> Vec<&str> {
+        self.profiles.keys().map(String::as_str).collect()
+    }
+
+    /// Every option of the file and of its profiles, with the keys of
+    /// tables joined by dots.
+    fn entries(&self) -> Vec<Entry<'_>> {
+        let mut entries = Vec::new();
+        flatten(None, "", &self.options, &mut entries);
+        for (profile, table) in &self.profiles {
+            if let Value::Table(table) = table {
+                flatten(Some(profile), "", table, &mut entries);
+            }
+        }
+        entries
+    }
+
+    /// Lays the options of profile `name` over the rest of the file.
+    pub fn with_profile(mut self, name: &str) -> Result<Self> {
+        let profiles = self.profiles();
+        if !profiles.contains(&name) {
+            match profiles.is_empty() {
+                true => bail!("Config file {} has no profiles", self.name),
+                false => bail!(
+                    "No profile `{}` in config file {}, which has {}",
+                    name,
+                    self.name,
+                    profiles.join(", ")
+                ),
+            }
+        }
+        self.profile = Some(name.to_string());
+        Ok(self)
+    }
+
+    /// The file's options as command-line arguments of `command`, leaving
+    /// out those `given` on the command line and those that conflict with
+    /// one. A command that takes the rest of the line comes last.
+    pub fn to_args(
+        &self,
+        command: &Command,
+        given: impl Fn(&str) -> bool,
+    ) -> Result<Vec<OsString>> {
+        let mut command = command.clone();
+        command.build();
+        let given_args: Vec<_> = command
+            .get_arguments()
+            .filter(|arg| given(arg.get_id().as_str()))
+            .collect();
+        // Every key is checked, including those of profiles not in use.
+        let mut options = Vec::new();
+        for entry in self.entries() {
+            let key = entry.key.as_str();
+            let name = ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == key)
+                .map(|(_, long)| long.to_string())
+                .unwrap_or_else(|| key.replace(['.', '_'], "-"));
+            let Some(arg) = command
+                .get_arguments()
+                .filter(|arg| !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version))
+                .filter(|arg| {
+                    !arg.get_long()
+                        .is_some_and(|long| COMMAND_LINE_ONLY.contains(&long))
+                })
+                .find(|arg| {
+                    arg.get_long() == Some(name.as_str())
+                        || arg
+                            .get_all_aliases()
+                            .is_some_and(|aliases| aliases.contains(&name.as_str()))
+                })
+            else {
+                bail!(
+                    "Unknown key `{}` in config file {}",
+                    entry.full_key(),
+                    self.name
+                );
+            };
+            options.push((entry, arg));
+        }
+        // The profile's options replace the file's own.
+        let selected =
+            |profile: Option<&str>| profile.is_some() && profile == self.profile.as_deref();
+        let replaced: Vec<&str> = options
+            .iter()
+            .filter(|(entry, _)| selected(entry.profile))
+            .map(|(_, arg)| arg.get_id().as_str())
+            .collect();
+        options.retain(|(entry, arg)| match entry.profile {
+            Some(_) => selected(entry.profile),
+            None => !replaced.contains(&arg.get_id().as_str()),
+        });
+
+        let mut args = Vec::new();
+        let mut trailing = Vec::new();
+        for (entry, arg) in options {
+            let long = arg.get_long().unwrap();
+            let id = arg.get_id().as_str();
+            if given(id) {
+                continue;
+            }
+            if given_args.iter().any(|given| {
+                command
+                    .get_arg_conflicts_with(given)
+                    .iter()
+                    .any(|conflict| conflict.get_id() == id)
+                    || command
+                        .get_arg_conflicts_with(arg)
+                        .iter()
+                        .any(|conflict| conflict.get_id() == given.get_id())
+            }) {
+                continue;
+            }
+
+            let invalid = |expected: &str| {
+                anyhow::anyhow!(
+                    "Key `{}` in config file {} expects {}",
+                    entry.full_key(),
+                    self.name,
+                    expected
+                )
+            };
+            let option = format!("--{}", long);
+            let values = match entry.value {
+                Value::Array(values) => values
+                    .iter()
+                    .map(|value| to_arg(value).ok_or_else(|| invalid("a flat array")))
+                    .collect::<Result<Vec<_>>>()?,
+                value => vec![to_arg(value).ok_or_else(|| invalid("a value"))?],
+            };
+            let range = arg.get_num_args().unwrap_or_default();
+            match arg.get_action() {
+                ArgAction::SetTrue => match entry.value {
+                    Value::Boolean(true) => args.push(option.into()),
+                    Value::Boolean(false) => {}
+                    _ => return Err(invalid("true or false")),
+                },
+                _ if range.max_values() == usize::MAX => {
+                    trailing.push(OsString::from(option));
+                    trailing.extend(values.into_iter().map(OsString::from));
+                }
+                _ if range.min_values() == 0 && *entry.value == Value::Boolean(true) => {
+                    args.push(option.into());
+                }
+                ArgAction::Append => {
+                    args.extend(
+                        values
+                            .iter()
+                            .map(|value| format!("{}={}", option, value).into()),
+                    );
+                }
+                _ => match values.as_slice() {
+                    [value] => args.push(format!("{}={}", option, value).into()),
+                    _ if arg.get_value_delimiter().is_some() => {
+                        args.push(format!("{}={}", option, values.join(",")).into())
+                    }
+                    _ => return Err(invalid("a single value")),
+                },
+            }
+        }
+        args.extend(trailing);
+        Ok(args)
+    }
+}
+
+/// Adds the keys of `table` to `entries`, prefixed with `prefix`, with
+/// those of nested tables joined by dots.
+fn flatten<'a>(
+    profile: Option<&'a str>,
+    prefix: &str,
+    table: &'a Table,
+    entries: &mut Vec<Entry<'a>>,
+) {
+    for (key, value) in table {
+        let key = match prefix.is_empty() {
+            true => key.clone(),
+            false => format!("{}.{}", prefix, key),
+        };
+        match value {
+            Value::Table(table) => flatten(profile, &key, table, entries),
+            value => entries.push(Entry {
+                profile,
+                key,
+                value,
+            }),
+        }
+    }
+}
+
+/// `value` as an option value on the command line, unless it is an array
+/// or a table.
+fn to_arg(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Integer(number) => Some(number.to_string()),
+        Value::Float(number) => Some(number.to_string()),
+        Value::Boolean(flag) => Some(flag.to_string()),
+        Value::Datetime(datetime) => Some(datetime.to_string()),
+        Value::Array(_) | Value::Table(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries() {
+        let file = ConfigFile::parse(
+            "test.toml",
+            r#"
+interval = "5s"
+columns = ["rss", "disk_read"]
+
+[alerts]
+cpu = 80
+
+[profile.ci]
+once = true
+"#,
+        )
+        .unwrap();
+        let entries: Vec<(String, &Value)> = file
+            .entries()
+            .iter()
+            .map(|entry| (entry.full_key(), entry.value))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("interval".to_string(), &Value::String("5s".into())),
+                (
+                    "columns".to_string(),
+                    &Value::Array(vec!["rss".into(), "disk_read".into()])
+                ),
+                ("alerts.cpu".to_string(), &Value::Integer(80)),
+                ("profile.ci.once".to_string(), &Value::Boolean(true)),
+            ]
+        );
+        let error = ConfigFile::parse("test.toml", "[profile]\nci = 1\n")
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "`profile.ci` in config file test.toml is not a table"
+        );
+    }
+
+    #[test]
+    fn test_default_location() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| OsString::from(value))
+            }
+        };
+        #[cfg(not(windows))]
+        {
+            assert_eq!(
+                location(env(&[("XDG_CONFIG_HOME", "/xdg"), ("HOME", "/home/me")])),
+                Some(PathBuf::from("/xdg/chronologger/config.toml"))
+            );
+            assert_eq!(
+                location(env(&[("XDG_CONFIG_HOME", ""), ("HOME", "/home/me")])),
+                Some(PathBuf::from("/home/me/.config/chronologger/config.toml"))
+            );
+        }
+        #[cfg(windows)]
+        assert_eq!(
+            location(env(&[("APPDATA", r"C:\Users\me\AppData\Roaming")])),
+            Some(PathBuf::from(
+                r"C:\Users\me\AppData\Roaming\chronologger\config.toml"
+            ))
+        );
+        assert_eq!(location(env(&[])), None);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let file = ConfigFile::parse(
+            "test.toml",
+            "interval = 1\n[profile.ci]\nonce = true\n[profile.soak.filters]\nnames = []\n",
+        )
+        .unwrap();
+        assert_eq!(file.profiles(), ["ci", "soak"]);
+        let error = file.with_profile("nightly").unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "No profile `nightly` in config file test.toml, which has ci, soak"
+        );
+        let file = ConfigFile::parse("test.toml", "interval = 1\n").unwrap();
+        assert!(file.with_profile("ci").is_err());
+    }
+}
@@ -0,0 +1,446 @@
+use crate::per_pid::PerPid;
+use std::{fs, path::PathBuf, sync::Arc};
+
+/// Hex digits of a container ID.
+const ID_DIGITS: usize = 64;
+
+/// Hex digits of the ID written when it is not resolved to a name, as
+/// `docker ps` shows it.
+const SHORT_ID_DIGITS: usize = 12;
+
+/// What container runtimes prefix the scope of a container with under the
+/// systemd cgroup driver.
+const SCOPE_PREFIXES: [&str; 5] = [
+    "docker-",
+    "cri-containerd-",
+    "crio-",
+    "libpod-",
+    "containerd-",
+];
+
+/// The container a process runs in, as found in its cgroup path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Container {
+    /// The UID of the Kubernetes pod the container belongs to.
+    pub pod: Option<String>,
+    /// The full container ID, if the process is in one rather than only in
+    /// the pod.
+    pub id: Option<String>,
+}
+
+impl Container {
+    /// Finds the container in `/proc/<pid>/cgroup`, trying each hierarchy
+    /// until one names it, which covers cgroup v1, v2 and hybrid hosts.
+    pub fn parse(membership: &str) -> Option<Self> {
+        membership.lines().find_map(|line| {
+            let path = line.splitn(3, ':').nth(2)?;
+            Self::from_path(path)
+        })
+    }
+
+    /// Finds the container in one cgroup path, as laid out by the cgroupfs
+    /// driver (`/docker/<id>`, `/kubepods/burstable/pod<uid>/<id>`) or the
+    /// systemd one (`/system.slice/docker-<id>.scope`,
+    /// `/kubepods.slice/.../kubepods-burstable-pod<uid>.slice/cri-containerd-<id>.scope`).
+    fn from_path(path: &str) -> Option<Self> {
+        let mut container = Self {
+            pod: None,
+            id: None,
+        };
+        for segment in path.split('/') {
+            if let Some(pod) = pod_uid(segment) {
+                container.pod = Some(pod);
+            } else if let Some(id) = container_id(segment) {
+                container.id = Some(id.to_string());
+            }
+        }
+        (container.pod.is_some() || container.id.is_some()).then_some(container)
+    }
+
+    /// How the column shows the container: `name` if it was resolved,
+    /// otherwise the short ID, after the pod UID for Kubernetes.
+    fn label(&self, name: Option<&str>) -> String {
+        let id = self
+            .id
+            .as_deref()
+            .map(|id| name.unwrap_or(&id[..SHORT_ID_DIGITS]));
+        match (&self.pod, id) {
+            (Some(pod), Some(id)) => format!("{}/{}", pod, id),
+            (Some(pod), None) => pod.clone(),
+            (None, id) => id.unwrap_or_default().to_string(),
+        }
+    }
+}
+
+/// The pod UID in a `pod<uid>` (cgroupfs) or `kubepods-<class>-pod<uid>.slice`
+/// (systemd, with the UID's dashes as underscores) segment.
+fn pod_uid(segment: &str) -> Option<String> {
+    let segment = segment.strip_suffix(".slice").unwrap_or(segment);
+    let (_, uid) = segment.rsplit_once("pod")?;
+    let uid = uid.replace('_', "-");
+    let is_uid = uid.len() == 36 && uid.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+    is_uid.then_some(uid)
+}
+
+/// The container ID in a `<id>` (cgroupfs) or `<runtime>-<id>.scope`
+/// (systemd) segment.
+fn container_id(segment: &str) -> Option<&str> {
+    let id = match segment.strip_suffix(".scope") {
+        Some(scope) => SCOPE_PREFIXES
+            .iter()
+            .find_map(|prefix| scope.strip_prefix(prefix))?,
+        None => segment,
+    };
+    (id.len() == ID_DIGITS && id.chars().all(|c| c.is_ascii_hexdigit())).then_some(id)
+}
+
+/// Looks up the container of each process for the `container` column. A
+/// PID's container is read once, and forgotten once a tick goes by without
+/// the PID, like the cgroups of the throttling columns.
+pub struct Containers {
+    root: PathBuf,
+    /// The label of each PID, `None` for processes outside any container.
+    labels: PerPid<Option<Arc<str>>>,
+    #[cfg(all(unix, feature = "containers"))]
+    resolver: Option<resolve::Resolver>,
+}
+
+impl Containers {
+    pub fn new() -> Self {
+        Self::with_root(PathBuf::from("/"))
+    }
+
+    /// Reads `/proc` below `root` instead of `/`.
+    fn with_root(root: PathBuf) -> Self {
+        Self {
+            root,
+            labels: PerPid::new(),
+            #[cfg(all(unix, feature = "containers"))]
+            resolver: None,
+        }
+    }
+
+    /// Names containers by asking the Docker Engine API on `socket`.
+    #[cfg(all(unix, feature = "containers"))]
+    pub fn resolve_with(&mut self, socket: PathBuf) {
+        self.resolver = Some(resolve::Resolver::new(socket));
+    }
+
+    /// Starts a new tick, forgetting PIDs the tick that ended did not see.
+    pub fn begin_tick(&mut self) {
+        self.labels.begin_tick();
+    }
+
+    /// The label of the container `pid` runs in, or `None` outside any.
+    pub fn label(&mut self, pid: u32) -> Option<Arc<str>> {
+        let label = match self.labels.take(pid) {
+            Some(label) => label,
+            None => self.lookup(pid),
+        };
+        self.labels.insert(pid, label.clone());
+        label
+    }
+
+    fn lookup(&mut self, pid: u32) -> Option<Arc<str>> {
+        let path = self.root.join("proc").join(pid.to_string()).join("cgroup");
+        let container = Container::parse(&fs::read_to_string(path).ok()?)?;
+        #[cfg(all(unix, feature = "containers"))]
+        if let (Some(resolver), Some(id)) = (&mut self.resolver, &container.id) {
+            let name = resolver.name(id);
+            return Some(container.label(name.as_deref()).into());
+        }
+        Some(container.label(None).into())
+    }
+}
+
+#[cfg(all(unix, feature = "containers"))]
+mod resolve {
+    use crate::http::Response;
+    use anyhow::{Context, Result};
+    use log::{debug, warn};
+    use std::{
+        collections::HashMap,
+        io::{Read, Write},
+        os::unix::net::UnixStream,
+        path::PathBuf,
+        time::Duration,
+    };
+
+    /// How long to wait for the runtime before leaving the ID unresolved.
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Names containers through the Docker Engine API, which dockerd and
+    /// Podman serve on a Unix socket. Each ID is asked about once; if the
+    /// socket cannot be reached the resolver gives up for the rest of the
+    /// run.
+    pub struct Resolver {
+        socket: PathBuf,
+        names: HashMap<String, Option<String>>,
+        unreachable: bool,
+    }
+
+    impl Resolver {
+        pub fn new(socket: PathBuf) -> Self {
+            Self {
+                socket,
+                names: HashMap::new(),
+                unreachable: false,
+            }
+        }
+
+        /// The name of container `id`, if the runtime knows it.
+        pub fn name(&mut self, id: &str) -> Option<String> {
+            if let Some(name) = self.names.get(id) {
+                return name.clone();
+            }
+            if self.unreachable {
+                return None;
+            }
+            let name = match self.inspect(id) {
+                Ok(name) => name,
+                Err(e) => {
+                    warn!(
+                        "Cannot resolve container names through {}, writing IDs: {:#}",
+                        self.socket.display(),
+                        e
+                    );
+                    self.unreachable = true;
+                    None
+                }
+            };
+            self.names.insert(id.to_string(), name.clone());
+            name
+        }
+
+        /// Asks the runtime about `id`. A container it does not know, e.g.
+        /// one run by another runtime, has no name.
+        fn inspect(&self, id: &str) -> Result<Option<String>> {
+            let mut stream = UnixStream::connect(&self.socket)
+                .with_context(|| format!("Failed to connect to {}", self.socket.display()))?;
+            stream.set_read_timeout(Some(TIMEOUT))?;
+            stream.set_write_timeout(Some(TIMEOUT))?;
+            // HTTP/1.0 keeps the reply from being chunked.
+            let request = format!(
+                "GET /containers/{}/json HTTP/1.0\r\nHost: docker\r\n\r\n",
+                id
+            );
+            stream.write_all(request.as_bytes())?;
+            let mut raw = Vec::new();
+            stream.read_to_end(&mut raw)?;
+            let response = parse_response(&raw)?;
+            if !response.is_success() {
+                debug!(
+                    "Container {} is unknown to the runtime: {}",
+                    id, response.status
+                );
+                return Ok(None);
+            }
+            let details: serde_json::Value =
+                serde_json::from_str(&response.body).context("Malformed container details")?;
+            Ok(details["Name"]
+                .as_str()
+                .map(|name| name.trim_start_matches('/').to_string()))
+        }
+    }
+
+    /// Parses a whole HTTP response as read from its connection. The runtime
+    /// serves the API on a Unix socket, so the HTTP client cannot reach it.
+    pub fn parse_response(raw: &[u8]) -> Result<Response> {
+        let text = String::from_utf8_lossy(raw);
+        let status = text
+            .lines()
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok())
+            .context("Malformed HTTP response")?;
+        let body = text
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_string())
+            .unwrap_or_default();
+
+        Ok(Response { status, body })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::os::unix::net::UnixListener;
+
+        #[test]
+        fn test_parse_response() {
+            let response = parse_response(b"HTTP/1.1 202 Accepted\r\nContent-Length: 2\r\n\r\nok")
+                .expect("Failed to parse response");
+            assert_eq!(response.status, 202);
+            assert_eq!(response.body, "ok");
+            assert!(response.is_success());
+        }
+
+        #[test]
+        fn test_names_from_engine_api() {
+            let dir = tempfile::TempDir::new().unwrap();
+            let socket = dir.path().join("docker.sock");
+            let listener = UnixListener::bind(&socket).unwrap();
+            let server = std::thread::spawn(move || {
+                let mut requests = Vec::new();
+                for reply in [
+                    "HTTP/1.0 200 OK\r\n\r\n{\"Name\": \"/web-1\"}",
+                    "HTTP/1.0 404 Not Found\r\n\r\n{\"message\": \"No such container\"}",
+                ] {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut request = [0; 256];
+                    let read = stream.read(&mut request).unwrap();
+                    requests.push(String::from_utf8_lossy(&request[..read]).into_owned());
+                    stream.write_all(reply.as_bytes()).unwrap();
+                }
+                requests
+            });
+
+            let mut resolver = Resolver::new(socket.clone());
+            assert_eq!(resolver.name("abc").as_deref(), Some("web-1"));
+            assert_eq!(resolver.name("abc").as_deref(), Some("web-1"), "Not cached");
+            assert_eq!(resolver.name("def"), None);
+            let requests = server.join().unwrap();
+            assert!(requests[0].starts_with("GET /containers/abc/json HTTP/1.0\r\n"));
+
+            // Once the socket is gone, nothing else is asked.
+            std::fs::remove_file(&socket).unwrap();
+            assert_eq!(resolver.name("ghi"), None);
+            assert!(resolver.unreachable);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    const ID: &str = "3f9c1e0b5a7d4c2e8f6a1b3d5c7e9f0a2b4d6c8e0f1a3b5c7d9e1f2a4b6c8d0e";
+    const POD: &str = "8d3f4e1a-2b5c-4d6e-9f0a-1b2c3d4e5f6a";
+
+    fn fixture(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn parse(membership: &str) -> Option<String> {
+        Container::parse(membership).map(|container| container.label(None))
+    }
+
+    #[test]
+    fn test_docker_cgroup_v1() {
+        let membership = format!(
+            "12:memory:/docker/{id}\n4:cpu,cpuacct:/docker/{id}\n1:name=systemd:/docker/{id}\n",
+            id = ID
+        );
+        assert_eq!(parse(&membership).unwrap(), &ID[..12]);
+        let parsed = Container::parse(&membership).unwrap();
+        assert_eq!(parsed.id.as_deref(), Some(ID));
+        assert_eq!(parsed.pod, None);
+    }
+
+    #[test]
+    fn test_cgroup_v2() {
+        assert_eq!(
+            parse(&format!("0::/system.slice/docker-{}.scope\n", ID)).unwrap(),
+            &ID[..12]
+        );
+        assert_eq!(
+            parse(&format!("0::/kubepods/burstable/pod{}/{}\n", POD, ID)).unwrap(),
+            format!("{}/{}", POD, &ID[..12])
+        );
+        assert_eq!(
+            parse(&format!(
+                "0::/machine.slice/libpod-{}.scope/container\n",
+                ID
+            ))
+            .unwrap(),
+            &ID[..12]
+        );
+    }
+
+    #[test]
+    fn test_systemd_driver() {
+        let pod = POD.replace('-', "_");
+        let membership = format!(
+            "0::/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod{}.slice/\
+             cri-containerd-{}.scope\n",
+            pod, ID
+        );
+        assert_eq!(
+            parse(&membership).unwrap(),
+            format!("{}/{}", POD, &ID[..12])
+        );
+        let guaranteed = format!(
+            "0::/kubepods.slice/kubepods-pod{}.slice/crio-{}.scope\n",
+            pod, ID
+        );
+        assert_eq!(
+            parse(&guaranteed).unwrap(),
+            format!("{}/{}", POD, &ID[..12])
+        );
+        // A process in the pod but in none of its containers.
+        let pod_only = format!(
+            "0::/kubepods.slice/kubepods-besteffort.slice/kubepods-besteffort-pod{}.slice\n",
+            pod
+        );
+        assert_eq!(parse(&pod_only).unwrap(), POD);
+    }
+
+    #[test]
+    fn test_outside_containers() {
+        for membership in [
+            "0::/user.slice/user-1000.slice/session-2.scope\n",
+            "0::/system.slice/sshd.service\n",
+            "0::/\n",
+            "12:memory:/\n1:name=systemd:/init.scope\n",
+            // Too short to be a container ID.
+            "0::/docker/3f9c1e0b5a7d\n",
+            "",
+        ] {
+            assert_eq!(
+                parse(membership),
+                None,
+                "Found a container in {:?}",
+                membership
+            );
+        }
+        let container = Container {
+            pod: None,
+            id: Some(ID.to_string()),
+        };
+        assert_eq!(container.label(Some("web-1")), "web-1");
+    }
+
+    #[test]
+    fn test_labels_cached_per_pid() {
+        let root = TempDir::new().unwrap();
+        fixture(
+            root.path(),
+            "proc/10/cgroup",
+            &format!("0::/docker/{}\n", ID),
+        );
+        fixture(
+            root.path(),
+            "proc/11/cgroup",
+            "0::/system.slice/cron.service\n",
+        );
+        let mut containers = Containers::with_root(root.path().to_path_buf());
+
+        containers.begin_tick();
+        assert_eq!(containers.label(10).as_deref(), Some(&ID[..12]));
+        assert_eq!(containers.label(11), None);
+        assert_eq!(containers.label(12), None, "A PID without /proc entry");
+
+        // Cached: the process moving does not change its label while its
+        // PID stays in view.
+        fixture(root.path(), "proc/10/cgroup", "0::/\n");
+        containers.begin_tick();
+        assert_eq!(containers.label(10).as_deref(), Some(&ID[..12]));
+        containers.begin_tick();
+        containers.begin_tick();
+        assert_eq!(containers.label(10), None, "A forgotten PID is read again");
+    }
+}
@@ -0,0 +1,366 @@
+use crate::{ProcessLogger, SortKey};
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use std::{
+    fs,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    sync::mpsc::{self, Receiver, Sender},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Pause,
+    Resume,
+    Rotate,
+    Flush,
+    Set { key: String, value: Value },
+    Status,
+}
+
+struct Pending {
+    command: Command,
+    reply: Sender<Value>,
+}
+
+/// Accepts newline-delimited JSON commands on a Unix domain socket. Commands
+/// are queued and applied by the sampling loop at the next tick boundary.
+/// Access control is left to the socket's filesystem permissions (0600).
+pub struct ControlSocket {
+    path: PathBuf,
+    commands: Receiver<Pending>,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    paused: bool,
+}
+
+impl ControlSocket {
+    pub fn bind(path: &Path) -> Result<Self> {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                bail!("{} exists and is not a socket", path.display());
+            }
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .context("Failed to restrict control socket permissions!")?;
+        listener
+            .set_nonblocking(true)
+            .context("Failed to configure control socket!")?;
+        info!("Listening for control commands on {}", path.display());
+
+        let (sender, commands) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            thread::spawn(move || accept(listener, sender, stop))
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            commands,
+            stop,
+            handle: Some(handle),
+            paused: false,
+        })
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Applies every command received since the last tick boundary.
+    pub fn apply_pending(&mut self, logger: &mut ProcessLogger) {
+        while let Ok(Pending { command, reply }) = self.commands.try_recv() {
+            info!("Applying control command: {:?}", command);
+            let result = match self.apply(command, logger) {
+                Ok(value) => value,
+                Err(e) => {
+                    warn!("Control command failed: {:#}", e);
+                    json!({ "ok": false, "error": format!("{:#}", e) })
+                }
+            };
+            let _ = reply.send(result);
+        }
+    }
+
+    fn apply(&mut self, command: Command, logger: &mut ProcessLogger) -> Result<Value> {
+        match command {
+            Command::Pause => self.paused = true,
+            Command::Resume => self.paused = false,
+            Command::Rotate => logger.reopen()?,
+            Command::Flush => logger.output.flush()?,
+            Command::Set { key, value } => set(&key, &value, logger)?,
+            Command::Status => {
+                return Ok(json!({
+                    "ok": true,
+                    "paused": self.paused,
+                    "ticks": logger.ticks,
+                    "rows": logger.rows,
+                    "last_tick_ms": logger.last_tick.as_secs_f64() * 1000.0,
+                    "output": logger.file_path,
+                    "overhead": logger.overhead().to_json(),
+                    "preflight": logger.preflight,
+                }))
+            }
+        }
+        Ok(json!({ "ok": true }))
+    }
+}
+
+/// Applies a `set` command. `top_cpu` and `top_memory` log only the N
+/// processes with the highest CPU or memory usage from the next tick on, like
+/// `--top N` with `--sort-by cpu` or `memory`.
+fn set(key: &str, value: &Value, logger: &mut ProcessLogger) -> Result<()> {
+    let sort_by = match key {
+        "top_cpu" => SortKey::Cpu,
+        "top_memory" => SortKey::Memory,
+        _ => bail!("Unknown setting: {}", key),
+    };
+    let count = value
+        .as_u64()
+        .filter(|&count| count > 0)
+        .with_context(|| format!("{} takes a positive integer, not {}", key, value))?;
+    logger.set_top(usize::try_from(count).unwrap_or(usize::MAX), sort_by);
+    Ok(())
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn accept(listener: UnixListener, sender: Sender<Pending>, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let sender = sender.clone();
+                thread::spawn(move || {
+                    if let Err(e) = serve(stream, sender) {
+                        debug!("Control connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+            Err(e) => {
+                warn!("Failed to accept control connection: {}", e);
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+fn serve(stream: UnixStream, sender: Sender<Pending>) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = match parse_command(&line) {
+            Ok(command) => {
+                let (reply, replied) = mpsc::channel();
+                if sender.send(Pending { command, reply }).is_ok() {
+                    replied.recv().unwrap_or_else(
+                        |_| json!({ "ok": false, "error": "Logger is shutting down" }),
+                    )
+                } else {
+                    json!({ "ok": false, "error": "Logger is shutting down" })
+                }
+            }
+            Err(e) => json!({ "ok": false, "error": e }),
+        };
+        writeln!(writer, "{}", result)?;
+    }
+    Ok(())
+}
+
+fn parse_command(line: &str) -> Result<Command, String> {
+    let request: Value = serde_json::from_str(line).map_err(|e| format!("Invalid JSON: {}", e))?;
+    let command = request["cmd"].as_str().ok_or("Missing \"cmd\"")?;
+    match command {
+        "pause" => Ok(Command::Pause),
+        "resume" => Ok(Command::Resume),
+        "rotate" => Ok(Command::Rotate),
+        "flush" => Ok(Command::Flush),
+        "status" => Ok(Command::Status),
+        "set" => Ok(Command::Set {
+            key: request["key"]
+                .as_str()
+                .ok_or("Missing \"key\"")?
+                .to_string(),
+            value: request["value"].clone(),
+        }),
+        _ => Err(format!("Unknown command: {}", command)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{retry::RetryPolicy, source::testing::FakeSource, OutputFormat};
+    use tempfile::tempdir;
+
+    /// Sends each command on a connection of its own thread while `logger`
+    /// keeps sampling, and returns the replies once all were answered.
+    fn drive(
+        control: &mut ControlSocket,
+        logger: &mut ProcessLogger,
+        socket_path: &Path,
+        commands: &'static [&'static str],
+    ) -> Vec<Value> {
+        let client = thread::spawn({
+            let socket_path = socket_path.to_path_buf();
+            move || {
+                let stream = UnixStream::connect(&socket_path).expect("Failed to connect");
+                let mut writer = stream.try_clone().unwrap();
+                let mut reader = BufReader::new(stream);
+                commands
+                    .iter()
+                    .map(|command| {
+                        writeln!(writer, "{}", command).unwrap();
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        serde_json::from_str::<Value>(&line).unwrap()
+                    })
+                    .collect()
+            }
+        });
+        while !client.is_finished() {
+            control.apply_pending(logger);
+            if !control.is_paused() {
+                logger.log_processes().expect("Failed to log processes");
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        client.join().unwrap()
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command(r#"{"cmd":"pause"}"#), Ok(Command::Pause));
+        assert_eq!(
+            parse_command(r#"{"cmd":"set","key":"top_cpu","value":20}"#),
+            Ok(Command::Set {
+                key: "top_cpu".to_string(),
+                value: json!(20)
+            })
+        );
+        assert!(parse_command(r#"{"cmd":"explode"}"#).is_err());
+        assert!(parse_command("not json").is_err());
+    }
+
+    #[test]
+    fn test_scripted_client() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let output = dir.path().join("out.csv");
+        let socket_path = dir.path().join("control.sock");
+
+        let mut logger = ProcessLogger::new(output.to_str().unwrap(), OutputFormat::Csv)
+            .expect("Failed to create ProcessLogger");
+        let mut control = ControlSocket::bind(&socket_path).expect("Failed to bind socket");
+        let mode = fs::metadata(&socket_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let replies = drive(
+            &mut control,
+            &mut logger,
+            &socket_path,
+            &[
+                r#"{"cmd":"pause"}"#,
+                r#"{"cmd":"status"}"#,
+                r#"{"cmd":"resume"}"#,
+                r#"{"cmd":"flush"}"#,
+                r#"{"cmd":"set","key":"top_cpu","value":2}"#,
+                r#"{"cmd":"set","key":"top_cpu","value":0}"#,
+                r#"{"cmd":"set","key":"colour","value":1}"#,
+                r#"{"cmd":"bogus"}"#,
+            ],
+        );
+        assert_eq!(replies[0]["ok"], true);
+        assert_eq!(replies[1]["paused"], true);
+        assert!(replies[1]["overhead"]["ticks"].is_u64());
+        assert_eq!(replies[2]["ok"], true);
+        assert_eq!(replies[3]["ok"], true);
+        assert_eq!(replies[4]["ok"], true);
+        assert_eq!(replies[5]["ok"], false);
+        assert_eq!(replies[6]["ok"], false);
+        assert_eq!(replies[7]["ok"], false);
+        assert!(!control.is_paused());
+        logger.log_processes().expect("Failed to log processes");
+        assert!(logger.samples.len() <= 2, "{} rows", logger.samples.len());
+
+        drop(control);
+        assert!(!socket_path.exists(), "Socket file must be removed");
+    }
+
+    #[test]
+    fn test_rotate_keeps_written_rows() {
+        let dir = tempdir().expect("Failed to create temp dir");
+        let output = dir.path().join("out.csv");
+        let moved = dir.path().join("out.csv.1");
+        let socket_path = dir.path().join("control.sock");
+
+        let mut logger = ProcessLogger::with_source(
+            output.to_str().unwrap(),
+            OutputFormat::Csv,
+            Box::new(FakeSource::new(3)),
+            RetryPolicy::default(),
+        )
+        .expect("Failed to create ProcessLogger");
+        logger.write_header().expect("Failed to write header");
+        let mut control = ControlSocket::bind(&socket_path).expect("Failed to bind socket");
+        let rows = |path: &Path| {
+            let text = fs::read_to_string(path).unwrap();
+            text.lines().filter(|line| line.contains("worker-")).count()
+        };
+
+        // Rotating while the file is still in place would truncate it.
+        let replies = drive(
+            &mut control,
+            &mut logger,
+            &socket_path,
+            &[r#"{"cmd":"rotate"}"#],
+        );
+        assert_eq!(replies[0]["ok"], false);
+        logger.output.flush().unwrap();
+        let written = rows(&output);
+        assert!(written >= 3, "{} rows", written);
+
+        fs::rename(&output, &moved).unwrap();
+        let replies = drive(
+            &mut control,
+            &mut logger,
+            &socket_path,
+            &[r#"{"cmd":"rotate"}"#],
+        );
+        assert_eq!(replies[0]["ok"], true);
+        logger.log_processes().expect("Failed to log processes");
+        logger.finish().expect("Failed to finish");
+        assert!(rows(&moved) >= written);
+        assert!(rows(&output) >= 3);
+        assert!(fs::read_to_string(&output)
+            .unwrap()
+            .contains("Process Name"));
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::Result;
+use log::{error, info};
+use std::{
+    panic, process,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Mutex, TryLockError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Exit status after a panic, distinct from the 1 of an ordinary error and
+/// the 2 of a usage error.
+pub const PANIC_EXIT_CODE: i32 = 70;
+
+/// How long the hook waits for a writer that is busy on another thread.
+const FLUSH_WAIT: Duration = Duration::from_secs(1);
+
+type FlushFn = Box<dyn Fn() -> Result<()> + Send>;
+
+static FLUSHES: Mutex<Vec<FlushFn>> = Mutex::new(Vec::new());
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// Installs a panic hook that logs the panic, makes a best-effort attempt to
+/// flush everything registered with [`on_panic`] and exits with
+/// [`PANIC_EXIT_CODE`]. Without it, rows buffered since the last flush are
+/// lost when a panic unwinds past the writer.
+pub fn install() {
+    panic::set_hook(Box::new(|info| {
+        // A second panic, on another thread or from a flush, leaves the
+        // first one time to finish flushing before giving up.
+        if PANICKING.swap(true, Ordering::SeqCst) {
+            thread::sleep(FLUSH_WAIT * 5);
+            process::exit(PANIC_EXIT_CODE);
+        }
+        let message = info
+            .payload()
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("Box<dyn Any>");
+        let location = info
+            .location()
+            .map(|location| location.to_string())
+            .unwrap_or_else(|| "unknown location".to_string());
+        error!(
+            "Thread '{}' panicked at {}: {}",
+            thread::current().name().unwrap_or("<unnamed>"),
+            location,
+            message
+        );
+        flush_all();
+        process::exit(PANIC_EXIT_CODE);
+    }));
+}
+
+/// Registers `flush` to run if the process panics.
+pub fn on_panic(flush: impl Fn() -> Result<()> + Send + 'static) {
+    lock(&FLUSHES).push(Box::new(flush));
+}
+
+/// Locks `mutex` for the hook, waiting at most [`FLUSH_WAIT`] and accepting
+/// a poisoned lock. Returns `None` if another thread keeps holding it, which
+/// is also the case when the panic happened while it was held.
+pub fn try_lock_for_panic<T>(mutex: &Mutex<T>) -> Option<std::sync::MutexGuard<'_, T>> {
+    let started = Instant::now();
+    loop {
+        match mutex.try_lock() {
+            Ok(guard) => return Some(guard),
+            Err(TryLockError::Poisoned(poisoned)) => return Some(poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) if started.elapsed() < FLUSH_WAIT => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(TryLockError::WouldBlock) => return None,
+        }
+    }
+}
+
+fn flush_all() {
+    let Some(flushes) = try_lock_for_panic(&FLUSHES) else {
+        error!("Could not flush output after the panic");
+        return;
+    };
+    for flush in flushes.iter() {
+        match flush() {
+            Ok(()) => info!("Flushed buffered output after the panic"),
+            Err(e) => error!("Failed to flush output after the panic: {:#}", e),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,173 @@
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    os::unix::io::{AsRawFd, FromRawFd},
+    path::{Path, PathBuf},
+    process,
+};
+
+/// A daemon that has left the terminal but not yet told the command that
+/// started it whether it is up.
+pub struct Detached {
+    pipe: File,
+}
+
+impl Detached {
+    /// Lets the command that started the daemon exit successfully, and
+    /// moves stdin, stdout and stderr off the terminal. Until then errors
+    /// still reach the terminal.
+    pub fn ready(mut self) -> Result<()> {
+        let null = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")
+            .context("Failed to open /dev/null")?;
+        for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+            // SAFETY: both descriptors are open for the duration of the call.
+            if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+                return Err(io::Error::last_os_error())
+                    .context("Failed to detach from the terminal");
+            }
+        }
+        self.pipe
+            .write_all(b"\n")
+            .context("Failed to report that the daemon started")
+    }
+}
+
+/// Runs the rest of the process as a daemon in a session of its own, with
+/// no controlling terminal. The command that started it exits once the
+/// daemon calls [`Detached::ready`], with status 1 if it exits first.
+///
+/// Must be called before any thread is started, since only the calling
+/// thread survives the fork.
+pub fn detach() -> Result<Detached> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two descriptors.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error()).context("Failed to create a pipe to the daemon");
+    }
+    // SAFETY: the descriptors were just created and are owned by nothing else.
+    let (mut reader, writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    if fork().context("Failed to start the daemon")?.is_some() {
+        drop(writer);
+        let mut byte = [0];
+        let started = matches!(reader.read(&mut byte), Ok(1));
+        process::exit(if started { 0 } else { 1 });
+    }
+    drop(reader);
+    // SAFETY: takes no arguments; a forked child is never a process group
+    // leader, so it gets a session of its own.
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error()).context("Failed to start a new session");
+    }
+    // A second fork leaves the daemon outside the session leader, so it can
+    // never acquire a controlling terminal again.
+    if fork().context("Failed to start the daemon")?.is_some() {
+        // SAFETY: exits the intermediate process without running anything
+        // that the daemon also owns.
+        unsafe { libc::_exit(0) };
+    }
+    info!("Running as a daemon with PID {}", process::id());
+    Ok(Detached { pipe: writer })
+}
+
+/// Forks, returning the child's PID in the parent and `None` in the child.
+fn fork() -> io::Result<Option<libc::pid_t>> {
+    // SAFETY: no other thread exists yet, see `detach`.
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(None),
+        pid => Ok(Some(pid)),
+    }
+}
+
+/// The file `--pid-file` writes this process's PID to, removed again when
+/// the run ends.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes the PID file, unless it names another process that is still
+    /// running. A file left behind by a process that has exited is
+    /// replaced.
+    pub fn create(path: &Path) -> Result<Self> {
+        if let Some(pid) = read_pid(path) {
+            if pid != process::id() && is_running(pid) {
+                bail!(
+                    "chronologger is already running with PID {} from {}",
+                    pid,
+                    path.display()
+                );
+            }
+            warn!("Replacing stale PID file {}", path.display());
+        }
+        fs::write(path, format!("{}\n", process::id()))
+            .with_context(|| format!("Failed to write PID file {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove PID file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+fn read_pid(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Whether a process with this PID exists, including one owned by another
+/// user.
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: signal 0 only checks that the process exists.
+    let found = unsafe { libc::kill(pid, 0) } == 0;
+    found || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_file_written_and_removed() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("chronologger.pid");
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", process::id())
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_pid_file_of_running_process() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("chronologger.pid");
+        // init is always running.
+        fs::write(&path, "1\n").unwrap();
+        let error = PidFile::create(&path).unwrap_err();
+        assert!(error.to_string().contains("already running with PID 1"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1\n");
+
+        // Beyond the largest PID Linux and macOS hand out.
+        fs::write(&path, "2147483646\n").unwrap();
+        let _pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(read_pid(&path), Some(process::id()));
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{numfmt, ProcessSample, Subject};
+use chrono::{DateTime, Local};
+use std::collections::HashMap;
+
+/// How many rows `--dedup` held back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Rows the full-table ticks sampled.
+    pub sampled: u64,
+    /// Rows left out because they repeated the process's last written row.
+    pub suppressed: u64,
+}
+
+impl Stats {
+    /// The share of sampled rows that were suppressed.
+    pub fn ratio(&self) -> f64 {
+        match self.sampled {
+            0 => 0.0,
+            sampled => self.suppressed as f64 / sampled as f64,
+        }
+    }
+}
+
+/// What to write for one tick.
+pub struct Filtered<'a> {
+    /// The last rows of the processes that exited since the previous tick,
+    /// and when they were sampled.
+    pub exited: Option<(DateTime<Local>, &'a [ProcessSample])>,
+    pub rows: &'a [ProcessSample],
+}
+
+struct Entry {
+    /// The process's last written row.
+    row: ProcessSample,
+    /// Ticks since `row` was written.
+    since_written: u64,
+    /// Whether the process's row was held back on the tick it was last seen.
+    held: bool,
+    /// The last tick the process was seen on.
+    seen: u64,
+}
+
+/// Leaves a process's row out of the output while it repeats the last one
+/// written for the process, as the output would show it. A row is still
+/// written every `max_gap` ticks, and the last held-back row of a process is
+/// written, at the time it was sampled, as soon as the process is gone.
+pub struct Dedup {
+    max_gap: u64,
+    entries: HashMap<Subject, Entry>,
+    tick: u64,
+    /// When the previous tick was sampled.
+    last: Option<DateTime<Local>>,
+    rows: Vec<ProcessSample>,
+    exited: Vec<ProcessSample>,
+    scratch: (String, String),
+    stats: Stats,
+}
+
+impl Dedup {
+    pub fn new(max_gap: u64) -> Self {
+        Self {
+            max_gap,
+            entries: HashMap::new(),
+            tick: 0,
+            last: None,
+            rows: Vec::new(),
+            exited: Vec::new(),
+            scratch: (String::new(), String::new()),
+            stats: Stats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Filters the tick sampled at `now`.
+    pub fn filter(&mut self, now: &DateTime<Local>, samples: &[ProcessSample]) -> Filtered<'_> {
+        self.tick += 1;
+        self.rows.clear();
+        for sample in samples {
+            self.stats.sampled += 1;
+            let subject = sample.subject();
+            match self.entries.get_mut(&subject) {
+                Some(entry) => {
+                    entry.since_written += 1;
+                    entry.seen = self.tick;
+                    if entry.since_written < self.max_gap
+                        && same_row(&mut self.scratch, &entry.row, sample)
+                    {
+                        entry.held = true;
+                        self.stats.suppressed += 1;
+                        continue;
+                    }
+                    entry.row.clone_from(sample);
+                    entry.since_written = 0;
+                    entry.held = false;
+                }
+                None => {
+                    self.entries.insert(
+                        subject,
+                        Entry {
+                            row: sample.clone(),
+                            since_written: 0,
+                            held: false,
+                            seen: self.tick,
+                        },
+                    );
+                }
+            }
+            self.rows.push(sample.clone());
+        }
+
+        self.exited.clear();
+        let tick = self.tick;
+        let exited = &mut self.exited;
+        self.entries.retain(|_, entry| {
+            if entry.seen == tick {
+                return true;
+            }
+            if entry.held {
+                exited.push(entry.row.clone());
+            }
+            false
+        });
+        self.exited.sort_by_key(ProcessSample::subject);
+        let last = self.last.replace(*now);
+        let exited = match last {
+            Some(last) if !self.exited.is_empty() => Some((last, self.exited.as_slice())),
+            _ => None,
+        };
+        Filtered {
+            exited,
+            rows: &self.rows,
+        }
+    }
+
+    /// Forgets every process's last written row, so that the next tick
+    /// writes a row for each, e.g. at the start of a new output file.
+    pub fn restart(&mut self) {
+        self.entries.clear();
+    }
+
+    /// The rows held back on the last tick, with when it was sampled, so the
+    /// output shows every process up to the end of the run.
+    pub fn flush(&mut self) -> Option<(DateTime<Local>, Vec<ProcessSample>)> {
+        let last = self.last?;
+        let mut rows: Vec<ProcessSample> = self
+            .entries
+            .values_mut()
+            .filter(|entry| entry.held && entry.seen == self.tick)
+            .map(|entry| {
+                entry.held = false;
+                entry.row.clone()
+            })
+            .collect();
+        rows.sort_by_key(ProcessSample::subject);
+        (!rows.is_empty()).then_some((last, rows))
+    }
+}
+
+/// Whether `a` and `b` are written the same apart from their timestamp.
+fn same_row(scratch: &mut (String, String), a: &ProcessSample, b: &ProcessSample) -> bool {
+    a.name == b.name
+        && a.extras == b.extras
+        && same_fixed(scratch, a.cpu_usage as f64, b.cpu_usage as f64)
+        && same_fixed(scratch, a.memory_usage, b.memory_usage)
+}
+
+fn same_fixed(scratch: &mut (String, String), a: f64, b: f64) -> bool {
+    if a == b {
+        return true;
+    }
+    scratch.0.clear();
+    scratch.1.clear();
+    numfmt::write_fixed(&mut scratch.0, a, numfmt::PERCENT_PRECISION);
+    numfmt::write_fixed(&mut scratch.1, b, numfmt::PERCENT_PRECISION);
+    scratch.0 == scratch.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+    use chrono::TimeZone;
+    use sysinfo::PidExt;
+
+    fn at(second: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 1, 1, 0, 0, second).unwrap()
+    }
+
+    fn pids(rows: &[ProcessSample]) -> Vec<u32> {
+        rows.iter().map(|row| row.pid.unwrap().as_u32()).collect()
+    }
+
+    #[test]
+    fn test_gap_capped_suppression() {
+        let mut dedup = Dedup::new(3);
+        let mut written = Vec::new();
+        for second in 0..7 {
+            // Differences below the written precision do not count.
+            let cpu = 5.0 + second as f32 * 0.0001;
+            let Filtered { exited, rows } =
+                dedup.filter(&at(second), &[sample(1, "worker-1", cpu, 1.0)]);
+            assert!(exited.is_none());
+            written.push(rows.len());
+        }
+        assert_eq!(written, [1, 0, 0, 1, 0, 0, 1]);
+        assert_eq!(
+            dedup.stats(),
+            Stats {
+                sampled: 7,
+                suppressed: 4
+            }
+        );
+        assert!((dedup.stats().ratio() - 4.0 / 7.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_changes_and_exits_are_written() {
+        let mut dedup = Dedup::new(60);
+        let Filtered { rows, .. } = dedup.filter(
+            &at(0),
+            &[
+                sample(1, "worker-1", 5.0, 1.0),
+                sample(2, "worker-2", 7.0, 1.0),
+            ],
+        );
+        assert_eq!(pids(rows), [1, 2]);
+        let Filtered { rows, .. } = dedup.filter(
+            &at(1),
+            &[
+                sample(1, "worker-1", 5.0, 1.0),
+                sample(2, "worker-2", 7.0, 1.0),
+            ],
+        );
+        assert!(rows.is_empty());
+
+        // A changed value is written right away, the unchanged one is not.
+        let Filtered { exited, rows } = dedup.filter(
+            &at(2),
+            &[
+                sample(1, "worker-1", 5.0, 1.0),
+                sample(2, "worker-2", 9.5, 1.0),
+            ],
+        );
+        assert!(exited.is_none());
+        assert_eq!(pids(rows), [2]);
+        assert_eq!(rows[0].cpu_usage, 9.5);
+
+        // Process 1 exits: its held-back row is written at the time it was
+        // last seen, ahead of the new tick.
+        let Filtered { exited, rows } = dedup.filter(
+            &at(3),
+            &[
+                sample(2, "worker-2", 12.0, 1.0),
+                sample(3, "worker-3", 1.0, 1.0),
+            ],
+        );
+        let (when, exited) = exited.unwrap();
+        assert_eq!(when, at(2));
+        assert_eq!(pids(exited), [1]);
+        assert_eq!(pids(rows), [2, 3]);
+
+        // Process 2 exits right after a written row: nothing is held back.
+        let Filtered { exited, .. } = dedup.filter(&at(4), &[sample(3, "worker-3", 1.0, 1.0)]);
+        assert!(exited.is_none());
+
+        let (when, held) = dedup.flush().unwrap();
+        assert_eq!(when, at(4));
+        assert_eq!(pids(&held), [3]);
+        assert!(dedup.flush().is_none());
+    }
+}
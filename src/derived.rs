@@ -0,0 +1,223 @@
+//! The derived columns of `--derived`, computed from consecutive samples of
+//! the same process: its CPU time since it was first sampled, the CPU time
+//! of the tick and how fast its memory grew.
+
+use crate::per_pid::PerPid;
+use std::time::Instant;
+
+/// What a process used at one tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Usage {
+    /// When the process started, in source-specific units, 0 if unknown,
+    /// which tells it apart from a later process given its PID.
+    pub start_time: u64,
+    /// CPU usage in percent of one CPU over the tick.
+    pub cpu_usage: f32,
+    /// CPU time in milliseconds since the process started, if the source
+    /// reads it. Without it, the CPU time of a tick is worked out from the
+    /// CPU usage and the length of the tick.
+    pub cpu_time: Option<u64>,
+    /// Resident memory in KB.
+    pub memory: u64,
+}
+
+/// The derived values of one process at one tick, all `None` on the first
+/// tick the process is sampled, which has nothing to compare with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Values {
+    /// CPU time in milliseconds since the process was first sampled.
+    pub cpu_time: Option<u64>,
+    /// CPU time in milliseconds during the tick.
+    pub cpu_delta: Option<u64>,
+    /// Change of resident memory since the previous tick in bytes per
+    /// second.
+    pub memory_growth: Option<i64>,
+}
+
+struct State {
+    start_time: u64,
+    /// CPU time in milliseconds, unrounded so that short ticks add up.
+    cpu_time: f64,
+    /// The CPU time the source reported for the process.
+    total_cpu_time: Option<u64>,
+    memory: u64,
+}
+
+/// Keeps the state of each process between ticks. A PID is forgotten once a
+/// tick goes by without it, like the cgroups of the throttling columns.
+pub struct Derived {
+    processes: PerPid<State>,
+    /// When the current and the previous tick started.
+    now: Option<Instant>,
+    previous: Option<Instant>,
+}
+
+impl Derived {
+    pub fn new() -> Self {
+        Self {
+            processes: PerPid::new(),
+            now: None,
+            previous: None,
+        }
+    }
+
+    /// Starts a tick sampled at `now`, forgetting PIDs the tick that ended
+    /// did not see.
+    pub fn begin_tick(&mut self, now: Instant) {
+        self.processes.begin_tick();
+        self.previous = self.now.replace(now);
+    }
+
+    /// Forgets every process and the previous tick, e.g. after a suspend,
+    /// which the monotonic clock the rates are computed with does not see.
+    /// Each process then starts over as if sampled for the first time.
+    pub fn reset(&mut self) {
+        self.processes.clear();
+        self.now = None;
+        self.previous = None;
+    }
+
+    /// The derived values of `pid`, which used `usage` in this tick.
+    pub fn read(&mut self, pid: u32, usage: Usage) -> Values {
+        let previous = self
+            .processes
+            .take(pid)
+            .filter(|state| state.start_time == usage.start_time);
+        let elapsed = match (self.now, self.previous) {
+            (Some(now), Some(previous)) => now.duration_since(previous).as_secs_f64(),
+            _ => 0.0,
+        };
+        let cpu_delta = match (usage.cpu_time, previous.as_ref()) {
+            (
+                Some(now),
+                Some(State {
+                    total_cpu_time: Some(before),
+                    ..
+                }),
+            ) => now.saturating_sub(*before) as f64,
+            _ => f64::from(usage.cpu_usage) / 100.0 * elapsed * 1000.0,
+        };
+        // The first sample is the baseline the CPU time is counted from.
+        let cpu_time = previous
+            .as_ref()
+            .map_or(0.0, |state| state.cpu_time + cpu_delta);
+        self.processes.insert(
+            pid,
+            State {
+                start_time: usage.start_time,
+                cpu_time,
+                total_cpu_time: usage.cpu_time,
+                memory: usage.memory,
+            },
+        );
+        match previous {
+            Some(state) if elapsed > 0.0 => {
+                let growth = (usage.memory as f64 - state.memory as f64) * 1024.0 / elapsed;
+                Values {
+                    cpu_time: Some(cpu_time.round() as u64),
+                    cpu_delta: Some(cpu_delta.round() as u64),
+                    memory_growth: Some(growth.round() as i64),
+                }
+            }
+            _ => Values::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn usage(start_time: u64, cpu_usage: f32, memory: u64) -> Usage {
+        Usage {
+            start_time,
+            cpu_usage,
+            cpu_time: None,
+            memory,
+        }
+    }
+
+    #[test]
+    fn test_deltas_between_ticks() {
+        let mut derived = Derived::new();
+        let start = Instant::now();
+        derived.begin_tick(start);
+        let first = derived.read(10, usage(5, 0.0, 1000));
+        assert_eq!(first, Values::default());
+
+        derived.begin_tick(start + Duration::from_secs(2));
+        let second = derived.read(10, usage(5, 50.0, 1500));
+        assert_eq!(
+            second,
+            Values {
+                cpu_time: Some(1000),
+                cpu_delta: Some(1000),
+                memory_growth: Some(256_000),
+            }
+        );
+
+        derived.begin_tick(start + Duration::from_secs(3));
+        let third = derived.read(10, usage(5, 200.0, 500));
+        assert_eq!(third.cpu_time, Some(3000));
+        assert_eq!(third.cpu_delta, Some(2000));
+        assert_eq!(third.memory_growth, Some(-1_024_000));
+    }
+
+    #[test]
+    fn test_new_process_starts_over() {
+        let mut derived = Derived::new();
+        let start = Instant::now();
+        derived.begin_tick(start);
+        derived.read(10, usage(5, 0.0, 1000));
+        derived.begin_tick(start + Duration::from_secs(1));
+        derived.read(10, usage(5, 100.0, 1000));
+
+        // The PID now belongs to another process.
+        derived.begin_tick(start + Duration::from_secs(2));
+        let reused = derived.read(10, usage(6, 100.0, 2000));
+        assert_eq!(reused, Values::default());
+        derived.begin_tick(start + Duration::from_secs(3));
+        assert_eq!(derived.read(10, usage(6, 100.0, 2000)).cpu_time, Some(1000));
+
+        // A PID missing for a tick is forgotten.
+        derived.begin_tick(start + Duration::from_secs(4));
+        derived.begin_tick(start + Duration::from_secs(5));
+        assert_eq!(derived.read(10, usage(6, 0.0, 2000)).memory_growth, None);
+    }
+
+    #[test]
+    fn test_cpu_time_from_source() {
+        let mut derived = Derived::new();
+        let start = Instant::now();
+        let usage = |cpu_time| Usage {
+            cpu_time: Some(cpu_time),
+            // The source's usage is ignored once it reports CPU time.
+            ..usage(5, 100.0, 1000)
+        };
+        derived.begin_tick(start);
+        assert_eq!(derived.read(10, usage(60_000)), Values::default());
+        derived.begin_tick(start + Duration::from_secs(1));
+        let second = derived.read(10, usage(60_250));
+        assert_eq!(second.cpu_delta, Some(250));
+        assert_eq!(second.cpu_time, Some(250));
+        derived.begin_tick(start + Duration::from_secs(2));
+        let third = derived.read(10, usage(60_400));
+        assert_eq!(third.cpu_delta, Some(150));
+        assert_eq!(third.cpu_time, Some(400));
+    }
+
+    #[test]
+    fn test_reset_starts_every_process_over() {
+        let mut derived = Derived::new();
+        let start = Instant::now();
+        derived.begin_tick(start);
+        derived.read(10, usage(5, 0.0, 1000));
+        derived.begin_tick(start + Duration::from_secs(1));
+        assert!(derived.read(10, usage(5, 100.0, 1000)).cpu_time.is_some());
+
+        derived.reset();
+        derived.begin_tick(start + Duration::from_secs(2));
+        assert_eq!(derived.read(10, usage(5, 100.0, 9000)), Values::default());
+    }
+}
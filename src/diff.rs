@@ -0,0 +1,319 @@
+//! `chronologger diff`: compares what each process used in two captures,
+//! e.g. before and after a change, and fails on regressions.
+
+use crate::analyze::{self, ProcessStats};
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, io::Write, path::Path};
+
+/// How processes are matched and what counts as a regression.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Names in the first capture to compare under another name, e.g. a
+    /// renamed binary.
+    pub renames: Vec<(String, String)>,
+    /// Percentage points the mean or peak CPU usage of a process may rise
+    /// by.
+    pub cpu_threshold: f64,
+    /// Likewise for memory usage.
+    pub memory_threshold: f64,
+    /// Prints a JSON array instead of a table.
+    pub json: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            renames: Vec::new(),
+            cpu_threshold: 5.0,
+            memory_threshold: 1.0,
+            json: false,
+        }
+    }
+}
+
+/// Parses one `--map OLD=NEW`.
+pub fn parse_rename(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((old, new)) if !old.is_empty() && !new.is_empty() => {
+            Ok((old.to_string(), new.to_string()))
+        }
+        _ => Err("Expected OLD=NEW".to_string()),
+    }
+}
+
+/// Mean and peak usage of every process with one name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Usage {
+    pub samples: usize,
+    pub cpu_mean: f64,
+    pub cpu_peak: f64,
+    pub memory_mean: f64,
+    pub memory_peak: f64,
+}
+
+impl Usage {
+    fn to_json(self) -> Value {
+        json!({
+            "samples": self.samples,
+            "cpu_mean": self.cpu_mean,
+            "cpu_peak": self.cpu_peak,
+            "memory_mean": self.memory_mean,
+            "memory_peak": self.memory_peak,
+        })
+    }
+}
+
+/// How one process name fared between the captures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub name: String,
+    pub before: Option<Usage>,
+    pub after: Option<Usage>,
+    /// Whether usage rose beyond a threshold.
+    pub regressed: bool,
+}
+
+impl Comparison {
+    fn status(&self) -> &'static str {
+        match (self.before, self.after) {
+            (None, _) => "new",
+            (_, None) => "gone",
+            _ if self.regressed => "REGRESSED",
+            _ => "ok",
+        }
+    }
+
+    /// The change of mean CPU usage, for sorting.
+    fn cpu_change(&self) -> f64 {
+        let mean = |usage: Option<Usage>| usage.map_or(0.0, |usage| usage.cpu_mean);
+        mean(self.after) - mean(self.before)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "status": self.status().to_lowercase(),
+            "before": self.before.map(Usage::to_json),
+            "after": self.after.map(Usage::to_json),
+        })
+    }
+}
+
+/// Combines the processes of each name. The mean is over every sample of
+/// the name, so a name run by several processes weighs each sample alike.
+fn by_name(stats: Vec<ProcessStats>, renames: &[(String, String)]) -> BTreeMap<String, Usage> {
+    let mut names: BTreeMap<String, Usage> = BTreeMap::new();
+    for process in stats {
+        let name = renames
+            .iter()
+            .find(|(old, _)| *old == process.name)
+            .map_or(process.name, |(_, new)| new.clone());
+        let usage = names.entry(name).or_insert(Usage {
+            samples: 0,
+            cpu_mean: 0.0,
+            cpu_peak: 0.0,
+            memory_mean: 0.0,
+            memory_peak: 0.0,
+        });
+        let weight = |mean: f64, other: f64| {
+            (mean * usage.samples as f64 + other * process.samples as f64)
+                / (usage.samples + process.samples) as f64
+        };
+        usage.cpu_mean = weight(usage.cpu_mean, process.cpu.mean);
+        usage.memory_mean = weight(usage.memory_mean, process.memory.mean);
+        usage.cpu_peak = usage.cpu_peak.max(process.cpu.max);
+        usage.memory_peak = usage.memory_peak.max(process.memory.max);
+        usage.samples += process.samples;
+    }
+    names
+}
+
+/// Compares the processes of `before` and `after` by name, largest rise in
+/// mean CPU usage first.
+pub fn compare(
+    before: Vec<ProcessStats>,
+    after: Vec<ProcessStats>,
+    options: &Options,
+) -> Vec<Comparison> {
+    let before = by_name(before, &options.renames);
+    let mut after = by_name(after, &[]);
+    let mut pairs: Vec<(String, Option<Usage>, Option<Usage>)> = before
+        .into_iter()
+        .map(|(name, usage)| {
+            let after = after.remove(&name);
+            (name, Some(usage), after)
+        })
+        .collect();
+    pairs.extend(
+        after
+            .into_iter()
+            .map(|(name, usage)| (name, None, Some(usage))),
+    );
+    let mut comparisons: Vec<Comparison> = pairs
+        .into_iter()
+        .map(|(name, before, after)| {
+            let regressed = before.zip(after).is_some_and(|(before, after)| {
+                after.cpu_mean - before.cpu_mean > options.cpu_threshold
+                    || after.cpu_peak - before.cpu_peak > options.cpu_threshold
+                    || after.memory_mean - before.memory_mean > options.memory_threshold
+                    || after.memory_peak - before.memory_peak > options.memory_threshold
+            });
+            Comparison {
+                name,
+                before,
+                after,
+                regressed,
+            }
+        })
+        .collect();
+    comparisons.sort_by(|a, b| {
+        b.cpu_change()
+            .total_cmp(&a.cpu_change())
+            .then(a.name.cmp(&b.name))
+    });
+    comparisons
+}
+
+/// Compares the captures at `before` and `after`, prints the comparison to
+/// `out` and fails if any process regressed.
+pub fn run(before: &Path, after: &Path, options: &Options, mut out: impl Write) -> Result<()> {
+    let comparisons = compare(analyze::analyze(before)?, analyze::analyze(after)?, options);
+    if options.json {
+        let comparisons: Vec<Value> = comparisons.iter().map(Comparison::to_json).collect();
+        serde_json::to_writer_pretty(&mut out, &comparisons)?;
+        writeln!(out)?;
+    } else {
+        writeln!(
+            out,
+            "{:<24} {:>17} {:>17} {:>17} {:>17}  STATUS",
+            "NAME", "CPU MEAN", "CPU PEAK", "MEM MEAN", "MEM PEAK"
+        )?;
+        for comparison in &comparisons {
+            let change = |value: fn(&Usage) -> f64| match (comparison.before, comparison.after) {
+                (Some(before), Some(after)) => {
+                    format!("{:.2} -> {:.2}", value(&before), value(&after))
+                }
+                (Some(before), None) => format!("{:.2} -> -", value(&before)),
+                (None, Some(after)) => format!("- -> {:.2}", value(&after)),
+                (None, None) => "-".to_string(),
+            };
+            writeln!(
+                out,
+                "{:<24} {:>17} {:>17} {:>17} {:>17}  {}",
+                comparison.name,
+                change(|usage| usage.cpu_mean),
+                change(|usage| usage.cpu_peak),
+                change(|usage| usage.memory_mean),
+                change(|usage| usage.memory_peak),
+                comparison.status()
+            )?;
+        }
+    }
+    out.flush()?;
+    let regressions = comparisons
+        .iter()
+        .filter(|comparison| comparison.regressed)
+        .count();
+    if regressions > 0 {
+        bail!(
+            "{} process(es) regressed by more than {} CPU or {} memory percentage points",
+            regressions,
+            options.cpu_threshold,
+            options.memory_threshold
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn capture(rows: &[(u32, &str, f64, f64)]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "Timestamp,PID,Process Name,CPU Usage (%),Memory Usage (%)"
+        )
+        .unwrap();
+        for (pid, name, cpu, memory) in rows {
+            writeln!(
+                file,
+                "2026-01-01T00:00:00+00:00,{},{},{:.2},{:.2}",
+                pid, name, cpu, memory
+            )
+            .unwrap();
+        }
+        file
+    }
+
+    #[test]
+    fn test_compare() {
+        let before = capture(&[
+            (1, "api", 10.0, 2.0),
+            (1, "api", 20.0, 2.0),
+            (2, "old-worker", 5.0, 1.0),
+            (3, "cron", 1.0, 0.5),
+        ]);
+        let after = capture(&[
+            (7, "api", 30.0, 2.5),
+            (8, "api", 10.0, 2.5),
+            (9, "worker", 6.0, 1.0),
+            (10, "sidecar", 2.0, 0.5),
+        ]);
+        let options = Options {
+            renames: vec![("old-worker".to_string(), "worker".to_string())],
+            ..Options::default()
+        };
+        let comparisons = compare(
+            analyze::analyze(before.path()).unwrap(),
+            analyze::analyze(after.path()).unwrap(),
+            &options,
+        );
+        let statuses: Vec<(&str, &str)> = comparisons
+            .iter()
+            .map(|comparison| (comparison.name.as_str(), comparison.status()))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("api", "REGRESSED"),
+                ("sidecar", "new"),
+                ("worker", "ok"),
+                ("cron", "gone")
+            ]
+        );
+        // The mean rose by 5 points, within the threshold, the peak by 10.
+        let api = comparisons[0].after.unwrap();
+        assert_eq!((api.samples, api.cpu_mean, api.cpu_peak), (2, 20.0, 30.0));
+
+        let mut out = Vec::new();
+        let error = run(before.path(), after.path(), &options, &mut out).unwrap_err();
+        assert!(error.to_string().starts_with("1 process(es) regressed"));
+        let printed = String::from_utf8(out).unwrap();
+        assert!(printed.lines().nth(1).unwrap().contains("15.00 -> 20.00"));
+
+        let lenient = Options {
+            cpu_threshold: 10.0,
+            json: true,
+            ..options
+        };
+        let mut out = Vec::new();
+        run(before.path(), after.path(), &lenient, &mut out).unwrap();
+        let printed: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(printed[0]["status"], "ok");
+        assert_eq!(printed[0]["after"]["cpu_peak"], 30.0);
+    }
+
+    #[test]
+    fn test_parse_rename() {
+        assert_eq!(
+            parse_rename("app=app-v2"),
+            Ok(("app".to_string(), "app-v2".to_string()))
+        );
+        assert!(parse_rename("app").is_err());
+        assert!(parse_rename("=app").is_err());
+    }
+}
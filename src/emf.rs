@@ -0,0 +1,149 @@
+use crate::{columns::Extra, ProcessSample};
+use chrono::{DateTime, Local};
+use serde_json::{json, Map, Value};
+use sysinfo::PidExt;
+
+/// CloudWatch rejects directives with more metrics or dimensions than these.
+pub const MAX_METRICS_PER_DIRECTIVE: usize = 100;
+pub const MAX_DIMENSIONS_PER_SET: usize = 30;
+
+const METRICS: [(&str, &str); 2] = [("CPUUsage", "Percent"), ("MemoryUsage", "Percent")];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EmfDimensions {
+    /// Aggregate by process name only.
+    Name,
+    /// Keep one series per process (name and PID).
+    PidName,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmfConfig {
+    pub namespace: String,
+    pub dimensions: EmfDimensions,
+}
+
+/// Builds one Embedded Metric Format document per sample. Each document
+/// declares a single dimension set and two metrics, well within the EMF
+/// limits, so a tick never needs more than one directive per line. Extra
+/// columns are added as properties rather than metrics.
+pub fn documents(
+    config: &EmfConfig,
+    extras: &[Extra],
+    timestamp: &DateTime<Local>,
+    samples: &[ProcessSample],
+) -> Vec<Value> {
+    let dimensions: &[&str] = match config.dimensions {
+        EmfDimensions::Name => &["ProcessName"],
+        EmfDimensions::PidName => &["ProcessName", "PID"],
+    };
+    debug_assert!(dimensions.len() <= MAX_DIMENSIONS_PER_SET);
+    debug_assert!(METRICS.len() <= MAX_METRICS_PER_DIRECTIVE);
+
+    let metrics: Vec<_> = METRICS
+        .iter()
+        .map(|(name, unit)| json!({ "Name": name, "Unit": unit }))
+        .collect();
+
+    samples
+        .iter()
+        .map(|sample| {
+            let mut document = Map::new();
+            document.insert(
+                "_aws".to_string(),
+                json!({
+                    "Timestamp": timestamp.timestamp_millis(),
+                    "CloudWatchMetrics": [{
+                        "Namespace": config.namespace,
+                        "Dimensions": [dimensions],
+                        "Metrics": metrics,
+                    }],
+                }),
+            );
+            document.insert("ProcessName".to_string(), json!(sample.name));
+            if let Some(pid) = sample.pid {
+                document.insert("PID".to_string(), json!(pid.as_u32().to_string()));
+            }
+            document.insert("CPUUsage".to_string(), json!(sample.cpu_usage));
+            document.insert("MemoryUsage".to_string(), json!(sample.memory_usage));
+            for (extra, value) in extras.iter().zip(&sample.extras) {
+                if let Some(value) = value {
+                    document.insert(extra.emf_name().to_string(), value.to_json());
+                }
+            }
+            Value::Object(document)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sysinfo::Pid;
+
+    /// Checks the structural rules of the EMF specification.
+    fn validate(document: &Value) {
+        let aws = &document["_aws"];
+        assert!(aws["Timestamp"].is_i64(), "Timestamp must be an integer");
+        let directives = aws["CloudWatchMetrics"]
+            .as_array()
+            .expect("CloudWatchMetrics must be an array");
+        for directive in directives {
+            let namespace = directive["Namespace"].as_str().expect("Missing Namespace");
+            assert!(!namespace.is_empty() && namespace.len() <= 255);
+
+            for set in directive["Dimensions"].as_array().unwrap() {
+                let set = set.as_array().expect("Dimension set must be an array");
+                assert!(set.len() <= MAX_DIMENSIONS_PER_SET);
+                for dimension in set {
+                    let key = dimension.as_str().unwrap();
+                    assert!(
+                        document[key].is_string(),
+                        "Dimension {} must be a string",
+                        key
+                    );
+                }
+            }
+
+            let metrics = directive["Metrics"].as_array().unwrap();
+            assert!(metrics.len() <= MAX_METRICS_PER_DIRECTIVE);
+            for metric in metrics {
+                let name = metric["Name"].as_str().expect("Missing metric name");
+                assert!(
+                    document[name].is_number(),
+                    "Metric {} must be a number",
+                    name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_documents_are_valid_emf() {
+        let samples = vec![
+            ProcessSample {
+                pid: Some(Pid::from_u32(1)),
+                name: "init".to_string(),
+                cpu_usage: 0.5,
+                memory_usage: 0.1,
+                extras: Vec::new(),
+            },
+            ProcessSample {
+                pid: Some(Pid::from_u32(2)),
+                name: "sshd".to_string(),
+                cpu_usage: 2.0,
+                memory_usage: 0.3,
+                extras: Vec::new(),
+            },
+        ];
+        for dimensions in [EmfDimensions::Name, EmfDimensions::PidName] {
+            let config = EmfConfig {
+                namespace: "Chronologger".to_string(),
+                dimensions,
+            };
+            let documents = documents(&config, &[], &Local::now(), &samples);
+            assert_eq!(documents.len(), 2);
+            documents.iter().for_each(validate);
+        }
+    }
+}
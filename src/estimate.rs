@@ -0,0 +1,146 @@
+use std::{fmt, time::Duration};
+
+/// Projected output size of a run, from one sample serialized in the
+/// active format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Estimate {
+    pub header_bytes: u64,
+    pub full_tick_bytes: u64,
+    pub focus_tick_bytes: u64,
+    pub full_ticks: u64,
+    pub focus_ticks: u64,
+}
+
+impl Estimate {
+    pub fn total_bytes(&self) -> u64 {
+        self.header_bytes
+            + self.full_tick_bytes * self.full_ticks
+            + self.focus_tick_bytes * self.focus_ticks
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "about {} over {} ticks ({} bytes per tick",
+            human_bytes(self.total_bytes()),
+            self.full_ticks,
+            self.full_tick_bytes
+        )?;
+        if self.focus_ticks > 0 {
+            write!(
+                f,
+                ", plus {} focus ticks of {} bytes",
+                self.focus_ticks, self.focus_tick_bytes
+            )?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// How many full-table and focus ticks a run of `duration` takes, following
+/// [`crate::schedule::Schedule`]: ticks fall on multiples of each interval
+/// from the start, and a focus tick due together with a full one is
+/// covered by it.
+pub fn tick_counts(
+    duration: Duration,
+    interval: Duration,
+    focus_interval: Option<Duration>,
+) -> (u64, u64) {
+    let full = multiples_below(duration, interval);
+    let Some(focus) = focus_interval else {
+        return (full, 0);
+    };
+    let (a, b) = (interval.as_nanos(), focus.as_nanos());
+    let shared = match (a, b) {
+        (0, _) | (_, 0) => 0,
+        _ => {
+            let lcm = a / gcd(a, b) * b;
+            multiples_below(
+                duration,
+                Duration::from_nanos(lcm.min(u64::MAX as u128) as u64),
+            )
+        }
+    };
+    (full, multiples_below(duration, focus) - shared)
+}
+
+/// Counts the multiples of `step`, starting at zero, that are less than
+/// `limit`.
+fn multiples_below(limit: Duration, step: Duration) -> u64 {
+    if step.is_zero() {
+        return u64::from(!limit.is_zero());
+    }
+    limit.as_nanos().div_ceil(step.as_nanos()) as u64
+}
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_counts() {
+        let secs = Duration::from_secs;
+        assert_eq!(tick_counts(secs(60), secs(1), None), (60, 0));
+        assert_eq!(tick_counts(secs(61), secs(2), None), (31, 0));
+        // Every tenth focus tick coincides with a full one.
+        assert_eq!(
+            tick_counts(secs(10), secs(1), Some(Duration::from_millis(100))),
+            (10, 90)
+        );
+        // 3s and 2s deadlines meet every 6s: at 0, 6, 12 and 18.
+        assert_eq!(tick_counts(secs(20), secs(3), Some(secs(2))), (7, 6));
+    }
+
+    #[test]
+    fn test_total_bytes() {
+        // A week at one tick a minute, each tick 300 rows of 60 bytes.
+        let (full_ticks, focus_ticks) = tick_counts(
+            Duration::from_secs(7 * 86_400),
+            Duration::from_secs(60),
+            None,
+        );
+        let estimate = Estimate {
+            header_bytes: 58,
+            full_tick_bytes: 300 * 60,
+            focus_tick_bytes: 0,
+            full_ticks,
+            focus_ticks,
+        };
+        assert_eq!(estimate.full_ticks, 10_080);
+        assert_eq!(estimate.total_bytes(), 58 + 10_080 * 18_000);
+        assert!(estimate
+            .to_string()
+            .starts_with("about 173.0 MB over 10080 ticks"));
+    }
+
+    #[test]
+    fn test_human_bytes() {
+        assert_eq!(human_bytes(512), "512 B");
+        assert_eq!(human_bytes(1536), "1.5 KB");
+        assert_eq!(human_bytes(3 << 30), "3.0 GB");
+    }
+}
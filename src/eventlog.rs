@@ -0,0 +1,178 @@
+use crate::{alerts::Alert, sink::Sink, ProcessSample};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use serde_json::json;
+use std::{ffi::OsStr, iter, os::windows::ffi::OsStrExt, ptr};
+use sysinfo::{Pid, PidExt};
+use windows_sys::Win32::{
+    Foundation::HANDLE,
+    System::EventLog::{
+        DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_INFORMATION_TYPE,
+        EVENTLOG_WARNING_TYPE, REPORT_EVENT_TYPE,
+    },
+};
+
+const SOURCE_NAME: &str = "Chronologger";
+const SAMPLE_EVENT_ID: u32 = 1000;
+const ALERT_EVENT_ID: u32 = 1001;
+
+/// Writes one informational event per tick to the Windows Application log
+/// containing the heaviest processes as a compact JSON array, and a warning
+/// event for every alert raised.
+pub struct EventLogSink {
+    handle: HANDLE,
+    top: usize,
+    run_id: Option<String>,
+}
+
+// The event source handle is only used from the thread owning the sink.
+unsafe impl Send for EventLogSink {}
+
+impl EventLogSink {
+    pub fn new(top: usize, run_id: Option<String>) -> Result<Self> {
+        let source = wide(SOURCE_NAME);
+        // SAFETY: `source` is a NUL-terminated UTF-16 string that outlives the call.
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), source.as_ptr()) };
+        if handle == 0 {
+            bail!(
+                "Failed to register event source '{}': {}",
+                SOURCE_NAME,
+                std::io::Error::last_os_error()
+            );
+        }
+        info!(
+            "Writing events to the Windows Event Log as '{}'",
+            SOURCE_NAME
+        );
+        Ok(Self {
+            handle,
+            top,
+            run_id,
+        })
+    }
+
+    fn report(&self, kind: REPORT_EVENT_TYPE, event_id: u32, message: &str) -> Result<()> {
+        let message = wide(message);
+        let strings = [message.as_ptr()];
+        // SAFETY: `strings` holds one valid NUL-terminated UTF-16 pointer that
+        // outlives the call, and no raw data is passed.
+        let ok = unsafe {
+            ReportEventW(
+                self.handle,
+                kind,
+                0,
+                event_id,
+                ptr::null_mut(),
+                strings.len() as u16,
+                0,
+                strings.as_ptr(),
+                ptr::null(),
+            )
+        };
+        if ok == 0 {
+            bail!("Failed to write event: {}", std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Sink for EventLogSink {
+    fn publish(&mut self, timestamp: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        let mut heaviest: Vec<&ProcessSample> = samples.iter().collect();
+        heaviest.sort_by(|a, b| b.cpu_usage.total_cmp(&a.cpu_usage));
+        heaviest.truncate(self.top);
+
+        let entries: Vec<_> = heaviest
+            .iter()
+            .map(|sample| {
+                json!({
+                    "pid": sample.pid.map(Pid::as_u32),
+                    "name": sample.name,
+                    "cpu": (sample.cpu_usage as f64 * 100.0).round() / 100.0,
+                    "mem": (sample.memory_usage * 100.0).round() / 100.0,
+                })
+            })
+            .collect();
+        let mut message = json!({ "timestamp": timestamp.to_rfc3339(), "top": entries });
+        if let Some(run_id) = &self.run_id {
+            message["run_id"] = json!(run_id);
+        }
+
+        if let Err(e) = self.report(
+            EVENTLOG_INFORMATION_TYPE,
+            SAMPLE_EVENT_ID,
+            &message.to_string(),
+        ) {
+            warn!("{:#}", e);
+        }
+        Ok(())
+    }
+
+    fn alert(&mut self, alert: &Alert) -> Result<()> {
+        let mut message = alert.to_json();
+        if let Some(run_id) = &self.run_id {
+            message["run_id"] = json!(run_id);
+        }
+        if let Err(e) = self.report(EVENTLOG_WARNING_TYPE, ALERT_EVENT_ID, &message.to_string()) {
+            warn!("{:#}", e);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EventLogSink {
+    fn drop(&mut self) {
+        // SAFETY: `handle` was returned by RegisterEventSourceW and is closed once.
+        unsafe { DeregisterEventSource(self.handle) };
+    }
+}
+
+fn wide(value: &str) -> Vec<u16> {
+    OsStr::new(value)
+        .encode_wide()
+        .chain(iter::once(0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::Metric;
+
+    #[test]
+    #[ignore = "requires an elevated Windows session"]
+    fn test_write_events() {
+        let mut sink = EventLogSink::new(5, None).expect("Failed to register event source");
+        let samples = vec![ProcessSample {
+            pid: Some(Pid::from_u32(4)),
+            name: "System".to_string(),
+            cpu_usage: 1.0,
+            memory_usage: 0.1,
+            extras: Vec::new(),
+        }];
+        sink.report(
+            EVENTLOG_INFORMATION_TYPE,
+            SAMPLE_EVENT_ID,
+            "chronologger test event",
+        )
+        .expect("Failed to write informational event");
+        sink.publish(&Local::now(), &samples)
+            .expect("Failed to publish samples");
+        sink.report(
+            EVENTLOG_WARNING_TYPE,
+            ALERT_EVENT_ID,
+            "chronologger test alert",
+        )
+        .expect("Failed to write warning event");
+        sink.alert(&Alert {
+            timestamp: Local::now().to_rfc3339(),
+            pid: Some(4),
+            name: "System".to_string(),
+            metric: Metric::Cpu,
+            value: 95.0,
+            threshold: 90.0,
+        })
+        .expect("Failed to report alert");
+    }
+}
@@ -0,0 +1,41 @@
+//! The `--output`s after the first: each writes every full-table tick in a
+//! format of its own, fed like a sink, so a failing one does not keep the
+//! others from being written.
+
+use crate::{sink::Sink, Output, ProcessSample, RowBuffers};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+
+pub struct OutputSink {
+    path: String,
+    output: Output,
+    buffers: RowBuffers,
+}
+
+impl OutputSink {
+    /// Writes to `output`, whose header must already have been written.
+    pub fn new(path: &str, output: Output) -> Self {
+        Self {
+            path: path.to_string(),
+            output,
+            buffers: RowBuffers::default(),
+        }
+    }
+}
+
+impl Sink for OutputSink {
+    fn publish(&mut self, timestamp: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        self.output
+            .write(&mut self.buffers, timestamp, samples, None)
+            .with_context(|| format!("Failed to write to {}", self.path))
+    }
+
+    /// Seals the output. CSV, JSON Lines and EMF files end with their
+    /// integrity footer; the other formats have nowhere to keep theirs.
+    fn finish(&mut self) -> Result<()> {
+        self.output
+            .seal()
+            .with_context(|| format!("Failed to finish {}", self.path))?;
+        Ok(())
+    }
+}
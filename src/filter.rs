@@ -0,0 +1,309 @@
+use anyhow::{Context, Result};
+use log::info;
+use regex::{Regex, RegexBuilder};
+use sysinfo::Pid;
+
+/// Prefix of a `--name` or `--exclude` value that is a regex.
+const REGEX_PREFIX: &str = "re:";
+
+/// How a `--name` or `--exclude` value matches process names: a regex after
+/// `re:`, a glob if it has `*`, `?` or `[`, or else a substring, or the whole
+/// name if `exact`.
+#[derive(Debug, Clone)]
+enum NamePattern {
+    /// Lowercased unless `exact`.
+    Text(String),
+    Regex(Regex),
+}
+
+impl NamePattern {
+    fn new(pattern: &str, exact: bool) -> Result<Self> {
+        let regex = match pattern.strip_prefix(REGEX_PREFIX) {
+            Some(regex) => regex.to_string(),
+            None if pattern.contains(['*', '?', '[']) => glob_to_regex(pattern),
+            None if exact => return Ok(NamePattern::Text(pattern.to_string())),
+            None => return Ok(NamePattern::Text(pattern.to_lowercase())),
+        };
+        RegexBuilder::new(&regex)
+            .case_insensitive(!exact)
+            .build()
+            .map(NamePattern::Regex)
+            .with_context(|| format!("Invalid name pattern {:?}", pattern))
+    }
+
+    /// Whether `name`, or `lowered` for a substring, matches.
+    fn matches(&self, name: &str, lowered: &str, exact: bool) -> bool {
+        match self {
+            NamePattern::Text(text) if exact => name == text,
+            NamePattern::Text(text) => lowered.contains(text.as_str()),
+            NamePattern::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// An anchored regex matching what the glob `pattern` does: `*` any text,
+/// `?` any character and `[...]` or `[!...]` a set of characters.
+fn glob_to_regex(pattern: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                let rest: String = chars.clone().collect();
+                let Some(end) = rest.find(']') else {
+                    // An unclosed `[` is literal, as in the shell.
+                    regex.push_str("\\[");
+                    continue;
+                };
+                let class = &rest[..end];
+                regex.push('[');
+                let class = match class.strip_prefix('!') {
+                    Some(negated) => {
+                        regex.push('^');
+                        negated
+                    }
+                    None => class,
+                };
+                for c in class.chars() {
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+                for _ in rest[..=end].chars() {
+                    chars.next();
+                }
+            }
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Checks a `--name` or `--exclude` value on the command line.
+pub fn parse_pattern(value: &str) -> Result<String, String> {
+    NamePattern::new(value, false)
+        .map(|_| value.to_string())
+        .map_err(|e| format!("{:#}", e))
+}
+
+/// The user ID of `user`, a user name or a numeric ID.
+#[cfg(unix)]
+pub fn resolve_user(user: &str) -> Result<u32> {
+    use sysinfo::{SystemExt, UserExt};
+
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+    let mut system = sysinfo::System::new();
+    system.refresh_users_list();
+    system
+        .users()
+        .iter()
+        .find(|candidate| candidate.name() == user)
+        .map(|candidate| *candidate.uid())
+        .with_context(|| format!("Unknown user {:?}", user))
+}
+
+/// Which processes `--pid`, `--name`, `--user` and `--exclude` restrict
+/// logging to. A process is logged if it is one of the PIDs, or matches one
+/// of the names and belongs to one of the users, where either is given, and
+/// its name matches none of the exclusions.
+#[derive(Debug, Clone)]
+pub struct ProcessFilter {
+    pids: Vec<Pid>,
+    names: Vec<NamePattern>,
+    users: Vec<u32>,
+    excludes: Vec<NamePattern>,
+    exact: bool,
+    /// Whether each of `pids` was running on the previous tick.
+    alive: Vec<bool>,
+    /// Whether each of `pids` has been seen on the current tick.
+    seen: Vec<bool>,
+    /// Lowercased process name.
+    lowered: String,
+}
+
+impl ProcessFilter {
+    /// Matches any of `pids` and any process whose name matches one of
+    /// `names`, ignoring case unless `exact`.
+    pub fn new(pids: Vec<Pid>, names: Vec<String>, exact: bool) -> Result<Self> {
+        let names = names
+            .iter()
+            .map(|name| NamePattern::new(name, exact))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            alive: vec![false; pids.len()],
+            seen: vec![false; pids.len()],
+            pids,
+            names,
+            users: Vec::new(),
+            excludes: Vec::new(),
+            exact,
+            lowered: String::new(),
+        })
+    }
+
+    /// Also matches the processes of `users`, narrowed to `names` if any.
+    pub fn with_users(mut self, users: Vec<u32>) -> Self {
+        self.users = users;
+        self
+    }
+
+    /// Leaves out processes whose name matches one of `excludes`.
+    pub fn with_excludes(mut self, excludes: Vec<String>) -> Result<Self> {
+        self.excludes = excludes
+            .iter()
+            .map(|exclude| NamePattern::new(exclude, self.exact))
+            .collect::<Result<_>>()?;
+        Ok(self)
+    }
+
+    pub fn pids(&self) -> &[Pid] {
+        &self.pids
+    }
+
+    /// Whether only explicit PIDs are logged, so that the source can refresh
+    /// just those.
+    pub fn is_pids_only(&self) -> bool {
+        self.names.is_empty() && self.users.is_empty() && self.excludes.is_empty()
+    }
+
+    /// Whether the process, owned by `uid` if known, is logged. Watched PIDs
+    /// are noted as running. A group replayed from a capture has no PID, so
+    /// only its name can match.
+    pub fn matches(&mut self, pid: Option<Pid>, name: &str, uid: Option<u32>) -> bool {
+        self.lowered.clear();
+        if !self.exact {
+            self.lowered
+                .extend(name.chars().flat_map(char::to_lowercase));
+        }
+        let (lowered, exact) = (self.lowered.as_str(), self.exact);
+        if self
+            .excludes
+            .iter()
+            .any(|exclude| exclude.matches(name, lowered, exact))
+        {
+            return false;
+        }
+        if let Some(index) = self.pids.iter().position(|watched| Some(*watched) == pid) {
+            self.seen[index] = true;
+            return true;
+        }
+        if self.names.is_empty() && self.users.is_empty() {
+            // Only exclusions, or only PIDs.
+            return self.pids.is_empty();
+        }
+        (self.users.is_empty() || uid.is_some_and(|uid| self.users.contains(&uid)))
+            && (self.names.is_empty()
+                || self
+                    .names
+                    .iter()
+                    .any(|target| target.matches(name, lowered, exact)))
+    }
+
+    /// Ends a tick, logging the watched PIDs that were running on the
+    /// previous tick but not on this one.
+    pub fn end_tick(&mut self) {
+        for (index, pid) in self.pids.iter().enumerate() {
+            if self.alive[index] && !self.seen[index] {
+                info!("Watched process {} exited", pid);
+            }
+        }
+        std::mem::swap(&mut self.alive, &mut self.seen);
+        self.seen.fill(false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sysinfo::PidExt;
+
+    fn names(names: &[&str], exact: bool) -> ProcessFilter {
+        let names = names.iter().map(|name| name.to_string()).collect();
+        ProcessFilter::new(Vec::new(), names, exact).unwrap()
+    }
+
+    #[test]
+    fn test_name_substring_ignores_case() {
+        let mut filter = names(&["Postgres"], false);
+        assert!(filter.matches(Some(Pid::from_u32(10)), "postgres", None));
+        assert!(filter.matches(Some(Pid::from_u32(11)), "POSTGRES: checkpointer", None));
+        assert!(filter.matches(Some(Pid::from_u32(12)), "my-postgres-exporter", None));
+        assert!(!filter.matches(Some(Pid::from_u32(13)), "postmaster", None));
+        assert!(!filter.is_pids_only());
+    }
+
+    #[test]
+    fn test_exact_name() {
+        let mut filter = names(&["nginx"], true);
+        assert!(filter.matches(Some(Pid::from_u32(10)), "nginx", None));
+        assert!(!filter.matches(Some(Pid::from_u32(11)), "Nginx", None));
+        assert!(!filter.matches(Some(Pid::from_u32(12)), "nginx: worker process", None));
+    }
+
+    #[test]
+    fn test_glob_and_regex_names() {
+        let mut filter = names(&["python3.*", "re:^kworker/\\d+:", "[!a-z]*d"], false);
+        assert!(filter.matches(Some(Pid::from_u32(1)), "Python3.12", None));
+        assert!(!filter.matches(Some(Pid::from_u32(2)), "python3", None));
+        assert!(filter.matches(Some(Pid::from_u32(3)), "kworker/0:1-events", None));
+        assert!(!filter.matches(Some(Pid::from_u32(4)), "my-kworker/0:1", None));
+        assert!(filter.matches(Some(Pid::from_u32(5)), "9d", None));
+        assert!(!filter.matches(Some(Pid::from_u32(6)), "sshd", None));
+        // Regex metacharacters in a glob are literal.
+        assert!(!names(&["a+b*"], false).matches(Some(Pid::from_u32(7)), "aab", None));
+
+        assert!(parse_pattern("re:(unclosed").is_err());
+        assert!(parse_pattern("[z-a]*").is_err());
+        // An unclosed `[` is literal.
+        assert!(names(&["[a*"], false).matches(Some(Pid::from_u32(8)), "[abc", None));
+    }
+
+    #[test]
+    fn test_pid_or_name() {
+        let mut filter = ProcessFilter::new(
+            vec![Pid::from_u32(42)],
+            vec!["redis".to_string(), "memcached".to_string()],
+            false,
+        )
+        .unwrap();
+        assert!(filter.matches(Some(Pid::from_u32(42)), "bash", None));
+        assert!(filter.matches(Some(Pid::from_u32(7)), "redis-server", None));
+        assert!(filter.matches(Some(Pid::from_u32(8)), "memcached", None));
+        assert!(!filter.matches(Some(Pid::from_u32(9)), "bash", None));
+
+        let pids_only = ProcessFilter::new(vec![Pid::from_u32(42)], Vec::new(), false).unwrap();
+        assert!(pids_only.is_pids_only());
+    }
+
+    #[test]
+    fn test_users_and_excludes() {
+        let mut filter = names(&["python"], false).with_users(vec![1000]);
+        assert!(filter.matches(Some(Pid::from_u32(1)), "python3", Some(1000)));
+        assert!(!filter.matches(Some(Pid::from_u32(2)), "python3", Some(0)));
+        assert!(!filter.matches(Some(Pid::from_u32(3)), "python3", None));
+        assert!(!filter.matches(Some(Pid::from_u32(4)), "bash", Some(1000)));
+
+        let mut filter = ProcessFilter::new(vec![Pid::from_u32(5)], Vec::new(), false)
+            .unwrap()
+            .with_excludes(vec!["kworker*".to_string(), "chrome".to_string()])
+            .unwrap();
+        assert!(!filter.is_pids_only());
+        assert!(filter.matches(Some(Pid::from_u32(5)), "bash", None));
+        assert!(!filter.matches(Some(Pid::from_u32(6)), "bash", None));
+
+        let mut filter = ProcessFilter::new(Vec::new(), Vec::new(), false)
+            .unwrap()
+            .with_excludes(vec!["kworker*".to_string(), "chrome".to_string()])
+            .unwrap();
+        assert!(filter.matches(Some(Pid::from_u32(6)), "bash", None));
+        assert!(!filter.matches(Some(Pid::from_u32(7)), "kworker/1:0", None));
+        assert!(!filter.matches(Some(Pid::from_u32(8)), "Google Chrome Helper", None));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::ProcessSample;
+use std::{collections::BTreeMap, fmt};
+use sysinfo::{Pid, PidExt, ProcessExt, ProcessRefreshKind, System, SystemExt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FocusTarget {
+    Pid(Pid),
+    Name(String),
+}
+
+impl FocusTarget {
+    pub fn parse(value: &str) -> Self {
+        match value.parse::<u32>() {
+            Ok(pid) => FocusTarget::Pid(Pid::from_u32(pid)),
+            Err(_) => FocusTarget::Name(value.to_string()),
+        }
+    }
+
+    fn matches(&self, pid: Pid, name: &str) -> bool {
+        match self {
+            FocusTarget::Pid(target) => *target == pid,
+            FocusTarget::Name(target) => target == name,
+        }
+    }
+}
+
+/// Formats the target as it is given to `--focus`.
+impl fmt::Display for FocusTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FocusTarget::Pid(pid) => write!(f, "{}", pid),
+            FocusTarget::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Tracks the focused processes between full-table ticks. Each focused PID
+/// gets its own `System` because sysinfo computes CPU usage against the
+/// global CPU time of the last refresh, which per-PID refreshes would
+/// otherwise keep resetting for each other and for the full table.
+pub struct Focus {
+    targets: Vec<FocusTarget>,
+    systems: BTreeMap<Pid, System>,
+    samples: Vec<ProcessSample>,
+}
+
+impl Focus {
+    pub fn new(targets: Vec<FocusTarget>) -> Self {
+        Self {
+            targets,
+            systems: BTreeMap::new(),
+            samples: Vec::new(),
+        }
+    }
+
+    /// Re-resolves the targets against a fresh full-table tick, so name
+    /// targets pick up new processes and exited ones are dropped.
+    pub fn resolve(&mut self, samples: &[ProcessSample]) {
+        let matching: Vec<Pid> = samples
+            .iter()
+            .filter_map(|sample| {
+                let pid = sample.pid?;
+                self.targets
+                    .iter()
+                    .any(|target| target.matches(pid, &sample.name))
+                    .then_some(pid)
+            })
+            .collect();
+        self.systems.retain(|pid, _| matching.contains(pid));
+        for pid in matching {
+            self.systems.entry(pid).or_insert_with(|| {
+                let mut system = System::new();
+                system.refresh_process_specifics(pid, ProcessRefreshKind::new().with_cpu());
+                system
+            });
+        }
+    }
+
+    /// Refreshes only the focused processes and returns their samples.
+    pub fn sample(&mut self, total_memory: f64) -> &mut [ProcessSample] {
+        let samples = &mut self.samples;
+        samples.clear();
+        self.systems.retain(|pid, system| {
+            if !system.refresh_process_specifics(*pid, ProcessRefreshKind::new().with_cpu()) {
+                return false;
+            }
+            if let Some(process) = system.process(*pid) {
+                samples.push(ProcessSample {
+                    pid: Some(*pid),
+                    name: process.name().to_string(),
+                    cpu_usage: process.cpu_usage(),
+                    memory_usage: process.memory() as f64 / total_memory * 100.0,
+                    extras: Vec::new(),
+                });
+            }
+            true
+        });
+        &mut self.samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            FocusTarget::parse("1234"),
+            FocusTarget::Pid(Pid::from_u32(1234))
+        );
+        assert_eq!(
+            FocusTarget::parse("postgres"),
+            FocusTarget::Name("postgres".to_string())
+        );
+    }
+
+    #[test]
+    fn test_samples_only_focused_process() {
+        let pid = Pid::from_u32(std::process::id());
+        let table: Vec<ProcessSample> = [1, std::process::id()]
+            .into_iter()
+            .map(|pid| ProcessSample {
+                pid: Some(Pid::from_u32(pid)),
+                name: "chronologger".to_string(),
+                cpu_usage: 0.0,
+                memory_usage: 0.0,
+                extras: Vec::new(),
+            })
+            .collect();
+
+        let mut focus = Focus::new(vec![FocusTarget::Pid(pid)]);
+        focus.resolve(&table);
+
+        let samples = focus.sample(1024.0 * 1024.0);
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].pid, Some(pid));
+    }
+}
@@ -0,0 +1,356 @@
+//! The GPU columns: how busy each process keeps the GPUs and how much GPU
+//! memory it holds, from NVIDIA's management library, NVML. The library is
+//! loaded when the run starts rather than linked, so a build with the `gpu`
+//! feature still runs on hosts without it, leaving the columns empty.
+
+use log::warn;
+use std::{
+    collections::HashMap,
+    ffi::{c_char, c_int, c_uint, c_ulonglong, c_void, CStr},
+    mem, ptr,
+};
+
+/// What NVML is loaded from, as the driver installs it.
+const LIBRARY: &CStr = c"libnvidia-ml.so.1";
+
+const NVML_SUCCESS: c_int = 0;
+const NVML_ERROR_INSUFFICIENT_SIZE: c_int = 7;
+
+/// `usedGpuMemory` when the driver cannot tell, as under Windows' WDDM.
+const NOT_AVAILABLE: c_ulonglong = c_ulonglong::MAX;
+
+type Device = *mut c_void;
+
+/// `nvmlProcessInfo_t` of the v3 process queries.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ProcessInfo {
+    pid: c_uint,
+    used_gpu_memory: c_ulonglong,
+    gpu_instance_id: c_uint,
+    compute_instance_id: c_uint,
+}
+
+/// `nvmlProcessUtilizationSample_t`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UtilizationSample {
+    pid: c_uint,
+    /// Microseconds since the Unix epoch.
+    time_stamp: c_ulonglong,
+    /// Percent of the device's streaming multiprocessors in use.
+    sm_util: c_uint,
+    mem_util: c_uint,
+    enc_util: c_uint,
+    dec_util: c_uint,
+}
+
+type Init = unsafe extern "C" fn() -> c_int;
+type GetCount = unsafe extern "C" fn(*mut c_uint) -> c_int;
+type GetHandle = unsafe extern "C" fn(c_uint, *mut Device) -> c_int;
+type GetProcesses = unsafe extern "C" fn(Device, *mut c_uint, *mut ProcessInfo) -> c_int;
+type GetUtilization =
+    unsafe extern "C" fn(Device, *mut UtilizationSample, *mut c_uint, c_ulonglong) -> c_int;
+
+/// The NVML functions the columns need.
+struct Nvml {
+    shutdown: Init,
+    get_compute_processes: GetProcesses,
+    get_graphics_processes: GetProcesses,
+    get_utilization: GetUtilization,
+    devices: Vec<Device>,
+}
+
+// The device handles are opaque and NVML is thread-safe.
+unsafe impl Send for Nvml {}
+
+impl Nvml {
+    /// Loads NVML and finds the GPUs, or tells why it cannot.
+    fn load() -> Result<Self, String> {
+        // SAFETY: the library is never unloaded, so the symbols found in it
+        // stay valid, and each is given the signature NVML documents.
+        unsafe {
+            let library = libc::dlopen(LIBRARY.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if library.is_null() {
+                return Err(dl_error());
+            }
+            let symbol = |name: &CStr| {
+                let symbol = libc::dlsym(library, name.as_ptr());
+                if symbol.is_null() {
+                    Err(format!("NVML has no {}", name.to_string_lossy()))
+                } else {
+                    Ok(symbol)
+                }
+            };
+            let init: Init = mem::transmute(symbol(c"nvmlInit_v2")?);
+            let get_count: GetCount = mem::transmute(symbol(c"nvmlDeviceGetCount_v2")?);
+            let get_handle: GetHandle = mem::transmute(symbol(c"nvmlDeviceGetHandleByIndex_v2")?);
+            let shutdown: Init = mem::transmute(symbol(c"nvmlShutdown")?);
+            let get_compute_processes: GetProcesses =
+                mem::transmute(symbol(c"nvmlDeviceGetComputeRunningProcesses_v3")?);
+            let get_graphics_processes: GetProcesses =
+                mem::transmute(symbol(c"nvmlDeviceGetGraphicsRunningProcesses_v3")?);
+            let get_utilization: GetUtilization =
+                mem::transmute(symbol(c"nvmlDeviceGetProcessUtilization")?);
+            check(init(), "initialize NVML")?;
+            // From here on, dropping it shuts NVML down again.
+            let mut nvml = Self {
+                shutdown,
+                get_compute_processes,
+                get_graphics_processes,
+                get_utilization,
+                devices: Vec::new(),
+            };
+            let mut count = 0;
+            check(get_count(&mut count), "count the GPUs")?;
+            for index in 0..count {
+                let mut device = ptr::null_mut();
+                check(get_handle(index, &mut device), "open a GPU")?;
+                nvml.devices.push(device);
+            }
+            if nvml.devices.is_empty() {
+                return Err("No NVIDIA GPU found".to_string());
+            }
+            Ok(nvml)
+        }
+    }
+
+    /// The processes holding memory on `device`, through `get`.
+    fn processes(&self, device: Device, get: GetProcesses) -> Vec<ProcessInfo> {
+        let mut infos = Vec::new();
+        // The list can grow between asking for its length and reading it.
+        for _ in 0..3 {
+            let mut count = infos.len() as c_uint;
+            // SAFETY: `infos` has room for `count` entries.
+            let status = unsafe { get(device, &mut count, infos.as_mut_ptr()) };
+            match status {
+                NVML_SUCCESS => {
+                    infos.truncate(count as usize);
+                    return infos;
+                }
+                NVML_ERROR_INSUFFICIENT_SIZE => {
+                    let empty = ProcessInfo {
+                        pid: 0,
+                        used_gpu_memory: 0,
+                        gpu_instance_id: 0,
+                        compute_instance_id: 0,
+                    };
+                    infos = vec![empty; count as usize + 4];
+                }
+                _ => break,
+            }
+        }
+        Vec::new()
+    }
+
+    /// The utilization samples of `device` taken after `since`.
+    fn utilization(&self, device: Device, since: c_ulonglong) -> Vec<UtilizationSample> {
+        let mut count = 0;
+        // SAFETY: a null buffer asks for the number of samples.
+        let status = unsafe { (self.get_utilization)(device, ptr::null_mut(), &mut count, since) };
+        // NOT_FOUND means no process used the GPU since `since`.
+        if status != NVML_ERROR_INSUFFICIENT_SIZE {
+            return Vec::new();
+        }
+        let empty = UtilizationSample {
+            pid: 0,
+            time_stamp: 0,
+            sm_util: 0,
+            mem_util: 0,
+            enc_util: 0,
+            dec_util: 0,
+        };
+        let mut samples = vec![empty; count as usize];
+        // SAFETY: `samples` has room for `count` entries.
+        let status =
+            unsafe { (self.get_utilization)(device, samples.as_mut_ptr(), &mut count, since) };
+        match status {
+            NVML_SUCCESS => {
+                samples.truncate(count as usize);
+                samples
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Drop for Nvml {
+    fn drop(&mut self) {
+        // SAFETY: NVML was initialized when it was loaded.
+        unsafe { (self.shutdown)() };
+    }
+}
+
+fn check(status: c_int, action: &str) -> Result<(), String> {
+    match status {
+        NVML_SUCCESS => Ok(()),
+        status => Err(format!("Failed to {}: NVML error {}", action, status)),
+    }
+}
+
+fn dl_error() -> String {
+    // SAFETY: dlerror returns a NUL-terminated message or null.
+    let message = unsafe { libc::dlerror() };
+    if message.is_null() {
+        return "Failed to load NVML".to_string();
+    }
+    let message = unsafe { CStr::from_ptr(message as *const c_char) };
+    message.to_string_lossy().into_owned()
+}
+
+/// What one process used of the GPUs in a tick.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Percent of one GPU's streaming multiprocessors, summed over the
+    /// GPUs, as the CPU column sums cores.
+    pub utilization: Option<u64>,
+    /// GPU memory in bytes, summed over the GPUs.
+    pub memory: Option<u64>,
+}
+
+/// Reads the GPU usage of every process once per tick.
+pub struct Gpu {
+    nvml: Option<Nvml>,
+    /// The newest utilization sample read from each device, in NVML's
+    /// microseconds, so each tick reads only the samples since.
+    last_seen: Vec<c_ulonglong>,
+    usage: HashMap<u32, Usage>,
+}
+
+impl Gpu {
+    /// Loads NVML, warning once when the columns will be empty because it
+    /// cannot be.
+    pub fn new() -> Self {
+        let nvml = match Nvml::load() {
+            Ok(nvml) => Some(nvml),
+            Err(e) => {
+                warn!("The GPU columns will be empty: {}", e);
+                None
+            }
+        };
+        let devices = nvml.as_ref().map_or(0, |nvml| nvml.devices.len());
+        Self {
+            nvml,
+            last_seen: vec![0; devices],
+            usage: HashMap::new(),
+        }
+    }
+
+    /// Reads what every process used of every GPU since the previous tick.
+    pub fn begin_tick(&mut self) {
+        self.usage.clear();
+        let Some(nvml) = &self.nvml else {
+            return;
+        };
+        for (device, last_seen) in nvml.devices.iter().zip(&mut self.last_seen) {
+            let samples = nvml.utilization(*device, *last_seen);
+            *last_seen = samples
+                .iter()
+                .map(|sample| sample.time_stamp)
+                .max()
+                .unwrap_or(*last_seen);
+            let compute = nvml.processes(*device, nvml.get_compute_processes);
+            let graphics = nvml.processes(*device, nvml.get_graphics_processes);
+            add_device(&mut self.usage, &samples, &compute, &graphics);
+        }
+    }
+
+    /// What `pid` used of the GPUs in this tick. A process no GPU reports
+    /// used none, which is only known when NVML found a GPU.
+    pub fn usage(&self, pid: u32) -> Usage {
+        match (&self.nvml, self.usage.get(&pid)) {
+            (None, _) => Usage::default(),
+            (Some(_), Some(usage)) => *usage,
+            (Some(_), None) => Usage {
+                utilization: Some(0),
+                memory: Some(0),
+            },
+        }
+    }
+}
+
+/// Adds what each process used of one device to `usage`: the mean of its
+/// utilization samples, and the memory it holds as a compute or graphics
+/// process, which may list it twice.
+fn add_device(
+    usage: &mut HashMap<u32, Usage>,
+    samples: &[UtilizationSample],
+    compute: &[ProcessInfo],
+    graphics: &[ProcessInfo],
+) {
+    let mut utilization: HashMap<u32, (u64, u64)> = HashMap::new();
+    for sample in samples {
+        let (sum, count) = utilization.entry(sample.pid).or_default();
+        *sum += u64::from(sample.sm_util);
+        *count += 1;
+    }
+    for (pid, (sum, count)) in utilization {
+        let entry = usage.entry(pid).or_default();
+        *entry.utilization.get_or_insert(0) += sum / count;
+    }
+    let mut memory: HashMap<u32, u64> = HashMap::new();
+    for info in compute.iter().chain(graphics) {
+        if info.used_gpu_memory == NOT_AVAILABLE {
+            continue;
+        }
+        let held = memory.entry(info.pid).or_default();
+        *held = (*held).max(info.used_gpu_memory);
+    }
+    for (pid, held) in memory {
+        let entry = usage.entry(pid).or_default();
+        *entry.memory.get_or_insert(0) += held;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(pid: u32, sm_util: u32) -> UtilizationSample {
+        UtilizationSample {
+            pid,
+            time_stamp: 0,
+            sm_util,
+            mem_util: 0,
+            enc_util: 0,
+            dec_util: 0,
+        }
+    }
+
+    fn info(pid: u32, used_gpu_memory: u64) -> ProcessInfo {
+        ProcessInfo {
+            pid,
+            used_gpu_memory,
+            gpu_instance_id: 0,
+            compute_instance_id: 0,
+        }
+    }
+
+    #[test]
+    fn test_add_device() {
+        let mut usage = HashMap::new();
+        add_device(
+            &mut usage,
+            &[sample(10, 40), sample(10, 60), sample(11, 5)],
+            &[info(10, 1 << 30)],
+            &[info(10, 1 << 30), info(12, NOT_AVAILABLE)],
+        );
+        // A second GPU.
+        add_device(&mut usage, &[sample(10, 20)], &[info(10, 1 << 20)], &[]);
+        assert_eq!(
+            usage[&10],
+            Usage {
+                utilization: Some(70),
+                memory: Some((1 << 30) + (1 << 20)),
+            }
+        );
+        assert_eq!(
+            usage[&11],
+            Usage {
+                utilization: Some(5),
+                memory: None,
+            }
+        );
+        assert!(!usage.contains_key(&12));
+    }
+}
@@ -0,0 +1,228 @@
+use crate::{backoff::Backoff, sink::Sink, ProcessSample};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use std::{
+    fmt::Write as _,
+    io::Write,
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends per-process gauges to Carbon using the Graphite plaintext protocol.
+/// While the connection is down points are dropped and counted rather than
+/// buffered, and reconnects are attempted with exponential backoff.
+pub struct GraphiteSink {
+    address: SocketAddr,
+    prefix: String,
+    /// Graphite tags appended to every metric name.
+    tags: String,
+    stream: Option<TcpStream>,
+    backoff: Backoff,
+    next_attempt: Instant,
+    buffer: String,
+    dropped: u64,
+}
+
+impl GraphiteSink {
+    /// Sends metrics named after `host`, tagged with `run_id` if given.
+    pub fn new(address: &str, host: &str, run_id: Option<&str>) -> Result<Self> {
+        let address = address
+            .to_socket_addrs()
+            .with_context(|| format!("Failed to resolve Graphite address: {}", address))?
+            .next()
+            .with_context(|| format!("No address found for {}", address))?;
+        info!("Sending metrics to Graphite at {}", address);
+        Ok(Self {
+            address,
+            prefix: format!("chronologger.{}", sanitize(host)),
+            tags: run_id
+                .map(|run_id| format!(";run_id={}", sanitize(run_id)))
+                .unwrap_or_default(),
+            stream: None,
+            backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(60)),
+            next_attempt: Instant::now(),
+            buffer: String::new(),
+            dropped: 0,
+        })
+    }
+
+    fn connect(&mut self) -> Option<&mut TcpStream> {
+        if self.stream.is_none() && Instant::now() >= self.next_attempt {
+            match TcpStream::connect_timeout(&self.address, CONNECT_TIMEOUT).and_then(|stream| {
+                stream
+                    .set_write_timeout(Some(WRITE_TIMEOUT))
+                    .map(|_| stream)
+            }) {
+                Ok(stream) => {
+                    info!("Connected to Graphite at {}", self.address);
+                    self.backoff.reset();
+                    self.stream = Some(stream);
+                }
+                Err(e) => self.schedule_reconnect(e),
+            }
+        }
+        self.stream.as_mut()
+    }
+
+    fn schedule_reconnect(&mut self, error: std::io::Error) {
+        let delay = self.backoff.next_delay();
+        warn!(
+            "Graphite connection to {} failed, retrying in {:?}: {}",
+            self.address, delay, error
+        );
+        self.stream = None;
+        self.next_attempt = Instant::now() + delay;
+    }
+}
+
+impl Sink for GraphiteSink {
+    fn publish(&mut self, timestamp: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        let epoch = timestamp.timestamp();
+        self.buffer.clear();
+        for sample in samples {
+            let path = format!("{}.{}", self.prefix, sanitize(&sample.name));
+            let _ = writeln!(
+                self.buffer,
+                "{}.cpu{} {:.2} {}",
+                path, self.tags, sample.cpu_usage, epoch
+            );
+            let _ = writeln!(
+                self.buffer,
+                "{}.memory{} {:.2} {}",
+                path, self.tags, sample.memory_usage, epoch
+            );
+        }
+        let points = samples.len() as u64 * 2;
+
+        let buffer = std::mem::take(&mut self.buffer);
+        let result = match self.connect() {
+            Some(stream) => stream.write_all(buffer.as_bytes()).map_err(Some),
+            None => Err(None),
+        };
+        self.buffer = buffer;
+
+        if let Err(error) = result {
+            self.dropped += points;
+            if let Some(error) = error {
+                self.schedule_reconnect(error);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let Some(stream) = self.stream.as_mut() {
+            let _ = stream.flush();
+        }
+        if self.dropped > 0 {
+            warn!(
+                "Dropped {} Graphite points while disconnected",
+                self.dropped
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Replaces characters that would split or break a Graphite metric path.
+fn sanitize(component: &str) -> String {
+    let sanitized: String = component
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if sanitized.is_empty() {
+        "_".to_string()
+    } else {
+        sanitized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+    };
+    use sysinfo::{Pid, PidExt};
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("my.app worker"), "my_app_worker");
+        assert_eq!(sanitize("kworker/0:1"), "kworker_0_1");
+        assert_eq!(sanitize(""), "_");
+    }
+
+    #[test]
+    fn test_publish_lines() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+        let address = listener.local_addr().unwrap().to_string();
+        let mut sink = GraphiteSink::new(&address, "build.host", Some("run-1"))
+            .expect("Failed to create sink");
+
+        let timestamp = Local::now();
+        let samples = vec![ProcessSample {
+            pid: Some(Pid::from_u32(9)),
+            name: "cc1 plus".to_string(),
+            cpu_usage: 97.5,
+            memory_usage: 1.5,
+            extras: Vec::new(),
+        }];
+        sink.publish(&timestamp, &samples)
+            .expect("Failed to publish samples");
+        sink.finish().expect("Failed to finish sink");
+
+        let (stream, _) = listener.accept().expect("Failed to accept connection");
+        let lines: Vec<String> = BufReader::new(stream)
+            .lines()
+            .take(2)
+            .map(|line| line.expect("Failed to read line"))
+            .collect();
+        assert_eq!(
+            lines[0],
+            format!(
+                "chronologger.build_host.cc1_plus.cpu;run_id=run-1 97.50 {}",
+                timestamp.timestamp()
+            )
+        );
+        assert_eq!(
+            lines[1],
+            format!(
+                "chronologger.build_host.cc1_plus.memory;run_id=run-1 1.50 {}",
+                timestamp.timestamp()
+            )
+        );
+    }
+
+    #[test]
+    fn test_counts_dropped_points_when_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let mut sink = GraphiteSink::new(&address, "host", None).expect("Failed to create sink");
+        let samples = vec![ProcessSample {
+            pid: Some(Pid::from_u32(1)),
+            name: "init".to_string(),
+            cpu_usage: 0.0,
+            memory_usage: 0.0,
+            extras: Vec::new(),
+        }];
+        sink.publish(&Local::now(), &samples)
+            .expect("Publishing must not fail while disconnected");
+        sink.publish(&Local::now(), &samples)
+            .expect("Publishing must not fail while disconnected");
+        assert_eq!(sink.dropped, 4);
+        assert!(sink.stream.is_none());
+    }
+}
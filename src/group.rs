@@ -0,0 +1,198 @@
+//! `--group-by`: one row per group of processes instead of one per process,
+//! for services that run many identical workers.
+
+use crate::{
+    columns::{Extra, ExtraValue},
+    redact::Redactor,
+    schema::Kind,
+    ProcessSample,
+};
+use std::{collections::HashMap, path::Path};
+
+/// What the processes of a group share.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    /// The process name.
+    Name,
+    /// The program and its first argument, e.g. `python3 worker.py`.
+    CmdlinePrefix,
+}
+
+/// Folds each tick's samples into one per group: the sum of their CPU and
+/// memory usage under the group's name, with no PID. Of the optional
+/// columns, counts are summed, so the instances column counts the
+/// processes, and other values are kept if every process shares them.
+pub struct Grouper {
+    by: GroupBy,
+    /// Whether each optional column is summed.
+    summed: Vec<bool>,
+    /// Masks secrets in the command line prefixes.
+    redactor: Option<Redactor>,
+    /// The command line prefix of each row, noted while the tick is sampled.
+    prefixes: Vec<Option<String>>,
+    /// Where each group's sample is, for a tick.
+    groups: HashMap<String, usize>,
+}
+
+impl Grouper {
+    pub fn new(by: GroupBy, extras: &[Extra], redactor: Option<Redactor>) -> Self {
+        Self {
+            by,
+            summed: extras.iter().map(|extra| is_summed(*extra)).collect(),
+            redactor,
+            prefixes: Vec::new(),
+            groups: HashMap::new(),
+        }
+    }
+
+    /// Notes the command line of the process sampled into `row`, where the
+    /// source has it. Rows without one are grouped by name.
+    pub fn note(&mut self, row: usize, cmd: Option<&[String]>) {
+        if self.by != GroupBy::CmdlinePrefix {
+            return;
+        }
+        if self.prefixes.len() <= row {
+            self.prefixes.resize(row + 1, None);
+        }
+        self.prefixes[row] = cmd.and_then(|cmd| self.prefix(cmd));
+    }
+
+    fn prefix(&self, cmd: &[String]) -> Option<String> {
+        let (program, args) = cmd.split_first()?;
+        let program = Path::new(program).file_name().map_or_else(
+            || program.clone(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        let prefix = match args.first() {
+            Some(first) => format!("{} {}", program, first),
+            None => program,
+        };
+        Some(match &self.redactor {
+            Some(redactor) => redactor.redact(&prefix).into_owned(),
+            None => prefix,
+        })
+    }
+
+    /// Replaces `samples` with a sample per group, in the order each group's
+    /// first process was sampled.
+    pub fn group(&mut self, samples: &mut Vec<ProcessSample>) {
+        self.groups.clear();
+        let mut grouped: Vec<ProcessSample> = Vec::new();
+        for (row, sample) in samples.drain(..).enumerate() {
+            let key = match self.prefixes.get_mut(row).and_then(Option::take) {
+                Some(prefix) => prefix,
+                None => sample.name.clone(),
+            };
+            match self.groups.get(&key) {
+                Some(&index) => {
+                    let group = &mut grouped[index];
+                    group.cpu_usage += sample.cpu_usage;
+                    group.memory_usage += sample.memory_usage;
+                    let values = group.extras.iter_mut().zip(sample.extras);
+                    for ((value, other), summed) in values.zip(&self.summed) {
+                        *value = merge(value.take(), other, *summed);
+                    }
+                }
+                None => {
+                    self.groups.insert(key.clone(), grouped.len());
+                    grouped.push(ProcessSample {
+                        pid: None,
+                        name: key,
+                        ..sample
+                    });
+                }
+            }
+        }
+        self.prefixes.clear();
+        *samples = grouped;
+    }
+}
+
+/// Whether a group's value of `extra` is the sum over its processes rather
+/// than the value they share.
+fn is_summed(extra: Extra) -> bool {
+    matches!(extra.kind(), Kind::Count | Kind::Signed)
+        && !extra.is_throttling()
+        && !matches!(
+            extra,
+            Extra::StartTime | Extra::Elapsed | Extra::SamplerOverhead
+        )
+}
+
+/// Sums the values of a summed column and keeps a shared one, leaving the
+/// group's value empty if any process's is, or a shared one differs.
+fn merge(value: Option<ExtraValue>, other: Option<ExtraValue>, summed: bool) -> Option<ExtraValue> {
+    match (value?, other?) {
+        (ExtraValue::Count(a), ExtraValue::Count(b)) if summed => Some(ExtraValue::Count(a + b)),
+        (ExtraValue::Signed(a), ExtraValue::Signed(b)) if summed => Some(ExtraValue::Signed(a + b)),
+        (a, b) => (a == b).then_some(a),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn sample(pid: u32, name: &str, cpu_usage: f32, extras: Vec<Option<u64>>) -> ProcessSample {
+        ProcessSample {
+            extras: extras
+                .into_iter()
+                .map(|value| value.map(ExtraValue::Count))
+                .collect(),
+            ..test_support::sample(pid, name, cpu_usage, 1.5)
+        }
+    }
+
+    #[test]
+    fn test_group_by_name() {
+        let extras = [Extra::Instances, Extra::Rss, Extra::Ppid];
+        let mut grouper = Grouper::new(GroupBy::Name, &extras, None);
+        let mut samples = vec![
+            sample(12, "nginx", 1.0, vec![Some(1), Some(100), Some(11)]),
+            sample(3, "sshd", 0.5, vec![Some(1), Some(50), Some(1)]),
+            sample(11, "nginx", 2.0, vec![Some(1), Some(200), Some(1)]),
+            sample(13, "nginx", 3.0, vec![Some(1), Some(300), Some(11)]),
+        ];
+        grouper.group(&mut samples);
+        assert_eq!(samples.len(), 2);
+        let nginx = &samples[0];
+        assert_eq!(nginx.name, "nginx");
+        // A group, even of one process, has no PID.
+        assert!(samples.iter().all(|sample| sample.pid.is_none()));
+        assert_eq!((nginx.cpu_usage, nginx.memory_usage), (6.0, 4.5));
+        // Their parents differ, so the group has none.
+        assert_eq!(
+            nginx.extras,
+            [
+                Some(ExtraValue::Count(3)),
+                Some(ExtraValue::Count(600)),
+                None
+            ]
+        );
+        assert_eq!(samples[1].extras[2], Some(ExtraValue::Count(1)));
+    }
+
+    #[test]
+    fn test_group_by_cmdline_prefix() {
+        let mut grouper = Grouper::new(GroupBy::CmdlinePrefix, &[], None);
+        let cmd = |line: &str| -> Vec<String> { line.split(' ').map(String::from).collect() };
+        let mut samples = Vec::new();
+        let lines = [
+            "/usr/bin/python3 worker.py --queue a",
+            "python3 worker.py --queue b",
+            "/usr/bin/python3 web.py",
+        ];
+        for (row, line) in lines.iter().enumerate() {
+            grouper.note(row, Some(&cmd(line)));
+            samples.push(sample(row as u32 + 1, "python3", 1.0, Vec::new()));
+        }
+        // No command line, e.g. a kernel thread.
+        grouper.note(3, None);
+        samples.push(sample(4, "kworker", 0.0, Vec::new()));
+        grouper.group(&mut samples);
+        let names: Vec<&str> = samples.iter().map(|sample| sample.name.as_str()).collect();
+        assert_eq!(names, ["python3 worker.py", "python3 web.py", "kworker"]);
+        assert_eq!(samples[0].cpu_usage, 2.0);
+    }
+}
@@ -0,0 +1,385 @@
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    net::{TcpListener, TcpStream},
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, OnceLock},
+    thread,
+    time::Duration,
+};
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An `http://` or `https://host[:port][/path]` endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    /// Whether the endpoint is reached over TLS.
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Self> {
+        let (tls, rest) = match url.strip_prefix("https://") {
+            Some(rest) => (true, rest),
+            None => (
+                false,
+                url.strip_prefix("http://").with_context(|| {
+                    format!(
+                        "Unsupported URL (only http:// and https:// are supported): {}",
+                        url
+                    )
+                })?,
+            ),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], rest[index..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        if authority.is_empty() {
+            bail!("Missing host in URL: {}", url);
+        }
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .with_context(|| format!("Invalid port in URL: {}", url))?,
+            ),
+            None => (authority, if tls { 443 } else { 80 }),
+        };
+
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    /// Joins `suffix` onto the base path of this URL.
+    pub fn join(&self, suffix: &str) -> String {
+        format!("{}/{}", self.path, suffix.trim_start_matches('/'))
+    }
+
+    /// The full URL of `path` on this endpoint's host.
+    fn with_path(&self, path: &str) -> String {
+        format!(
+            "{}://{}:{}{}",
+            if self.tls { "https" } else { "http" },
+            self.host,
+            self.port,
+            if path.is_empty() { "/" } else { path }
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// The client every request goes through, so that connections to the same
+/// endpoint are kept open between requests.
+fn agent() -> &'static ureq::Agent {
+    static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+    AGENT.get_or_init(|| {
+        ureq::AgentBuilder::new()
+            .user_agent(concat!("chronologger/", env!("CARGO_PKG_VERSION")))
+            .build()
+    })
+}
+
+/// Sends a single request and reads the whole response. A response with an
+/// error status is returned like any other.
+pub fn send(
+    method: &str,
+    url: &Url,
+    path: &str,
+    content_type: &str,
+    body: &[u8],
+    timeout: Duration,
+) -> Result<Response> {
+    let target = url.with_path(path);
+    let response = match agent()
+        .request(method, &target)
+        .timeout(timeout)
+        .set("Content-Type", content_type)
+        .send_bytes(body)
+    {
+        Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to send HTTP request to {}", target))
+        }
+    };
+    let status = response.status();
+    let body = response
+        .into_string()
+        .context("Failed to read HTTP response!")?;
+    Ok(Response { status, body })
+}
+
+/// An incoming request as seen by a [`serve`] handler.
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+}
+
+/// The response produced by a [`serve`] handler.
+#[derive(Debug)]
+pub struct Reply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Reply {
+    pub fn json(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self {
+            status: 404,
+            content_type: "text/plain",
+            body: "Not Found\n".to_string(),
+        }
+    }
+}
+
+/// Serves requests one at a time on `listener` until `stop` is set. The
+/// listener is polled so shutdown never waits on a client.
+pub fn serve<F>(listener: TcpListener, stop: Arc<AtomicBool>, handler: F) -> Result<()>
+where
+    F: Fn(&Request) -> Reply,
+{
+    listener
+        .set_nonblocking(true)
+        .context("Failed to configure listener!")?;
+    while !stop.load(Ordering::SeqCst) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                if let Err(e) = handle_connection(stream, &handler) {
+                    debug!("Failed to serve request from {}: {:#}", peer, e);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+            Err(e) => {
+                warn!("Failed to accept connection: {}", e);
+                thread::sleep(ACCEPT_POLL_INTERVAL);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn handle_connection<F>(mut stream: TcpStream, handler: &F) -> Result<()>
+where
+    F: Fn(&Request) -> Reply,
+{
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header == "\r\n" || header == "\n" {
+            break;
+        }
+    }
+
+    let reply = match parse_request_line(&request_line) {
+        Some(request) => handler(&request),
+        None => Reply {
+            status: 400,
+            content_type: "text/plain",
+            body: "Bad Request\n".to_string(),
+        },
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        reply.status,
+        reason(reply.status),
+        reply.content_type,
+        reply.body.len()
+    )?;
+    stream.write_all(reply.body.as_bytes())?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn parse_request_line(line: &str) -> Option<Request> {
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (key.to_string(), value.to_string())
+        })
+        .collect();
+
+    Some(Request {
+        method,
+        path: path.to_string(),
+        query,
+    })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url_parse() {
+        let url = Url::parse("http://localhost:9091/prefix/").expect("Failed to parse URL");
+        assert_eq!(url.host, "localhost");
+        assert_eq!(url.port, 9091);
+        assert_eq!(url.path, "/prefix");
+        assert_eq!(url.join("/metrics/job/a"), "/prefix/metrics/job/a");
+
+        let url = Url::parse("http://example.com").expect("Failed to parse URL");
+        assert_eq!(url.port, 80);
+        assert_eq!(url.path, "");
+
+        let url = Url::parse("https://example.com/api").expect("Failed to parse URL");
+        assert!(url.tls);
+        assert_eq!(url.port, 443);
+        assert_eq!(
+            url.with_path(&url.join("v1")),
+            "https://example.com:443/api/v1"
+        );
+
+        assert!(Url::parse("ftp://example.com").is_err());
+        assert!(Url::parse("http://host:notaport").is_err());
+    }
+
+    #[test]
+    fn test_parse_request_line() {
+        let request =
+            parse_request_line("GET /history?seconds=300&x HTTP/1.1\r\n").expect("Failed to parse");
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/history");
+        assert_eq!(request.query["seconds"], "300");
+        assert_eq!(request.query["x"], "");
+        assert!(parse_request_line("\r\n").is_none());
+    }
+}
+
+#[cfg(test)]
+pub mod testing {
+    use super::Url;
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{TcpListener, TcpStream},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread::{self, JoinHandle},
+    };
+
+    /// Accepts `count` requests on an ephemeral port, answering each with
+    /// `status`, and returns the raw requests (head and body).
+    pub fn capture_requests(count: usize, status: u16) -> (Url, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+        let url = local_url(&listener);
+        let handle = thread::spawn(move || {
+            listener
+                .incoming()
+                .take(count)
+                .map(|stream| answer(stream.expect("Failed to accept connection"), status))
+                .collect()
+        });
+        (url, handle)
+    }
+
+    /// Answers every request on an ephemeral port with `status` for the
+    /// rest of the test run, counting them.
+    pub fn count_requests(status: u16) -> (Url, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+        let url = local_url(&listener);
+        let requests = Arc::new(AtomicUsize::new(0));
+        {
+            let requests = requests.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    requests.fetch_add(1, Ordering::SeqCst);
+                    answer(stream.expect("Failed to accept connection"), status);
+                }
+            });
+        }
+        (url, requests)
+    }
+
+    /// The URL of `listener`.
+    pub fn local_url(listener: &TcpListener) -> Url {
+        Url {
+            tls: false,
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            path: String::new(),
+        }
+    }
+
+    /// Reads one request from `stream` and answers it with `status`,
+    /// returning the raw request (head and body).
+    pub fn answer(mut stream: TcpStream, status: u16) -> String {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request = String::new();
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("Failed to read request");
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                content_length = value.trim().parse().unwrap();
+            }
+            request.push_str(&line);
+            if line == "\r\n" || line.is_empty() {
+                break;
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).expect("Failed to read body");
+        request.push_str(&String::from_utf8_lossy(&body));
+        write!(
+            stream,
+            "HTTP/1.1 {} OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status
+        )
+        .unwrap();
+        request
+    }
+}
@@ -0,0 +1,398 @@
+use crate::{
+    http::{self, Url},
+    push::{self, Overflow, Push, PushError, PushQueue},
+    sink::Sink,
+    ProcessSample,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, SecondsFormat};
+use log::{info, warn};
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, ErrorKind, Seek, SeekFrom, Write},
+    path::PathBuf,
+    time::Duration,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+pub const MAX_BUFFERED_SAMPLES: usize = 100_000;
+
+#[derive(Debug, Clone)]
+pub struct IngestConfig {
+    pub url: Url,
+    pub host: String,
+    /// Samples sent in one request at most.
+    pub batch: usize,
+    /// Samples held in memory at most before they are spilled or dropped.
+    pub max_buffered: usize,
+    /// JSON Lines file that samples go to while the endpoint cannot take
+    /// them, and that is sent first once it can.
+    pub spill: Option<PathBuf>,
+    /// Added to every request.
+    pub run_id: Option<String>,
+}
+
+/// POSTs every tick's samples as JSON to a collector, in batches of up to
+/// `batch` samples. Requests are sent on a background thread; samples that
+/// cannot be delivered are buffered up to `max_buffered`, then written to
+/// the spill file if there is one and dropped otherwise.
+pub struct IngestSink {
+    queue: PushQueue<String>,
+}
+
+impl IngestSink {
+    pub fn new(config: IngestConfig) -> Result<Self> {
+        let spill = config
+            .spill
+            .clone()
+            .map(|path| Spill::open(path, config.batch))
+            .transpose()?;
+        if let Some(spill) = spill.as_ref().filter(|spill| spill.pending > 0) {
+            info!(
+                "Sending {} samples left in {} first",
+                spill.pending,
+                spill.path.display()
+            );
+        }
+        info!(
+            "Posting samples to {}:{}{} in batches of up to {}",
+            config.url.host, config.url.port, config.url.path, config.batch
+        );
+        let max_buffered = config.max_buffered;
+        let spill = spill.map(|spill| Box::new(spill) as Box<dyn Overflow<String>>);
+        Ok(Self {
+            queue: PushQueue::new(Poster { config }, max_buffered, spill),
+        })
+    }
+}
+
+impl Sink for IngestSink {
+    fn publish(&mut self, timestamp: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        let timestamp = timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, false);
+        self.queue.publish(samples.iter().map(|sample| {
+            let mut record = sample.to_json();
+            record["timestamp"] = timestamp.as_str().into();
+            record.to_string()
+        }));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let dropped = self.queue.finish();
+        if dropped > 0 {
+            warn!("Dropped {} samples that could not be delivered", dropped);
+        }
+        Ok(())
+    }
+}
+
+/// The samples that did not fit in memory, one JSON object per line. The
+/// lines before `offset` have been delivered; the file is removed once all
+/// of them have.
+struct Spill {
+    path: PathBuf,
+    offset: u64,
+    /// Lines from `offset` on.
+    pending: u64,
+    /// Lines read at once.
+    batch: usize,
+    /// The offset after the lines last read.
+    read_end: u64,
+}
+
+impl Spill {
+    /// Opens the spill file at `path`, picking up the samples a previous
+    /// run could not deliver.
+    fn open(path: PathBuf, batch: usize) -> Result<Self> {
+        let pending = match File::open(&path) {
+            Ok(file) => BufReader::new(file).lines().count() as u64,
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to open {}", path.display()));
+            }
+        };
+        Ok(Self {
+            path,
+            offset: 0,
+            pending,
+            batch,
+            read_end: 0,
+        })
+    }
+}
+
+impl Overflow<String> for Spill {
+    fn store(&mut self, records: &[String]) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        let mut text = String::new();
+        for record in records {
+            text.push_str(record);
+            text.push('\n');
+        }
+        file.write_all(text.as_bytes())
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        self.pending += records.len() as u64;
+        Ok(())
+    }
+
+    fn pending(&self) -> u64 {
+        self.pending
+    }
+
+    /// Reads up to `batch` pending lines.
+    fn read(&mut self) -> Result<Vec<String>> {
+        let mut file = File::open(&self.path)
+            .with_context(|| format!("Failed to open {}", self.path.display()))?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut reader = BufReader::new(file);
+        let mut records = Vec::new();
+        self.read_end = self.offset;
+        let mut line = String::new();
+        while records.len() < self.batch {
+            line.clear();
+            let read = reader
+                .read_line(&mut line)
+                .with_context(|| format!("Failed to read {}", self.path.display()))?;
+            if read == 0 {
+                break;
+            }
+            self.read_end += read as u64;
+            records.push(line.trim_end().to_string());
+        }
+        Ok(records)
+    }
+
+    /// Marks the `count` lines last read as delivered.
+    fn consume(&mut self, count: u64) {
+        self.offset = self.read_end;
+        self.pending = self.pending.saturating_sub(count);
+        if self.pending == 0 {
+            if let Err(e) = fs::remove_file(&self.path) {
+                if e.kind() != ErrorKind::NotFound {
+                    warn!("Failed to remove {}: {}", self.path.display(), e);
+                }
+            }
+            self.offset = 0;
+            self.read_end = 0;
+        }
+    }
+
+    fn close(&mut self) {
+        if self.pending > 0 {
+            warn!(
+                "Left {} samples in {} to send next time",
+                self.pending,
+                self.path.display()
+            );
+        }
+    }
+}
+
+struct Poster {
+    config: IngestConfig,
+}
+
+impl Push for Poster {
+    type Item = String;
+
+    const NAME: &'static str = "HTTP push";
+    const ITEMS: &'static str = "samples";
+
+    fn batch_len(&self, _records: &VecDeque<String>) -> usize {
+        self.config.batch
+    }
+
+    fn push(&mut self, records: &[String]) -> Result<(), PushError> {
+        let config = &self.config;
+        let body = encode(&config.host, config.run_id.as_deref(), records);
+        let response = http::send(
+            "POST",
+            &config.url,
+            &config.url.path,
+            "application/json",
+            body.as_bytes(),
+            REQUEST_TIMEOUT,
+        )
+        .map_err(PushError::Retryable)?;
+
+        match response.status {
+            200..=299 => Ok(()),
+            429 | 500..=599 => Err(PushError::Retryable(push::status_error(
+                "Endpoint", &response,
+            ))),
+            _ => Err(PushError::Rejected(push::status_error(
+                "Endpoint", &response,
+            ))),
+        }
+    }
+}
+
+/// Encodes a batch as `{"host": ..., "run_id": ..., "samples": [...]}`,
+/// the samples as they were encoded when published.
+fn encode(host: &str, run_id: Option<&str>, records: &[String]) -> String {
+    let mut body = format!("{{\"host\":{}", serde_json::Value::from(host));
+    if let Some(run_id) = run_id {
+        body.push_str(&format!(",\"run_id\":{}", serde_json::Value::from(run_id)));
+    }
+    body.push_str(",\"samples\":[");
+    body.push_str(&records.join(","));
+    body.push_str("]}");
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::testing::{capture_requests, count_requests};
+    use crate::test_support::sample;
+    use std::{sync::atomic::Ordering, thread};
+    use tempfile::TempDir;
+
+    fn config(url: Url, spill: Option<PathBuf>) -> IngestConfig {
+        IngestConfig {
+            url,
+            host: "host1".to_string(),
+            batch: 2,
+            max_buffered: 1000,
+            spill,
+            run_id: Some("run-1".to_string()),
+        }
+    }
+
+    fn payload(request: &str) -> serde_json::Value {
+        let body = &request[request.find("\r\n\r\n").unwrap() + 4..];
+        serde_json::from_str(body).expect("Invalid JSON payload")
+    }
+
+    #[test]
+    fn test_posts_batches() {
+        let (url, server) = capture_requests(2, 200);
+        let url = Url {
+            path: "/ingest".to_string(),
+            ..url
+        };
+        let mut sink = IngestSink::new(config(url, None)).unwrap();
+        let samples = [
+            sample(1, "init", 1.5, 0.25),
+            sample(2, "sshd", 1.5, 0.25),
+            sample(3, "cron", 1.5, 0.25),
+        ];
+        sink.publish(&Local::now(), &samples)
+            .expect("Failed to publish samples");
+        sink.finish().expect("Failed to finish sink");
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /ingest HTTP/1.1"));
+        let first = payload(&requests[0]);
+        assert_eq!(first["host"], "host1");
+        assert_eq!(first["run_id"], "run-1");
+        let batch = first["samples"].as_array().unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[1]["name"], "sshd");
+        assert_eq!(batch[1]["cpu_percent"], 1.5);
+        assert!(batch[1]["timestamp"].is_string());
+        assert_eq!(payload(&requests[1])["samples"][0]["pid"], 3);
+    }
+
+    #[test]
+    fn test_spill_is_sent_first() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("spill.jsonl");
+        let mut spill = Spill::open(path.clone(), 2).unwrap();
+        assert_eq!(spill.pending, 0);
+        spill
+            .store(&[
+                r#"{"pid":1}"#.to_string(),
+                r#"{"pid":2}"#.to_string(),
+                r#"{"pid":3}"#.to_string(),
+            ])
+            .unwrap();
+        let records = spill.read().unwrap();
+        assert_eq!(records, [r#"{"pid":1}"#, r#"{"pid":2}"#]);
+        spill.consume(records.len() as u64);
+        assert_eq!(spill.read().unwrap(), [r#"{"pid":3}"#]);
+
+        // A later run picks the file up again and sends it before its own
+        // samples, removing it once it is through.
+        let (url, server) = capture_requests(3, 200);
+        let mut sink = IngestSink::new(config(url, Some(path.clone()))).unwrap();
+        sink.publish(&Local::now(), &[sample(4, "init", 1.5, 0.25)])
+            .expect("Failed to publish samples");
+        sink.finish().expect("Failed to finish sink");
+
+        let pids: Vec<u64> = server
+            .join()
+            .unwrap()
+            .iter()
+            .flat_map(|request| {
+                let samples = payload(request)["samples"].as_array().unwrap().clone();
+                samples
+                    .into_iter()
+                    .map(|sample| sample["pid"].as_u64().unwrap())
+            })
+            .collect();
+        assert_eq!(pids, [1, 2, 3, 4]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_spills_when_unreachable() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("spill.jsonl");
+        // Nothing listens on a port that was just released.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = Url {
+            tls: false,
+            host: "127.0.0.1".to_string(),
+            port,
+            path: String::new(),
+        };
+        let mut sink = IngestSink::new(IngestConfig {
+            max_buffered: 1,
+            ..config(url, Some(path.clone()))
+        })
+        .unwrap();
+        sink.publish(
+            &Local::now(),
+            &[sample(1, "init", 1.5, 0.25), sample(2, "sshd", 1.5, 0.25)],
+        )
+        .expect("Failed to publish samples");
+        sink.finish().expect("Failed to finish sink");
+
+        assert_eq!(sink.queue.dropped(), 0);
+        assert_eq!(Spill::open(path, 2).unwrap().pending, 2);
+    }
+
+    #[test]
+    fn test_backs_off_while_endpoint_fails() {
+        let (url, requests) = count_requests(503);
+        let mut sink = IngestSink::new(IngestConfig {
+            max_buffered: 4,
+            ..config(url, None)
+        })
+        .unwrap();
+        // A tick every 150ms, while the first retry waits 500ms and the
+        // second a second.
+        for pid in 1..=6 {
+            sink.publish(&Local::now(), &[sample(pid, "init", 1.5, 0.25)])
+                .expect("Failed to publish samples");
+            thread::sleep(Duration::from_millis(150));
+        }
+        let sent = requests.load(Ordering::SeqCst);
+        assert!((1..=2).contains(&sent), "{} requests", sent);
+        assert!(sink.queue.buffered() <= 4);
+        assert_eq!(sink.queue.dropped(), 2);
+
+        sink.finish().expect("Failed to finish sink");
+        assert_eq!(sink.queue.dropped(), 6);
+    }
+}
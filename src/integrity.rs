@@ -0,0 +1,500 @@
+use crate::metadata;
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use sha2::{Digest as _, Sha256};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// Starts the footer line of a CSV capture.
+const CSV_FOOTER: &str = "# integrity: ";
+/// Names the footer line of a JSON lines capture.
+const JSON_FOOTER: &str = "Integrity";
+/// Names the footer in the sidecar of a format that cannot carry one.
+const SIDECAR_FOOTER: &str = "integrity";
+/// First bytes of an Avro object container file.
+pub const AVRO_MAGIC: &[u8] = b"Obj\x01";
+/// First bytes of a SQLite database.
+pub const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+/// First bytes of a Parquet file.
+pub const PARQUET_MAGIC: &[u8] = b"PAR1";
+/// First bytes of a gzip stream, such as `.gz` output.
+pub const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+/// First bytes of a zstd frame, such as `.zst` output.
+pub const ZSTD_MAGIC: &[u8] = b"\x28\xb5\x2f\xfd";
+
+/// How the rows of the data section are told apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rows {
+    /// CSV records, which end at a newline outside quotes.
+    Csv,
+    /// One row per line.
+    Lines,
+    /// Rows cannot be told from the bytes and are counted by the writer.
+    #[cfg(any(feature = "avro", feature = "parquet"))]
+    Counted,
+}
+
+/// What the footer of a cleanly finished capture records about its data
+/// section, i.e. everything after the CSV header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Footer {
+    pub rows: u64,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+impl Footer {
+    /// The footer as the last line of a CSV capture.
+    pub fn to_comment(&self) -> String {
+        format!(
+            "{}rows={} bytes={} sha256={}\n",
+            CSV_FOOTER, self.rows, self.bytes, self.sha256
+        )
+    }
+
+    /// The footer as the last line of a JSON lines capture.
+    pub fn to_json_line(&self) -> String {
+        format!("{}\n", json!({ JSON_FOOTER: self.to_json() }))
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "Rows": self.rows, "Bytes": self.bytes, "Sha256": self.sha256 })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        Some(Self {
+            rows: value.get("Rows")?.as_u64()?,
+            bytes: value.get("Bytes")?.as_u64()?,
+            sha256: value.get("Sha256")?.as_str()?.to_string(),
+        })
+    }
+
+    /// Reads a footer line in either layout.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if let Some(fields) = line.strip_prefix(CSV_FOOTER) {
+            let mut footer = Self {
+                rows: 0,
+                bytes: 0,
+                sha256: String::new(),
+            };
+            let (mut rows, mut bytes) = (None, None);
+            for field in fields.split_whitespace() {
+                match field.split_once('=')? {
+                    ("rows", value) => rows = value.parse().ok(),
+                    ("bytes", value) => bytes = value.parse().ok(),
+                    ("sha256", value) => footer.sha256 = value.to_string(),
+                    _ => {}
+                }
+            }
+            footer.rows = rows?;
+            footer.bytes = bytes?;
+            return (!footer.sha256.is_empty()).then_some(footer);
+        }
+        if line.starts_with('{') {
+            let value: Value = serde_json::from_str(line).ok()?;
+            return Self::from_json(value.get(JSON_FOOTER)?);
+        }
+        None
+    }
+}
+
+/// Whether `line` is the footer of a capture rather than one of its rows.
+pub fn is_footer(line: &str) -> bool {
+    Footer::parse(line).is_some()
+}
+
+/// Counts and hashes the data section as it is written, so the footer
+/// needs no second pass over the file.
+#[derive(Clone)]
+pub struct Digest {
+    sha: Sha256,
+    rows_by: Rows,
+    rows: u64,
+    bytes: u64,
+    /// Whether the last CSV byte seen was inside a quoted field.
+    quoted: bool,
+    /// Set once the footer has been taken; later bytes are the footer's.
+    sealed: bool,
+}
+
+impl Digest {
+    pub fn new(rows_by: Rows) -> Self {
+        Self {
+            sha: Sha256::default(),
+            rows_by,
+            rows: 0,
+            bytes: 0,
+            quoted: false,
+            sealed: false,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        if self.sealed {
+            return;
+        }
+        self.sha.update(data);
+        self.bytes += data.len() as u64;
+        match self.rows_by {
+            Rows::Csv => {
+                // A quote inside a field is doubled, so toggling on every
+                // quote leaves `quoted` right after each field.
+                for &byte in data {
+                    match byte {
+                        b'"' => self.quoted = !self.quoted,
+                        b'\n' if !self.quoted => self.rows += 1,
+                        _ => {}
+                    }
+                }
+            }
+            Rows::Lines => {
+                self.rows += data.iter().filter(|&&byte| byte == b'\n').count() as u64;
+            }
+            #[cfg(any(feature = "avro", feature = "parquet"))]
+            Rows::Counted => {}
+        }
+    }
+
+    /// Feeds everything `reader` yields, e.g. the data section of a capture
+    /// being resumed.
+    pub fn update_from(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            match reader.read(&mut buffer)? {
+                0 => return Ok(()),
+                read => self.update(&buffer[..read]),
+            }
+        }
+    }
+
+    /// Adds rows that [`Rows::Counted`] cannot see in the bytes.
+    #[cfg(any(feature = "avro", feature = "parquet"))]
+    pub fn add_rows(&mut self, rows: u64) {
+        if !self.sealed {
+            self.rows += rows;
+        }
+    }
+
+    /// Forgets everything seen so far: what came before is not data.
+    pub fn restart(&mut self) {
+        *self = Self::new(self.rows_by);
+    }
+
+    pub fn footer(&self) -> Footer {
+        Footer {
+            rows: self.rows,
+            bytes: self.bytes,
+            sha256: format!("{:x}", self.sha.clone().finalize()),
+        }
+    }
+
+    /// Takes the footer and stops counting, so that writing the footer
+    /// itself leaves it unchanged.
+    pub fn seal(&mut self) -> Footer {
+        self.sealed = true;
+        self.footer()
+    }
+}
+
+pub type SharedDigest = Arc<Mutex<Digest>>;
+
+/// Passes every byte written to the wrapped writer through a [`Digest`].
+pub struct Hashed<W> {
+    inner: W,
+    digest: SharedDigest,
+}
+
+impl<W> Hashed<W> {
+    pub fn new(inner: W, rows_by: Rows) -> Self {
+        Self {
+            inner,
+            digest: Arc::new(Mutex::new(Digest::new(rows_by))),
+        }
+    }
+
+    pub fn digest(&self) -> &SharedDigest {
+        &self.digest
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+}
+
+impl<W: Write> Write for Hashed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.digest.lock().unwrap().update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Records `footer` in the sidecar of `output`, next to its metadata.
+pub fn write_sidecar(output: &Path, footer: &Footer) -> Result<()> {
+    metadata::update_sidecar(output, |object| {
+        let value = json!({ "rows": footer.rows, "bytes": footer.bytes, "sha256": footer.sha256 });
+        object.insert(SIDECAR_FOOTER.to_string(), value);
+    })
+}
+
+fn read_sidecar(output: &Path) -> Result<Option<Footer>> {
+    let path = metadata::sidecar_path(output);
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+    let object: Value = serde_json::from_str(&text)
+        .with_context(|| format!("Malformed metadata sidecar {}", path.display()))?;
+    let Some(value) = object.get(SIDECAR_FOOTER) else {
+        return Ok(None);
+    };
+    let footer = Footer {
+        rows: value["rows"].as_u64().unwrap_or_default(),
+        bytes: value["bytes"].as_u64().unwrap_or_default(),
+        sha256: value["sha256"].as_str().unwrap_or_default().to_string(),
+    };
+    Ok(Some(footer))
+}
+
+/// Recomputes the footer of the capture at `path` and checks it against the
+/// one recorded. Fails if they differ, or if there is none because the
+/// capture did not end cleanly.
+pub fn verify(path: &Path) -> Result<Footer> {
+    metadata::read(path)?.check_schema(path)?;
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    let first = reader
+        .fill_buf()
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if first.starts_with(GZIP_MAGIC) {
+        bail!(
+            "{} is gzip-compressed; decompress it first, e.g. with gzip -dk, and verify the result",
+            path.display()
+        );
+    }
+    if first.starts_with(ZSTD_MAGIC) {
+        bail!(
+            "{} is zstd-compressed; decompress it first, e.g. with zstd -dk, and verify the result",
+            path.display()
+        );
+    }
+    if first.starts_with(SQLITE_MAGIC) {
+        bail!(
+            "{} is a SQLite database, which has no integrity footer; check it with \
+             PRAGMA integrity_check instead",
+            path.display()
+        );
+    }
+    let (recorded, actual) = if first.starts_with(AVRO_MAGIC) {
+        verify_avro(path, &mut reader)?
+    } else if first.starts_with(PARQUET_MAGIC) {
+        verify_parquet(path, &mut reader)?
+    } else if first.first() == Some(&b'[') {
+        let actual = read_array(&mut reader)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        (read_sidecar(path)?, actual)
+    } else {
+        let rows_by = if first.first() == Some(&b'{') {
+            Rows::Lines
+        } else {
+            // The data section starts after the metadata and the header.
+            metadata::Metadata::parse_comments(&mut reader)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            reader.read_line(&mut String::new())?;
+            Rows::Csv
+        };
+        read_data(&mut reader, rows_by)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+    };
+
+    let Some(recorded) = recorded else {
+        bail!(
+            "{} has no integrity footer, so it did not end cleanly ({} rows, {} bytes of data)",
+            path.display(),
+            actual.rows,
+            actual.bytes
+        );
+    };
+    let mut mismatches = Vec::new();
+    if actual.rows != recorded.rows {
+        mismatches.push(format!("{} rows, not {}", actual.rows, recorded.rows));
+    }
+    if actual.bytes != recorded.bytes {
+        mismatches.push(format!("{} bytes, not {}", actual.bytes, recorded.bytes));
+    }
+    if actual.sha256 != recorded.sha256 {
+        mismatches.push(format!(
+            "SHA-256 {}, not {}",
+            actual.sha256, recorded.sha256
+        ));
+    }
+    if !mismatches.is_empty() {
+        bail!(
+            "{} does not match its integrity footer: {}",
+            path.display(),
+            mismatches.join(", ")
+        );
+    }
+    Ok(actual)
+}
+
+/// Digests the data section up to the footer, which is the last line if
+/// the capture has one.
+fn read_data(reader: &mut impl BufRead, rows_by: Rows) -> io::Result<(Option<Footer>, Footer)> {
+    let mut digest = Digest::new(rows_by);
+    // Each line is only digested once the next shows it is not the last.
+    let mut pending = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        digest.update(&pending);
+        std::mem::swap(&mut pending, &mut line);
+    }
+    let footer = std::str::from_utf8(&pending)
+        .ok()
+        .filter(|line| line.ends_with('\n'))
+        .and_then(Footer::parse);
+    if footer.is_none() {
+        digest.update(&pending);
+    }
+    Ok((footer, digest.footer()))
+}
+
+/// Digests the elements of a JSON array capture, one per line between the
+/// brackets. Its footer is in the sidecar.
+fn read_array(reader: &mut impl BufRead) -> io::Result<Footer> {
+    let mut digest = Digest::new(Rows::Lines);
+    reader.read_line(&mut String::new())?;
+    let mut pending = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        digest.update(&pending);
+        std::mem::swap(&mut pending, &mut line);
+    }
+    if pending != b"]\n" {
+        digest.update(&pending);
+    }
+    Ok(digest.footer())
+}
+
+/// Avro keeps its footer in the sidecar, covering the whole file.
+#[cfg(feature = "avro")]
+fn verify_avro(path: &Path, reader: &mut impl Read) -> Result<(Option<Footer>, Footer)> {
+    let mut digest = Digest::new(Rows::Counted);
+    digest
+        .update_from(reader)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    digest.add_rows(crate::avro::count_rows(path)?);
+    Ok((read_sidecar(path)?, digest.footer()))
+}
+
+#[cfg(not(feature = "avro"))]
+fn verify_avro(path: &Path, _reader: &mut impl Read) -> Result<(Option<Footer>, Footer)> {
+    bail!(
+        "Cannot verify {}: Avro support requires the `avro` feature",
+        path.display()
+    )
+}
+
+/// Parquet keeps its footer in the sidecar too.
+#[cfg(feature = "parquet")]
+fn verify_parquet(path: &Path, reader: &mut impl Read) -> Result<(Option<Footer>, Footer)> {
+    let mut digest = Digest::new(Rows::Counted);
+    digest
+        .update_from(reader)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    digest.add_rows(crate::parquet::count_rows(path)?);
+    Ok((read_sidecar(path)?, digest.footer()))
+}
+
+#[cfg(not(feature = "parquet"))]
+fn verify_parquet(path: &Path, _reader: &mut impl Read) -> Result<(Option<Footer>, Footer)> {
+    bail!(
+        "Cannot verify {}: Parquet support requires the `parquet` feature",
+        path.display()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = "2026-01-01T10:00:00+00:00,1,\"a \"\"b\"\"\nc\",1.00,2.00\n\
+                        2026-01-01T10:00:00+00:00,2,d,0.00,0.50\n";
+
+    fn digest(data: &str, rows_by: Rows) -> Footer {
+        let mut digest = Digest::new(rows_by);
+        digest.update(data.as_bytes());
+        digest.footer()
+    }
+
+    #[test]
+    fn test_known_digest() {
+        // The NIST two-block example of FIPS 180-4.
+        let mut digest = Digest::new(Rows::Lines);
+        digest.update(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq");
+        assert_eq!(
+            digest.footer().sha256,
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn test_counts_rows() {
+        assert_eq!(digest(DATA, Rows::Csv).rows, 2);
+        assert_eq!(digest(DATA, Rows::Lines).rows, 3);
+        assert_eq!(digest(DATA, Rows::Csv).bytes, DATA.len() as u64);
+    }
+
+    #[test]
+    fn test_footer_round_trip() {
+        let footer = digest(DATA, Rows::Csv);
+        assert_eq!(Footer::parse(&footer.to_comment()), Some(footer.clone()));
+        assert_eq!(Footer::parse(&footer.to_json_line()), Some(footer.clone()));
+        assert!(footer
+            .to_comment()
+            .starts_with("# integrity: rows=2 bytes="));
+        assert!(!is_footer("2026-01-01T10:00:00+00:00,1,a,1.00,2.00"));
+        assert!(!is_footer("{\"Timestamp\":1767261600000}"));
+        assert!(!is_footer("# integrity: rows=2"));
+    }
+
+    #[test]
+    fn test_seal_ignores_footer() {
+        let mut hashed = Hashed::new(Vec::new(), Rows::Csv);
+        hashed.write_all(b"Timestamp,PID\n").unwrap();
+        hashed.digest().lock().unwrap().restart();
+        hashed.write_all(DATA.as_bytes()).unwrap();
+        let footer = hashed.digest().lock().unwrap().seal();
+        hashed.write_all(footer.to_comment().as_bytes()).unwrap();
+        assert_eq!(footer, digest(DATA, Rows::Csv));
+        assert_eq!(hashed.digest().lock().unwrap().footer(), footer);
+
+        let (recorded, actual) = read_data(&mut &hashed.inner[14..], Rows::Csv).unwrap();
+        assert_eq!(recorded, Some(footer.clone()));
+        assert_eq!(actual, footer);
+    }
+}
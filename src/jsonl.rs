@@ -0,0 +1,274 @@
+use crate::{
+    columns::Extra,
+    lifecycle,
+    numfmt::{self, MemoryFormat, MemoryUnit},
+    suspend,
+    timestamp::TimestampFormat,
+    ProcessSample,
+};
+use chrono::{DateTime, Local};
+use serde_json::{json, Map, Value};
+use std::io::{self, Write};
+use sysinfo::{Pid, PidExt};
+
+/// Builds the JSON object of one row. Percentages are rounded as in CSV but
+/// written as numbers. Extra columns are keyed by their schema IDs and left
+/// out when they have no value, and a group's row has a null PID.
+pub fn record(
+    extras: &[Extra],
+    memory: &MemoryFormat,
+    timestamp: &Value,
+    sample: &ProcessSample,
+    scope: Option<&str>,
+    run_id: Option<&str>,
+) -> Value {
+    let mut record = Map::new();
+    record.insert("timestamp".to_string(), timestamp.clone());
+    record.insert("pid".to_string(), sample.pid.map(Pid::as_u32).into());
+    record.insert("name".to_string(), sample.name.as_str().into());
+    record.insert("cpu_percent".to_string(), percent(sample.cpu_usage as f64));
+    record.insert(
+        memory.unit.key().to_string(),
+        memory_value(memory, sample.memory_usage),
+    );
+    for (extra, value) in extras.iter().zip(&sample.extras) {
+        if let Some(value) = value {
+            record.insert(extra.id().to_string(), value.to_json());
+        }
+    }
+    stamp(&mut record, scope, run_id);
+    Value::Object(record)
+}
+
+/// Marks a tick in which no process was logged.
+pub fn heartbeat(
+    format: &TimestampFormat,
+    timestamp: &DateTime<Local>,
+    scope: Option<&str>,
+    run_id: Option<&str>,
+) -> Value {
+    marker(
+        format.to_json(timestamp),
+        json!({ "heartbeat": true }),
+        scope,
+        run_id,
+    )
+}
+
+/// Marks that `dropped` ticks are missing before `timestamp`.
+pub fn gap(
+    format: &TimestampFormat,
+    timestamp: &DateTime<Local>,
+    dropped: u64,
+    scope: Option<&str>,
+    run_id: Option<&str>,
+) -> Value {
+    marker(
+        format.to_json(timestamp),
+        json!({ "dropped_ticks": dropped }),
+        scope,
+        run_id,
+    )
+}
+
+/// Marks that nothing was sampled during `gap`.
+pub fn interruption(
+    format: &TimestampFormat,
+    gap: &suspend::Gap,
+    kind: suspend::GapKind,
+    scope: Option<&str>,
+    run_id: Option<&str>,
+) -> Value {
+    let key = match kind {
+        suspend::GapKind::Suspend => "suspended",
+        suspend::GapKind::Resume => "resumed",
+        suspend::GapKind::Reboot => "rebooted",
+    };
+    let fields = json!({
+        format!("{}_from", key): format.to_json(&gap.start),
+        format!("{}_seconds", key): gap.length.as_secs(),
+    });
+    marker(format.to_json(&gap.end), fields, scope, run_id)
+}
+
+/// Marks that a process was spawned or exited.
+pub fn event(
+    format: &TimestampFormat,
+    timestamp: &DateTime<Local>,
+    event: &lifecycle::Event,
+    scope: Option<&str>,
+    run_id: Option<&str>,
+) -> Value {
+    let mut fields = Map::new();
+    fields.insert("event".to_string(), event.kind.name().into());
+    fields.insert("pid".to_string(), event.pid.as_u32().into());
+    fields.insert("name".to_string(), event.name.as_str().into());
+    if let Some(parent) = event.parent {
+        fields.insert("parent_pid".to_string(), parent.as_u32().into());
+    }
+    if let Some(started) = &event.started {
+        fields.insert("started".to_string(), format.to_json(started));
+    }
+    if let Some((last_seen, cpu_usage, memory_usage)) = &event.last_seen {
+        fields.insert("last_seen".to_string(), format.to_json(last_seen));
+        fields.insert("last_cpu_percent".to_string(), percent(*cpu_usage as f64));
+        fields.insert("last_memory_percent".to_string(), percent(*memory_usage));
+    }
+    if let Some(lifetime) = event.lifetime() {
+        fields.insert("lifetime_seconds".to_string(), lifetime.as_secs().into());
+    }
+    if let Some(code) = event.exit_code {
+        fields.insert("exit_code".to_string(), code.into());
+    }
+    marker(
+        format.to_json(timestamp),
+        Value::Object(fields),
+        scope,
+        run_id,
+    )
+}
+
+/// Writes records as the elements of one JSON array, one per line. Every
+/// element but the first starts with the separator, so that each line is
+/// still one row and no trailing comma is left before the closing bracket.
+pub struct ArrayWriter<W: Write> {
+    writer: W,
+    started: bool,
+}
+
+impl<W: Write> ArrayWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            started: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Opens the array, on a line of its own.
+    pub fn open(&mut self) -> io::Result<()> {
+        self.started = false;
+        self.writer.write_all(b"[\n")
+    }
+
+    pub fn write(&mut self, value: &Value) -> io::Result<()> {
+        if self.started {
+            self.writer.write_all(b",")?;
+        }
+        self.started = true;
+        serde_json::to_writer(&mut self.writer, value)?;
+        self.writer.write_all(b"\n")
+    }
+
+    /// Closes the array, after which the output is a complete document.
+    pub fn close(&mut self) -> io::Result<()> {
+        self.writer.write_all(b"]\n")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+fn marker(timestamp: Value, fields: Value, scope: Option<&str>, run_id: Option<&str>) -> Value {
+    let mut marker = Map::new();
+    marker.insert("timestamp".to_string(), timestamp);
+    if let Value::Object(fields) = fields {
+        marker.extend(fields);
+    }
+    stamp(&mut marker, scope, run_id);
+    Value::Object(marker)
+}
+
+fn stamp(record: &mut Map<String, Value>, scope: Option<&str>, run_id: Option<&str>) {
+    if let Some(scope) = scope {
+        record.insert("scope".to_string(), scope.into());
+    }
+    if let Some(run_id) = run_id {
+        record.insert("run_id".to_string(), run_id.into());
+    }
+}
+
+/// Memory usage in the unit of `memory`: an integer for bytes, else rounded
+/// as in CSV.
+fn memory_value(memory: &MemoryFormat, percent_of_total: f64) -> Value {
+    match memory.unit {
+        MemoryUnit::Bytes => numfmt::memory_bytes(percent_of_total, memory.total_memory).into(),
+        _ => percent(memory.value(percent_of_total)),
+    }
+}
+
+/// `value` rounded to the precision of the CSV percentage columns.
+fn percent(value: f64) -> Value {
+    let scale = 10f64.powi(numfmt::PERCENT_PRECISION as i32);
+    let rounded = (value * scale).round() / scale;
+    serde_json::Number::from_f64(rounded)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::columns::ExtraValue;
+
+    #[test]
+    fn test_record() {
+        let sample = ProcessSample {
+            pid: Some(Pid::from_u32(42)),
+            name: "nginx".to_string(),
+            cpu_usage: 12.345_678,
+            memory_usage: 1.0 / 3.0,
+            extras: vec![Some(ExtraValue::Count(4096)), None],
+        };
+        let record = record(
+            &[Extra::Pss, Extra::Uss],
+            &MemoryFormat::default(),
+            &"2026-01-01T00:00:00+00:00".into(),
+            &sample,
+            Some("full"),
+            None,
+        );
+        assert_eq!(
+            record,
+            json!({
+                "timestamp": "2026-01-01T00:00:00+00:00",
+                "pid": 42,
+                "name": "nginx",
+                "cpu_percent": 12.35,
+                "memory_percent": 0.33,
+                "pss_bytes": 4096,
+                "scope": "full",
+            })
+        );
+        assert_eq!(
+            serde_json::to_string(&record["cpu_percent"]).unwrap(),
+            "12.35"
+        );
+    }
+
+    #[test]
+    fn test_array_writer() {
+        let mut array = ArrayWriter::new(Vec::new());
+        array.open().unwrap();
+        array.close().unwrap();
+        assert_eq!(array.get_ref(), b"[\n]\n");
+
+        let mut array = ArrayWriter::new(Vec::new());
+        array.open().unwrap();
+        array.write(&json!({ "pid": 1 })).unwrap();
+        array.write(&json!({ "pid": 2 })).unwrap();
+        array.close().unwrap();
+        let text = String::from_utf8(array.get_ref().clone()).unwrap();
+        assert_eq!(text, "[\n{\"pid\":1}\n,{\"pid\":2}\n]\n");
+        let document: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(document, json!([{ "pid": 1 }, { "pid": 2 }]));
+    }
+}
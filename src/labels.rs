@@ -0,0 +1,126 @@
+use crate::{numfmt, per_pid::PerPid};
+use sysinfo::{Pid, PidExt};
+
+/// Processes beyond this are not cached, bounding the cache on hosts with
+/// huge process tables.
+const MAX_CACHED_PROCESSES: usize = 65_536;
+
+struct Label {
+    start_time: u64,
+    name: String,
+    pid_text: String,
+}
+
+/// Remembers the name and formatted PID of each process seen in the
+/// previous tick, keyed by PID and start time, so a process still running
+/// can reuse them instead of renaming and formatting them again. A reused
+/// PID has a different start time and is treated as a new process, and a
+/// process that exits is forgotten after the next tick. Rows can be
+/// reordered or dropped freely, since nothing depends on their position.
+#[derive(Default)]
+pub struct LabelCache {
+    labels: PerPid<Label>,
+    hits: u64,
+    misses: u64,
+}
+
+impl LabelCache {
+    /// Starts a new tick, forgetting processes the tick that ended did not
+    /// see.
+    pub fn begin_tick(&mut self) {
+        self.labels.begin_tick();
+    }
+
+    /// Sets `name` to the name of the process with `pid` and `start_time`,
+    /// the cached one if the previous tick saw the process, otherwise the
+    /// one `label` returns.
+    pub fn write_name<'a>(
+        &mut self,
+        pid: Pid,
+        start_time: u64,
+        name: &mut String,
+        label: impl FnOnce() -> &'a str,
+    ) {
+        name.clear();
+        let cached = self
+            .labels
+            .take(pid.as_u32())
+            .filter(|cached| cached.start_time == start_time);
+        let entry = match cached {
+            Some(cached) => {
+                self.hits += 1;
+                name.push_str(&cached.name);
+                cached
+            }
+            None => {
+                self.misses += 1;
+                name.push_str(label());
+                if self.labels.len() >= MAX_CACHED_PROCESSES {
+                    return;
+                }
+                let mut pid_text = String::new();
+                numfmt::write_int(&mut pid_text, pid.as_u32());
+                Label {
+                    start_time,
+                    name: name.clone(),
+                    pid_text,
+                }
+            }
+        };
+        self.labels.insert(pid.as_u32(), entry);
+    }
+
+    /// The formatted PID of `pid`, if this tick cached it.
+    pub fn pid_text(&self, pid: Pid) -> Option<&str> {
+        self.labels
+            .get(pid.as_u32())
+            .map(|label| label.pid_text.as_str())
+    }
+
+    /// Returns the hit rate since the last call and resets the counters.
+    pub fn take_hit_rate(&mut self) -> f64 {
+        let total = self.hits + self.misses;
+        let rate = if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        };
+        self.hits = 0;
+        self.misses = 0;
+        rate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hits_and_invalidation() {
+        let mut cache = LabelCache::default();
+        let (a, b) = (Pid::from_u32(10), Pid::from_u32(20));
+        let mut name = String::new();
+        cache.begin_tick();
+        cache.write_name(a, 100, &mut name, || "nginx");
+        cache.write_name(b, 200, &mut name, || "sshd");
+        assert_eq!(cache.take_hit_rate(), 0.0);
+
+        // Seen in the other order, process 10 keeps its name.
+        cache.begin_tick();
+        // PID 20 was reused by a process started later.
+        cache.write_name(b, 300, &mut name, || "cron");
+        assert_eq!(name, "cron");
+        cache.write_name(a, 100, &mut name, || unreachable!());
+        assert_eq!(name, "nginx");
+        assert_eq!(cache.take_hit_rate(), 0.5);
+        assert_eq!(cache.pid_text(b), Some("20"));
+
+        // Process 10 exited, so it is forgotten.
+        cache.begin_tick();
+        cache.write_name(b, 300, &mut name, || unreachable!());
+        assert_eq!(cache.pid_text(a), None);
+        cache.begin_tick();
+        cache.write_name(a, 100, &mut name, || "nginx");
+        assert_eq!(cache.take_hit_rate(), 0.5);
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{timestamp::TimestampFormat, ProcessSample};
+use chrono::{DateTime, Local, TimeZone};
+use std::{collections::HashMap, time::Duration};
+use sysinfo::Pid;
+
+/// What happened to a process between two ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Spawned,
+    Exited,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Spawned => "SPAWNED",
+            Kind::Exited => "EXITED",
+        }
+    }
+}
+
+/// A process that appeared or disappeared between two ticks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub kind: Kind,
+    pub pid: Pid,
+    pub name: String,
+    /// When the process started, where the source knows.
+    pub started: Option<DateTime<Local>>,
+    /// The parent of a spawned process, where the source knows.
+    pub parent: Option<Pid>,
+    /// The last tick an exited process was seen on, and its CPU and memory
+    /// usage then. It exited between that tick and the one the event is
+    /// written at.
+    pub last_seen: Option<(DateTime<Local>, f32, f64)>,
+    /// How the command started by `--exec` exited, as a shell reports it.
+    pub exit_code: Option<i32>,
+}
+
+impl Event {
+    /// How long an exited process ran for at least: from its start to the
+    /// last tick it was seen on.
+    pub fn lifetime(&self) -> Option<Duration> {
+        let (last_seen, ..) = self.last_seen?;
+        (last_seen - self.started?).to_std().ok()
+    }
+
+    /// The process name field of the event's CSV row, e.g.
+    /// `[EXITED nginx, last seen 12:00:01, ran 42s, exit code 1]`.
+    pub fn label(&self, timestamps: &TimestampFormat) -> String {
+        let mut label = format!("[{} {}", self.kind.name(), self.name);
+        if let Some(parent) = self.parent {
+            label.push_str(&format!(", parent {}", parent));
+        }
+        if let Some((last_seen, ..)) = self.last_seen {
+            label.push_str(&format!(", last seen {}", timestamps.format(&last_seen)));
+        }
+        if let Some(lifetime) = self.lifetime() {
+            label.push_str(&format!(", ran {}s", lifetime.as_secs()));
+        }
+        if let Some(code) = self.exit_code {
+            label.push_str(&format!(", exit code {}", code));
+        }
+        label.push(']');
+        label
+    }
+}
+
+struct Tracked {
+    name: String,
+    /// The start time the source gave, 0 if unknown, which tells a process
+    /// apart from a later one given its PID.
+    start_time: u64,
+    cpu_usage: f32,
+    memory_usage: f64,
+    /// The tick the process was last seen on.
+    seen: u64,
+}
+
+/// Finds the processes that appeared and disappeared between consecutive
+/// ticks. The processes of the first tick were already running and are not
+/// reported as spawned.
+pub struct Lifecycle {
+    processes: HashMap<Pid, Tracked>,
+    tick: u64,
+    /// When the previous tick was sampled.
+    last: Option<DateTime<Local>>,
+    events: Vec<Event>,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self {
+            processes: HashMap::new(),
+            tick: 0,
+            last: None,
+            events: Vec::new(),
+        }
+    }
+
+    /// Starts a tick, forgetting the events of the previous one.
+    pub fn begin_tick(&mut self) {
+        self.tick += 1;
+        self.events.clear();
+    }
+
+    /// Notes that the process of `sample` is running. `start_time` is in
+    /// seconds since the Unix epoch, 0 if unknown.
+    pub fn observe(&mut self, sample: &ProcessSample, start_time: u64, parent: Option<Pid>) {
+        // The row of a group, replayed from a capture, is not one process.
+        let Some(pid) = sample.pid else {
+            return;
+        };
+        let tick = self.tick;
+        let reported = self.last.is_some();
+        let replaced = match self.processes.get_mut(&pid) {
+            Some(known)
+                if known.start_time == start_time || known.start_time == 0 || start_time == 0 =>
+            {
+                // The name may change when the process runs another program.
+                if known.name != sample.name {
+                    known.name.clone_from(&sample.name);
+                }
+                known.cpu_usage = sample.cpu_usage;
+                known.memory_usage = sample.memory_usage;
+                known.seen = tick;
+                return;
+            }
+            _ => self.processes.insert(
+                pid,
+                Tracked {
+                    name: sample.name.clone(),
+                    start_time,
+                    cpu_usage: sample.cpu_usage,
+                    memory_usage: sample.memory_usage,
+                    seen: tick,
+                },
+            ),
+        };
+        if !reported {
+            return;
+        }
+        // A PID given to another process: the first one exited.
+        if let Some(previous) = replaced {
+            let event = self.exited(pid, previous, None);
+            self.events.push(event);
+        }
+        self.events.push(Event {
+            kind: Kind::Spawned,
+            pid,
+            name: sample.name.clone(),
+            started: from_epoch(start_time),
+            parent,
+            last_seen: None,
+            exit_code: None,
+        });
+    }
+
+    /// Ends the tick sampled at `now`, reporting the processes not seen in
+    /// it as exited. `exit_code` gives how a process exited, where known.
+    pub fn end_tick(&mut self, now: &DateTime<Local>, exit_code: impl Fn(Pid) -> Option<i32>) {
+        let tick = self.tick;
+        let gone: Vec<Pid> = self
+            .processes
+            .iter()
+            .filter(|(_, tracked)| tracked.seen != tick)
+            .map(|(pid, _)| *pid)
+            .collect();
+        for pid in gone {
+            let tracked = self.processes.remove(&pid).expect("Listed above");
+            let event = self.exited(pid, tracked, exit_code(pid));
+            self.events.push(event);
+        }
+        // Exits first, then spawns, each in PID order.
+        self.events
+            .sort_by_key(|event| (event.kind == Kind::Spawned, event.pid));
+        self.last = Some(*now);
+    }
+
+    /// The events found by the last tick.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    fn exited(&self, pid: Pid, tracked: Tracked, exit_code: Option<i32>) -> Event {
+        Event {
+            kind: Kind::Exited,
+            pid,
+            name: tracked.name,
+            started: from_epoch(tracked.start_time),
+            parent: None,
+            last_seen: self
+                .last
+                .map(|last| (last, tracked.cpu_usage, tracked.memory_usage)),
+            exit_code,
+        }
+    }
+}
+
+fn from_epoch(seconds: u64) -> Option<DateTime<Local>> {
+    if seconds == 0 {
+        return None;
+    }
+    Local.timestamp_opt(seconds as i64, 0).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+    use crate::timestamp::Style;
+    use sysinfo::PidExt;
+
+    fn tick(
+        lifecycle: &mut Lifecycle,
+        now: &DateTime<Local>,
+        processes: &[(u32, &str, u64)],
+    ) -> Vec<(Kind, u32, String)> {
+        lifecycle.begin_tick();
+        for (pid, name, start_time) in processes {
+            lifecycle.observe(
+                &sample(*pid, name, 1.5, 0.25),
+                *start_time,
+                Some(Pid::from_u32(1)),
+            );
+        }
+        lifecycle.end_tick(now, |pid| (pid.as_u32() == 3).then_some(137));
+        lifecycle
+            .events()
+            .iter()
+            .map(|event| (event.kind, event.pid.as_u32(), event.name.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_spawns_and_exits() {
+        let mut lifecycle = Lifecycle::new();
+        let first = Local.timestamp_opt(1_700_000_100, 0).unwrap();
+        let second = Local.timestamp_opt(1_700_000_101, 0).unwrap();
+        let third = Local.timestamp_opt(1_700_000_102, 0).unwrap();
+        let running = [(2, "init", 1_700_000_000), (3, "worker", 1_700_000_050)];
+        assert!(tick(&mut lifecycle, &first, &running).is_empty());
+
+        let events = tick(&mut lifecycle, &second, &[running[1], (4, "job", 0)]);
+        assert_eq!(
+            events,
+            [
+                (Kind::Exited, 2, "init".to_string()),
+                (Kind::Spawned, 4, "job".to_string())
+            ]
+        );
+        let spawned = &lifecycle.events()[1];
+        assert_eq!(spawned.parent, Some(Pid::from_u32(1)));
+        assert_eq!(spawned.started, None);
+
+        let events = tick(&mut lifecycle, &third, &[(4, "job", 0)]);
+        assert_eq!(events, [(Kind::Exited, 3, "worker".to_string())]);
+        let exited = &lifecycle.events()[0];
+        assert_eq!(exited.exit_code, Some(137));
+        assert_eq!(exited.last_seen, Some((second, 1.5, 0.25)));
+        assert_eq!(exited.lifetime(), Some(Duration::from_secs(51)));
+    }
+
+    #[test]
+    fn test_reused_pid() {
+        let mut lifecycle = Lifecycle::new();
+        let now = Local::now();
+        tick(&mut lifecycle, &now, &[(5, "old", 100)]);
+        let events = tick(&mut lifecycle, &now, &[(5, "new", 200)]);
+        assert_eq!(
+            events,
+            [
+                (Kind::Exited, 5, "old".to_string()),
+                (Kind::Spawned, 5, "new".to_string())
+            ]
+        );
+        // A process that runs another program is the same process.
+        assert!(tick(&mut lifecycle, &now, &[(5, "exec", 200)]).is_empty());
+    }
+
+    #[test]
+    fn test_label() {
+        let event = Event {
+            kind: Kind::Exited,
+            pid: Pid::from_u32(7),
+            name: "nginx".to_string(),
+            started: Local.timestamp_opt(1_700_000_000, 0).single(),
+            parent: None,
+            last_seen: Some((Local.timestamp_opt(1_700_000_042, 0).unwrap(), 0.0, 0.0)),
+            exit_code: Some(1),
+        };
+        let label = event.label(&TimestampFormat::new(Style::Epoch, false));
+        assert_eq!(
+            label,
+            "[EXITED nginx, last seen 1700000042, ran 42s, exit code 1]"
+        );
+    }
+}
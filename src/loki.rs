@@ -0,0 +1,289 @@
+use crate::{
+    http::{self, Url},
+    push::{self, Push, PushError, PushQueue},
+    sink::Sink,
+    ProcessSample,
+};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
+
+const PUSH_PATH: &str = "loki/api/v1/push";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+pub const MAX_BUFFERED_ENTRIES: usize = 100_000;
+
+#[derive(Debug, Clone)]
+pub struct LokiConfig {
+    pub url: Url,
+    pub host: String,
+    pub batch_bytes: usize,
+    pub max_buffered: usize,
+    /// Added to the labels of every stream.
+    pub run_id: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    timestamp_ns: String,
+    line: String,
+}
+
+/// Ships every tick's samples to Grafana Loki as JSON log lines, one stream
+/// per process name. Pushes happen on a background thread; entries that
+/// cannot be delivered yet, or are published while a push is under way, are
+/// buffered up to `max_buffered` and then dropped oldest first.
+pub struct LokiSink {
+    queue: PushQueue<Entry>,
+}
+
+impl LokiSink {
+    pub fn new(config: LokiConfig) -> Self {
+        info!(
+            "Pushing samples to Loki at {}:{} in batches of up to {} bytes",
+            config.url.host, config.url.port, config.batch_bytes
+        );
+        let max_buffered = config.max_buffered;
+        Self {
+            queue: PushQueue::new(Pusher { config }, max_buffered, None),
+        }
+    }
+}
+
+impl Sink for LokiSink {
+    fn publish(&mut self, timestamp: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        let timestamp_ns = timestamp
+            .timestamp_nanos_opt()
+            .unwrap_or_default()
+            .to_string();
+        self.queue.publish(samples.iter().map(|sample| Entry {
+            name: sample.name.clone(),
+            timestamp_ns: timestamp_ns.clone(),
+            line: sample.to_json().to_string(),
+        }));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let dropped = self.queue.finish();
+        if dropped > 0 {
+            warn!(
+                "Dropped {} Loki entries that could not be delivered",
+                dropped
+            );
+        }
+        Ok(())
+    }
+}
+
+struct Pusher {
+    config: LokiConfig,
+}
+
+impl Push for Pusher {
+    type Item = Entry;
+
+    const NAME: &'static str = "Loki push";
+    const ITEMS: &'static str = "entries";
+
+    /// As many entries as fit in `batch_bytes`, and at least one.
+    fn batch_len(&self, entries: &VecDeque<Entry>) -> usize {
+        let mut size = 0;
+        let mut count = 0;
+        for entry in entries {
+            size += entry.line.len();
+            if count > 0 && size > self.config.batch_bytes {
+                break;
+            }
+            count += 1;
+        }
+        count
+    }
+
+    fn push(&mut self, batch: &[Entry]) -> Result<(), PushError> {
+        let config = &self.config;
+        let body = encode(&config.host, config.run_id.as_deref(), batch);
+        let response = http::send(
+            "POST",
+            &config.url,
+            &config.url.join(PUSH_PATH),
+            "application/json",
+            body.as_bytes(),
+            REQUEST_TIMEOUT,
+        )
+        .map_err(PushError::Retryable)?;
+
+        match response.status {
+            200..=299 => Ok(()),
+            429 | 500..=599 => Err(PushError::Retryable(push::status_error("Loki", &response))),
+            _ => Err(PushError::Rejected(push::status_error("Loki", &response))),
+        }
+    }
+}
+
+/// Encodes a batch as a Loki push request with one stream per process name.
+fn encode(host: &str, run_id: Option<&str>, batch: &[Entry]) -> String {
+    let mut streams: BTreeMap<&str, Vec<[&str; 2]>> = BTreeMap::new();
+    for entry in batch {
+        streams
+            .entry(&entry.name)
+            .or_default()
+            .push([&entry.timestamp_ns, &entry.line]);
+    }
+
+    let streams: Vec<_> = streams
+        .into_iter()
+        .map(|(name, values)| {
+            let mut stream = json!({ "job": "chronologger", "host": host, "name": name });
+            if let Some(run_id) = run_id {
+                stream["run_id"] = json!(run_id);
+            }
+            json!({ "stream": stream, "values": values })
+        })
+        .collect();
+    json!({ "streams": streams }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::testing::{answer, capture_requests, count_requests, local_url};
+    use crate::test_support::sample;
+    use std::{
+        net::TcpListener,
+        sync::{atomic::Ordering, mpsc},
+        thread,
+    };
+
+    #[test]
+    fn test_push_labels_and_lines() {
+        let (url, server) = capture_requests(1, 204);
+        let mut sink = LokiSink::new(LokiConfig {
+            url,
+            host: "host1".to_string(),
+            batch_bytes: 1 << 20,
+            max_buffered: 1000,
+            run_id: Some("run-1".to_string()),
+        });
+        sink.publish(
+            &Local::now(),
+            &[sample(1, "init", 1.5, 0.25), sample(2, "sshd", 1.5, 0.25)],
+        )
+        .expect("Failed to publish samples");
+        sink.finish().expect("Failed to finish sink");
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /loki/api/v1/push HTTP/1.1"));
+        let body = &requests[0][requests[0].find("\r\n\r\n").unwrap() + 4..];
+        let payload: serde_json::Value = serde_json::from_str(body).expect("Invalid JSON payload");
+        let streams = payload["streams"].as_array().expect("Missing streams");
+        assert_eq!(streams.len(), 2);
+        assert_eq!(
+            streams[0]["stream"],
+            json!({ "job": "chronologger", "host": "host1", "name": "init", "run_id": "run-1" })
+        );
+        let line: serde_json::Value =
+            serde_json::from_str(streams[1]["values"][0][1].as_str().unwrap()).unwrap();
+        assert_eq!(line["pid"], 2);
+        assert_eq!(line["name"], "sshd");
+        assert_eq!(line["cpu_percent"], 1.5);
+    }
+
+    #[test]
+    fn test_drops_entries_when_loki_rejects() {
+        let (url, server) = capture_requests(1, 400);
+        let mut sink = LokiSink::new(LokiConfig {
+            url,
+            host: "host1".to_string(),
+            batch_bytes: 1 << 20,
+            max_buffered: 1000,
+            run_id: None,
+        });
+        sink.publish(&Local::now(), &[sample(1, "init", 1.5, 0.25)])
+            .expect("Failed to publish samples");
+        sink.finish().expect("Failed to finish sink");
+        server.join().unwrap();
+
+        assert_eq!(sink.queue.dropped(), 1);
+    }
+
+    #[test]
+    fn test_backs_off_while_rate_limited() {
+        let (url, requests) = count_requests(429);
+        let mut sink = LokiSink::new(LokiConfig {
+            url,
+            host: "host1".to_string(),
+            batch_bytes: 1 << 20,
+            max_buffered: 1000,
+            run_id: None,
+        });
+        // Ticks keep coming while the first retry waits 500ms.
+        for pid in 1..=4 {
+            sink.publish(&Local::now(), &[sample(pid, "init", 1.5, 0.25)])
+                .unwrap();
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(sink.queue.buffered(), 4);
+        sink.finish().expect("Failed to finish sink");
+        assert_eq!(sink.queue.dropped(), 4);
+    }
+
+    #[test]
+    fn test_bounds_buffer_while_push_stalls() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = local_url(&listener);
+        let (connected, stalled) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            connected.send(()).unwrap();
+            released.recv().unwrap();
+            answer(stream, 204);
+            answer(listener.accept().unwrap().0, 204)
+        });
+        let mut sink = LokiSink::new(LokiConfig {
+            url,
+            host: "host1".to_string(),
+            batch_bytes: 1 << 20,
+            max_buffered: 5,
+            run_id: None,
+        });
+        sink.publish(&Local::now(), &[sample(1, "init", 1.5, 0.25)])
+            .unwrap();
+        stalled.recv().unwrap();
+
+        // The first push is still waiting for Loki.
+        for tick in 0..3 {
+            let samples: Vec<_> = (0..4)
+                .map(|index| sample(10 + tick * 4 + index, "worker", 1.5, 0.25))
+                .collect();
+            sink.publish(&Local::now(), &samples).unwrap();
+        }
+        assert_eq!(sink.queue.buffered(), 5);
+        assert_eq!(sink.queue.dropped(), 7);
+
+        release.send(()).unwrap();
+        sink.finish().expect("Failed to finish sink");
+        let request = server.join().unwrap();
+        let body = &request[request.find("\r\n\r\n").unwrap() + 4..];
+        let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+        let pids: Vec<u64> = payload["streams"][0]["values"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|value| {
+                let line: serde_json::Value =
+                    serde_json::from_str(value[1].as_str().unwrap()).unwrap();
+                line["pid"].as_u64().unwrap()
+            })
+            .collect();
+        assert_eq!(pids, [17, 18, 19, 20, 21]);
+        assert_eq!(sink.queue.dropped(), 7);
+    }
+}
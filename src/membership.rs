@@ -0,0 +1,276 @@
+//! `--in-cgroup` and `--container`: restricting a capture to the processes
+//! of one cgroup or container, found in `/proc/<pid>/cgroup`.
+
+use crate::{containers::Container, per_pid::PerPid};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+/// Where the unified cgroup hierarchy is mounted.
+const CGROUP_ROOT: &str = "sys/fs/cgroup";
+
+/// Limits at or above this, such as cgroup v1's stand-in for no limit, are
+/// no limit at all.
+const UNLIMITED_BYTES: u64 = 1 << 62;
+
+/// Which processes are captured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// The processes of this cgroup and the cgroups below it, by the path
+    /// `/proc/<pid>/cgroup` gives, e.g. `/system.slice/nginx.service`.
+    Cgroup(String),
+    /// The processes of the container whose ID starts with this, or of the
+    /// Kubernetes pod with this UID.
+    Container(String),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Cgroup(path) => f.write_str(path),
+            Target::Container(id) => f.write_str(id),
+        }
+    }
+}
+
+/// Parses `--in-cgroup`: a cgroup path, or its directory under
+/// `/sys/fs/cgroup`.
+pub fn parse_cgroup(value: &str) -> Result<Target, String> {
+    if !value.starts_with('/') {
+        return Err("Cgroup path must start with /".to_string());
+    }
+    let path = value[1..]
+        .strip_prefix(CGROUP_ROOT)
+        .filter(|path| path.is_empty() || path.starts_with('/'))
+        .unwrap_or(value)
+        .trim_end_matches('/');
+    match path {
+        "" => Ok(Target::Cgroup("/".to_string())),
+        path => Ok(Target::Cgroup(path.to_string())),
+    }
+}
+
+/// Parses `--container`: a container ID or a prefix of it, as `docker ps`
+/// shows, or a pod UID.
+pub fn parse_container(value: &str) -> Result<Target, String> {
+    let id = value.to_ascii_lowercase();
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        return Err("Expected a container ID or pod UID".to_string());
+    }
+    Ok(Target::Container(id))
+}
+
+impl Target {
+    /// The cgroup path in one line of `/proc/<pid>/cgroup` that puts the
+    /// process in the target, if any.
+    fn path_in<'a>(&self, line: &'a str) -> Option<&'a str> {
+        let path = line.splitn(3, ':').nth(2)?;
+        let member = match self {
+            Target::Cgroup(target) if target == "/" => true,
+            Target::Cgroup(target) => path
+                .strip_prefix(target.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/')),
+            Target::Container(target) => Container::parse(line).is_some_and(|container| {
+                container.pod.as_deref() == Some(target.as_str())
+                    || container
+                        .id
+                        .is_some_and(|id| id.starts_with(target.as_str()))
+            }),
+        };
+        member.then_some(path)
+    }
+}
+
+/// Tells which processes belong to the target. A PID's cgroups are read
+/// once, and forgotten once a tick goes by without the PID, like the
+/// containers of the `container` column.
+pub struct Membership {
+    root: PathBuf,
+    target: Target,
+    /// Whether each PID belongs to the target.
+    members: PerPid<bool>,
+}
+
+impl Membership {
+    pub fn new(target: Target) -> Self {
+        Self::with_root(PathBuf::from("/"), target)
+    }
+
+    /// Reads `/proc` and `/sys/fs/cgroup` below `root` instead of `/`.
+    fn with_root(root: PathBuf, target: Target) -> Self {
+        Self {
+            root,
+            target,
+            members: PerPid::new(),
+        }
+    }
+
+    /// Starts a new tick, forgetting PIDs the tick that ended did not see.
+    pub fn begin_tick(&mut self) {
+        self.members.begin_tick();
+    }
+
+    /// Whether `pid` belongs to the target. A process whose cgroups cannot
+    /// be read, e.g. because it already exited, does not.
+    pub fn contains(&mut self, pid: u32) -> bool {
+        let member = match self.members.take(pid) {
+            Some(member) => member,
+            None => self.membership(pid).is_some_and(|membership| {
+                membership
+                    .lines()
+                    .any(|line| self.target.path_in(line).is_some())
+            }),
+        };
+        self.members.insert(pid, member);
+        member
+    }
+
+    fn membership(&self, pid: impl fmt::Display) -> Option<String> {
+        fs::read_to_string(self.root.join("proc").join(pid.to_string()).join("cgroup")).ok()
+    }
+
+    /// The memory limit of the target in KB, if it has one. A container's
+    /// cgroup is found through one of its processes, so a container with no
+    /// process running has none.
+    pub fn memory_limit(&self) -> Option<f64> {
+        let cgroups = self.root.join(CGROUP_ROOT);
+        let limit = match &self.target {
+            Target::Cgroup(path) => limit_of(&cgroups, "", path),
+            Target::Container(_) => {
+                let membership = fs::read_dir(self.root.join("proc"))
+                    .ok()?
+                    .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+                    .find_map(|pid| {
+                        self.membership(pid).filter(|membership| {
+                            membership
+                                .lines()
+                                .any(|line| self.target.path_in(line).is_some())
+                        })
+                    })?;
+                membership.lines().find_map(|line| {
+                    let path = self.target.path_in(line)?;
+                    let controllers = line.split(':').nth(1)?;
+                    limit_of(&cgroups, controllers, path)
+                })
+            }
+        }?;
+        Some(limit as f64 / 1024.0)
+    }
+}
+
+/// The memory limit in bytes of the cgroup at `path` of the hierarchy with
+/// `controllers`: `memory.max` in the unified hierarchy, or, for a cgroup
+/// given by path alone, `memory.limit_in_bytes` of the v1 memory controller.
+fn limit_of(cgroups: &Path, controllers: &str, path: &str) -> Option<u64> {
+    let relative = path.trim_start_matches('/');
+    let file = match controllers {
+        "" if cgroups.join(relative).join("memory.max").is_file() => {
+            cgroups.join(relative).join("memory.max")
+        }
+        "" => cgroups
+            .join("memory")
+            .join(relative)
+            .join("memory.limit_in_bytes"),
+        controllers if controllers.split(',').any(|c| c == "memory") => cgroups
+            .join(controllers)
+            .join(relative)
+            .join("memory.limit_in_bytes"),
+        _ => return None,
+    };
+    // The unified hierarchy's `max`, for no limit, is not a number.
+    let limit = fs::read_to_string(file).ok()?.trim().parse::<u64>().ok()?;
+    (limit < UNLIMITED_BYTES).then_some(limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const ID: &str = "4f1c9a3b2e7d8f60a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718";
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_cgroup("/sys/fs/cgroup/system.slice/nginx.service/"),
+            Ok(Target::Cgroup("/system.slice/nginx.service".to_string()))
+        );
+        assert_eq!(parse_cgroup("/"), Ok(Target::Cgroup("/".to_string())));
+        assert!(parse_cgroup("system.slice").is_err());
+        assert_eq!(
+            parse_container("4F1C9A3B2E7D"),
+            Ok(Target::Container("4f1c9a3b2e7d".to_string()))
+        );
+        assert!(parse_container("web").is_err());
+    }
+
+    #[test]
+    fn test_cgroup_members() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+        write(dir, "proc/10/cgroup", "0::/system.slice/nginx.service\n");
+        write(
+            dir,
+            "proc/11/cgroup",
+            "0::/system.slice/nginx.service/worker\n",
+        );
+        write(
+            dir,
+            "proc/12/cgroup",
+            "0::/system.slice/nginx.service-old\n",
+        );
+        write(
+            dir,
+            "sys/fs/cgroup/system.slice/nginx.service/memory.max",
+            "1048576\n",
+        );
+        let target = parse_cgroup("/system.slice/nginx.service").unwrap();
+        let mut membership = Membership::with_root(dir.to_path_buf(), target);
+
+        membership.begin_tick();
+        assert!(membership.contains(10));
+        assert!(membership.contains(11), "Cgroups below belong to it");
+        assert!(!membership.contains(12));
+        assert!(!membership.contains(13), "Unreadable processes do not");
+        assert_eq!(membership.memory_limit(), Some(1024.0));
+
+        write(
+            dir,
+            "sys/fs/cgroup/system.slice/nginx.service/memory.max",
+            "max\n",
+        );
+        assert_eq!(membership.memory_limit(), None);
+    }
+
+    #[test]
+    fn test_container_members() {
+        let root = TempDir::new().unwrap();
+        let dir = root.path();
+        let scope = format!("/system.slice/docker-{}.scope", ID);
+        write(
+            dir,
+            "proc/20/cgroup",
+            &format!("9:memory:{}\n3:cpu,cpuacct:{}\n0::/\n", scope, scope),
+        );
+        write(dir, "proc/21/cgroup", "9:memory:/user.slice\n0::/\n");
+        write(
+            dir,
+            &format!("sys/fs/cgroup/memory{}/memory.limit_in_bytes", scope),
+            "2097152\n",
+        );
+        let target = parse_container(&ID[..12]).unwrap();
+        let mut membership = Membership::with_root(dir.to_path_buf(), target);
+
+        membership.begin_tick();
+        assert!(membership.contains(20));
+        assert!(!membership.contains(21));
+        assert_eq!(membership.memory_limit(), Some(2048.0));
+    }
+}
@@ -0,0 +1,558 @@
+use crate::{membership::Target, numfmt::MemoryUnit, Config, Invariants, Mode};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use std::{
+    fs::{self, File},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+use sysinfo::{ProcessorExt, RefreshKind, System, SystemExt};
+
+/// Version of the output schema: the default columns, their order and what
+/// their values mean. Bump it whenever a default column is added, removed,
+/// renamed or changes its meaning, e.g. if CPU usage were normalized to the
+/// number of cores, and describe the new version below. Readers refuse
+/// captures with a version newer than this.
+///
+/// Versions:
+///
+/// 1. `Timestamp` (RFC 3339 with the local offset), `PID`, `Process Name`,
+///    `CPU Usage (%)` (100% per fully used core) and `Memory Usage (%)` (of
+///    total memory), followed by the optional columns, `Scope` and `Run ID`
+///    when enabled. Markers have an empty PID and a bracketed name.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// What comment lines of the metadata block start with.
+const COMMENT: &str = "# ";
+
+/// How a capture was taken: `key: value` pairs in the order they were
+/// recorded. CSV can carry them as `#` comment lines above the header, and
+/// every format in the [`Sidecar`] next to the output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    entries: Vec<(String, String)>,
+}
+
+impl Metadata {
+    /// Describes a run with `config` about to start at `started`, writing
+    /// `columns`.
+    pub fn capture(
+        config: &Config,
+        invariants: &Invariants,
+        columns: &[&str],
+        started: DateTime<Local>,
+    ) -> Self {
+        let mut metadata = Self::default();
+        metadata.push("tool_version", env!("CARGO_PKG_VERSION"));
+        // Focused names are as telling as the names they focus on.
+        let secrets: &[&str] = match &config.anonymize {
+            Some(_) => &["--anonymize-key", "--focus"],
+            None => &["--anonymize-key"],
+        };
+        let args = std::env::args_os().map(|arg| arg.to_string_lossy().into_owned());
+        let line = command_line(masked(args, secrets).into_iter());
+        match &config.redactor {
+            Some(redactor) => metadata.push("command_line", redactor.redact(&line)),
+            None => metadata.push("command_line", line),
+        }
+        metadata.push("run_id", &config.run_id);
+        metadata.push("hostname", &invariants.hostname);
+        match &config.replay {
+            // The live host is not the one the samples were taken on.
+            Some(replay) => metadata.push("replay_of", replay.path.display()),
+            None => {
+                let system = System::new_with_specifics(RefreshKind::new().with_cpu());
+                metadata.push("os", system.long_os_version().unwrap_or_default());
+                metadata.push("kernel", system.kernel_version().unwrap_or_default());
+                let model = system.processors().first().map(|cpu| cpu.brand().trim());
+                if let Some(model) = model.filter(|model| !model.is_empty()) {
+                    metadata.push("cpu_model", model);
+                }
+                metadata.push("cpu_count", system.processors().len());
+            }
+        }
+        metadata.push("total_memory_kb", invariants.total_memory);
+        if let Some(boot_time) = invariants.boot_time {
+            metadata.push(
+                "boot_time_utc",
+                boot_time
+                    .with_timezone(&Utc)
+                    .to_rfc3339_opts(SecondsFormat::Secs, true),
+            );
+            let uptime = (started - boot_time).num_seconds().max(0);
+            metadata.push("uptime_seconds", uptime);
+        }
+        metadata.push(
+            "start_utc",
+            started
+                .with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+        );
+        metadata.push(
+            "start_local",
+            started.to_rfc3339_opts(SecondsFormat::Millis, false),
+        );
+        if config.once {
+            metadata.push("mode", "snapshot");
+        } else {
+            metadata.push("interval_seconds", config.interval.as_secs_f64());
+        }
+        if !config.focus.is_empty() {
+            metadata.push(
+                "focus_interval_seconds",
+                config.focus_interval.as_secs_f64(),
+            );
+        }
+        if let Some(duration) = config.duration.filter(|_| !config.once) {
+            metadata.push("duration_seconds", duration);
+        }
+        if let Some(max_gap) = config.dedup {
+            metadata.push("dedup_max_gap", max_gap);
+        }
+        // The interval only holds until chronologger has to slow down.
+        if let Some(max) = config.max_self_cpu {
+            metadata.push("max_self_cpu_percent", max);
+        }
+        if config.mode != Mode::Process {
+            metadata.push(
+                "output_mode",
+                clap::ValueEnum::to_possible_value(&config.mode)
+                    .map(|value| value.get_name().to_string())
+                    .unwrap_or_default(),
+            );
+        }
+        // What 100% CPU usage stands for: one fully used core, or every
+        // core of the host.
+        let cpu_usage = if config.normalize_cpu {
+            "normalized"
+        } else {
+            "per-core"
+        };
+        metadata.push("cpu_usage", cpu_usage);
+        // The cores CPU usage is normalized to, as the backend counts them.
+        if config.replay.is_none() {
+            metadata.push("cores", invariants.cpu_count);
+        }
+        if config.memory_unit != MemoryUnit::Percent {
+            metadata.push(
+                "memory_usage",
+                clap::ValueEnum::to_possible_value(&config.memory_unit)
+                    .map(|value| value.get_name().to_string())
+                    .unwrap_or_default(),
+            );
+        }
+        if config.aggregate_tree {
+            metadata.push("tree_usage", "aggregated");
+        }
+        if let Some(by) = config.group_by {
+            metadata.push(
+                "group_by",
+                clap::ValueEnum::to_possible_value(&by)
+                    .map(|value| value.get_name().to_string())
+                    .unwrap_or_default(),
+            );
+        }
+        if let Some((count, key)) = config.top {
+            let key = clap::ValueEnum::to_possible_value(&key)
+                .map(|value| value.get_name().to_string())
+                .unwrap_or_default();
+            metadata.push("top", format!("{} by {}", count, key));
+        }
+        if !config.pids.is_empty() {
+            let pids: Vec<String> = config.pids.iter().map(ToString::to_string).collect();
+            metadata.push("pid_filter", pids.join(","));
+        }
+        if !config.names.is_empty() {
+            let filter = if config.exact {
+                "name_filter_exact"
+            } else {
+                "name_filter"
+            };
+            metadata.push(filter, config.names.join(","));
+        }
+        if !config.users.is_empty() {
+            let users: Vec<String> = config.users.iter().map(ToString::to_string).collect();
+            metadata.push("user_filter", users.join(","));
+        }
+        if !config.excludes.is_empty() {
+            metadata.push("exclude_filter", config.excludes.join(","));
+        }
+        match &config.membership {
+            Some(target @ Target::Cgroup(_)) => metadata.push("cgroup_filter", target),
+            Some(target @ Target::Container(_)) => metadata.push("container_filter", target),
+            None => {}
+        }
+        if !config.focus.is_empty() {
+            let targets: Vec<String> = config.focus.iter().map(ToString::to_string).collect();
+            metadata.push("focus", targets.join(","));
+        }
+        if let Some(anonymize) = &config.anonymize {
+            metadata.push(
+                "anonymized_names",
+                clap::ValueEnum::to_possible_value(&anonymize.mode)
+                    .map(|value| value.get_name().to_string())
+                    .unwrap_or_default(),
+            );
+        }
+        if let Some(root) = &config.host_proc {
+            metadata.push("host_proc", root.display());
+        }
+        metadata.push("columns", columns.join(","));
+        metadata.push("schema_version", SCHEMA_VERSION);
+        metadata
+    }
+
+    pub fn push(&mut self, key: &str, value: impl ToString) {
+        // Every entry has to fit on its comment line.
+        let value = value.to_string().replace(['\r', '\n'], " ");
+        self.entries.push((key.to_string(), value));
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The entries, in the order they were recorded.
+    #[cfg(feature = "sqlite")]
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// The output schema version the capture was written with, if recorded.
+    pub fn schema_version(&self) -> Result<Option<u32>> {
+        self.get("schema_version")
+            .map(|version| {
+                version
+                    .parse()
+                    .with_context(|| format!("Invalid schema version {:?}", version))
+            })
+            .transpose()
+    }
+
+    /// Checks that the capture at `path` this describes can be read, i.e.
+    /// was not written with a newer output schema, and returns its version.
+    pub fn check_schema(&self, path: &Path) -> Result<Option<u32>> {
+        let version = self
+            .schema_version()
+            .with_context(|| format!("Cannot read {}", path.display()))?;
+        if let Some(version) = version.filter(|&version| version > SCHEMA_VERSION) {
+            bail!(
+                "Cannot read {}: it was written with output schema version {}, but this \
+                 chronologger only understands up to version {}; upgrade it to read the file",
+                path.display(),
+                version,
+                SCHEMA_VERSION
+            );
+        }
+        Ok(version)
+    }
+
+    /// Writes the entries as `# key: value` lines.
+    pub fn write_comments(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        for (key, value) in &self.entries {
+            writeln!(writer, "{}{}: {}", COMMENT, key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the `#` lines at the start of `reader`, leaving it at the first
+    /// line that is not one.
+    pub fn parse_comments(reader: &mut impl BufRead) -> Result<Self> {
+        let mut metadata = Self::default();
+        loop {
+            let buffer = reader.fill_buf()?;
+            if buffer.first() != Some(&b'#') {
+                return Ok(metadata);
+            }
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let line = line.trim_end_matches(['\r', '\n']);
+            let line = line.strip_prefix('#').unwrap_or(line).trim_start();
+            if let Some((key, value)) = line.split_once(": ") {
+                metadata.push(key, value);
+            }
+        }
+    }
+
+    /// The entries as a JSON object of strings.
+    fn to_json(&self) -> Map<String, Value> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone().into()))
+            .collect()
+    }
+
+    /// Reads the entries of a sidecar written by [`Sidecar`]. Values that
+    /// are not strings are kept as JSON text.
+    fn read_sidecar(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read metadata sidecar {}", path.display()))?;
+        let object: Map<String, Value> = serde_json::from_str(&text)
+            .with_context(|| format!("Malformed metadata sidecar {}", path.display()))?;
+        let mut metadata = Self::default();
+        for (key, value) in object {
+            match value {
+                Value::String(value) => metadata.push(&key, value),
+                value => metadata.push(&key, value),
+            }
+        }
+        Ok(metadata)
+    }
+}
+
+/// What `<output>.meta.json` holds about a run: its metadata and resolved
+/// options, written as it starts, and how it ended, added at shutdown.
+#[derive(Debug, Clone)]
+pub struct Sidecar {
+    metadata: Metadata,
+    /// Every option's value, whether given or defaulted.
+    options: Value,
+}
+
+impl Sidecar {
+    pub fn new(metadata: Metadata, options: Value) -> Self {
+        Self { metadata, options }
+    }
+
+    /// Writes the sidecar of `output` as the run starts, replacing any left
+    /// by an earlier capture at the same path.
+    pub fn write_start(&self, output: &Path) -> Result<()> {
+        let mut object = self.metadata.to_json();
+        object.insert("options".to_string(), self.options.clone());
+        write_object(&sidecar_path(output), &object)
+    }
+}
+
+/// How a run ended, added to its sidecar at shutdown.
+#[derive(Debug, Clone)]
+pub struct RunEnd {
+    pub ended: DateTime<Local>,
+    /// `completed`, `stopped` or what the run failed with.
+    pub reason: String,
+    pub ticks: u64,
+    pub rows: u64,
+    /// The run summary that is also logged.
+    pub summary: Value,
+}
+
+impl RunEnd {
+    pub fn write(&self, output: &Path) -> Result<()> {
+        update_sidecar(output, |object| {
+            let end_utc = self
+                .ended
+                .with_timezone(&Utc)
+                .to_rfc3339_opts(SecondsFormat::Millis, true);
+            object.insert("end_utc".to_string(), end_utc.into());
+            object.insert("termination_reason".to_string(), self.reason.clone().into());
+            object.insert("ticks".to_string(), self.ticks.into());
+            object.insert("rows".to_string(), self.rows.into());
+            object.insert("summary".to_string(), self.summary.clone());
+        })
+    }
+}
+
+/// Applies `update` to the sidecar of `output`, which starts out empty if
+/// there is none.
+pub fn update_sidecar(output: &Path, update: impl FnOnce(&mut Map<String, Value>)) -> Result<()> {
+    let path = sidecar_path(output);
+    let mut object = match fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text)
+            .with_context(|| format!("Malformed metadata sidecar {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Map::new(),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to read metadata sidecar {}", path.display()));
+        }
+    };
+    update(&mut object);
+    write_object(&path, &object)
+}
+
+/// Writes `object` to a temporary file renamed over `path`, so that a run
+/// killed meanwhile leaves the previous version rather than half of it.
+fn write_object(path: &Path, object: &Map<String, Value>) -> Result<()> {
+    let mut text = serde_json::to_string_pretty(object)?;
+    text.push('\n');
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+    fs::write(&temporary, text)
+        .and_then(|()| fs::rename(&temporary, path))
+        .with_context(|| format!("Failed to write metadata sidecar {}", path.display()))
+}
+
+/// Where the metadata of an output that cannot carry comments is kept.
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    let mut path = output.as_os_str().to_owned();
+    path.push(".meta.json");
+    PathBuf::from(path)
+}
+
+/// Reads the metadata of the capture at `path`: its sidecar if it has one,
+/// else the comment block at the top of the file, which may be empty.
+pub fn read(path: &Path) -> Result<Metadata> {
+    let sidecar = sidecar_path(path);
+    if sidecar.is_file() {
+        return Metadata::read_sidecar(&sidecar);
+    }
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    Metadata::parse_comments(&mut BufReader::new(file))
+        .with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// Replaces the values of the `secrets` options in `args` with `***`.
+fn masked(args: impl Iterator<Item = String>, secrets: &[&str]) -> Vec<String> {
+    let mut secret_next = false;
+    args.map(|arg| {
+        if std::mem::take(&mut secret_next) {
+            return "***".to_string();
+        }
+        for secret in secrets {
+            if arg == *secret {
+                secret_next = true;
+            } else if arg.starts_with(&format!("{}=", secret)) {
+                return format!("{}=***", secret);
+            }
+        }
+        arg
+    })
+    .collect()
+}
+
+/// Joins `args` into one line a POSIX shell would split back into them.
+fn command_line(args: impl Iterator<Item = String>) -> String {
+    let quoted: Vec<String> = args
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./:=,@%+".contains(c));
+            if plain {
+                arg
+            } else {
+                format!("'{}'", arg.replace('\'', r"'\''"))
+            }
+        })
+        .collect();
+    quoted.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn sample() -> Metadata {
+        let mut metadata = Metadata::default();
+        metadata.push("tool_version", "1.0.1");
+        metadata.push("command_line", "chronologger -o 'a b.csv'");
+        metadata.push("note", "two\nlines");
+        metadata.push("schema_version", SCHEMA_VERSION);
+        metadata
+    }
+
+    #[test]
+    fn test_comment_round_trip() {
+        let mut text = Vec::new();
+        sample().write_comments(&mut text).unwrap();
+        text.extend_from_slice(b"Timestamp,PID\n#1,2\n");
+
+        let mut reader = &text[..];
+        let parsed = Metadata::parse_comments(&mut reader).unwrap();
+        assert_eq!(parsed.get("note"), Some("two lines"));
+        assert_eq!(parsed, {
+            let mut expected = sample();
+            expected.entries[2].1 = "two lines".to_string();
+            expected
+        });
+        assert_eq!(reader, b"Timestamp,PID\n#1,2\n", "Read past the block");
+
+        let mut reader = &b"Timestamp,PID\n"[..];
+        assert_eq!(
+            Metadata::parse_comments(&mut reader).unwrap(),
+            Metadata::default()
+        );
+    }
+
+    #[test]
+    fn test_sidecar_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("capture.jsonl");
+        fs::write(&output, "{}\n").unwrap();
+        assert_eq!(read(&output).unwrap(), Metadata::default());
+
+        let options = serde_json::json!({ "interval": "1" });
+        Sidecar::new(sample(), options)
+            .write_start(&output)
+            .unwrap();
+        assert_eq!(
+            sidecar_path(&output),
+            dir.path().join("capture.jsonl.meta.json")
+        );
+        let read = read(&output).unwrap();
+        assert_eq!(read.get("command_line"), Some("chronologger -o 'a b.csv'"));
+        assert_eq!(read.get("schema_version"), Some("1"));
+    }
+
+    #[test]
+    fn test_schema_version_check() {
+        let path = Path::new("capture.csv");
+        assert_eq!(sample().check_schema(path).unwrap(), Some(SCHEMA_VERSION));
+        assert_eq!(Metadata::default().check_schema(path).unwrap(), None);
+
+        let mut future = Metadata::default();
+        future.push("schema_version", SCHEMA_VERSION + 1);
+        let error = future.check_schema(path).unwrap_err().to_string();
+        assert!(
+            error.contains("only understands up to version"),
+            "{}",
+            error
+        );
+
+        let mut garbled = Metadata::default();
+        garbled.push("schema_version", "two");
+        assert!(garbled.check_schema(path).is_err());
+    }
+
+    #[test]
+    fn test_command_line_quoting() {
+        let args = ["chronologger", "-o", "my file.csv", "--focus", "it's", ""];
+        assert_eq!(
+            command_line(args.iter().map(ToString::to_string)),
+            r"chronologger -o 'my file.csv' --focus 'it'\''s' ''"
+        );
+    }
+
+    #[test]
+    fn test_masked_secrets() {
+        let args = [
+            "chronologger",
+            "--anonymize-key",
+            "k",
+            "--focus=db",
+            "-o",
+            "x",
+        ];
+        let masked = masked(
+            args.iter().map(ToString::to_string),
+            &["--anonymize-key", "--focus"],
+        );
+        assert_eq!(
+            masked,
+            [
+                "chronologger",
+                "--anonymize-key",
+                "***",
+                "--focus=***",
+                "-o",
+                "x"
+            ]
+        );
+    }
+}
@@ -0,0 +1,366 @@
+use crate::{backoff::Backoff, recovery::Unrecoverable, sink::Sink, ProcessSample};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use rumqttc::{
+    Client, ClientError, Connection, Event, MqttOptions, Outgoing, QoS, RecvTimeoutError,
+    TlsConfiguration, Transport,
+};
+use serde_json::json;
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use sysinfo::PidExt;
+
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+const POLL_TIMEOUT: Duration = Duration::from_millis(200);
+const FINISH_TIMEOUT: Duration = Duration::from_secs(5);
+pub const MAX_BUFFERED_MESSAGES: usize = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub url: String,
+    pub topic_prefix: String,
+    pub host: String,
+    pub qos: u8,
+    pub ca_file: Option<PathBuf>,
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    pub max_buffered: usize,
+    /// Added to every message.
+    pub run_id: Option<String>,
+}
+
+/// Publishes one retained JSON message per process per tick to
+/// `<prefix>/<host>/<name>/<pid>`. Messages queue up to `max_buffered` while
+/// the broker is unreachable; beyond that they are dropped and counted.
+pub struct MqttSink {
+    client: Client,
+    topic_prefix: String,
+    qos: QoS,
+    run_id: Option<String>,
+    dropped: u64,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MqttSink {
+    pub fn new(config: MqttConfig) -> Result<Self> {
+        let options = options(&config)?;
+        let qos = rumqttc::qos(config.qos).context("Invalid MQTT QoS")?;
+        info!(
+            "Publishing samples to MQTT broker {} under {}/{}",
+            config.url, config.topic_prefix, config.host
+        );
+
+        let (client, connection) = Client::new(options, config.max_buffered);
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            thread::spawn(move || run(connection, stop))
+        };
+
+        Ok(Self {
+            client,
+            topic_prefix: format!(
+                "{}/{}",
+                config.topic_prefix.trim_end_matches('/'),
+                topic_level(&config.host)
+            ),
+            qos,
+            run_id: config.run_id,
+            dropped: 0,
+            stop,
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Sink for MqttSink {
+    fn publish(&mut self, timestamp: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        let timestamp = timestamp.to_rfc3339();
+        for sample in samples {
+            // A group has no PID, so its level is `group`.
+            let topic = match sample.pid {
+                Some(pid) => format!(
+                    "{}/{}/{}",
+                    self.topic_prefix,
+                    topic_level(&sample.name),
+                    pid.as_u32()
+                ),
+                None => format!("{}/{}/group", self.topic_prefix, topic_level(&sample.name)),
+            };
+            let mut payload = sample.to_json();
+            payload["timestamp"] = json!(timestamp);
+            if let Some(run_id) = &self.run_id {
+                payload["run_id"] = json!(run_id);
+            }
+            match self
+                .client
+                .try_publish(topic, self.qos, true, payload.to_string())
+            {
+                Ok(()) => {}
+                Err(ClientError::TryRequest(_)) => self.dropped += 1,
+                // The client only fails like this once its event loop is gone.
+                Err(e) => {
+                    return Err(e)
+                        .context("Failed to queue MQTT message")
+                        .context(Unrecoverable)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        // The disconnect is queued behind any pending messages, so they are
+        // sent first if the broker is reachable.
+        if self.client.try_disconnect().is_err() {
+            self.stop.store(true, Ordering::SeqCst);
+        }
+        let deadline = Instant::now() + FINISH_TIMEOUT;
+        while self.handle.as_ref().is_some_and(|h| !h.is_finished()) {
+            if Instant::now() >= deadline {
+                warn!("Timed out delivering pending MQTT messages");
+                self.stop.store(true, Ordering::SeqCst);
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        if self.dropped > 0 {
+            warn!(
+                "Dropped {} MQTT messages while the broker was unreachable",
+                self.dropped
+            );
+        }
+        Ok(())
+    }
+}
+
+fn run(mut connection: Connection, stop: Arc<AtomicBool>) {
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+    let mut connected = false;
+    while !stop.load(Ordering::SeqCst) {
+        match connection.recv_timeout(POLL_TIMEOUT) {
+            Ok(Ok(Event::Incoming(rumqttc::Packet::ConnAck(_)))) => {
+                info!("Connected to MQTT broker");
+                connected = true;
+                backoff.reset();
+            }
+            Ok(Ok(Event::Outgoing(Outgoing::Disconnect))) => break,
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(Err(e)) => {
+                let delay = backoff.next_delay();
+                if connected {
+                    warn!(
+                        "Lost connection to MQTT broker, reconnecting in {:?}: {}",
+                        delay, e
+                    );
+                    connected = false;
+                } else {
+                    warn!("MQTT connection failed, retrying in {:?}: {}", delay, e);
+                }
+                sleep_unless_stopped(delay, &stop);
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+fn sleep_unless_stopped(delay: Duration, stop: &AtomicBool) {
+    let deadline = Instant::now() + delay;
+    while Instant::now() < deadline && !stop.load(Ordering::SeqCst) {
+        thread::sleep(POLL_TIMEOUT);
+    }
+}
+
+/// The broker's host and port, after checking the whole configuration
+/// including any TLS files.
+pub fn broker_address(config: &MqttConfig) -> Result<(String, u16)> {
+    Ok(options(config)?.broker_address())
+}
+
+fn options(config: &MqttConfig) -> Result<MqttOptions> {
+    let (tls, address) = match config.url.split_once("://") {
+        Some(("mqtt", address)) => (false, address),
+        Some(("mqtts", address)) => (true, address),
+        _ => bail!(
+            "MQTT broker URL must start with mqtt:// or mqtts://: {}",
+            config.url
+        ),
+    };
+    let address = address.trim_end_matches('/');
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .with_context(|| format!("Invalid MQTT broker port: {}", port))?,
+        ),
+        None => (address, if tls { 8883 } else { 1883 }),
+    };
+
+    let client_id = format!("chronologger-{}-{}", config.host, std::process::id());
+    let mut options = MqttOptions::new(client_id, host, port);
+    options.set_keep_alive(KEEP_ALIVE).set_clean_session(false);
+
+    if tls {
+        let transport = match &config.ca_file {
+            Some(ca_file) => {
+                let ca = fs::read(ca_file)
+                    .with_context(|| format!("Failed to read {}", ca_file.display()))?;
+                let client_auth = match (&config.client_cert, &config.client_key) {
+                    (Some(cert), Some(key)) => Some((
+                        fs::read(cert)
+                            .with_context(|| format!("Failed to read {}", cert.display()))?,
+                        fs::read(key)
+                            .with_context(|| format!("Failed to read {}", key.display()))?,
+                    )),
+                    _ => None,
+                };
+                Transport::tls(ca, client_auth, None)
+            }
+            None => Transport::tls_with_config(TlsConfiguration::default()),
+        };
+        options.set_transport(transport);
+    } else if config.ca_file.is_some() || config.client_cert.is_some() {
+        bail!("MQTT TLS options require an mqtts:// broker URL");
+    }
+    Ok(options)
+}
+
+/// Replaces characters that are not allowed or would add levels in a topic.
+fn topic_level(value: &str) -> String {
+    let level: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '+' | '#' | '\0' => '_',
+            c => c,
+        })
+        .collect();
+    if level.is_empty() {
+        "_".to_string()
+    } else {
+        level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+    };
+    use sysinfo::Pid;
+
+    fn config(url: &str) -> MqttConfig {
+        MqttConfig {
+            url: url.to_string(),
+            topic_prefix: "site/metrics/".to_string(),
+            host: "edge-1".to_string(),
+            qos: 0,
+            ca_file: None,
+            client_cert: None,
+            client_key: None,
+            max_buffered: 16,
+            run_id: Some("run-1".to_string()),
+        }
+    }
+
+    /// Reads one MQTT control packet, returning its type and body.
+    fn read_packet(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+        let mut header = [0u8; 1];
+        stream.read_exact(&mut header).ok()?;
+        let (mut length, mut shift) = (0usize, 0);
+        loop {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).ok()?;
+            length |= ((byte[0] & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0u8; length];
+        stream.read_exact(&mut body).ok()?;
+        Some((header[0], body))
+    }
+
+    #[test]
+    fn test_topic_level() {
+        assert_eq!(topic_level("kworker/0:1"), "kworker_0:1");
+        assert_eq!(topic_level("a+b#c"), "a_b_c");
+        assert_eq!(topic_level(""), "_");
+    }
+
+    #[test]
+    fn test_options() {
+        assert_eq!(
+            options(&config("mqtt://broker")).unwrap().broker_address(),
+            ("broker".to_string(), 1883)
+        );
+        assert_eq!(
+            options(&config("mqtt://broker:2883"))
+                .unwrap()
+                .broker_address(),
+            ("broker".to_string(), 2883)
+        );
+        assert!(options(&config("http://broker")).is_err());
+    }
+
+    #[test]
+    fn test_publish_to_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+        let port = listener.local_addr().unwrap().port();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("Failed to accept client");
+            let mut publishes = Vec::new();
+            while let Some((header, body)) = read_packet(&mut stream) {
+                match header >> 4 {
+                    // CONNECT is answered with a successful CONNACK.
+                    1 => stream.write_all(&[0x20, 0x02, 0x00, 0x00]).unwrap(),
+                    3 => {
+                        let length = u16::from_be_bytes([body[0], body[1]]) as usize;
+                        let topic = String::from_utf8(body[2..2 + length].to_vec()).unwrap();
+                        let payload = String::from_utf8(body[2 + length..].to_vec()).unwrap();
+                        publishes.push((header & 0x01 == 1, topic, payload));
+                    }
+                    14 => break,
+                    _ => {}
+                }
+            }
+            publishes
+        });
+
+        let mut sink = MqttSink::new(config(&format!("mqtt://127.0.0.1:{}", port)))
+            .expect("Failed to create sink");
+        let samples = vec![ProcessSample {
+            pid: Some(Pid::from_u32(42)),
+            name: "sensor/reader".to_string(),
+            cpu_usage: 3.5,
+            memory_usage: 1.25,
+            extras: Vec::new(),
+        }];
+        sink.publish(&Local::now(), &samples)
+            .expect("Failed to publish samples");
+        sink.finish().expect("Failed to finish sink");
+
+        let publishes = broker.join().unwrap();
+        assert_eq!(publishes.len(), 1);
+        let (retained, topic, payload) = &publishes[0];
+        assert!(retained, "Messages must be retained");
+        assert_eq!(topic, "site/metrics/edge-1/sensor_reader/42");
+        let payload: serde_json::Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(payload["pid"], 42);
+        assert_eq!(payload["cpu_percent"], 3.5);
+        assert!(payload["timestamp"].is_string());
+        assert_eq!(payload["run_id"], "run-1");
+    }
+}
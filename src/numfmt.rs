@@ -0,0 +1,252 @@
+use std::fmt::Write as _;
+
+/// The precision of the percentage columns.
+pub const PERCENT_PRECISION: usize = 2;
+
+/// Largest precision handled by the fast path.
+const MAX_FAST_PRECISION: usize = 6;
+
+/// Scaled values must stay well inside the range where an f64 resolves
+/// fractions finer than `TIE_TOLERANCE`.
+const MAX_FAST_SCALED: f64 = (1u64 << 32) as f64;
+
+/// How close to a rounding tie the fast path gives up, leaving exact
+/// rounding of the binary value to `{:.N}`.
+const TIE_TOLERANCE: f64 = 1e-5;
+
+/// Appends an integer to `buffer`.
+pub fn write_int(buffer: &mut String, value: impl itoa::Integer) {
+    buffer.push_str(itoa::Buffer::new().format(value));
+}
+
+/// Appends `value` with `precision` decimals, producing exactly what
+/// `format!("{:.precision$}", value)` would. Ordinary values are scaled,
+/// rounded and written as integers; negative, huge or non-finite values and
+/// values too close to a rounding tie go through `std::fmt` instead.
+pub fn write_fixed(buffer: &mut String, value: f64, precision: usize) {
+    if precision <= MAX_FAST_PRECISION && value.is_finite() && value.is_sign_positive() {
+        let scale = 10u64.pow(precision as u32);
+        let scaled = value * scale as f64;
+        if scaled < MAX_FAST_SCALED {
+            let floor = scaled.floor();
+            if (scaled - floor - 0.5).abs() > TIE_TOLERANCE {
+                let rounded = scaled.round() as u64;
+                write_int(buffer, rounded / scale);
+                if precision > 0 {
+                    buffer.push('.');
+                    let fraction = itoa::Buffer::new().format(rounded % scale).len();
+                    for _ in fraction..precision {
+                        buffer.push('0');
+                    }
+                    write_int(buffer, rounded % scale);
+                }
+                return;
+            }
+        }
+    }
+    let _ = write!(buffer, "{:.*}", precision, value);
+}
+
+/// How memory usage is written, chosen with `--memory-unit`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MemoryUnit {
+    /// Share of physical memory, with two decimals.
+    #[default]
+    Percent,
+    /// Resident bytes.
+    Bytes,
+    /// Resident KiB, with two decimals.
+    Kib,
+    /// Resident MiB, with two decimals.
+    Mib,
+}
+
+impl MemoryUnit {
+    /// The CSV header of the memory column.
+    pub fn header(self) -> &'static str {
+        match self {
+            MemoryUnit::Percent => "Memory Usage (%)",
+            MemoryUnit::Bytes => "Memory Usage (bytes)",
+            MemoryUnit::Kib => "Memory Usage (KiB)",
+            MemoryUnit::Mib => "Memory Usage (MiB)",
+        }
+    }
+
+    /// The key of the memory field in JSON Lines and JSON records.
+    pub fn key(self) -> &'static str {
+        match self {
+            MemoryUnit::Percent => "memory_percent",
+            MemoryUnit::Bytes => "memory_bytes",
+            MemoryUnit::Kib => "memory_kib",
+            MemoryUnit::Mib => "memory_mib",
+        }
+    }
+
+    /// The unit `--print-schema` gives the memory column.
+    pub fn unit(self) -> &'static str {
+        match self {
+            MemoryUnit::Percent => "percent",
+            MemoryUnit::Bytes => "bytes",
+            MemoryUnit::Kib => "kibibytes",
+            MemoryUnit::Mib => "mebibytes",
+        }
+    }
+
+    /// Bytes per unit, or `None` for percentages.
+    fn bytes(self) -> Option<u64> {
+        match self {
+            MemoryUnit::Percent => None,
+            MemoryUnit::Bytes => Some(1),
+            MemoryUnit::Kib => Some(1024),
+            MemoryUnit::Mib => Some(1024 * 1024),
+        }
+    }
+}
+
+/// Converts a share of `total_memory` KB back to the bytes it was computed
+/// from, a multiple of the KB the source counts in.
+pub fn memory_bytes(percent: f64, total_memory: f64) -> u64 {
+    (percent / 100.0 * total_memory).round().max(0.0) as u64 * 1024
+}
+
+/// Writes memory usage, which is sampled as a share of the host's total
+/// memory, in a chosen unit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MemoryFormat {
+    pub unit: MemoryUnit,
+    /// Total memory in KB. Until it is set, absolute usage is written as 0.
+    pub total_memory: f64,
+}
+
+impl MemoryFormat {
+    /// Appends `percent` of total memory in the unit, as the CSV column
+    /// holds it.
+    pub fn write(&self, buffer: &mut String, percent: f64) {
+        match self.unit {
+            MemoryUnit::Percent => write_fixed(buffer, percent, PERCENT_PRECISION),
+            MemoryUnit::Bytes => write_int(buffer, memory_bytes(percent, self.total_memory)),
+            _ => write_fixed(buffer, self.value(percent), PERCENT_PRECISION),
+        }
+    }
+
+    /// `percent` of total memory in the unit, unrounded.
+    pub fn value(&self, percent: f64) -> f64 {
+        match self.unit.bytes() {
+            None => percent,
+            Some(bytes) => memory_bytes(percent, self.total_memory) as f64 / bytes as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(value: f64, precision: usize) -> String {
+        let mut buffer = String::new();
+        write_fixed(&mut buffer, value, precision);
+        buffer
+    }
+
+    #[test]
+    fn test_golden_values() {
+        let golden = [
+            (0.0, "0.00"),
+            (-0.0, "-0.00"),
+            (0.004, "0.00"),
+            (0.005, "0.01"),
+            (0.015, "0.01"),
+            (0.125, "0.12"),
+            (0.375, "0.38"),
+            (1e-300, "0.00"),
+            (5e-324, "0.00"),
+            (7.5, "7.50"),
+            (99.995, "100.00"),
+            (99.985, "99.98"),
+            (99.996, "100.00"),
+            (100.0, "100.00"),
+            (1234.5678, "1234.57"),
+            (6400.0, "6400.00"),
+            (1e20, "100000000000000000000.00"),
+            (-1.236, "-1.24"),
+            (f64::NAN, "NaN"),
+            (f64::INFINITY, "inf"),
+        ];
+        for (value, expected) in golden {
+            assert_eq!(fixed(value, 2), expected, "{:e}", value);
+        }
+    }
+
+    #[test]
+    fn test_matches_std_fmt() {
+        // A cheap deterministic generator covering several magnitudes.
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..200_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let magnitude = 10f64.powi((state % 9) as i32 - 4);
+            let value = (state >> 11) as f64 / (1u64 << 53) as f64 * magnitude;
+            for precision in [0, 1, 2, 3] {
+                assert_eq!(
+                    fixed(value, precision),
+                    format!("{:.*}", precision, value),
+                    "{:e} at precision {}",
+                    value,
+                    precision
+                );
+            }
+            // CPU usage is an f32; widening it is exact.
+            let cpu = value as f32;
+            assert_eq!(fixed(cpu as f64, 2), format!("{:.2}", cpu), "{:e}", cpu);
+        }
+        // Every exact cent and half cent, where ties are most likely.
+        for cents in 0..400_000u64 {
+            let value = cents as f64 / 200.0;
+            assert_eq!(fixed(value, 2), format!("{:.2}", value), "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_memory_format() {
+        // 16 GiB.
+        let total_memory = 16.0 * 1024.0 * 1024.0;
+        let write = |unit, percent| {
+            let mut buffer = String::new();
+            MemoryFormat { unit, total_memory }.write(&mut buffer, percent);
+            buffer
+        };
+        assert_eq!(write(MemoryUnit::Percent, 0.5), "0.50");
+        // 0.5% of 16 GiB, rounded to the KB it was sampled in.
+        assert_eq!(write(MemoryUnit::Bytes, 0.5), "85899264");
+        assert_eq!(write(MemoryUnit::Kib, 0.5), "83886.00");
+        assert_eq!(write(MemoryUnit::Mib, 0.5), "81.92");
+        assert_eq!(write(MemoryUnit::Mib, 100.0), "16384.00");
+        // Without the total, absolute usage cannot be known.
+        let mut buffer = String::new();
+        MemoryFormat {
+            unit: MemoryUnit::Bytes,
+            total_memory: 0.0,
+        }
+        .write(&mut buffer, 12.5);
+        assert_eq!(buffer, "0");
+    }
+
+    #[test]
+    fn test_memory_bytes_round_trip() {
+        let total_memory = 8_000_000.0;
+        for kb in [0u64, 1, 4, 1023, 123_456, 7_999_999, 8_000_000] {
+            let percent = kb as f64 / total_memory * 100.0;
+            assert_eq!(memory_bytes(percent, total_memory), kb * 1024, "{} KB", kb);
+        }
+    }
+
+    #[test]
+    fn test_write_int() {
+        let mut buffer = String::new();
+        write_int(&mut buffer, 0u32);
+        buffer.push(',');
+        write_int(&mut buffer, u32::MAX);
+        assert_eq!(buffer, "0,4294967295");
+    }
+}
@@ -0,0 +1,351 @@
+use crate::{
+    http::{self, Url},
+    push::{self, Push, PushError, PushQueue},
+    sink::Sink,
+    ProcessSample,
+};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
+use sysinfo::{Pid, PidExt};
+
+const METRICS_PATH: &str = "v1/metrics";
+/// The OTLP/gRPC port, which this exporter cannot talk to.
+const GRPC_PORT: u16 = 4317;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Ticks sent in one export request at most, when the exporter has fallen
+/// behind.
+const MAX_TICKS_PER_REQUEST: usize = 60;
+pub const MAX_BUFFERED_TICKS: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    pub url: Url,
+    /// The `service.instance.id` and `host.name` of every resource.
+    pub instance: String,
+    pub max_buffered: usize,
+    /// Added to the attributes of every resource.
+    pub run_id: Option<String>,
+}
+
+/// One process's sample of a tick.
+#[derive(Debug, Clone)]
+struct Point {
+    /// Empty for a `--group-by` group.
+    pid: Option<u32>,
+    name: String,
+    cpu_usage: f32,
+    memory_usage: f64,
+}
+
+#[derive(Debug, Clone)]
+struct Tick {
+    time_unix_nano: String,
+    points: Vec<Point>,
+}
+
+/// Exports every tick's samples to an OpenTelemetry collector as OTLP/HTTP
+/// JSON gauges, with a resource per process. Exports happen on a
+/// background thread; ticks that cannot be delivered yet, or are published
+/// while an export is under way, are buffered up to `max_buffered` and then
+/// dropped oldest first.
+pub struct OtlpSink {
+    queue: PushQueue<Tick>,
+}
+
+impl OtlpSink {
+    pub fn new(config: OtlpConfig) -> Self {
+        if config.url.port == GRPC_PORT {
+            warn!(
+                "Port {} is usually OTLP/gRPC; chronologger exports OTLP/HTTP with JSON, \
+                 which collectors receive on port 4318",
+                GRPC_PORT
+            );
+        }
+        info!(
+            "Exporting OTLP metrics to {}:{}{}",
+            config.url.host,
+            config.url.port,
+            config.url.join(METRICS_PATH)
+        );
+        let max_buffered = config.max_buffered;
+        Self {
+            queue: PushQueue::new(Exporter { config }, max_buffered, None),
+        }
+    }
+}
+
+impl Sink for OtlpSink {
+    fn publish(&mut self, timestamp: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        let tick = Tick {
+            time_unix_nano: timestamp
+                .timestamp_nanos_opt()
+                .unwrap_or_default()
+                .to_string(),
+            points: samples
+                .iter()
+                .map(|sample| Point {
+                    pid: sample.pid.map(Pid::as_u32),
+                    name: sample.name.clone(),
+                    cpu_usage: sample.cpu_usage,
+                    memory_usage: sample.memory_usage,
+                })
+                .collect(),
+        };
+        self.queue.publish([tick]);
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let dropped = self.queue.finish();
+        if dropped > 0 {
+            warn!(
+                "Dropped {} ticks that could not be exported over OTLP",
+                dropped
+            );
+        }
+        Ok(())
+    }
+}
+
+struct Exporter {
+    config: OtlpConfig,
+}
+
+impl Push for Exporter {
+    type Item = Tick;
+
+    const NAME: &'static str = "OTLP export";
+    const ITEMS: &'static str = "ticks";
+
+    fn batch_len(&self, _ticks: &VecDeque<Tick>) -> usize {
+        MAX_TICKS_PER_REQUEST
+    }
+
+    fn push(&mut self, batch: &[Tick]) -> Result<(), PushError> {
+        let config = &self.config;
+        let body = encode(&config.instance, config.run_id.as_deref(), batch).to_string();
+        let response = http::send(
+            "POST",
+            &config.url,
+            &config.url.join(METRICS_PATH),
+            "application/json",
+            body.as_bytes(),
+            REQUEST_TIMEOUT,
+        )
+        .map_err(PushError::Retryable)?;
+
+        // The statuses OTLP/HTTP clients retry on.
+        match response.status {
+            200..=299 => Ok(()),
+            429 | 502..=504 => Err(PushError::Retryable(push::status_error(
+                "The collector",
+                &response,
+            ))),
+            _ => Err(PushError::Rejected(push::status_error(
+                "The collector",
+                &response,
+            ))),
+        }
+    }
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// A gauge with a data point per value, in the order of the ticks.
+fn gauge(name: &str, description: &str, points: &[(&str, f64)]) -> Value {
+    let points: Vec<Value> = points
+        .iter()
+        .map(|(time_unix_nano, value)| json!({ "timeUnixNano": time_unix_nano, "asDouble": value }))
+        .collect();
+    json!({
+        "name": name,
+        "description": description,
+        "unit": "%",
+        "gauge": { "dataPoints": points },
+    })
+}
+
+/// Encodes a batch as an OTLP `ExportMetricsServiceRequest` with one
+/// resource per process, by PID and name, and one per group, by name.
+fn encode(instance: &str, run_id: Option<&str>, batch: &[Tick]) -> Value {
+    type Series<'a> = (Vec<(&'a str, f64)>, Vec<(&'a str, f64)>);
+    let mut processes: BTreeMap<(Option<u32>, &str), Series> = BTreeMap::new();
+    for tick in batch {
+        for point in &tick.points {
+            let (cpu, memory) = processes.entry((point.pid, &point.name)).or_default();
+            cpu.push((&tick.time_unix_nano, point.cpu_usage as f64));
+            memory.push((&tick.time_unix_nano, point.memory_usage));
+        }
+    }
+
+    let resources: Vec<Value> = processes
+        .into_iter()
+        .map(|((pid, name), (cpu, memory))| {
+            let mut attributes = vec![
+                string_attribute("service.name", "chronologger"),
+                string_attribute("service.instance.id", instance),
+                string_attribute("host.name", instance),
+            ];
+            if let Some(pid) = pid {
+                attributes.push(
+                    json!({ "key": "process.pid", "value": { "intValue": pid.to_string() } }),
+                );
+            }
+            attributes.push(string_attribute("process.executable.name", name));
+            if let Some(run_id) = run_id {
+                attributes.push(string_attribute("chronologger.run_id", run_id));
+            }
+            json!({
+                "resource": { "attributes": attributes },
+                "scopeMetrics": [{
+                    "scope": { "name": "chronologger", "version": env!("CARGO_PKG_VERSION") },
+                    "metrics": [
+                        gauge(
+                            "chronologger.process.cpu_percent",
+                            "Process CPU usage in percent.",
+                            &cpu,
+                        ),
+                        gauge(
+                            "chronologger.process.memory_percent",
+                            "Process memory usage in percent of total memory.",
+                            &memory,
+                        ),
+                    ],
+                }],
+            })
+        })
+        .collect();
+    json!({ "resourceMetrics": resources })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::testing::{capture_requests, count_requests};
+    use crate::test_support::sample;
+    use std::{sync::atomic::Ordering, thread};
+
+    #[test]
+    fn test_export_resources_and_gauges() {
+        let (url, server) = capture_requests(1, 200);
+        let mut sink = OtlpSink::new(OtlpConfig {
+            url,
+            instance: "host1".to_string(),
+            max_buffered: 100,
+            run_id: Some("run-1".to_string()),
+        });
+        let now = Local::now();
+        sink.publish(
+            &now,
+            &[sample(2, "sshd", 1.5, 0.25), sample(1, "init", 1.5, 0.25)],
+        )
+        .expect("Failed to publish samples");
+        sink.finish().expect("Failed to finish sink");
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("POST /v1/metrics HTTP/1.1"));
+        let body = &requests[0][requests[0].find("\r\n\r\n").unwrap() + 4..];
+        let payload: Value = serde_json::from_str(body).expect("Invalid JSON payload");
+        let resources = payload["resourceMetrics"].as_array().unwrap();
+        assert_eq!(resources.len(), 2);
+        let attributes = &resources[0]["resource"]["attributes"];
+        assert_eq!(
+            attributes[3],
+            json!({ "key": "process.pid", "value": { "intValue": "1" } })
+        );
+        assert_eq!(
+            attributes[4],
+            string_attribute("process.executable.name", "init")
+        );
+        assert_eq!(
+            attributes[5],
+            string_attribute("chronologger.run_id", "run-1")
+        );
+        let metrics = &resources[1]["scopeMetrics"][0]["metrics"];
+        assert_eq!(metrics[0]["name"], "chronologger.process.cpu_percent");
+        let point = &metrics[0]["gauge"]["dataPoints"][0];
+        assert_eq!(point["asDouble"], 1.5);
+        assert_eq!(
+            point["timeUnixNano"],
+            now.timestamp_nanos_opt().unwrap().to_string()
+        );
+        assert_eq!(metrics[1]["gauge"]["dataPoints"][0]["asDouble"], 0.25);
+    }
+
+    #[test]
+    fn test_batches_ticks_per_process() {
+        let earlier = Tick {
+            time_unix_nano: "1".to_string(),
+            points: vec![Point {
+                pid: Some(7),
+                name: "db".to_string(),
+                cpu_usage: 1.0,
+                memory_usage: 2.0,
+            }],
+        };
+        let later = Tick {
+            time_unix_nano: "2".to_string(),
+            ..earlier.clone()
+        };
+        let payload = encode("host1", None, &[earlier, later]);
+        let resources = payload["resourceMetrics"].as_array().unwrap();
+        assert_eq!(resources.len(), 1);
+        assert_eq!(
+            resources[0]["resource"]["attributes"]
+                .as_array()
+                .unwrap()
+                .len(),
+            5
+        );
+        let points = &resources[0]["scopeMetrics"][0]["metrics"][0]["gauge"]["dataPoints"];
+        assert_eq!(points[0]["timeUnixNano"], "1");
+        assert_eq!(points[1]["timeUnixNano"], "2");
+    }
+
+    #[test]
+    fn test_drops_ticks_when_rejected() {
+        let (url, server) = capture_requests(1, 400);
+        let mut sink = OtlpSink::new(OtlpConfig {
+            url,
+            instance: "host1".to_string(),
+            max_buffered: 100,
+            run_id: None,
+        });
+        sink.publish(&Local::now(), &[sample(1, "init", 1.5, 0.25)])
+            .expect("Failed to publish samples");
+        sink.finish().expect("Failed to finish sink");
+        server.join().unwrap();
+
+        assert_eq!(sink.queue.dropped(), 1);
+    }
+
+    #[test]
+    fn test_bounds_buffer_while_collector_is_down() {
+        let (url, requests) = count_requests(503);
+        let mut sink = OtlpSink::new(OtlpConfig {
+            url,
+            instance: "host1".to_string(),
+            max_buffered: 3,
+            run_id: None,
+        });
+        for pid in 1..=5 {
+            sink.publish(&Local::now(), &[sample(pid, "init", 1.5, 0.25)])
+                .expect("Failed to publish samples");
+            thread::sleep(Duration::from_millis(80));
+        }
+        // The first retry waits 500ms, whatever is published meanwhile.
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(sink.queue.buffered(), 3);
+        assert_eq!(sink.queue.dropped(), 2);
+        sink.finish().expect("Failed to finish sink");
+        assert_eq!(sink.queue.dropped(), 5);
+    }
+}
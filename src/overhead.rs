@@ -0,0 +1,425 @@
+use serde_json::json;
+use std::{
+    fmt,
+    io::{self, Write},
+    sync::atomic::{AtomicU64, Ordering},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Only the most recent tick latencies are kept for the p95, so long runs at
+/// a short focus interval do not grow without bound.
+const MAX_LATENCIES: usize = 100_000;
+
+/// Counts the bytes passed to the wrapped writer.
+pub struct Counted<W> {
+    inner: W,
+    written: Arc<AtomicU64>,
+}
+
+impl<W> Counted<W> {
+    pub fn new(inner: W, written: Arc<AtomicU64>) -> Self {
+        Self { inner, written }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Tracks what running chronologger costs: its own CPU time and peak memory,
+/// the bytes it wrote and how long its ticks took.
+pub struct Overhead {
+    started: Instant,
+    written: Arc<AtomicU64>,
+    ticks: u64,
+    total_latency: Duration,
+    latencies: Vec<Duration>,
+    next_latency: usize,
+    /// How late ticks started after their scheduled deadline.
+    lagged_ticks: u64,
+    total_lag: Duration,
+    max_lag: Duration,
+}
+
+#[derive(Debug)]
+pub struct Summary {
+    pub wall_time: Duration,
+    /// User plus system CPU time, when the platform reports it.
+    pub cpu_time: Option<Duration>,
+    /// Peak resident memory in KB, when the platform reports it.
+    pub peak_rss: Option<u64>,
+    pub bytes_written: u64,
+    pub ticks: u64,
+    /// Rows written to the output.
+    pub rows: u64,
+    /// Distinct PIDs sampled.
+    pub processes: u64,
+    pub mean_tick: Duration,
+    pub p95_tick: Duration,
+    /// How late ticks started after their deadline, on average and at worst.
+    pub mean_lag: Duration,
+    pub max_lag: Duration,
+    /// Ticks discarded because the output fell behind.
+    pub dropped_ticks: u64,
+    /// Ticks skipped because sampling or writing them failed.
+    pub failed_ticks: u64,
+    /// Ticks skipped because an earlier one ran past their deadline.
+    pub missed_ticks: u64,
+    /// Processes left out because they could not be read.
+    pub skipped_processes: u64,
+    /// Times the system was suspended during the run.
+    pub suspend_gaps: u64,
+    /// Ticks that logged no process and only wrote a heartbeat row.
+    pub heartbeat_ticks: u64,
+    /// Rows sampled and left out by `--dedup`, if it is on.
+    pub dedup: Option<crate::dedup::Stats>,
+    /// Optional columns, by their `--print-schema` identifier, with reads
+    /// the OS denied, and how many.
+    pub denied: Vec<(&'static str, u64)>,
+}
+
+impl Overhead {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            written: Arc::new(AtomicU64::new(0)),
+            ticks: 0,
+            total_latency: Duration::ZERO,
+            latencies: Vec::new(),
+            next_latency: 0,
+            lagged_ticks: 0,
+            total_lag: Duration::ZERO,
+            max_lag: Duration::ZERO,
+        }
+    }
+
+    /// The counter outputs should add their bytes to.
+    pub fn written(&self) -> Arc<AtomicU64> {
+        self.written.clone()
+    }
+
+    pub fn record_tick(&mut self, latency: Duration) {
+        self.ticks += 1;
+        self.total_latency += latency;
+        if self.latencies.len() < MAX_LATENCIES {
+            self.latencies.push(latency);
+        } else {
+            self.latencies[self.next_latency] = latency;
+            self.next_latency = (self.next_latency + 1) % MAX_LATENCIES;
+        }
+    }
+
+    /// Records that a tick scheduled for `due` started at `started`, both
+    /// offsets from the start of the run.
+    pub fn record_lag(&mut self, due: Duration, started: Duration) {
+        let lag = started.saturating_sub(due);
+        self.lagged_ticks += 1;
+        self.total_lag += lag;
+        self.max_lag = self.max_lag.max(lag);
+    }
+
+    pub fn summary(&self) -> Summary {
+        let (cpu_time, peak_rss) = match resource_usage() {
+            Some((cpu_time, peak_rss)) => (Some(cpu_time), Some(peak_rss)),
+            None => (None, None),
+        };
+        let mut latencies = self.latencies.clone();
+        latencies.sort_unstable();
+        let p95_tick = match latencies.len() {
+            0 => Duration::ZERO,
+            len => latencies[(len * 95).div_ceil(100) - 1],
+        };
+        Summary {
+            wall_time: self.started.elapsed(),
+            cpu_time,
+            peak_rss,
+            bytes_written: self.written.load(Ordering::Relaxed),
+            ticks: self.ticks,
+            rows: 0,
+            processes: 0,
+            mean_tick: match self.ticks {
+                0 => Duration::ZERO,
+                ticks => self.total_latency / ticks as u32,
+            },
+            p95_tick,
+            mean_lag: match self.lagged_ticks {
+                0 => Duration::ZERO,
+                ticks => self.total_lag / ticks as u32,
+            },
+            max_lag: self.max_lag,
+            dropped_ticks: 0,
+            failed_ticks: 0,
+            missed_ticks: 0,
+            skipped_processes: 0,
+            suspend_gaps: 0,
+            heartbeat_ticks: 0,
+            dedup: None,
+            denied: Vec::new(),
+        }
+    }
+}
+
+impl Summary {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "wall_time_ms": self.wall_time.as_millis() as u64,
+            "cpu_time_ms": self.cpu_time.map(|time| time.as_millis() as u64),
+            "peak_rss_kb": self.peak_rss,
+            "bytes_written": self.bytes_written,
+            "ticks": self.ticks,
+            "rows": self.rows,
+            "processes": self.processes,
+            "mean_tick_ms": self.mean_tick.as_secs_f64() * 1000.0,
+            "p95_tick_ms": self.p95_tick.as_secs_f64() * 1000.0,
+            "mean_lag_ms": self.mean_lag.as_secs_f64() * 1000.0,
+            "max_lag_ms": self.max_lag.as_secs_f64() * 1000.0,
+            "dropped_ticks": self.dropped_ticks,
+            "failed_ticks": self.failed_ticks,
+            "missed_ticks": self.missed_ticks,
+            "skipped_processes": self.skipped_processes,
+            "suspend_gaps": self.suspend_gaps,
+            "heartbeat_ticks": self.heartbeat_ticks,
+            "suppressed_rows": self.dedup.map(|dedup| dedup.suppressed),
+            "suppression_ratio": self.dedup.map(|dedup| dedup.ratio()),
+            "denied_reads": self
+                .denied
+                .iter()
+                .map(|(column, count)| (column.to_string(), (*count).into()))
+                .collect::<serde_json::Map<_, _>>(),
+        })
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ticks, {} rows of {} processes in {:?}",
+            self.ticks, self.rows, self.processes, self.wall_time
+        )?;
+        if let Some(cpu_time) = self.cpu_time {
+            write!(f, ", CPU time {:?}", cpu_time)?;
+        }
+        if let Some(peak_rss) = self.peak_rss {
+            write!(f, ", peak RSS {} KB", peak_rss)?;
+        }
+        write!(
+            f,
+            ", {} bytes written, tick latency mean {:?} p95 {:?}",
+            self.bytes_written, self.mean_tick, self.p95_tick
+        )?;
+        if !self.max_lag.is_zero() {
+            write!(
+                f,
+                ", schedule lag mean {:?} max {:?}",
+                self.mean_lag, self.max_lag
+            )?;
+        }
+        if self.dropped_ticks > 0 {
+            write!(f, ", {} ticks dropped", self.dropped_ticks)?;
+        }
+        if self.failed_ticks > 0 {
+            write!(f, ", {} failed ticks skipped", self.failed_ticks)?;
+        }
+        if self.missed_ticks > 0 {
+            write!(f, ", {} ticks missed", self.missed_ticks)?;
+        }
+        if self.skipped_processes > 0 {
+            write!(
+                f,
+                ", {} unreadable processes skipped",
+                self.skipped_processes
+            )?;
+        }
+        if self.suspend_gaps > 0 {
+            write!(f, ", {} suspend gaps", self.suspend_gaps)?;
+        }
+        if self.heartbeat_ticks > 0 {
+            write!(f, ", {} heartbeat-only ticks", self.heartbeat_ticks)?;
+        }
+        if let Some(dedup) = self.dedup {
+            write!(
+                f,
+                ", {} of {} rows suppressed ({:.1}%)",
+                dedup.suppressed,
+                dedup.sampled,
+                dedup.ratio() * 100.0
+            )?;
+        }
+        if !self.denied.is_empty() {
+            let denied: Vec<String> = self
+                .denied
+                .iter()
+                .map(|(column, count)| format!("{} {}", column, count))
+                .collect();
+            write!(f, ", reads denied: {}", denied.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// This process's user plus system CPU time so far, when the platform
+/// reports it.
+pub fn cpu_time() -> Option<Duration> {
+    resource_usage().map(|(cpu_time, _)| cpu_time)
+}
+
+/// This process's CPU time and peak resident memory in KB.
+#[cfg(unix)]
+fn resource_usage() -> Option<(Duration, u64)> {
+    // SAFETY: rusage is plain data and getrusage only writes into it.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+    let time = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    // macOS reports the peak in bytes, everything else in KB.
+    let peak_rss = if cfg!(target_os = "macos") {
+        usage.ru_maxrss as u64 / 1024
+    } else {
+        usage.ru_maxrss as u64
+    };
+    Some((time(usage.ru_utime) + time(usage.ru_stime), peak_rss))
+}
+
+#[cfg(windows)]
+fn resource_usage() -> Option<(Duration, u64)> {
+    use windows_sys::Win32::{
+        Foundation::FILETIME,
+        System::ProcessStatus::{K32GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS},
+        System::Threading::{GetCurrentProcess, GetProcessTimes},
+    };
+
+    let zero = FILETIME {
+        dwLowDateTime: 0,
+        dwHighDateTime: 0,
+    };
+    let (mut created, mut exited, mut kernel, mut user) = (zero, zero, zero, zero);
+    // SAFETY: the pseudo handle of the current process is always valid and
+    // the out parameters are live for the duration of the calls.
+    let mut counters: PROCESS_MEMORY_COUNTERS = unsafe { std::mem::zeroed() };
+    unsafe {
+        let process = GetCurrentProcess();
+        if GetProcessTimes(process, &mut created, &mut exited, &mut kernel, &mut user) == 0 {
+            return None;
+        }
+        counters.cb = std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+        if K32GetProcessMemoryInfo(process, &mut counters, counters.cb) == 0 {
+            return None;
+        }
+    }
+    // FILETIME counts 100ns intervals.
+    let time = |time: FILETIME| {
+        Duration::from_nanos(((time.dwHighDateTime as u64) << 32 | time.dwLowDateTime as u64) * 100)
+    };
+    Some((
+        time(kernel) + time(user),
+        counters.PeakWorkingSetSize as u64 / 1024,
+    ))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn resource_usage() -> Option<(Duration, u64)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_after_busy_run() {
+        let mut overhead = Overhead::new();
+        let mut writer = Counted::new(Vec::new(), overhead.written());
+        for tick in 1..=100u64 {
+            let started = Instant::now();
+            // Burn some CPU so the process has measurable CPU time.
+            let mut hash = tick;
+            for i in 0..200_000u64 {
+                hash = std::hint::black_box(hash.wrapping_mul(31).wrapping_add(i));
+            }
+            writeln!(writer, "{}", hash).unwrap();
+            overhead.record_tick(started.elapsed());
+        }
+
+        let summary = overhead.summary();
+        assert_eq!(summary.ticks, 100);
+        assert_eq!(summary.bytes_written, writer.inner.len() as u64);
+        assert!(summary.mean_tick > Duration::ZERO);
+        assert!(summary.p95_tick >= summary.mean_tick / 2);
+        assert!(summary.p95_tick <= summary.wall_time);
+        if cfg!(any(unix, windows)) {
+            assert!(summary.cpu_time.unwrap() > Duration::ZERO);
+            assert!(summary.peak_rss.unwrap() > 0);
+        }
+
+        let json = summary.to_json();
+        for field in [
+            "wall_time_ms",
+            "cpu_time_ms",
+            "peak_rss_kb",
+            "bytes_written",
+            "ticks",
+            "rows",
+            "processes",
+            "mean_tick_ms",
+            "p95_tick_ms",
+            "mean_lag_ms",
+            "max_lag_ms",
+            "dropped_ticks",
+            "failed_ticks",
+            "missed_ticks",
+            "skipped_processes",
+            "suspend_gaps",
+            "heartbeat_ticks",
+            "denied_reads",
+        ] {
+            assert!(json.get(field).is_some(), "Missing {}", field);
+        }
+    }
+
+    #[test]
+    fn test_p95_keeps_recent_latencies() {
+        let mut overhead = Overhead::new();
+        for _ in 0..MAX_LATENCIES {
+            overhead.record_tick(Duration::from_secs(1));
+        }
+        for _ in 0..MAX_LATENCIES {
+            overhead.record_tick(Duration::from_millis(1));
+        }
+        let summary = overhead.summary();
+        assert_eq!(summary.p95_tick, Duration::from_millis(1));
+        assert_eq!(summary.ticks, 2 * MAX_LATENCIES as u64);
+    }
+
+    #[test]
+    fn test_lag_behind_schedule() {
+        let mut overhead = Overhead::new();
+        overhead.record_lag(Duration::ZERO, Duration::from_millis(2));
+        overhead.record_lag(Duration::from_secs(1), Duration::from_millis(1010));
+        // A tick that starts early, e.g. after a clock adjustment, is on time.
+        overhead.record_lag(Duration::from_secs(2), Duration::from_millis(1990));
+        let summary = overhead.summary();
+        assert_eq!(summary.mean_lag, Duration::from_millis(4));
+        assert_eq!(summary.max_lag, Duration::from_millis(10));
+    }
+}
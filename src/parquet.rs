@@ -0,0 +1,298 @@
+use crate::{integrity::SharedDigest, numfmt, overhead::Counted, Destination, ProcessSample};
+use ::parquet::{
+    basic::Compression,
+    data_type::{ByteArray, ByteArrayType, DoubleType, FloatType, Int32Type, Int64Type},
+    file::{
+        properties::WriterProperties,
+        reader::{FileReader, SerializedFileReader},
+        writer::SerializedFileWriter,
+    },
+    format::KeyValue,
+    schema::{parser::parse_message_type, types::Type},
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use std::{
+    fs::File,
+    path::Path,
+    sync::{Arc, OnceLock},
+};
+use sysinfo::PidExt;
+
+const SCHEMA: &str = "
+message process_sample {
+    required int64 timestamp (TIMESTAMP(MILLIS, true));
+    required int32 pid (INTEGER(32, false));
+    required binary name (STRING);
+    required float cpu;
+    required double mem;
+    required int64 memory_bytes (INTEGER(64, false));
+}
+";
+
+/// File metadata key of the run ID.
+const RUN_ID_KEY: &str = "chronologger.run_id";
+
+/// Rows per row group unless `--parquet-row-group` says otherwise: large
+/// enough for readers to scan efficiently, small enough to hold in memory
+/// at once.
+pub const DEFAULT_ROW_GROUP_ROWS: usize = 100_000;
+
+pub fn schema() -> &'static Arc<Type> {
+    static SCHEMA_CELL: OnceLock<Arc<Type>> = OnceLock::new();
+    SCHEMA_CELL
+        .get_or_init(|| Arc::new(parse_message_type(SCHEMA).expect("Invalid Parquet schema")))
+}
+
+/// The rows of the row group being filled, one buffer per column.
+#[derive(Default)]
+struct Columns {
+    timestamp: Vec<i64>,
+    pid: Vec<i32>,
+    name: Vec<ByteArray>,
+    cpu: Vec<f32>,
+    mem: Vec<f64>,
+    memory_bytes: Vec<i64>,
+}
+
+impl Columns {
+    fn len(&self) -> usize {
+        self.timestamp.len()
+    }
+}
+
+/// Writes samples into a Parquet file, a row group every `row_group_rows`
+/// rows. The file can only be read once [`close`](Self::close) has written
+/// its footer.
+pub struct ParquetOutput {
+    writer: SerializedFileWriter<Counted<Destination>>,
+    row_group_rows: usize,
+    pending: Columns,
+    /// Total memory in KB, which memory percentages are converted back to
+    /// bytes with. Until it is set, memory is written as 0 bytes.
+    total_memory: f64,
+    /// Digest of a file destination, which cannot see where rows end.
+    digest: Option<SharedDigest>,
+}
+
+impl ParquetOutput {
+    pub fn new(file: Counted<Destination>, row_group_rows: Option<usize>) -> Result<Self> {
+        let digest = file.get_ref().digest().cloned();
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_created_by(format!("chronologger {}", env!("CARGO_PKG_VERSION")))
+            .build();
+        let writer = SerializedFileWriter::new(file, schema().clone(), Arc::new(properties))
+            .context("Failed to start Parquet file")?;
+        Ok(Self {
+            writer,
+            row_group_rows: row_group_rows.unwrap_or(DEFAULT_ROW_GROUP_ROWS),
+            pending: Columns::default(),
+            total_memory: 0.0,
+            digest,
+        })
+    }
+
+    pub fn digest(&self) -> Option<&SharedDigest> {
+        self.digest.as_ref()
+    }
+
+    /// The destination the file is written to.
+    pub fn get_ref(&self) -> &Counted<Destination> {
+        self.writer.inner()
+    }
+
+    /// Sets the host's total memory in KB.
+    pub fn set_total_memory(&mut self, total_memory: f64) {
+        self.total_memory = total_memory;
+    }
+
+    /// Records `run_id` in the file metadata, which is written with the
+    /// footer.
+    pub fn set_run_id(&mut self, run_id: &str) {
+        self.writer
+            .append_key_value_metadata(KeyValue::new(RUN_ID_KEY.to_string(), run_id.to_string()));
+    }
+
+    pub fn write_tick(
+        &mut self,
+        timestamp: &DateTime<Local>,
+        samples: &[ProcessSample],
+    ) -> Result<()> {
+        let timestamp = timestamp.timestamp_millis();
+        for sample in samples {
+            let columns = &mut self.pending;
+            columns.timestamp.push(timestamp);
+            // Groups, which have no PID, are not written to Parquet.
+            columns
+                .pid
+                .push(sample.pid.map_or(0, |pid| pid.as_u32() as i32));
+            columns.name.push(ByteArray::from(sample.name.as_str()));
+            columns.cpu.push(sample.cpu_usage);
+            columns.mem.push(sample.memory_usage);
+            columns
+                .memory_bytes
+                .push(numfmt::memory_bytes(sample.memory_usage, self.total_memory) as i64);
+            if columns.len() >= self.row_group_rows {
+                self.write_row_group()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the pending rows as a row group of their own.
+    fn write_row_group(&mut self) -> Result<()> {
+        let rows = self.pending.len();
+        if rows == 0 {
+            return Ok(());
+        }
+        let columns = std::mem::take(&mut self.pending);
+        let mut row_group = self
+            .writer
+            .next_row_group()
+            .context("Failed to start row group")?;
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column().context("Failed to start column")? {
+            let written = match index {
+                0 => column
+                    .typed::<Int64Type>()
+                    .write_batch(&columns.timestamp, None, None),
+                1 => column
+                    .typed::<Int32Type>()
+                    .write_batch(&columns.pid, None, None),
+                2 => column
+                    .typed::<ByteArrayType>()
+                    .write_batch(&columns.name, None, None),
+                3 => column
+                    .typed::<FloatType>()
+                    .write_batch(&columns.cpu, None, None),
+                4 => column
+                    .typed::<DoubleType>()
+                    .write_batch(&columns.mem, None, None),
+                _ => column
+                    .typed::<Int64Type>()
+                    .write_batch(&columns.memory_bytes, None, None),
+            };
+            written.context("Failed to write record!")?;
+            column.close().context("Failed to write record!")?;
+            index += 1;
+        }
+        row_group.close().context("Failed to write row group")?;
+        if let Some(digest) = &self.digest {
+            digest.lock().unwrap().add_rows(rows as u64);
+        }
+        Ok(())
+    }
+
+    /// Writes the pending rows and the footer. Nothing can be written after
+    /// it.
+    pub fn close(&mut self) -> Result<()> {
+        self.write_row_group()?;
+        self.writer
+            .finish()
+            .context("Failed to write Parquet footer")?;
+        Ok(())
+    }
+}
+
+/// Counts the rows in the Parquet file at `path`.
+pub fn count_rows(path: &Path) -> Result<u64> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let reader = SerializedFileReader::new(file)
+        .with_context(|| format!("{} is not Parquet", path.display()))?;
+    Ok(reader.metadata().file_metadata().num_rows() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::samples;
+    use crate::{
+        integrity::{Hashed, Rows},
+        retry::Retrying,
+    };
+    use ::parquet::record::Field;
+    use tempfile::NamedTempFile;
+
+    fn output(file: &NamedTempFile, row_group_rows: Option<usize>) -> ParquetOutput {
+        ParquetOutput::new(
+            Counted::new(
+                Destination::File(Hashed::new(
+                    Retrying::new(file.reopen().unwrap(), Default::default()),
+                    Rows::Counted,
+                )),
+                Default::default(),
+            ),
+            row_group_rows,
+        )
+        .expect("Failed to create Parquet output")
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let timestamp = Local::now();
+        let samples = samples();
+        let mut output = output(&temp_file, None);
+        // 16 GiB.
+        output.set_total_memory(16.0 * 1024.0 * 1024.0);
+        output.set_run_id("run-1");
+        for _ in 0..2 {
+            output
+                .write_tick(&timestamp, &samples)
+                .expect("Failed to write tick");
+        }
+        output.close().expect("Failed to close");
+
+        let reader = SerializedFileReader::new(File::open(temp_file.path()).unwrap()).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.num_row_groups(), 1);
+        let run_id = metadata
+            .file_metadata()
+            .key_value_metadata()
+            .and_then(|pairs| pairs.iter().find(|pair| pair.key == RUN_ID_KEY))
+            .and_then(|pair| pair.value.clone());
+        assert_eq!(run_id.as_deref(), Some("run-1"));
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), samples.len() * 2);
+        assert_eq!(count_rows(temp_file.path()).unwrap(), rows.len() as u64);
+        let fields: Vec<(&String, &Field)> = rows[1].get_column_iter().collect();
+        assert_eq!(
+            fields[0].1,
+            &Field::TimestampMillis(timestamp.timestamp_millis())
+        );
+        assert_eq!(fields[1].1, &Field::UInt(2));
+        assert_eq!(fields[2].1, &Field::Str("proc-2".to_string()));
+        assert_eq!(fields[3].1, &Field::Float(3.0));
+        assert_eq!(fields[4].1, &Field::Double(0.5));
+        // 0.5% of 16 GiB.
+        assert_eq!(fields[5].1, &Field::ULong(83_886 * 1024));
+
+        let footer = output.digest().unwrap().lock().unwrap().footer();
+        assert_eq!(footer.rows, rows.len() as u64);
+        assert_eq!(footer.bytes, temp_file.as_file().metadata().unwrap().len());
+    }
+
+    #[test]
+    fn test_row_groups() {
+        let temp_file = NamedTempFile::new().expect("Failed to create temp file");
+        let mut output = output(&temp_file, Some(2));
+        output
+            .write_tick(&Local::now(), &samples())
+            .expect("Failed to write tick");
+        output.close().expect("Failed to close");
+
+        let reader = SerializedFileReader::new(File::open(temp_file.path()).unwrap()).unwrap();
+        let groups: Vec<i64> = reader
+            .metadata()
+            .row_groups()
+            .iter()
+            .map(|group| group.num_rows())
+            .collect();
+        assert_eq!(groups, [2, 2, 1]);
+    }
+}
@@ -0,0 +1,82 @@
+use std::{collections::HashMap, mem};
+
+/// State kept for each process from one tick to the next. A PID is
+/// forgotten once a tick goes by without it, so a table that sees processes
+/// come and go does not grow without bound.
+#[derive(Debug)]
+pub struct PerPid<T> {
+    /// The state of each PID seen in the previous tick, until this tick
+    /// takes it.
+    previous: HashMap<u32, T>,
+    /// The state of each PID seen so far in this tick.
+    current: HashMap<u32, T>,
+}
+
+impl<T> Default for PerPid<T> {
+    fn default() -> Self {
+        Self {
+            previous: HashMap::new(),
+            current: HashMap::new(),
+        }
+    }
+}
+
+impl<T> PerPid<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a new tick, forgetting PIDs the tick that ended did not see.
+    pub fn begin_tick(&mut self) {
+        self.previous = mem::take(&mut self.current);
+    }
+
+    /// Takes what the previous tick kept for `pid`, if it saw the PID.
+    pub fn take(&mut self, pid: u32) -> Option<T> {
+        self.previous.remove(&pid)
+    }
+
+    /// Keeps `state` for `pid` until the next tick.
+    pub fn insert(&mut self, pid: u32, state: T) {
+        self.current.insert(pid, state);
+    }
+
+    /// What this tick has kept for `pid` so far.
+    pub fn get(&self, pid: u32) -> Option<&T> {
+        self.current.get(&pid)
+    }
+
+    /// How many PIDs this tick has kept state for so far.
+    pub fn len(&self) -> usize {
+        self.current.len()
+    }
+
+    /// Forgets every PID, as if no tick had been seen.
+    pub fn clear(&mut self) {
+        self.previous.clear();
+        self.current.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forgets_pids_missing_for_a_tick() {
+        let mut state = PerPid::new();
+        state.begin_tick();
+        state.insert(1, "one");
+        state.insert(2, "two");
+
+        state.begin_tick();
+        assert_eq!(state.take(1), Some("one"));
+        assert_eq!(state.take(1), None);
+        state.insert(1, "one again");
+
+        // PID 2 was not seen in the tick that just ended.
+        state.begin_tick();
+        assert_eq!(state.take(2), None);
+        assert_eq!(state.take(1), Some("one again"));
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::{bail, Result};
+use log::{info, warn};
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{self, ErrorKind, Write},
+    iter,
+    os::windows::{
+        ffi::OsStrExt,
+        io::{AsRawHandle, FromRawHandle},
+    },
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Arc,
+    thread,
+};
+use windows_sys::Win32::{
+    Foundation::{GetLastError, ERROR_PIPE_CONNECTED, HANDLE, INVALID_HANDLE_VALUE},
+    Storage::FileSystem::PIPE_ACCESS_OUTBOUND,
+    System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_TYPE_BYTE, PIPE_WAIT,
+    },
+};
+
+pub const PIPE_PREFIX: &str = r"\\.\pipe\";
+const PIPE_BUFFER_BYTES: u32 = 64 * 1024;
+
+pub fn is_pipe_path(path: &str) -> bool {
+    path.len() > PIPE_PREFIX.len()
+        && path
+            .get(..PIPE_PREFIX.len())
+            .is_some_and(|prefix| prefix.eq_ignore_ascii_case(PIPE_PREFIX))
+}
+
+/// Serves the output to a single named pipe client. Output written while no
+/// client is connected is dropped; a new client is accepted in the background
+/// and starts receiving data at the next flush so it never sees a partial tick.
+pub struct PipeWriter {
+    name: String,
+    pipe: File,
+    connected: bool,
+    reconnected: Arc<AtomicBool>,
+    waiting: bool,
+    dropped: u64,
+}
+
+impl PipeWriter {
+    /// Creates the pipe and blocks until the first client connects.
+    pub fn create(name: &str) -> Result<Self> {
+        let wide_name: Vec<u16> = OsStr::new(name)
+            .encode_wide()
+            .chain(iter::once(0))
+            .collect();
+        // SAFETY: `wide_name` is a NUL-terminated UTF-16 string that outlives
+        // the call, and no security attributes are passed.
+        let handle = unsafe {
+            CreateNamedPipeW(
+                wide_name.as_ptr(),
+                PIPE_ACCESS_OUTBOUND,
+                PIPE_TYPE_BYTE | PIPE_WAIT,
+                1,
+                PIPE_BUFFER_BYTES,
+                0,
+                0,
+                ptr::null(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            bail!(
+                "Failed to create named pipe {}: {}",
+                name,
+                io::Error::last_os_error()
+            );
+        }
+        // SAFETY: `handle` is a valid pipe handle owned by nobody else.
+        let pipe = unsafe { File::from_raw_handle(handle as _) };
+
+        info!("Waiting for a client to connect to {}...", name);
+        connect(handle)?;
+        info!("Client connected to {}", name);
+
+        Ok(Self {
+            name: name.to_string(),
+            pipe,
+            connected: true,
+            reconnected: Arc::new(AtomicBool::new(false)),
+            waiting: false,
+            dropped: 0,
+        })
+    }
+
+    fn handle(&self) -> HANDLE {
+        self.pipe.as_raw_handle() as HANDLE
+    }
+
+    fn wait_for_client(&mut self) {
+        self.connected = false;
+        self.waiting = true;
+        warn!(
+            "Client disconnected from {}, waiting for a new one",
+            self.name
+        );
+
+        let handle = self.handle();
+        // SAFETY: `handle` is the server end of the pipe owned by `self.pipe`.
+        unsafe { DisconnectNamedPipe(handle) };
+        let reconnected = self.reconnected.clone();
+        thread::spawn(move || match connect(handle) {
+            Ok(()) => reconnected.store(true, Ordering::SeqCst),
+            Err(e) => warn!("{:#}", e),
+        });
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.connected {
+            match self.pipe.write(buf) {
+                Err(e) if e.kind() == ErrorKind::BrokenPipe => self.wait_for_client(),
+                result => return result,
+            }
+        }
+        self.dropped += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.waiting && self.reconnected.swap(false, Ordering::SeqCst) {
+            info!(
+                "Client connected to {} ({} bytes dropped while disconnected)",
+                self.name, self.dropped
+            );
+            self.waiting = false;
+            self.connected = true;
+            self.dropped = 0;
+        }
+        Ok(())
+    }
+}
+
+fn connect(handle: HANDLE) -> Result<()> {
+    // SAFETY: `handle` is a valid, synchronous named pipe server handle.
+    let ok = unsafe { ConnectNamedPipe(handle, ptr::null_mut()) };
+    // A client connecting between creation and this call is still a success.
+    if ok == 0 && unsafe { GetLastError() } != ERROR_PIPE_CONNECTED {
+        bail!(
+            "Failed to accept a pipe client: {}",
+            io::Error::last_os_error()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader},
+        time::Duration,
+    };
+
+    fn open_client(name: &str) -> BufReader<File> {
+        loop {
+            match File::open(name) {
+                Ok(file) => return BufReader::new(file),
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_is_pipe_path() {
+        assert!(is_pipe_path(r"\\.\pipe\chronologger"));
+        assert!(is_pipe_path(r"\\.\PIPE\chronologger"));
+        assert!(!is_pipe_path(r"\\.\pipe\"));
+        assert!(!is_pipe_path("process_usage.csv"));
+    }
+
+    #[test]
+    fn test_client_reconnect() {
+        let name = format!(r"\\.\pipe\chronologger-test-{}", std::process::id());
+
+        let client = thread::spawn({
+            let name = name.clone();
+            move || {
+                let mut first = open_client(&name);
+                let mut line = String::new();
+                first.read_line(&mut line).unwrap();
+                drop(first);
+
+                let mut second = open_client(&name);
+                let mut next = String::new();
+                second.read_line(&mut next).unwrap();
+                (line, next)
+            }
+        });
+
+        let mut writer = PipeWriter::create(&name).expect("Failed to create pipe");
+        writeln!(writer, "first").unwrap();
+        writer.flush().unwrap();
+
+        // Keep writing ticks until the second client has been accepted.
+        while !client.is_finished() {
+            writeln!(writer, "tick").unwrap();
+            writer.flush().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let (line, next) = client.join().unwrap();
+        assert_eq!(line, "first\n");
+        assert_eq!(next, "tick\n");
+    }
+}
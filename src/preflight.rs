@@ -0,0 +1,390 @@
+use crate::{Backend, Config, NONE_PATH, STDOUT_PATH};
+use anyhow::{bail, Result};
+use log::{error, info, warn};
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    net::{TcpStream, ToSocketAddrs},
+    path::Path,
+    time::Duration,
+};
+use sysinfo::{System, SystemExt};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// The run can go ahead, but probably not as intended.
+    Warning,
+    /// The run cannot go ahead.
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.detail)
+    }
+}
+
+/// Checks run before the output is created or the header written, so that a
+/// mistake in the configuration fails the run at once rather than an hour in.
+#[derive(Debug, Default)]
+pub struct Preflight {
+    pub checks: Vec<Check>,
+}
+
+impl Preflight {
+    /// Runs every check for `config`. Checks that only look at the
+    /// configuration come first, and the output file is not touched if any
+    /// of them failed.
+    pub fn run(config: &Config) -> Self {
+        let mut preflight = Self::default();
+        preflight.check_backend(config.backend);
+        match &config.host_proc {
+            Some(root) => {
+                preflight.check_host_proc(root);
+                for pid in &config.pids {
+                    preflight.check_host_pid(root, *pid);
+                }
+            }
+            // A replay logs recorded PIDs, not live ones.
+            None if config.replay.is_some() => {}
+            None => {
+                for pid in &config.pids {
+                    preflight.check_pid(*pid);
+                }
+            }
+        }
+        #[cfg(unix)]
+        if let Some(path) = &config.control_socket {
+            preflight.check_parent("control socket", path);
+        }
+        if let crate::cgroup::Selection::Path(dir) = &config.cgroup {
+            preflight.check_cgroup(dir);
+        }
+        if let Some(pushgateway) = &config.pushgateway {
+            let address = format!("{}:{}", pushgateway.url.host, pushgateway.url.port);
+            preflight.probe("pushgateway", &address);
+        }
+        if let Some(address) = &config.graphite {
+            preflight.probe("graphite", address);
+        }
+        if let Some(ingest) = &config.ingest {
+            let address = format!("{}:{}", ingest.url.host, ingest.url.port);
+            preflight.probe("http endpoint", &address);
+        }
+        #[cfg(feature = "loki")]
+        if let Some(loki) = &config.loki {
+            preflight.probe("loki", &format!("{}:{}", loki.url.host, loki.url.port));
+        }
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = &config.mqtt {
+            match crate::mqtt::broker_address(mqtt) {
+                Ok((host, port)) => preflight.probe("mqtt", &format!("{}:{}", host, port)),
+                Err(e) => preflight.push("mqtt", Status::Failed, format!("{:#}", e)),
+            }
+        }
+
+        if !preflight.failed() {
+            preflight.check_output(&config.output);
+        }
+        preflight
+    }
+
+    /// Logs every check and fails if any check failed, or, with `strict`,
+    /// if any check produced a warning.
+    pub fn finish(&self, strict: bool) -> Result<()> {
+        for check in &self.checks {
+            match check.status {
+                Status::Ok => info!("Preflight {}", check),
+                Status::Warning => warn!("Preflight {}", check),
+                Status::Failed => error!("Preflight {}", check),
+            }
+        }
+        let blocking: Vec<String> = self
+            .checks
+            .iter()
+            .filter(|check| {
+                check.status == Status::Failed || (strict && check.status == Status::Warning)
+            })
+            .map(Check::to_string)
+            .collect();
+        if !blocking.is_empty() {
+            bail!("Preflight failed: {}", blocking.join("; "));
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        self.checks
+            .iter()
+            .map(|check| {
+                serde_json::json!({
+                    "check": check.name,
+                    "status": format!("{:?}", check.status).to_lowercase(),
+                    "detail": check.detail,
+                })
+            })
+            .collect()
+    }
+
+    fn failed(&self) -> bool {
+        self.checks
+            .iter()
+            .any(|check| check.status == Status::Failed)
+    }
+
+    fn push(&mut self, name: &str, status: Status, detail: String) {
+        self.checks.push(Check {
+            name: name.to_string(),
+            status,
+            detail,
+        });
+    }
+
+    fn check_backend(&mut self, backend: Backend) {
+        let available =
+            backend == Backend::Sysinfo || cfg!(all(target_os = "linux", feature = "procfs"));
+        if available {
+            self.push(
+                "backend",
+                Status::Ok,
+                format!("{:?}", backend).to_lowercase(),
+            );
+        } else {
+            self.push(
+                "backend",
+                Status::Warning,
+                "procfs is unavailable in this build, falling back to sysinfo".to_string(),
+            );
+        }
+    }
+
+    fn check_pid(&mut self, pid: sysinfo::Pid) {
+        let mut system = System::new();
+        if system.refresh_process(pid) {
+            self.push("pid", Status::Ok, format!("process {} found", pid));
+        } else {
+            self.push(
+                "pid",
+                Status::Warning,
+                format!("process {} not found, it will not be logged", pid),
+            );
+        }
+    }
+
+    fn check_host_proc(&mut self, root: &Path) {
+        if root.join("stat").is_file() && root.join("meminfo").is_file() {
+            self.push("host proc", Status::Ok, root.display().to_string());
+        } else {
+            self.push(
+                "host proc",
+                Status::Failed,
+                format!("{} is not a procfs mount", root.display()),
+            );
+        }
+    }
+
+    /// Looks a PID up in the host's procfs, as sysinfo only sees the PID
+    /// namespace chronologger runs in.
+    fn check_host_pid(&mut self, root: &Path, pid: sysinfo::Pid) {
+        if root.join(pid.to_string()).is_dir() {
+            self.push("pid", Status::Ok, format!("process {} found", pid));
+        } else {
+            self.push(
+                "pid",
+                Status::Warning,
+                format!("process {} not found, it will not be logged", pid),
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    fn check_parent(&mut self, name: &str, path: &Path) {
+        match path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            Some(parent) if !parent.is_dir() => self.push(
+                name,
+                Status::Failed,
+                format!("directory {} does not exist", parent.display()),
+            ),
+            _ => self.push(name, Status::Ok, path.display().to_string()),
+        }
+    }
+
+    /// A `--cgroup` directory must have CPU bandwidth counters to read.
+    fn check_cgroup(&mut self, dir: &Path) {
+        let stat = dir.join("cpu.stat");
+        if stat.is_file() {
+            self.push("cgroup", Status::Ok, dir.display().to_string());
+        } else {
+            self.push(
+                "cgroup",
+                Status::Failed,
+                format!("{} does not exist", stat.display()),
+            );
+        }
+    }
+
+    /// Opens a TCP connection to a network sink. An unreachable sink is only
+    /// a warning, since sinks reconnect on their own.
+    fn probe(&mut self, name: &str, address: &str) {
+        let connected = address.to_socket_addrs().map(|mut addresses| {
+            addresses.any(|address| TcpStream::connect_timeout(&address, PROBE_TIMEOUT).is_ok())
+        });
+        match connected {
+            Ok(true) => self.push(name, Status::Ok, format!("{} is reachable", address)),
+            Ok(false) => self.push(
+                name,
+                Status::Warning,
+                format!("{} is not reachable", address),
+            ),
+            Err(e) => self.push(
+                name,
+                Status::Warning,
+                format!("cannot resolve {}: {}", address, e),
+            ),
+        }
+    }
+
+    /// Creates the output's directory if needed and opens the real file for
+    /// writing, without truncating anything yet.
+    fn check_output(&mut self, output: &str) {
+        #[cfg(windows)]
+        if crate::pipe::is_pipe_path(output) {
+            self.push("output", Status::Ok, format!("named pipe {}", output));
+            return;
+        }
+        if output == STDOUT_PATH {
+            self.push("output", Status::Ok, "stdout".to_string());
+            return;
+        }
+        if output == NONE_PATH {
+            self.push("output", Status::Ok, "none".to_string());
+            return;
+        }
+        let path = Path::new(output);
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            if !parent.is_dir() {
+                if let Err(e) = fs::create_dir_all(parent) {
+                    self.push(
+                        "output",
+                        Status::Failed,
+                        format!("cannot create directory {}: {}", parent.display(), e),
+                    );
+                    return;
+                }
+                info!("Created output directory {}", parent.display());
+            }
+        }
+        match OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+        {
+            Ok(_) => self.push("output", Status::Ok, format!("{} is writable", output)),
+            Err(e) => self.push(
+                "output",
+                Status::Failed,
+                format!("cannot open {} for writing: {}", output, e),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sysinfo::{Pid, PidExt};
+    use tempfile::{NamedTempFile, TempDir};
+
+    fn config(args: &[&str]) -> Config {
+        let args = ["process_logger"].iter().chain(args);
+        Config::from_args(&Config::command().get_matches_from(args)).unwrap()
+    }
+
+    #[test]
+    fn test_creates_output_directory() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("nested/out.csv");
+        let preflight = Preflight::run(&config(&["--output", output.to_str().unwrap()]));
+        preflight.finish(true).expect("Preflight should pass");
+        assert!(output.exists());
+    }
+
+    #[test]
+    fn test_unwritable_output_fails() {
+        // A directory cannot be created below a regular file, even as root.
+        let file = NamedTempFile::new().unwrap();
+        let output = file.path().join("out.csv");
+        let preflight = Preflight::run(&config(&["--output", output.to_str().unwrap()]));
+        let error = preflight.finish(false).unwrap_err();
+        assert!(error.to_string().contains("cannot create directory"));
+    }
+
+    #[test]
+    fn test_strict_fails_before_output_is_created() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("out.csv");
+        let missing = Pid::from_u32(99_999_999).to_string();
+        let config = config(&["--output", output.to_str().unwrap(), "--pid", &missing]);
+
+        let preflight = Preflight::run(&config);
+        assert!(preflight.finish(false).is_ok());
+        assert!(preflight.finish(true).is_err());
+
+        #[cfg(unix)]
+        {
+            let socket = dir.path().join("missing/control.sock");
+            let config = Config {
+                control_socket: Some(socket),
+                ..config
+            };
+            let _ = fs::remove_file(&output);
+            let preflight = Preflight::run(&config);
+            assert!(preflight.finish(false).is_err());
+            assert!(!output.exists(), "Output created despite a failed check");
+        }
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "procfs"))]
+    fn test_host_proc_must_be_procfs() {
+        let dir = TempDir::new().unwrap();
+        let output = dir.path().join("out.csv");
+        let root = dir.path().join("proc");
+        fs::create_dir_all(root.join("4242")).unwrap();
+        let args = [
+            "--output",
+            output.to_str().unwrap(),
+            "--host-proc",
+            root.to_str().unwrap(),
+            "--pid",
+            "4242",
+        ];
+        let preflight = Preflight::run(&config(&args));
+        assert!(
+            preflight.finish(false).is_err(),
+            "Accepted a non-procfs root"
+        );
+
+        fs::write(root.join("stat"), "cpu  1 0 0 0 0 0 0 0\n").unwrap();
+        fs::write(root.join("meminfo"), "MemTotal: 1024 kB\n").unwrap();
+        let preflight = Preflight::run(&config(&args));
+        preflight.finish(true).expect("Host PID must be found");
+    }
+}
@@ -0,0 +1,613 @@
+use crate::source::{ProcessInfo, SystemSource, SystemStats};
+use log::debug;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+use sysinfo::{Pid, PidExt};
+
+/// Reads only the fields chronologger emits straight from /proc: name and
+/// CPU ticks from `/proc/<pid>/stat`, resident pages from `/proc/<pid>/statm`
+/// and total jiffies from `/proc/stat`. CPU usage is computed the same way as
+/// sysinfo, relative to one CPU, so both backends produce comparable values.
+pub struct ProcfsSource {
+    /// Where procfs is mounted, e.g. the host's /proc bind-mounted into a
+    /// container.
+    root: PathBuf,
+    processes: HashMap<Pid, Entry>,
+    watched: Option<Vec<Pid>>,
+    cpu_count: usize,
+    total_memory: u64,
+    boot_time: Option<u64>,
+    page_size_kb: u64,
+    /// Clock ticks per second, the unit of the CPU times in `stat`.
+    ticks_per_second: u64,
+    last_total_jiffies: u64,
+    last_idle_jiffies: u64,
+    /// Share of all CPUs in use since the previous refresh.
+    cpu_usage: f32,
+    /// Total and idle jiffies of each CPU at the previous refresh.
+    last_core_jiffies: Vec<(u64, u64)>,
+    /// Share of each CPU in use since the previous refresh.
+    core_usage: Vec<f32>,
+    generation: u64,
+    read_errors: u64,
+    buffer: String,
+}
+
+struct Entry {
+    name: String,
+    start_time: u64,
+    cpu_ticks: u64,
+    cpu_usage: f32,
+    memory: u64,
+    generation: u64,
+}
+
+/// The fields of `/proc/<pid>/stat` that are needed.
+#[derive(Debug, PartialEq)]
+struct Stat<'a> {
+    name: &'a str,
+    cpu_ticks: u64,
+    start_time: u64,
+}
+
+impl ProcfsSource {
+    #[cfg(test)]
+    pub fn new() -> Self {
+        Self::with_root(PathBuf::from("/proc"))
+    }
+
+    /// Reads processes, CPUs and memory from the procfs mounted at `root`.
+    pub fn with_root(root: PathBuf) -> Self {
+        let stat = fs::read_to_string(root.join("stat")).unwrap_or_default();
+        // SAFETY: sysconf has no preconditions.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        // SAFETY: likewise.
+        let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        let mut source = Self {
+            total_memory: read_total_memory(&root).unwrap_or(0),
+            root,
+            processes: HashMap::new(),
+            watched: None,
+            cpu_count: count_cpus(&stat).max(1),
+            boot_time: parse_boot_time(&stat),
+            page_size_kb: (page_size.max(1024) / 1024) as u64,
+            ticks_per_second: ticks_per_second.max(1) as u64,
+            last_total_jiffies: parse_total_jiffies(&stat).unwrap_or(0),
+            last_idle_jiffies: parse_idle_jiffies(&stat).unwrap_or(0),
+            cpu_usage: 0.0,
+            last_core_jiffies: parse_core_jiffies(&stat),
+            core_usage: Vec::new(),
+            generation: 0,
+            read_errors: 0,
+            buffer: String::with_capacity(1024),
+        };
+        source.refresh();
+        source
+    }
+
+    /// Reads a file of process `pid`, counting failures other than the
+    /// process having exited.
+    fn read_pid_file(&mut self, pid: Pid, file: &str) -> bool {
+        let path = self.root.join(pid.to_string()).join(file);
+        match read_into(&path, &mut self.buffer) {
+            Ok(()) => true,
+            // A process that exits while being read fails with ESRCH.
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound || e.raw_os_error() == Some(libc::ESRCH) =>
+            {
+                false
+            }
+            Err(e) => {
+                debug!(
+                    "Skipping process {}, failed to read {}: {}",
+                    pid,
+                    path.display(),
+                    e
+                );
+                self.read_errors += 1;
+                false
+            }
+        }
+    }
+
+    fn refresh_pid(&mut self, pid: Pid, per_cpu_jiffies: f32) {
+        if !self.read_pid_file(pid, "stat") {
+            return;
+        }
+        let Some(stat) = parse_stat(&self.buffer) else {
+            debug!("Skipping process {}, unparsable stat", pid);
+            self.read_errors += 1;
+            return;
+        };
+        let (cpu_ticks, start_time) = (stat.cpu_ticks, stat.start_time);
+        // Like sysinfo, the name is only read when a process is first seen.
+        let name = match self.processes.get(&pid) {
+            Some(entry) if entry.start_time == start_time => None,
+            _ => Some(stat.name.to_string()),
+        };
+
+        if !self.read_pid_file(pid, "statm") {
+            return;
+        }
+        let resident_pages = parse_statm_resident(&self.buffer).unwrap_or(0);
+        let memory = resident_pages * self.page_size_kb;
+        let max_usage = self.cpu_count as f32 * 100.0;
+        let generation = self.generation;
+
+        let entry = self.processes.entry(pid).or_insert_with(|| Entry {
+            name: String::new(),
+            start_time,
+            cpu_ticks,
+            cpu_usage: 0.0,
+            memory: 0,
+            generation,
+        });
+        if entry.start_time != start_time {
+            // The PID was reused by a new process; start its history over.
+            entry.start_time = start_time;
+            entry.cpu_ticks = cpu_ticks;
+        }
+        entry.cpu_usage = if per_cpu_jiffies > 0.0 {
+            (cpu_ticks.saturating_sub(entry.cpu_ticks) as f32 / per_cpu_jiffies * 100.0)
+                .min(max_usage)
+        } else {
+            0.0
+        };
+        entry.cpu_ticks = cpu_ticks;
+        entry.memory = memory;
+        entry.generation = generation;
+        if let Some(name) = name {
+            entry.name = name;
+        }
+    }
+}
+
+impl SystemSource for ProcfsSource {
+    fn refresh(&mut self) {
+        self.generation += 1;
+        let read = read_into(&self.root.join("stat"), &mut self.buffer).is_ok();
+        let total_jiffies = read
+            .then(|| parse_total_jiffies(&self.buffer))
+            .flatten()
+            .unwrap_or(self.last_total_jiffies);
+        let idle_jiffies = read
+            .then(|| parse_idle_jiffies(&self.buffer))
+            .flatten()
+            .unwrap_or(self.last_idle_jiffies);
+        let elapsed = total_jiffies.saturating_sub(self.last_total_jiffies);
+        let per_cpu_jiffies = elapsed as f32 / self.cpu_count as f32;
+        if elapsed > 0 {
+            let idle = idle_jiffies.saturating_sub(self.last_idle_jiffies);
+            self.cpu_usage = elapsed.saturating_sub(idle) as f32 / elapsed as f32 * 100.0;
+        }
+        self.last_total_jiffies = total_jiffies;
+        self.last_idle_jiffies = idle_jiffies;
+        if read {
+            let cores = parse_core_jiffies(&self.buffer);
+            self.core_usage.resize(cores.len(), 0.0);
+            for ((usage, (total, idle)), (last_total, last_idle)) in
+                self.core_usage.iter_mut().zip(&cores).zip(
+                    self.last_core_jiffies
+                        .iter()
+                        .chain(std::iter::repeat(&(0, 0))),
+                )
+            {
+                let elapsed = total.saturating_sub(*last_total);
+                if elapsed > 0 {
+                    let busy = elapsed.saturating_sub(idle.saturating_sub(*last_idle));
+                    *usage = busy as f32 / elapsed as f32 * 100.0;
+                }
+            }
+            self.last_core_jiffies = cores;
+        }
+
+        let pids: Vec<Pid> = match &self.watched {
+            Some(pids) => pids.clone(),
+            None => fs::read_dir(&self.root)
+                .map(|entries| {
+                    entries
+                        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                        .map(Pid::from_u32)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        };
+        for pid in pids {
+            self.refresh_pid(pid, per_cpu_jiffies);
+        }
+
+        let generation = self.generation;
+        self.processes
+            .retain(|_, entry| entry.generation == generation);
+    }
+
+    fn total_memory(&self) -> u64 {
+        self.total_memory
+    }
+
+    fn hostname(&self) -> Option<String> {
+        fs::read_to_string(self.root.join("sys/kernel/hostname"))
+            .ok()
+            .map(|name| name.trim().to_string())
+    }
+
+    fn boot_time(&self) -> Option<u64> {
+        self.boot_time
+    }
+
+    fn cpu_count(&self) -> Option<usize> {
+        Some(self.cpu_count)
+    }
+
+    fn system_stats(&self) -> Option<SystemStats> {
+        let meminfo = fs::read_to_string(self.root.join("meminfo")).ok()?;
+        let field = |name: &str| parse_meminfo_field(&meminfo, name).unwrap_or(0);
+        let total_memory = field("MemTotal");
+        let total_swap = field("SwapTotal");
+        Some(SystemStats {
+            cpu_usage: self.cpu_usage,
+            core_usage: self.core_usage.clone(),
+            used_memory: total_memory
+                .saturating_sub(field("MemFree"))
+                .saturating_sub(field("Buffers"))
+                .saturating_sub(field("Cached"))
+                .saturating_sub(field("SReclaimable")),
+            total_memory,
+            used_swap: total_swap.saturating_sub(field("SwapFree")),
+            total_swap,
+            load_average: fs::read_to_string(self.root.join("loadavg"))
+                .ok()
+                .and_then(|loadavg| parse_loadavg(&loadavg)),
+            processes: self.processes.len(),
+            uptime: fs::read_to_string(self.root.join("uptime"))
+                .ok()
+                .and_then(|uptime| parse_uptime(&uptime)),
+        })
+    }
+
+    fn processes(&self) -> Box<dyn Iterator<Item = ProcessInfo<'_>> + '_> {
+        Box::new(self.processes.iter().map(|(pid, entry)| ProcessInfo {
+            pid: *pid,
+            name: &entry.name,
+            cpu_usage: entry.cpu_usage,
+            memory: entry.memory,
+            start_time: entry.start_time,
+            cpu_time: Some(entry.cpu_ticks * 1000 / self.ticks_per_second),
+            details: None,
+        }))
+    }
+
+    fn watch(&mut self, pids: &[Pid]) -> bool {
+        self.watched = Some(pids.to_vec());
+        self.processes.retain(|pid, _| pids.contains(pid));
+        true
+    }
+
+    fn reset_baselines(&mut self) {
+        // Processes are treated as newly seen, so their next CPU usage is
+        // measured from the next refresh rather than across the gap.
+        self.processes.clear();
+    }
+
+    fn take_read_errors(&mut self) -> u64 {
+        std::mem::take(&mut self.read_errors)
+    }
+}
+
+/// Reads a whole file into the reusable buffer.
+fn read_into(path: &Path, buffer: &mut String) -> io::Result<()> {
+    buffer.clear();
+    fs::File::open(path)?.read_to_string(buffer)?;
+    Ok(())
+}
+
+fn parse_stat(stat: &str) -> Option<Stat<'_>> {
+    // The name is wrapped in parentheses and may itself contain spaces or
+    // parentheses, so it runs up to the last closing one.
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = &stat[open + 1..close];
+    // Fields after the name start at field 3 (state).
+    let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    let start_time: u64 = fields.get(19)?.parse().ok()?;
+    Some(Stat {
+        name,
+        cpu_ticks: utime + stime,
+        start_time,
+    })
+}
+
+fn parse_statm_resident(statm: &str) -> Option<u64> {
+    statm.split_whitespace().nth(1)?.parse().ok()
+}
+
+/// Sums the aggregate `cpu` line of /proc/stat the way sysinfo does:
+/// user, nice, system, idle, iowait, irq, softirq and steal.
+fn parse_total_jiffies(stat: &str) -> Option<u64> {
+    let line = stat.lines().next()?;
+    let mut fields = line.split_whitespace();
+    if fields.next()? != "cpu" {
+        return None;
+    }
+    fields.take(8).map(|field| field.parse::<u64>().ok()).sum()
+}
+
+/// Sums the idle and iowait fields of the aggregate `cpu` line of
+/// /proc/stat, which sysinfo counts as idle time too.
+fn parse_idle_jiffies(stat: &str) -> Option<u64> {
+    let line = stat.lines().next()?;
+    let mut fields = line.split_whitespace();
+    if fields.next()? != "cpu" {
+        return None;
+    }
+    fields
+        .skip(3)
+        .take(2)
+        .map(|field| field.parse::<u64>().ok())
+        .sum()
+}
+
+/// Reads the total and idle jiffies, counted as by [`parse_total_jiffies`]
+/// and [`parse_idle_jiffies`], of each `cpuN` line of /proc/stat.
+fn parse_core_jiffies(stat: &str) -> Vec<(u64, u64)> {
+    stat.lines()
+        .filter(|line| {
+            line.strip_prefix("cpu")
+                .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+        })
+        .map_while(|line| {
+            let fields: Vec<u64> = line
+                .split_whitespace()
+                .skip(1)
+                .take(8)
+                .map(|field| field.parse().ok())
+                .collect::<Option<_>>()?;
+            Some((fields.iter().sum(), fields.get(3)? + fields.get(4)?))
+        })
+        .collect()
+}
+
+/// Reads the `btime` line of `/proc/stat`, the boot time in seconds since
+/// the Unix epoch.
+fn parse_boot_time(stat: &str) -> Option<u64> {
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn count_cpus(stat: &str) -> usize {
+    stat.lines()
+        .filter(|line| {
+            line.strip_prefix("cpu")
+                .is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_digit()))
+        })
+        .count()
+}
+
+/// Reads MemTotal, scaled from KiB to kB like sysinfo does so memory
+/// percentages agree between the backends.
+fn read_total_memory(root: &Path) -> Option<u64> {
+    let meminfo = fs::read_to_string(root.join("meminfo")).ok()?;
+    parse_meminfo_field(&meminfo, "MemTotal")
+}
+
+/// Reads a field of /proc/meminfo, scaled from KiB to kB like sysinfo does.
+fn parse_meminfo_field(meminfo: &str, name: &str) -> Option<u64> {
+    let kib: u64 = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()?;
+    Some(kib * 128 / 125)
+}
+
+/// Reads the whole seconds since boot from /proc/uptime.
+fn parse_uptime(uptime: &str) -> Option<u64> {
+    let seconds: f64 = uptime.split_whitespace().next()?.parse().ok()?;
+    Some(seconds as u64)
+}
+
+/// Reads the 1, 5 and 15 minute load averages from /proc/loadavg.
+fn parse_loadavg(loadavg: &str) -> Option<[f64; 3]> {
+    let mut fields = loadavg.split_whitespace().map(|field| field.parse().ok());
+    Some([fields.next()??, fields.next()??, fields.next()??])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::SysinfoSource;
+    use std::{thread, time::Duration};
+    use tempfile::TempDir;
+
+    /// Writes a /proc tree with two CPUs, 8 GiB of memory and the given
+    /// processes as `(pid, name, cpu_ticks)`.
+    fn write_fixture(root: &Path, total_jiffies: u64, processes: &[(u32, &str, u64)]) {
+        fs::write(
+            root.join("stat"),
+            format!(
+                "cpu  {} 0 0 0 0 0 0 0 0 0\ncpu0 0 0 0 0 0 0 0 0 0 0\n\
+                 cpu1 0 0 0 0 0 0 0 0 0 0\nbtime 1767225600\n",
+                total_jiffies
+            ),
+        )
+        .unwrap();
+        fs::write(root.join("meminfo"), "MemTotal:        8388608 kB\n").unwrap();
+        fs::create_dir_all(root.join("sys/kernel")).unwrap();
+        fs::write(root.join("sys/kernel/hostname"), "node-7\n").unwrap();
+        for (pid, name, cpu_ticks) in processes {
+            let dir = root.join(pid.to_string());
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(
+                dir.join("stat"),
+                format!(
+                    "{} ({}) S 1 1 1 0 -1 0 0 0 0 0 {} 0 0 0 20 0 1 0 500 0 0",
+                    pid, name, cpu_ticks
+                ),
+            )
+            .unwrap();
+            fs::write(dir.join("statm"), "1000 256 0 0 0 0 0").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "1234 (my (odd) proc) S 1 1234 1234 0 -1 4194560 100 0 0 0 \
+                    250 75 0 0 20 0 1 0 98765 1000000 200 18446744073709551615";
+        assert_eq!(
+            parse_stat(stat),
+            Some(Stat {
+                name: "my (odd) proc",
+                cpu_ticks: 325,
+                start_time: 98765,
+            })
+        );
+        assert_eq!(parse_stat("garbage"), None);
+    }
+
+    #[test]
+    fn test_parse_proc_stat() {
+        let stat = "cpu  100 5 50 1000 10 1 2 3 0 0\ncpu0 50 2 25 500 5 0 1 1 0 0\n\
+                    cpu1 50 3 25 500 5 1 1 2 0 0\nintr 12345\nbtime 1767225600\n";
+        assert_eq!(parse_total_jiffies(stat), Some(1171));
+        assert_eq!(parse_idle_jiffies(stat), Some(1010));
+        assert_eq!(count_cpus(stat), 2);
+        assert_eq!(parse_boot_time(stat), Some(1_767_225_600));
+        assert_eq!(parse_boot_time("intr 12345\n"), None);
+        assert_eq!(parse_statm_resident("2000 350 100 10 0 500 0"), Some(350));
+    }
+
+    #[test]
+    fn test_reads_alternate_root() {
+        let root = TempDir::new().unwrap();
+        write_fixture(
+            root.path(),
+            1000,
+            &[(1, "systemd", 100), (4242, "postgres", 400)],
+        );
+        let mut source = ProcfsSource::with_root(root.path().to_path_buf());
+        assert_eq!(source.cpu_count, 2);
+        assert_eq!(source.total_memory(), 8388608 * 128 / 125);
+        assert_eq!(source.hostname().as_deref(), Some("node-7"));
+        assert_eq!(source.boot_time(), Some(1_767_225_600));
+
+        // 200 jiffies over two CPUs, of which the process used 50.
+        write_fixture(
+            root.path(),
+            1200,
+            &[(1, "systemd", 100), (4242, "postgres", 450)],
+        );
+        source.refresh();
+        let mut processes: Vec<_> = source
+            .processes()
+            .map(|p| (p.pid.as_u32(), p.name.to_string(), p.cpu_usage, p.memory))
+            .collect();
+        processes.sort_by_key(|process| process.0);
+        let memory = 256 * source.page_size_kb;
+        assert_eq!(
+            processes,
+            [
+                (1, "systemd".to_string(), 0.0, memory),
+                (4242, "postgres".to_string(), 50.0, memory),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_system_stats() {
+        let root = TempDir::new().unwrap();
+        write_fixture(root.path(), 1000, &[(1, "systemd", 100)]);
+        let mut source = ProcfsSource::with_root(root.path().to_path_buf());
+        write_fixture(root.path(), 1200, &[(1, "systemd", 100)]);
+        fs::write(
+            root.path().join("meminfo"),
+            "MemTotal:        8000 kB\nMemFree:         1000 kB\n\
+             MemAvailable:    5000 kB\nBuffers:          500 kB\n\
+             Cached:          2000 kB\nSwapTotal:       4000 kB\n\
+             SwapFree:        3000 kB\nSReclaimable:     250 kB\n",
+        )
+        .unwrap();
+        fs::write(root.path().join("loadavg"), "0.52 0.58 0.59 2/1017 12345\n").unwrap();
+        fs::write(root.path().join("uptime"), "86461.25 170000.50\n").unwrap();
+        // CPU 0 was busy for 150 of its 200 jiffies and CPU 1 idle.
+        fs::write(
+            root.path().join("stat"),
+            "cpu  1200 0 0 0 0 0 0 0 0 0\ncpu0 150 0 0 40 10 0 0 0 0 0\n\
+             cpu1 0 0 0 100 0 0 0 0 0 0\nbtime 1767225600\n",
+        )
+        .unwrap();
+        source.refresh();
+        // The aggregate line counts every jiffy as user time.
+        assert_eq!(
+            source.system_stats(),
+            Some(SystemStats {
+                cpu_usage: 100.0,
+                core_usage: vec![75.0, 0.0],
+                used_memory: 4250 * 128 / 125,
+                total_memory: 8000 * 128 / 125,
+                used_swap: 1000 * 128 / 125,
+                total_swap: 4000 * 128 / 125,
+                load_average: Some([0.52, 0.58, 0.59]),
+                processes: 1,
+                uptime: Some(86461),
+            })
+        );
+    }
+
+    #[test]
+    fn test_matches_sysinfo_backend() {
+        let mut procfs = ProcfsSource::new();
+        let mut sysinfo = SysinfoSource::new();
+        assert_eq!(procfs.total_memory(), sysinfo.total_memory());
+        assert_eq!(procfs.boot_time(), sysinfo.boot_time());
+
+        let own = Pid::from_u32(std::process::id());
+        for _ in 0..3 {
+            thread::sleep(Duration::from_millis(250));
+            sysinfo.refresh();
+            procfs.refresh();
+
+            let expected: HashMap<Pid, (String, f32, u64)> = sysinfo
+                .processes()
+                .map(|p| (p.pid, (p.name.to_string(), p.cpu_usage, p.memory)))
+                .collect();
+            let mut compared = 0;
+            for process in procfs.processes() {
+                let Some((name, cpu, memory)) = expected.get(&process.pid) else {
+                    continue;
+                };
+                compared += 1;
+                assert_eq!(process.name, name);
+                // Memory may move between the two reads; allow 4 MB of drift.
+                assert!(
+                    process.memory.abs_diff(*memory) <= 4096,
+                    "Memory of {} differs: {} vs {}",
+                    process.pid,
+                    process.memory,
+                    memory
+                );
+                // The test binary itself is busy with other tests.
+                if process.pid != own {
+                    assert!(
+                        (process.cpu_usage - cpu).abs() <= 15.0,
+                        "CPU of {} differs: {} vs {}",
+                        process.pid,
+                        process.cpu_usage,
+                        cpu
+                    );
+                }
+            }
+            assert!(compared > 0, "No processes seen by both backends");
+        }
+    }
+}
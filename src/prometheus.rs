@@ -0,0 +1,280 @@
+use crate::{
+    http::{self, Reply, Request},
+    sink::Sink,
+    ProcessSample,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::{error, info};
+use std::{
+    fmt::Write,
+    net::{SocketAddr, TcpListener},
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, RwLock},
+    thread::{self, JoinHandle},
+};
+
+/// The content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Renders per-process gauges in the Prometheus text exposition format.
+pub fn render(samples: &[ProcessSample]) -> String {
+    let mut out = String::new();
+
+    out.push_str("# HELP chronologger_process_cpu_percent Process CPU usage in percent.\n");
+    out.push_str("# TYPE chronologger_process_cpu_percent gauge\n");
+    for sample in samples {
+        let _ = writeln!(
+            out,
+            "chronologger_process_cpu_percent{{{}}} {:.2}",
+            labels(sample),
+            sample.cpu_usage
+        );
+    }
+
+    out.push_str("# HELP chronologger_process_memory_percent Process memory usage in percent of total memory.\n");
+    out.push_str("# TYPE chronologger_process_memory_percent gauge\n");
+    for sample in samples {
+        let _ = writeln!(
+            out,
+            "chronologger_process_memory_percent{{{}}} {:.2}",
+            labels(sample),
+            sample.memory_usage
+        );
+    }
+
+    out
+}
+
+/// The labels of a sample's series: its PID, which a group has none of,
+/// and its name.
+fn labels(sample: &ProcessSample) -> String {
+    let name = escape_label_value(&sample.name);
+    match sample.pid {
+        Some(pid) => format!("pid=\"{}\",name=\"{}\"", pid, name),
+        None => format!("name=\"{}\"", name),
+    }
+}
+
+/// Appends per-process memory gauges in bytes, from the share of
+/// `total_memory` bytes each process uses.
+fn render_memory_bytes(out: &mut String, samples: &[ProcessSample], total_memory: u64) {
+    out.push_str("# HELP chronologger_process_memory_bytes Process resident memory in bytes.\n");
+    out.push_str("# TYPE chronologger_process_memory_bytes gauge\n");
+    for sample in samples {
+        let bytes = (sample.memory_usage / 100.0 * total_memory as f64).round();
+        let _ = writeln!(
+            out,
+            "chronologger_process_memory_bytes{{{}}} {}",
+            labels(sample),
+            bytes
+        );
+    }
+}
+
+/// The last tick's samples and how many have been taken in all.
+#[derive(Default)]
+struct Latest {
+    samples: Vec<ProcessSample>,
+    samples_total: u64,
+}
+
+/// Serves the last tick's gauges at `/metrics` for Prometheus to scrape.
+/// The sampler only takes the write lock once per tick to swap them in, and
+/// scrapes render them under the read lock.
+pub struct MetricsSink {
+    latest: Arc<RwLock<Latest>>,
+    address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MetricsSink {
+    /// Serves on `listen`, converting memory shares to bytes of
+    /// `total_memory`.
+    pub fn new(listen: &str, total_memory: u64) -> Result<Self> {
+        let listener =
+            TcpListener::bind(listen).with_context(|| format!("Failed to listen on {}", listen))?;
+        let address = listener.local_addr()?;
+        info!("Serving Prometheus metrics on http://{}/metrics", address);
+
+        let latest = Arc::new(RwLock::new(Latest::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let latest = latest.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let result = http::serve(listener, stop, |request| {
+                    route(&latest, total_memory, request)
+                });
+                if let Err(e) = result {
+                    error!("Metrics endpoint stopped: {:#}", e);
+                }
+            })
+        };
+
+        Ok(Self {
+            latest,
+            address,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Sink for MetricsSink {
+    fn publish(&mut self, _timestamp: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        let samples = samples.to_vec();
+        let mut latest = self.latest.write().unwrap();
+        latest.samples = samples;
+        latest.samples_total += latest.samples.len() as u64;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        info!("Metrics endpoint on {} stopped", self.address);
+        Ok(())
+    }
+}
+
+fn route(latest: &RwLock<Latest>, total_memory: u64, request: &Request) -> Reply {
+    if request.path != "/metrics" {
+        return Reply::not_found();
+    }
+    if request.method != "GET" {
+        return Reply {
+            status: 405,
+            content_type: "text/plain",
+            body: "Method Not Allowed\n".to_string(),
+        };
+    }
+    let latest = latest.read().unwrap();
+    let mut body = render(&latest.samples);
+    render_memory_bytes(&mut body, &latest.samples, total_memory);
+    body.push_str(
+        "# HELP chronologger_samples_total Process samples taken since the run started.\n",
+    );
+    body.push_str("# TYPE chronologger_samples_total counter\n");
+    let _ = writeln!(body, "chronologger_samples_total {}", latest.samples_total);
+    Reply {
+        status: 200,
+        content_type: CONTENT_TYPE,
+        body,
+    }
+}
+
+/// Escapes backslashes, double quotes and newlines in a label value.
+pub fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sysinfo::{Pid, PidExt};
+
+    #[test]
+    fn test_escape_label_value() {
+        assert_eq!(escape_label_value(r#"a"b\c"#), r#"a\"b\\c"#);
+        assert_eq!(escape_label_value("line\nbreak"), "line\\nbreak");
+    }
+
+    #[test]
+    fn test_render() {
+        let samples = vec![ProcessSample {
+            pid: Some(Pid::from_u32(42)),
+            name: "my \"app\"".to_string(),
+            cpu_usage: 12.5,
+            memory_usage: 1.25,
+            extras: Vec::new(),
+        }];
+        let text = render(&samples);
+        assert!(text.contains("# TYPE chronologger_process_cpu_percent gauge"));
+        assert!(
+            text.contains(r#"chronologger_process_cpu_percent{pid="42",name="my \"app\""} 12.50"#)
+        );
+        assert!(text
+            .contains(r#"chronologger_process_memory_percent{pid="42",name="my \"app\""} 1.25"#));
+    }
+
+    #[test]
+    fn test_render_group_without_pid() {
+        let group = ProcessSample {
+            pid: None,
+            name: "nginx".to_string(),
+            cpu_usage: 2.0,
+            memory_usage: 1.0,
+            extras: Vec::new(),
+        };
+        let text = render(&[group]);
+        assert!(
+            text.contains(r#"chronologger_process_cpu_percent{name="nginx"} 2.00"#),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn test_metrics_endpoint() {
+        let mut sink = MetricsSink::new("127.0.0.1:0", 1 << 30).expect("Failed to start endpoint");
+        for pid in [1, 2] {
+            let samples = vec![ProcessSample {
+                pid: Some(Pid::from_u32(pid)),
+                name: r"C:\app".to_string(),
+                cpu_usage: 3.0,
+                memory_usage: 4.0,
+                extras: Vec::new(),
+            }];
+            sink.publish(&Local::now(), &samples).unwrap();
+        }
+
+        let url = http::Url {
+            tls: false,
+            host: "127.0.0.1".to_string(),
+            port: sink.address().port(),
+            path: String::new(),
+        };
+        let get = |path: &str| {
+            http::send(
+                "GET",
+                &url,
+                path,
+                "text/plain",
+                b"",
+                std::time::Duration::from_secs(5),
+            )
+            .expect("Request failed")
+        };
+        let response = get("/metrics");
+        assert_eq!(response.status, 200);
+        assert!(response
+            .body
+            .contains(r#"chronologger_process_cpu_percent{pid="2",name="C:\\app"} 3.00"#));
+        assert!(response
+            .body
+            .contains(r#"chronologger_process_memory_bytes{pid="2",name="C:\\app"} 42949673"#));
+        assert!(!response.body.contains(r#"pid="1""#), "{}", response.body);
+        assert!(response.body.contains("chronologger_samples_total 2\n"));
+        assert_eq!(get("/latest").status, 404);
+
+        sink.finish().unwrap();
+    }
+}
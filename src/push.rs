@@ -0,0 +1,290 @@
+use crate::{backoff::Backoff, http};
+use anyhow::Result;
+use log::warn;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Attempts made once the sink is finished before the items left are given
+/// up.
+const FINAL_PUSH_ATTEMPTS: u32 = 3;
+/// The longest wait between the final attempts.
+const FINAL_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Why a push failed.
+pub enum PushError {
+    /// The server could not take the items now; they are sent again later.
+    Retryable(anyhow::Error),
+    /// The server refused the items; they are dropped.
+    Rejected(anyhow::Error),
+}
+
+/// An error for the status `server` answered with, e.g. `Loki returned 400:
+/// ...`.
+pub fn status_error(server: &str, response: &http::Response) -> anyhow::Error {
+    anyhow::anyhow!(
+        "{} returned {}: {}",
+        server,
+        response.status,
+        response.body.trim()
+    )
+}
+
+/// The sending side of a [`PushQueue`].
+pub trait Push: Send + 'static {
+    type Item: Send + 'static;
+
+    /// Names the pushes in log messages, e.g. `Loki push`.
+    const NAME: &'static str;
+    /// What the items are called in log messages, e.g. `entries`.
+    const ITEMS: &'static str;
+
+    /// How many of the oldest `items` go in the next request, at least one.
+    fn batch_len(&self, items: &VecDeque<Self::Item>) -> usize;
+
+    fn push(&mut self, batch: &[Self::Item]) -> Result<(), PushError>;
+}
+
+/// Where items go that no longer fit in memory, instead of being dropped.
+/// Kept items are older than those in memory, so they are pushed first.
+pub trait Overflow<T>: Send {
+    fn store(&mut self, items: &[T]) -> Result<()>;
+
+    /// How many items are kept.
+    fn pending(&self) -> u64;
+
+    /// The oldest kept items, as many as go in one request.
+    fn read(&mut self) -> Result<Vec<T>>;
+
+    /// Forgets the `count` oldest kept items, once they have been pushed or
+    /// rejected.
+    fn consume(&mut self, count: u64);
+
+    /// Called as the queue stops, with the kept items still pending.
+    fn close(&mut self) {}
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    overflow: Option<Box<dyn Overflow<T>>>,
+    dropped: u64,
+    stopping: bool,
+}
+
+impl<T> State<T> {
+    fn kept(&self) -> u64 {
+        self.overflow
+            .as_ref()
+            .map_or(0, |overflow| overflow.pending())
+    }
+
+    /// Moves the oldest items beyond `max` out of memory.
+    fn trim(&mut self, max: usize) {
+        let excess = self.items.len().saturating_sub(max);
+        if excess > 0 {
+            let items: Vec<T> = self.items.drain(..excess).collect();
+            self.discard(items);
+        }
+    }
+
+    /// Hands `items` to the overflow, or drops them if there is none or it
+    /// cannot take them.
+    fn discard(&mut self, items: Vec<T>) {
+        if let Some(overflow) = &mut self.overflow {
+            match overflow.store(&items) {
+                Ok(()) => return,
+                Err(e) => warn!("{:#}", e),
+            }
+        }
+        self.dropped += items.len() as u64;
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    wakeup: Condvar,
+    max_buffered: usize,
+}
+
+/// Pushes items to a server on a background thread, so a slow or
+/// unreachable server does not stall sampling. Items that cannot be
+/// delivered yet, or are published while a push is under way, are buffered
+/// up to `max_buffered`; older ones go to the [`Overflow`] if there is one
+/// and are dropped otherwise. Failed pushes are retried with backoff.
+pub struct PushQueue<T: Send + 'static> {
+    shared: Arc<Shared<T>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> PushQueue<T> {
+    pub fn new<P: Push<Item = T>>(
+        pusher: P,
+        max_buffered: usize,
+        overflow: Option<Box<dyn Overflow<T>>>,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                items: VecDeque::new(),
+                overflow,
+                dropped: 0,
+                stopping: false,
+            }),
+            wakeup: Condvar::new(),
+            max_buffered,
+        });
+        let handle = {
+            let shared = shared.clone();
+            thread::spawn(move || run(pusher, &shared))
+        };
+        Self {
+            shared,
+            handle: Some(handle),
+        }
+    }
+
+    /// Queues `items` behind those waiting to be pushed.
+    pub fn publish(&self, items: impl IntoIterator<Item = T>) {
+        let mut state = self.lock();
+        state.items.extend(items);
+        state.trim(self.shared.max_buffered);
+        self.shared.wakeup.notify_one();
+    }
+
+    /// Items dropped so far.
+    pub fn dropped(&self) -> u64 {
+        self.lock().dropped
+    }
+
+    #[cfg(test)]
+    pub fn buffered(&self) -> usize {
+        self.lock().items.len()
+    }
+
+    /// Pushes what is left, giving up after a few failed attempts, and
+    /// stops the background thread. Returns the items dropped.
+    pub fn finish(&mut self) -> u64 {
+        self.lock().stopping = true;
+        self.shared.wakeup.notify_one();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        self.dropped()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.shared.state.lock().unwrap()
+    }
+}
+
+enum Batch<T> {
+    /// Taken out of memory, to be put back if it is to be retried.
+    Memory(Vec<T>),
+    /// Read from the overflow, which keeps it until it is consumed.
+    Kept(Vec<T>),
+}
+
+fn run<P: Push>(mut pusher: P, shared: &Shared<P::Item>) {
+    let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(30));
+    let mut failed_attempts = 0;
+
+    loop {
+        let batch = {
+            let mut state = shared.state.lock().unwrap();
+            while state.items.is_empty() && state.kept() == 0 && !state.stopping {
+                state = shared.wakeup.wait(state).unwrap();
+            }
+            let give_up = state.stopping && failed_attempts >= FINAL_PUSH_ATTEMPTS;
+            if give_up || state.items.is_empty() && state.kept() == 0 {
+                let items: Vec<_> = state.items.drain(..).collect();
+                if !items.is_empty() {
+                    state.discard(items);
+                }
+                if let Some(overflow) = &mut state.overflow {
+                    overflow.close();
+                }
+                return;
+            }
+
+            let kept = state.kept();
+            match &mut state.overflow {
+                Some(overflow) if kept > 0 => match overflow.read() {
+                    Ok(items) if !items.is_empty() => Batch::Kept(items),
+                    result => {
+                        if let Err(e) = result {
+                            warn!("{:#}; dropping the {} kept", e, P::ITEMS);
+                        }
+                        overflow.consume(kept);
+                        state.dropped += kept;
+                        continue;
+                    }
+                },
+                _ => {
+                    let count = pusher.batch_len(&state.items).clamp(1, state.items.len());
+                    Batch::Memory(state.items.drain(..count).collect())
+                }
+            }
+        };
+        let items = match &batch {
+            Batch::Memory(items) | Batch::Kept(items) => items,
+        };
+
+        match pusher.push(items) {
+            Ok(()) => {
+                backoff.reset();
+                failed_attempts = 0;
+            }
+            Err(PushError::Retryable(e)) => {
+                failed_attempts += 1;
+                let delay = backoff.next_delay();
+                warn!("{} failed, retrying in {:?}: {:#}", P::NAME, delay, e);
+                if let Batch::Memory(items) = batch {
+                    let mut state = shared.state.lock().unwrap();
+                    // Back ahead of the items published meanwhile, so the
+                    // oldest are still the first to go.
+                    for item in items.into_iter().rev() {
+                        state.items.push_front(item);
+                    }
+                    state.trim(shared.max_buffered);
+                }
+                wait(shared, delay);
+                continue;
+            }
+            Err(PushError::Rejected(e)) => {
+                warn!(
+                    "Dropped {} {} that were refused: {:#}",
+                    items.len(),
+                    P::ITEMS,
+                    e
+                );
+                shared.state.lock().unwrap().dropped += items.len() as u64;
+            }
+        }
+        if let Batch::Kept(items) = batch {
+            let mut state = shared.state.lock().unwrap();
+            if let Some(overflow) = &mut state.overflow {
+                overflow.consume(items.len() as u64);
+            }
+        }
+    }
+}
+
+/// Waits out `delay` before a retry. Items published meanwhile do not cut
+/// it short; finishing does, leaving a short pause between the final
+/// attempts.
+fn wait<T>(shared: &Shared<T>, delay: Duration) {
+    let deadline = Instant::now() + delay;
+    let mut state = shared.state.lock().unwrap();
+    while !state.stopping {
+        let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+            return;
+        };
+        state = shared.wakeup.wait_timeout(state, left).unwrap().0;
+    }
+    drop(state);
+    if let Some(left) = deadline.checked_duration_since(Instant::now()) {
+        thread::sleep(left.min(FINAL_RETRY_DELAY));
+    }
+}
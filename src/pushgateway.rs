@@ -0,0 +1,254 @@
+use crate::{
+    backoff::Backoff,
+    http::{self, Url},
+    prometheus,
+    sink::Sink,
+    ProcessSample,
+};
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Local};
+use log::{info, warn};
+use std::{
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const FINAL_PUSH_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone)]
+pub struct PushgatewayConfig {
+    pub url: Url,
+    pub job: String,
+    pub instance: String,
+    pub interval: Duration,
+    pub delete_on_exit: bool,
+    /// Added to the grouping key, so every pushed series carries it.
+    pub run_id: Option<String>,
+}
+
+impl PushgatewayConfig {
+    fn group_path(&self) -> String {
+        let mut path = "metrics".to_string();
+        push_label(&mut path, "job", &self.job);
+        push_label(&mut path, "instance", &self.instance);
+        if let Some(run_id) = &self.run_id {
+            push_label(&mut path, "run_id", run_id);
+        }
+        self.url.join(&path)
+    }
+}
+
+/// Appends a label of the grouping key to `path`. A value that is empty or
+/// has characters other than letters, digits, `-`, `.`, `_` and `~`, such as
+/// a `/` or a space, is written in the Pushgateway's base64 form, since even
+/// percent-encoded a `/` would split the path.
+fn push_label(path: &mut String, name: &str, value: &str) {
+    let plain = !value.is_empty()
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-._~".contains(&byte));
+    path.push('/');
+    path.push_str(name);
+    if plain {
+        path.push('/');
+        path.push_str(value);
+    } else if value.is_empty() {
+        path.push_str("@base64/=");
+    } else {
+        path.push_str("@base64/");
+        path.push_str(&URL_SAFE_NO_PAD.encode(value));
+    }
+}
+
+/// Pushes the latest tick's gauges to a Prometheus Pushgateway from a
+/// background thread so slow or failing pushes never delay sampling.
+pub struct PushgatewaySink {
+    config: PushgatewayConfig,
+    latest: Arc<Mutex<Option<String>>>,
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PushgatewaySink {
+    pub fn new(config: PushgatewayConfig) -> Result<Self> {
+        if config.job.is_empty() {
+            bail!("Invalid Pushgateway job name: {:?}", config.job);
+        }
+        info!(
+            "Pushing metrics to Pushgateway at {}:{} every {:?} as job '{}'",
+            config.url.host, config.url.port, config.interval, config.job
+        );
+
+        let latest: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+        let (stop, stopped) = mpsc::channel();
+        let handle = {
+            let config = config.clone();
+            let latest = latest.clone();
+            thread::spawn(move || {
+                let mut backoff = Backoff::new(Duration::from_secs(1), config.interval);
+                let mut wait = config.interval;
+                loop {
+                    match stopped.recv_timeout(wait) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        _ => return,
+                    }
+                    let Some(body) = latest.lock().unwrap().clone() else {
+                        continue;
+                    };
+                    match push(&config, &body) {
+                        Ok(()) => {
+                            backoff.reset();
+                            wait = config.interval;
+                        }
+                        Err(e) => {
+                            wait = backoff.next_delay();
+                            warn!("Pushgateway push failed, retrying in {:?}: {:#}", wait, e);
+                        }
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            config,
+            latest,
+            stop: Some(stop),
+            handle: Some(handle),
+        })
+    }
+}
+
+impl Sink for PushgatewaySink {
+    fn publish(&mut self, _timestamp: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        *self.latest.lock().unwrap() = Some(prometheus::render(samples));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        drop(self.stop.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+
+        if let Some(body) = self.latest.lock().unwrap().take() {
+            let mut backoff = Backoff::new(Duration::from_millis(500), Duration::from_secs(2));
+            let mut attempt = 1;
+            while let Err(e) = push(&self.config, &body) {
+                if attempt == FINAL_PUSH_ATTEMPTS {
+                    return Err(e.context("Final Pushgateway push failed!"));
+                }
+                thread::sleep(backoff.next_delay());
+                attempt += 1;
+            }
+        }
+
+        if self.config.delete_on_exit {
+            info!("Deleting Pushgateway group for job '{}'", self.config.job);
+            let response = http::send(
+                "DELETE",
+                &self.config.url,
+                &self.config.group_path(),
+                prometheus::CONTENT_TYPE,
+                b"",
+                REQUEST_TIMEOUT,
+            )
+            .context("Failed to delete Pushgateway group!")?;
+            if !response.is_success() {
+                bail!("Pushgateway returned {} on delete", response.status);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn push(config: &PushgatewayConfig, body: &str) -> Result<()> {
+    let response = http::send(
+        "PUT",
+        &config.url,
+        &config.group_path(),
+        prometheus::CONTENT_TYPE,
+        body.as_bytes(),
+        REQUEST_TIMEOUT,
+    )?;
+    if !response.is_success() {
+        bail!(
+            "Pushgateway returned {}: {}",
+            response.status,
+            response.body.trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::testing::capture_requests;
+    use crate::test_support::samples;
+
+    fn config(url: Url, delete_on_exit: bool) -> PushgatewayConfig {
+        PushgatewayConfig {
+            url,
+            job: "test".to_string(),
+            instance: "host1".to_string(),
+            interval: Duration::from_secs(3600),
+            delete_on_exit,
+            run_id: None,
+        }
+    }
+
+    #[test]
+    fn test_final_push_and_delete() {
+        let (url, server) = capture_requests(2, 200);
+        let mut sink = PushgatewaySink::new(config(url, true)).expect("Failed to create sink");
+        sink.publish(&Local::now(), &samples())
+            .expect("Failed to publish samples");
+        sink.finish().expect("Failed to finish sink");
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("PUT /metrics/job/test/instance/host1 HTTP/1.1"));
+        assert!(
+            requests[0].contains(r#"chronologger_process_cpu_percent{pid="1",name="proc-1"} 1.50"#)
+        );
+        assert!(requests[0]
+            .contains(r#"chronologger_process_memory_percent{pid="1",name="proc-1"} 0.25"#));
+        assert!(requests[1].starts_with("DELETE /metrics/job/test/instance/host1 HTTP/1.1"));
+    }
+
+    #[test]
+    fn test_run_id_in_grouping_key() {
+        let mut config = config(Url::parse("http://gateway:9091").unwrap(), false);
+        config.run_id = Some("0190-abc".to_string());
+        assert!(config
+            .group_path()
+            .ends_with("/metrics/job/test/instance/host1/run_id/0190-abc"));
+    }
+
+    #[test]
+    fn test_grouping_key_encodes_unsafe_values() {
+        let mut config = config(Url::parse("http://gateway:9091").unwrap(), false);
+        config.job = "batch/nightly".to_string();
+        config.instance = "build host".to_string();
+        config.run_id = Some(String::new());
+        assert!(config.group_path().ends_with(
+            "/metrics/job@base64/YmF0Y2gvbmlnaHRseQ/instance@base64/YnVpbGQgaG9zdA/run_id@base64/="
+        ));
+        config.instance = "host-1.example.com".to_string();
+        config.run_id = None;
+        assert!(config
+            .group_path()
+            .ends_with("/metrics/job@base64/YmF0Y2gvbmlnaHRseQ/instance/host-1.example.com"));
+    }
+
+    #[test]
+    fn test_push_reports_server_errors() {
+        let (url, server) = capture_requests(1, 500);
+        let result = push(&config(url, false), "body");
+        assert!(result.is_err(), "Push should fail on a 500 response");
+        server.join().unwrap();
+    }
+}
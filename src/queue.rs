@@ -0,0 +1,443 @@
+use crate::ProcessSample;
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use log::{debug, warn};
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// How often a blocked producer rechecks the shutdown flag.
+const BLOCK_POLL: Duration = Duration::from_millis(50);
+
+/// What to do with a tick when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Backpressure {
+    /// Wait for the writer, delaying the next sample.
+    Block,
+    /// Discard the oldest queued tick to make room.
+    DropOldest,
+    /// Discard the tick being queued.
+    DropNewest,
+}
+
+pub struct Tick {
+    pub now: DateTime<Local>,
+    pub samples: Vec<ProcessSample>,
+    pub scope: Option<&'static str>,
+    /// Ticks dropped just before this one.
+    dropped_before: u64,
+}
+
+/// The writing side of the queue.
+pub trait TickWriter: Send + 'static {
+    fn write_tick(&mut self, tick: &Tick) -> Result<()>;
+    /// Marks that `dropped` ticks were discarded before the tick at `now`.
+    fn write_gap(&mut self, now: &DateTime<Local>, dropped: u64, scope: Option<&str>)
+        -> Result<()>;
+}
+
+struct State {
+    ticks: VecDeque<Tick>,
+    /// Sample buffers handed back by the writer for reuse.
+    spare: Vec<Vec<ProcessSample>>,
+    writing: bool,
+    closed: bool,
+    full: bool,
+    dropped: u64,
+    /// Ticks dropped since the last one queued, carried by the next tick
+    /// and reported as a gap marker.
+    gap: u64,
+    error: Option<anyhow::Error>,
+}
+
+struct Shared<W> {
+    state: Mutex<State>,
+    changed: Condvar,
+    writer: Mutex<W>,
+}
+
+/// Hands ticks to a writer thread through a bounded queue, so a slow output
+/// does not stall sampling. What happens when the writer falls behind is set
+/// by the [`Backpressure`] policy.
+pub struct WriteQueue<W: TickWriter> {
+    shared: Arc<Shared<W>>,
+    capacity: usize,
+    policy: Backpressure,
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<W: TickWriter> WriteQueue<W> {
+    /// Starts the writer thread. A producer blocked by a full queue gives up
+    /// once `running` is cleared.
+    pub fn new(writer: W, capacity: usize, policy: Backpressure, running: Arc<AtomicBool>) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                ticks: VecDeque::with_capacity(capacity),
+                spare: Vec::new(),
+                writing: false,
+                closed: false,
+                full: false,
+                dropped: 0,
+                gap: 0,
+                error: None,
+            }),
+            changed: Condvar::new(),
+            writer: Mutex::new(writer),
+        });
+        let handle = {
+            let shared = shared.clone();
+            thread::spawn(move || run(&shared))
+        };
+        Self {
+            shared,
+            capacity: capacity.max(1),
+            policy,
+            running,
+            handle: Some(handle),
+        }
+    }
+
+    /// Queues a copy of `samples`, applying the backpressure policy if the
+    /// writer has fallen behind. Returns the writer's last error, if any.
+    pub fn push(
+        &self,
+        now: &DateTime<Local>,
+        samples: &[ProcessSample],
+        scope: Option<&'static str>,
+    ) -> Result<()> {
+        let mut state = self.lock();
+        if let Some(e) = state.error.take() {
+            return Err(e);
+        }
+        if state.ticks.len() >= self.capacity {
+            if !state.full {
+                state.full = true;
+                debug!(
+                    "Write queue is full ({} ticks), applying the {:?} policy",
+                    self.capacity, self.policy
+                );
+            }
+            match self.policy {
+                Backpressure::Block => {
+                    while state.ticks.len() >= self.capacity {
+                        if !self.running.load(Ordering::SeqCst) {
+                            drop_tick(&mut state);
+                            return Ok(());
+                        }
+                        state = self
+                            .shared
+                            .changed
+                            .wait_timeout(state, BLOCK_POLL)
+                            .unwrap()
+                            .0;
+                    }
+                }
+                Backpressure::DropOldest => {
+                    if let Some(tick) = state.ticks.pop_front() {
+                        state.dropped += 1;
+                        let gap = tick.dropped_before + 1;
+                        match state.ticks.front_mut() {
+                            Some(next) => next.dropped_before += gap,
+                            None => state.gap += gap,
+                        }
+                        state.spare.push(tick.samples);
+                    }
+                }
+                Backpressure::DropNewest => {
+                    drop_tick(&mut state);
+                    return Ok(());
+                }
+            }
+        }
+
+        let mut buffer = state.spare.pop().unwrap_or_default();
+        copy_samples(&mut buffer, samples);
+        let dropped_before = std::mem::take(&mut state.gap);
+        state.ticks.push_back(Tick {
+            now: *now,
+            samples: buffer,
+            scope,
+            dropped_before,
+        });
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Waits for every queued tick to be written, then runs `f` on the
+    /// writer, e.g. to flush or replace the output.
+    pub fn with_writer<T>(&self, f: impl FnOnce(&mut W) -> Result<T>) -> Result<T> {
+        let mut state = self.lock();
+        while !state.ticks.is_empty() || state.writing {
+            state = self.shared.changed.wait(state).unwrap();
+        }
+        if let Some(e) = state.error.take() {
+            return Err(e);
+        }
+        let mut writer = self.shared.writer.lock().unwrap();
+        drop(state);
+        f(&mut writer)
+    }
+
+    /// Ticks discarded by the backpressure policy so far.
+    pub fn dropped(&self) -> u64 {
+        self.lock().dropped
+    }
+
+    /// Writes the remaining ticks and stops the writer thread.
+    pub fn finish(&mut self) -> Result<()> {
+        self.lock().closed = true;
+        self.shared.changed.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        let dropped = self.dropped();
+        if dropped > 0 {
+            warn!("Dropped {} ticks because the output fell behind", dropped);
+        }
+        match self.lock().error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap()
+    }
+}
+
+impl<W: TickWriter> Drop for WriteQueue<W> {
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            let _ = self.finish();
+        }
+    }
+}
+
+fn drop_tick(state: &mut State) {
+    state.dropped += 1;
+    state.gap += 1;
+}
+
+fn run<W: TickWriter>(shared: &Shared<W>) {
+    let mut state = shared.state.lock().unwrap();
+    let mut scope = None;
+    loop {
+        let Some(tick) = state.ticks.pop_front() else {
+            if state.closed {
+                // Ticks dropped at the very end still get their marker.
+                let gap = std::mem::take(&mut state.gap);
+                if gap > 0 {
+                    let result = shared
+                        .writer
+                        .lock()
+                        .unwrap()
+                        .write_gap(&Local::now(), gap, scope);
+                    if let Err(e) = result {
+                        state.error.get_or_insert(e);
+                    }
+                }
+                return;
+            }
+            state.full = false;
+            state = shared.changed.wait(state).unwrap();
+            continue;
+        };
+        let gap = tick.dropped_before;
+        scope = tick.scope;
+        state.writing = true;
+        shared.changed.notify_all();
+        drop(state);
+
+        let result = {
+            let mut writer = shared.writer.lock().unwrap();
+            if gap > 0 {
+                writer.write_gap(&tick.now, gap, tick.scope)
+            } else {
+                Ok(())
+            }
+            .and_then(|()| writer.write_tick(&tick))
+        };
+
+        state = shared.state.lock().unwrap();
+        state.writing = false;
+        if let Err(e) = result {
+            state.error.get_or_insert(e);
+        }
+        state.spare.push(tick.samples);
+        shared.changed.notify_all();
+    }
+}
+
+/// Copies samples into a reused buffer, keeping the name allocations of the
+/// samples it overwrites.
+fn copy_samples(buffer: &mut Vec<ProcessSample>, samples: &[ProcessSample]) {
+    buffer.truncate(samples.len());
+    for (target, sample) in buffer.iter_mut().zip(samples) {
+        target.pid = sample.pid;
+        target.name.clone_from(&sample.name);
+        target.cpu_usage = sample.cpu_usage;
+        target.memory_usage = sample.memory_usage;
+    }
+    let start = buffer.len();
+    buffer.extend(samples[start..].iter().map(|sample| ProcessSample {
+        pid: sample.pid,
+        name: sample.name.clone(),
+        cpu_usage: sample.cpu_usage,
+        memory_usage: sample.memory_usage,
+        extras: sample.extras.clone(),
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use sysinfo::{Pid, PidExt};
+
+    /// Records what it writes, stalling each tick until the gate opens.
+    struct GatedWriter {
+        gate: Arc<AtomicBool>,
+        delay: Duration,
+        written: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TickWriter for GatedWriter {
+        fn write_tick(&mut self, tick: &Tick) -> Result<()> {
+            while !self.gate.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            thread::sleep(self.delay);
+            let id = tick.samples[0].pid.unwrap().as_u32();
+            self.written.lock().unwrap().push(id.to_string());
+            Ok(())
+        }
+
+        fn write_gap(
+            &mut self,
+            _now: &DateTime<Local>,
+            dropped: u64,
+            _scope: Option<&str>,
+        ) -> Result<()> {
+            self.written
+                .lock()
+                .unwrap()
+                .push(format!("gap {}", dropped));
+            Ok(())
+        }
+    }
+
+    struct Harness {
+        queue: WriteQueue<GatedWriter>,
+        gate: Arc<AtomicBool>,
+        running: Arc<AtomicBool>,
+        written: Arc<Mutex<Vec<String>>>,
+    }
+
+    fn harness(capacity: usize, policy: Backpressure, delay: Duration, open: bool) -> Harness {
+        let gate = Arc::new(AtomicBool::new(open));
+        let running = Arc::new(AtomicBool::new(true));
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let writer = GatedWriter {
+            gate: gate.clone(),
+            delay,
+            written: written.clone(),
+        };
+        Harness {
+            queue: WriteQueue::new(writer, capacity, policy, running.clone()),
+            gate,
+            running,
+            written,
+        }
+    }
+
+    fn push(queue: &WriteQueue<GatedWriter>, id: u32) {
+        let sample = ProcessSample {
+            pid: Some(Pid::from_u32(id)),
+            name: "worker".to_string(),
+            cpu_usage: 0.0,
+            memory_usage: 0.0,
+            extras: Vec::new(),
+        };
+        queue.push(&Local::now(), &[sample], None).unwrap();
+    }
+
+    /// Pushes tick 0 and waits until the writer has taken it, so the queue
+    /// itself is empty.
+    fn occupy_writer(harness: &Harness) {
+        push(&harness.queue, 0);
+        while !harness.queue.lock().writing {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn finish(mut harness: Harness) -> Vec<String> {
+        harness.gate.store(true, Ordering::SeqCst);
+        harness.queue.finish().unwrap();
+        let written = harness.written.lock().unwrap().clone();
+        written
+    }
+
+    #[test]
+    fn test_block_writes_every_tick() {
+        let harness = harness(2, Backpressure::Block, Duration::from_millis(5), true);
+        for id in 0..10 {
+            push(&harness.queue, id);
+        }
+        assert_eq!(harness.queue.dropped(), 0);
+        let expected: Vec<String> = (0..10).map(|id| id.to_string()).collect();
+        assert_eq!(finish(harness), expected);
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let harness = harness(2, Backpressure::DropNewest, Duration::ZERO, false);
+        occupy_writer(&harness);
+        for id in 1..=4 {
+            push(&harness.queue, id);
+        }
+        assert_eq!(harness.queue.dropped(), 2);
+        assert_eq!(finish(harness), ["0", "1", "2", "gap 2"]);
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let harness = harness(2, Backpressure::DropOldest, Duration::ZERO, false);
+        occupy_writer(&harness);
+        for id in 1..=4 {
+            push(&harness.queue, id);
+        }
+        assert_eq!(harness.queue.dropped(), 2);
+        assert_eq!(finish(harness), ["0", "gap 2", "3", "4"]);
+    }
+
+    #[test]
+    fn test_block_honors_shutdown() {
+        let harness = harness(1, Backpressure::Block, Duration::ZERO, false);
+        occupy_writer(&harness);
+        push(&harness.queue, 1);
+
+        let running = harness.running.clone();
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            running.store(false, Ordering::SeqCst);
+        });
+        let started = Instant::now();
+        push(&harness.queue, 2);
+        let blocked = started.elapsed();
+        stopper.join().unwrap();
+
+        assert!(blocked >= Duration::from_millis(90), "Did not block");
+        assert!(
+            blocked < Duration::from_secs(1),
+            "Blocked for {:?}",
+            blocked
+        );
+        assert_eq!(harness.queue.dropped(), 1);
+        assert_eq!(finish(harness), ["0", "1", "gap 1"]);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::retry;
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::{
+    fmt, io,
+    time::{Duration, Instant},
+};
+
+/// Failed ticks are reported at most this often; the rest are counted.
+const WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Marks an error that no amount of retrying will fix, such as a sink whose
+/// connection has been shut down, so the run stops instead of skipping ticks.
+#[derive(Debug)]
+pub struct Unrecoverable;
+
+impl fmt::Display for Unrecoverable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Unrecoverable error")
+    }
+}
+
+impl std::error::Error for Unrecoverable {}
+
+/// True if `error` was marked [`Unrecoverable`], either as its source or as
+/// context added with `.context(Unrecoverable)`, or if an output write behind
+/// it failed for good.
+pub fn is_unrecoverable(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<Unrecoverable>().is_some()
+        || error.chain().any(|cause| {
+            cause
+                .downcast_ref::<io::Error>()
+                .is_some_and(retry::is_write_failed)
+        })
+}
+
+/// True if `error` comes from writing to a pipe or socket whose reader has
+/// gone away, e.g. `chronologger -o - | head`. That ends the run normally.
+pub fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+    })
+}
+
+/// Decides whether a failed tick is skipped or ends the run. A tick is
+/// skipped unless its error is [`Unrecoverable`] or it is the
+/// `max_consecutive`-th failure in a row.
+pub struct TickErrors {
+    max_consecutive: u32,
+    consecutive: u32,
+    failed: u64,
+    suppressed: u64,
+    last_warning: Option<Instant>,
+}
+
+impl TickErrors {
+    pub fn new(max_consecutive: u32) -> Self {
+        Self {
+            max_consecutive: max_consecutive.max(1),
+            consecutive: 0,
+            failed: 0,
+            suppressed: 0,
+            last_warning: None,
+        }
+    }
+
+    /// Records the outcome of a tick, returning an error only if the run
+    /// should stop.
+    pub fn record(&mut self, result: Result<()>) -> Result<()> {
+        let error = match result {
+            Ok(()) => {
+                self.consecutive = 0;
+                return Ok(());
+            }
+            Err(error) => error,
+        };
+        if is_unrecoverable(&error) {
+            return Err(error);
+        }
+        self.failed += 1;
+        self.consecutive += 1;
+        if self.consecutive >= self.max_consecutive {
+            return Err(anyhow!(
+                "Giving up after {} consecutive failed ticks: {:#}",
+                self.consecutive,
+                error
+            ));
+        }
+
+        let now = Instant::now();
+        if self
+            .last_warning
+            .is_some_and(|last| now.duration_since(last) < WARNING_INTERVAL)
+        {
+            self.suppressed += 1;
+            return Ok(());
+        }
+        if self.suppressed > 0 {
+            warn!(
+                "Skipped tick: {:#} ({} more failed ticks since the last warning)",
+                error, self.suppressed
+            );
+        } else {
+            warn!("Skipped tick: {:#}", error);
+        }
+        self.suppressed = 0;
+        self.last_warning = Some(now);
+        Ok(())
+    }
+
+    /// Ticks skipped because of an error so far.
+    pub fn failed(&self) -> u64 {
+        self.failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    fn failure() -> Result<()> {
+        Err(anyhow!("EIO"))
+    }
+
+    #[test]
+    fn test_recovers_from_failures() {
+        let mut errors = TickErrors::new(3);
+        assert!(errors.record(failure()).is_ok());
+        assert!(errors.record(failure()).is_ok());
+        assert!(errors.record(Ok(())).is_ok());
+        assert!(errors.record(failure()).is_ok());
+        assert!(errors.record(failure()).is_ok());
+        assert_eq!(errors.failed(), 4);
+    }
+
+    #[test]
+    fn test_aborts_after_consecutive_failures() {
+        let mut errors = TickErrors::new(3);
+        assert!(errors.record(failure()).is_ok());
+        assert!(errors.record(failure()).is_ok());
+        let error = errors.record(failure()).unwrap_err();
+        assert!(error.to_string().contains("3 consecutive"));
+    }
+
+    #[test]
+    fn test_aborts_on_unrecoverable_error() {
+        let mut errors = TickErrors::new(10);
+        let result = Err(anyhow::Error::new(Unrecoverable)).context("Sink closed");
+        assert!(errors.record(result).is_err());
+        let result = failure().context(Unrecoverable).context("Sink closed");
+        assert!(errors.record(result).is_err());
+        assert_eq!(errors.failed(), 0);
+    }
+
+    #[test]
+    fn test_broken_pipe() {
+        let error = Err::<(), _>(io::Error::from(io::ErrorKind::BrokenPipe))
+            .context("Failed to flush writer!")
+            .unwrap_err();
+        assert!(is_broken_pipe(&error));
+        assert!(!is_broken_pipe(&failure().unwrap_err()));
+    }
+
+    #[test]
+    fn test_aborts_when_output_write_fails_for_good() {
+        use crate::retry::{testing::FlakyWriter, RetryPolicy, Retrying};
+        use std::io::{ErrorKind, Write};
+
+        let policy = RetryPolicy {
+            retries: 1,
+            delay: Duration::from_millis(1),
+        };
+        let mut errors = TickErrors::new(10);
+        let mut writer = Retrying::new(FlakyWriter::new(1, ErrorKind::TimedOut), policy);
+        let result = writer.flush().context("Failed to flush writer!");
+        assert!(errors.record(result).is_ok());
+
+        let mut writer = Retrying::new(FlakyWriter::new(2, ErrorKind::TimedOut), policy);
+        let result = writer
+            .write_all(b"row\n")
+            .context("Failed to write record!");
+        assert!(errors.record(result).is_err());
+        assert_eq!(errors.failed(), 0);
+    }
+}
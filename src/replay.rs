@@ -0,0 +1,303 @@
+use crate::{
+    columns::ExtraValue,
+    source::{ProcessInfo, SystemSource},
+    ProcessLogger, ProcessSample,
+};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Lines, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+use sysinfo::{Pid, PidExt};
+
+/// Names the layout of a raw capture in its first line.
+const FORMAT: &str = "chronologger-raw";
+const VERSION: u64 = 1;
+
+/// Options of `chronologger replay`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub path: PathBuf,
+    /// Waits between ticks as long as passed between them when recorded.
+    pub realtime: bool,
+}
+
+/// Writes the samples of every full tick to a raw capture: a line describing
+/// the system, then one JSON line per tick. Each tick is flushed, so a run
+/// that is cut short leaves a capture that replays up to its last tick.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    path: PathBuf,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, hostname: &str, total_memory: u64) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create raw capture {}", path.display()))?;
+        let mut recorder = Self {
+            writer: BufWriter::new(file),
+            path: path.to_path_buf(),
+        };
+        recorder.write_line(&json!({
+            "format": FORMAT,
+            "version": VERSION,
+            "hostname": hostname,
+            "total_memory": total_memory,
+        }))?;
+        Ok(recorder)
+    }
+
+    pub fn record(&mut self, now: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        let samples: Vec<Value> = samples
+            .iter()
+            .map(|sample| {
+                let mut value = json!({
+                    "pid": sample.pid.map(Pid::as_u32),
+                    "name": sample.name,
+                    "cpu": sample.cpu_usage,
+                    "mem": sample.memory_usage,
+                });
+                if !sample.extras.is_empty() {
+                    let extras: Vec<Value> = sample
+                        .extras
+                        .iter()
+                        .map(|extra| extra.as_ref().map_or(Value::Null, ExtraValue::to_json))
+                        .collect();
+                    value["extras"] = extras.into();
+                }
+                value
+            })
+            .collect();
+        self.write_line(&json!({ "timestamp": now.to_rfc3339(), "samples": samples }))
+    }
+
+    fn write_line(&mut self, value: &Value) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, value)?;
+        self.writer.write_all(b"\n")?;
+        self.writer
+            .flush()
+            .with_context(|| format!("Failed to write raw capture {}", self.path.display()))
+    }
+}
+
+/// One recorded tick.
+pub struct Tick {
+    pub timestamp: DateTime<Local>,
+    pub samples: Vec<ProcessSample>,
+}
+
+/// A raw capture being read back.
+pub struct Replay {
+    lines: Lines<BufReader<File>>,
+    line: usize,
+    options: Options,
+    system: RecordedSystem,
+}
+
+impl Replay {
+    pub fn open(options: &Options) -> Result<Self> {
+        let path = &options.path;
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open raw capture {}", path.display()))?;
+        let mut lines = BufReader::new(file).lines();
+        let header: Value = match lines.next() {
+            Some(line) => serde_json::from_str(&line?).unwrap_or(Value::Null),
+            None => bail!("Raw capture {} is empty", path.display()),
+        };
+        if header["format"] != FORMAT {
+            bail!("{} is not a raw capture", path.display());
+        }
+        if header["version"] != VERSION {
+            bail!(
+                "Raw capture {} has unsupported version {}",
+                path.display(),
+                header["version"]
+            );
+        }
+        let system = RecordedSystem {
+            hostname: header["hostname"].as_str().unwrap_or("unknown").to_string(),
+            total_memory: header["total_memory"].as_u64().unwrap_or(0),
+        };
+        Ok(Self {
+            lines,
+            line: 1,
+            options: options.clone(),
+            system,
+        })
+    }
+
+    /// The system the capture was recorded on.
+    pub fn system(&self) -> RecordedSystem {
+        self.system.clone()
+    }
+
+    /// Reads the next tick, or `None` at the end of the capture.
+    pub fn next_tick(&mut self) -> Result<Option<Tick>> {
+        let Some(line) = self.lines.next() else {
+            return Ok(None);
+        };
+        self.line += 1;
+        let path = self.options.path.display();
+        let line = line.with_context(|| format!("Failed to read raw capture {}", path))?;
+        let tick = serde_json::from_str::<Value>(&line)
+            .ok()
+            .and_then(|value| parse_tick(&value))
+            .with_context(|| format!("Malformed tick on line {} of {}", self.line, path))?;
+        Ok(Some(tick))
+    }
+}
+
+fn parse_tick(value: &Value) -> Option<Tick> {
+    let timestamp = DateTime::parse_from_rfc3339(value["timestamp"].as_str()?)
+        .ok()?
+        .with_timezone(&Local);
+    let samples = value["samples"]
+        .as_array()?
+        .iter()
+        .map(|sample| {
+            Some(ProcessSample {
+                // A group's row was written with a null PID.
+                pid: match &sample["pid"] {
+                    Value::Null => None,
+                    pid => Some(Pid::from_u32(u32::try_from(pid.as_u64()?).ok()?)),
+                },
+                name: sample["name"].as_str()?.to_string(),
+                // Non-finite values were written as null.
+                cpu_usage: sample["cpu"].as_f64().unwrap_or(f64::NAN) as f32,
+                memory_usage: sample["mem"].as_f64().unwrap_or(f64::NAN),
+                extras: match sample.get("extras") {
+                    Some(extras) => extras
+                        .as_array()?
+                        .iter()
+                        .map(ExtraValue::from_json)
+                        .collect(),
+                    None => Vec::new(),
+                },
+            })
+        })
+        .collect::<Option<_>>()?;
+    Some(Tick { timestamp, samples })
+}
+
+/// Stands in for the live system during a replay: the hostname and total
+/// memory it was recorded with, and no processes of its own.
+#[derive(Debug, Clone)]
+pub struct RecordedSystem {
+    hostname: String,
+    total_memory: u64,
+}
+
+impl SystemSource for RecordedSystem {
+    fn refresh(&mut self) {}
+
+    fn total_memory(&self) -> u64 {
+        self.total_memory
+    }
+
+    fn hostname(&self) -> Option<String> {
+        Some(self.hostname.clone())
+    }
+
+    fn processes(&self) -> Box<dyn Iterator<Item = ProcessInfo<'_>> + '_> {
+        Box::new(std::iter::empty())
+    }
+}
+
+/// Feeds every tick of `replay` through `logger`'s output and sinks, as fast
+/// as they take them or, with `--realtime`, spaced as they were recorded.
+pub fn run(logger: &mut ProcessLogger, replay: &mut Replay, running: &AtomicBool) -> Result<()> {
+    let mut previous: Option<DateTime<Local>> = None;
+    while running.load(Ordering::SeqCst) {
+        let Some(tick) = replay.next_tick()? else {
+            break;
+        };
+        if let (true, Some(previous)) = (replay.options.realtime, previous) {
+            let gap = (tick.timestamp - previous).to_std().unwrap_or_default();
+            if !sleep_while_running(gap, running) {
+                break;
+            }
+        }
+        previous = Some(tick.timestamp);
+        let result = logger.log_recorded(tick.timestamp, tick.samples);
+        logger.tick_errors.record(result)?;
+    }
+    Ok(())
+}
+
+/// Sleeps for `duration`, returning early with false if the run is stopped.
+fn sleep_while_running(duration: Duration, running: &AtomicBool) -> bool {
+    let step = Duration::from_millis(100);
+    let mut remaining = duration;
+    while !remaining.is_zero() {
+        if !running.load(Ordering::SeqCst) {
+            return false;
+        }
+        let nap = remaining.min(step);
+        thread::sleep(nap);
+        remaining -= nap;
+    }
+    running.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("raw.jsonl");
+        let now = Local::now();
+        let samples = vec![
+            ProcessSample {
+                pid: Some(Pid::from_u32(7)),
+                name: "name, with \"quotes\"".to_string(),
+                cpu_usage: 0.1,
+                memory_usage: 1.0 / 3.0,
+                extras: vec![Some(42.into()), None, Some(ExtraValue::Text("web".into()))],
+            },
+            ProcessSample {
+                pid: None,
+                name: "idle".to_string(),
+                cpu_usage: f32::NAN,
+                memory_usage: 0.0,
+                extras: Vec::new(),
+            },
+        ];
+        let mut recorder = Recorder::create(&path, "node-1", 1024).unwrap();
+        recorder.record(&now, &samples).unwrap();
+        recorder.record(&now, &[]).unwrap();
+
+        let options = Options {
+            path: path.clone(),
+            realtime: false,
+        };
+        let mut replay = Replay::open(&options).unwrap();
+        assert_eq!(replay.system().hostname().as_deref(), Some("node-1"));
+        assert_eq!(replay.system().total_memory(), 1024);
+        let tick = replay.next_tick().unwrap().unwrap();
+        assert_eq!(tick.timestamp, now);
+        assert_eq!(tick.samples[0].pid, samples[0].pid);
+        assert_eq!(tick.samples[0].name, samples[0].name);
+        assert_eq!(tick.samples[0].cpu_usage.to_bits(), 0.1f32.to_bits());
+        assert_eq!(
+            tick.samples[0].memory_usage.to_bits(),
+            (1.0f64 / 3.0).to_bits()
+        );
+        assert_eq!(tick.samples[0].extras, samples[0].extras);
+        assert_eq!(tick.samples[1].pid, None);
+        assert!(tick.samples[1].cpu_usage.is_nan());
+        assert!(replay.next_tick().unwrap().unwrap().samples.is_empty());
+        assert!(replay.next_tick().unwrap().is_none());
+
+        std::fs::write(&path, "timestamp,pid\n").unwrap();
+        assert!(Replay::open(&options).is_err(), "Accepted a CSV file");
+    }
+}
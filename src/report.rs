@@ -0,0 +1,311 @@
+//! `--report`: a plain-text summary printed or written when the run ends,
+//! with the CPU and memory usage of the heaviest processes over the run as
+//! sparklines and histograms of the usage of every sample.
+
+use crate::{summary::ProcStats, ProcessSample, STDOUT_PATH};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::info;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+use sysinfo::{Pid, PidExt};
+
+/// Characters of a sparkline, from none to the process's peak.
+const LEVELS: [char; 8] = ['_', '.', '-', '=', '+', '*', '#', '@'];
+
+/// Characters a sparkline spans however long the run.
+const WIDTH: usize = 60;
+
+/// Processes given sparklines.
+const PROCESSES: usize = 10;
+
+/// Characters of the name and PID before a sparkline.
+const LABEL_WIDTH: usize = 24;
+
+/// Characters of the longest histogram bar.
+const BAR_WIDTH: usize = 40;
+
+/// Bounds between the bins of a histogram.
+const BOUNDS: usize = 8;
+
+/// Upper bounds of the CPU usage histogram's bins, in percent; the last
+/// bin holds everything above them.
+const CPU_BINS: [f64; BOUNDS] = [1.0, 5.0, 10.0, 25.0, 50.0, 75.0, 100.0, 200.0];
+
+/// Likewise for memory usage.
+const MEMORY_BINS: [f64; BOUNDS] = [0.1, 0.5, 1.0, 2.0, 5.0, 10.0, 25.0, 50.0];
+
+/// What a process used over the ticks one character stands for.
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    cpu: f64,
+    memory: f64,
+    samples: u32,
+}
+
+impl Bucket {
+    fn merge(self, other: Bucket) -> Bucket {
+        Bucket {
+            cpu: self.cpu + other.cpu,
+            memory: self.memory + other.memory,
+            samples: self.samples + other.samples,
+        }
+    }
+}
+
+struct Process {
+    stats: ProcStats,
+    buckets: Vec<Bucket>,
+}
+
+/// Counts of samples per bin.
+#[derive(Debug, Default)]
+struct Histogram {
+    counts: [u64; BOUNDS + 1],
+}
+
+impl Histogram {
+    fn add(&mut self, bins: &[f64; BOUNDS], value: f64) {
+        let bin = bins
+            .iter()
+            .position(|&bound| value < bound)
+            .unwrap_or(bins.len());
+        self.counts[bin] += 1;
+    }
+}
+
+/// Accumulates the report over the run's full-table ticks. Each process
+/// keeps at most [`WIDTH`] buckets: once the run outgrows them, neighbours
+/// are merged and each bucket stands for twice the ticks, so memory stays
+/// bounded however long the run.
+#[derive(Default)]
+pub struct Report {
+    processes: HashMap<(Option<Pid>, String), Process>,
+    ticks: u64,
+    ticks_per_bucket: u64,
+    cpu: Histogram,
+    memory: Histogram,
+}
+
+impl Report {
+    /// Adds the samples of the tick taken at `now`.
+    pub fn record(&mut self, now: &DateTime<Local>, samples: &[ProcessSample]) {
+        self.ticks_per_bucket = self.ticks_per_bucket.max(1);
+        if self.ticks / self.ticks_per_bucket >= WIDTH as u64 {
+            self.ticks_per_bucket *= 2;
+            for process in self.processes.values_mut() {
+                process.buckets = process
+                    .buckets
+                    .chunks(2)
+                    .map(|pair| pair.iter().fold(Bucket::default(), |a, b| a.merge(*b)))
+                    .collect();
+            }
+        }
+        let bucket = (self.ticks / self.ticks_per_bucket) as usize;
+        for sample in samples {
+            let process = self
+                .processes
+                .entry((sample.pid, sample.name.clone()))
+                .or_insert_with(|| Process {
+                    stats: ProcStats::new(now),
+                    buckets: Vec::new(),
+                });
+            process.stats.add(now, sample);
+            if process.buckets.len() <= bucket {
+                process.buckets.resize(bucket + 1, Bucket::default());
+            }
+            let slot = &mut process.buckets[bucket];
+            slot.cpu += f64::from(sample.cpu_usage);
+            slot.memory += sample.memory_usage;
+            slot.samples += 1;
+            self.cpu.add(&CPU_BINS, f64::from(sample.cpu_usage));
+            self.memory.add(&MEMORY_BINS, sample.memory_usage);
+        }
+        self.ticks += 1;
+    }
+
+    /// Writes the report to `path`, or to stdout for `-`.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if path == Path::new(STDOUT_PATH) {
+            return self.write_to(std::io::stdout().lock());
+        }
+        info!("Writing the report to {}", path.display());
+        let file = File::create(path).context("Failed to create report file!")?;
+        self.write_to(BufWriter::new(file))
+    }
+
+    fn write_to<W: Write>(&self, mut out: W) -> Result<()> {
+        let samples: u64 = self.cpu.counts.iter().sum();
+        writeln!(
+            out,
+            "chronologger report: {} ticks, {} processes, {} samples",
+            self.ticks,
+            self.processes.len(),
+            samples
+        )?;
+        let mut processes: Vec<(&(Option<Pid>, String), &Process)> =
+            self.processes.iter().collect();
+        processes.sort_unstable_by(|(a_key, a), (b_key, b)| {
+            b.stats
+                .cpu_mean()
+                .total_cmp(&a.stats.cpu_mean())
+                .then(a_key.cmp(b_key))
+        });
+        if !processes.is_empty() {
+            writeln!(out)?;
+            writeln!(
+                out,
+                "Usage over the run, highest mean CPU usage first, {} tick(s) per character:",
+                self.ticks_per_bucket
+            )?;
+        }
+        for ((pid, name), process) in processes.iter().take(PROCESSES) {
+            // Long names are cut short to keep the sparklines aligned. A
+            // group has no PID to show.
+            let pid = pid.map_or_else(String::new, |pid| format!(" ({})", pid.as_u32()));
+            let room = LABEL_WIDTH.saturating_sub(pid.len());
+            let label: String = name.chars().take(room).chain(pid.chars()).collect();
+            let stats = &process.stats;
+            writeln!(
+                out,
+                "{:<LABEL_WIDTH$} cpu |{:<WIDTH$}| mean {:.2}% max {:.2}%",
+                label,
+                sparkline(&process.buckets, |bucket| bucket.cpu),
+                stats.cpu_mean(),
+                stats.cpu_max
+            )?;
+            writeln!(
+                out,
+                "{:<LABEL_WIDTH$} mem |{:<WIDTH$}| mean {:.2}% max {:.2}%",
+                "",
+                sparkline(&process.buckets, |bucket| bucket.memory),
+                stats.memory_mean(),
+                stats.memory_max
+            )?;
+        }
+        if processes.len() > PROCESSES {
+            writeln!(out, "and {} more processes", processes.len() - PROCESSES)?;
+        }
+        if samples > 0 {
+            writeln!(out)?;
+            writeln!(out, "CPU usage of every sample:")?;
+            histogram(&mut out, &CPU_BINS, &self.cpu)?;
+            writeln!(out)?;
+            writeln!(out, "Memory usage of every sample:")?;
+            histogram(&mut out, &MEMORY_BINS, &self.memory)?;
+        }
+        out.flush().context("Failed to flush report!")
+    }
+}
+
+/// One character per bucket, scaled to the highest mean of `value`, and a
+/// space for the buckets in which the process was not sampled.
+fn sparkline(buckets: &[Bucket], value: impl Fn(&Bucket) -> f64) -> String {
+    let mean = |bucket: &Bucket| value(bucket) / f64::from(bucket.samples);
+    let max = buckets
+        .iter()
+        .filter(|bucket| bucket.samples > 0)
+        .map(mean)
+        .fold(0.0f64, f64::max);
+    buckets
+        .iter()
+        .map(|bucket| {
+            if bucket.samples == 0 {
+                ' '
+            } else if max > 0.0 {
+                let level = (mean(bucket) / max * (LEVELS.len() - 1) as f64).round() as usize;
+                LEVELS[level.min(LEVELS.len() - 1)]
+            } else {
+                LEVELS[0]
+            }
+        })
+        .collect()
+}
+
+/// A row per bin: its range, a bar scaled to the fullest bin and the count.
+fn histogram(out: &mut impl Write, bins: &[f64; BOUNDS], histogram: &Histogram) -> Result<()> {
+    let most = histogram.counts.iter().copied().max().unwrap_or(0).max(1);
+    for (bin, &count) in histogram.counts.iter().enumerate() {
+        let range = match bin {
+            0 => format!("< {}%", bins[0]),
+            bin if bin == bins.len() => format!(">= {}%", bins[bin - 1]),
+            bin => format!("{}-{}%", bins[bin - 1], bins[bin]),
+        };
+        let bar = (count as f64 / most as f64 * BAR_WIDTH as f64).round() as usize;
+        // A bin holding any sample shows at least one character.
+        let bar = if count > 0 { bar.max(1) } else { 0 };
+        writeln!(out, "{:>10} |{} {}", range, "#".repeat(bar), count)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+
+    fn render(report: &Report) -> String {
+        let mut out = Vec::new();
+        report.write_to(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_report() {
+        let now = Local::now();
+        let mut report = Report::default();
+        for tick in 0..8 {
+            let mut samples = vec![sample(1, "init", 0.5, 0.2)];
+            // The worker starts on the third tick and ramps up.
+            if tick >= 2 {
+                samples.push(sample(42, "worker", tick as f32 * 10.0, 3.0));
+            }
+            report.record(&now, &samples);
+        }
+        let printed = render(&report);
+        let lines: Vec<&str> = printed.lines().collect();
+        assert_eq!(
+            lines[0],
+            "chronologger report: 8 ticks, 2 processes, 14 samples"
+        );
+        assert!(lines[3].starts_with("worker (42)"), "{}", printed);
+        assert!(lines[3].contains("|  -=+*#@"), "{}", lines[3]);
+        assert!(
+            lines[3].ends_with("| mean 45.00% max 70.00%"),
+            "{}",
+            lines[3]
+        );
+        assert!(lines[4].contains("|  @@@@@@"), "{}", lines[4]);
+        assert!(lines[5].starts_with("init (1)"));
+        assert!(lines[5].contains("|@@@@@@@@"), "{}", lines[5]);
+        let bar = |range: &str, width: usize, count: u64| {
+            format!("\n{:>10} |{} {}\n", range, "#".repeat(width), count)
+        };
+        assert!(printed.contains(&bar("< 1%", 40, 8)), "{}", printed);
+        assert!(printed.contains(&bar("50-75%", 15, 3)), "{}", printed);
+        assert!(printed.contains(&bar(">= 200%", 0, 0)), "{}", printed);
+        assert!(printed.contains(&bar("2-5%", 30, 6)), "{}", printed);
+    }
+
+    #[test]
+    fn test_long_runs_merge_buckets() {
+        let now = Local::now();
+        let mut report = Report::default();
+        for tick in 0..(WIDTH * 4) {
+            let cpu = if tick < WIDTH * 2 { 0.0 } else { 10.0 };
+            report.record(&now, &[sample(1, "worker", cpu, 1.0)]);
+        }
+        assert_eq!(report.ticks_per_bucket, 4);
+        let buckets = &report.processes.values().next().unwrap().buckets;
+        assert_eq!(buckets.len(), WIDTH);
+        let line = sparkline(buckets, |bucket| bucket.cpu);
+        assert_eq!(
+            line,
+            format!("{}{}", "_".repeat(WIDTH / 2), "@".repeat(WIDTH / 2))
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use crate::{integrity, metadata::Metadata, timestamp::TimestampFormat};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, TimeZone};
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// How much of the end of the file is searched for the last record.
+const TAIL_BYTES: u64 = 64 * 1024;
+
+/// What an existing output is expected to look like.
+pub enum Layout {
+    /// CSV with this header line, its `Timestamp` column written in this
+    /// format.
+    Csv(String, TimestampFormat),
+    /// One JSON document per line with a `Timestamp` in milliseconds.
+    JsonLines,
+    /// One JSON object per line with a `timestamp` written in this format.
+    Records(TimestampFormat),
+}
+
+/// Where an interrupted capture left off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumePoint {
+    /// Timestamp of the last complete record, if the file holds any.
+    pub last: Option<DateTime<Local>>,
+    /// Length of the file up to the end of its last complete line. A line
+    /// cut off by the interruption, or the integrity footer of a capture
+    /// that ended cleanly, lies beyond it and is dropped.
+    pub valid_len: u64,
+    /// Where the data section covered by the integrity footer starts.
+    pub data_start: u64,
+}
+
+/// Checks that the output at `path` was written in `layout` and finds where
+/// it left off. Returns `None` if there is nothing to resume, i.e. the file
+/// is missing or empty.
+pub fn inspect(path: &Path, layout: &Layout) -> Result<Option<ResumePoint>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to open {}", path.display()));
+        }
+    };
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(None);
+    }
+
+    let mut first = String::new();
+    let mut reader = BufReader::new(&mut file);
+    if let Layout::Csv(..) = layout {
+        // The header follows the run metadata, if any.
+        Metadata::parse_comments(&mut reader)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+    }
+    reader
+        .read_line(&mut first)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let data_start = match layout {
+        Layout::Csv(..) => reader.stream_position()?,
+        Layout::JsonLines | Layout::Records(_) => 0,
+    };
+    let first = first.trim_end_matches(['\r', '\n']);
+    let compatible = match layout {
+        Layout::Csv(header, _) => first == header,
+        // A capture without records may hold just its footer.
+        Layout::JsonLines => json_timestamp(first).is_some() || integrity::is_footer(first),
+        Layout::Records(timestamps) => {
+            record_timestamp(first, timestamps).is_some() || integrity::is_footer(first)
+        }
+    };
+    if !compatible {
+        bail!(
+            "Cannot resume {}: it was not written with this run's format and columns \
+             (first line {:?})",
+            path.display(),
+            first
+        );
+    }
+
+    let start = len.saturating_sub(TAIL_BYTES);
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(start))?;
+    file.read_to_end(&mut tail)?;
+    let Some(end) = tail.iter().rposition(|&byte| byte == b'\n') else {
+        bail!(
+            "Cannot resume {}: its last line is longer than {} bytes",
+            path.display(),
+            TAIL_BYTES
+        );
+    };
+    let complete = String::from_utf8_lossy(&tail[..end]);
+    let mut lines: Vec<&str> = complete.lines().collect();
+    // The first line of the tail may start partway through a record.
+    if start > 0 && !lines.is_empty() {
+        lines.remove(0);
+    }
+    let mut valid_len = start + end as u64 + 1;
+    if let Some(footer) = lines.last().filter(|line| integrity::is_footer(line)) {
+        valid_len -= footer.len() as u64 + 1;
+        lines.pop();
+    }
+    // `--columns` may have moved the timestamp, or left it out.
+    let column = match layout {
+        Layout::Csv(header, _) => header.split(',').position(|name| name == "Timestamp"),
+        Layout::JsonLines | Layout::Records(_) => None,
+    };
+    let last = lines.iter().rev().find_map(|line| match layout {
+        Layout::Csv(_, timestamps) => csv_timestamp(line, column?, timestamps),
+        Layout::JsonLines => json_timestamp(line),
+        Layout::Records(timestamps) => record_timestamp(line, timestamps),
+    });
+    Ok(Some(ResumePoint {
+        last,
+        valid_len: valid_len.max(data_start),
+        data_start,
+    }))
+}
+
+fn csv_timestamp(
+    line: &str,
+    column: usize,
+    timestamps: &TimestampFormat,
+) -> Option<DateTime<Local>> {
+    // Fields before the timestamp, such as a process name, may be quoted.
+    let record = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(line.as_bytes())
+        .into_records()
+        .next()?
+        .ok()?;
+    timestamps.parse(record.get(column)?)
+}
+
+fn json_timestamp(line: &str) -> Option<DateTime<Local>> {
+    let document: serde_json::Value = serde_json::from_str(line).ok()?;
+    Local
+        .timestamp_millis_opt(document.get("Timestamp")?.as_i64()?)
+        .single()
+}
+
+fn record_timestamp(line: &str, timestamps: &TimestampFormat) -> Option<DateTime<Local>> {
+    let document: serde_json::Value = serde_json::from_str(line).ok()?;
+    // The epoch styles are written as numbers.
+    match document.get("timestamp")? {
+        serde_json::Value::String(timestamp) => timestamps.parse(timestamp),
+        serde_json::Value::Number(timestamp) => timestamps.parse(&timestamp.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::timestamp::Style;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    const HEADER: &str = "Timestamp,PID,Process Name";
+
+    fn csv_layout() -> Layout {
+        Layout::Csv(HEADER.to_string(), TimestampFormat::default())
+    }
+
+    fn records() -> Layout {
+        Layout::Records(TimestampFormat::default())
+    }
+
+    fn file_with(contents: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_drops_cut_off_line() {
+        let complete = format!(
+            "{}\n2026-01-01T10:00:00+00:00,1,a\n2026-01-01T10:00:01+00:00,1,a\n",
+            HEADER
+        );
+        let file = file_with(&format!("{}2026-01-01T10:00:02+00:0", complete));
+        let point = inspect(file.path(), &csv_layout()).unwrap().unwrap();
+        assert_eq!(point.valid_len, complete.len() as u64);
+        assert_eq!(
+            point.last.unwrap(),
+            DateTime::parse_from_rfc3339("2026-01-01T10:00:01+00:00").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_header_only_and_missing() {
+        let file = file_with(&format!("{}\n", HEADER));
+        let point = inspect(file.path(), &csv_layout()).unwrap().unwrap();
+        assert_eq!(point.last, None);
+        assert_eq!(point.valid_len, HEADER.len() as u64 + 1);
+
+        let path = file.path().with_extension("missing");
+        assert_eq!(inspect(&path, &csv_layout()).unwrap(), None);
+        assert_eq!(inspect(file_with("").path(), &csv_layout()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_skips_metadata_block() {
+        let contents = format!(
+            "# tool_version: 1.0.1\n# columns: {}\n{}\n2026-01-01T10:00:00+00:00,1,a\n",
+            HEADER, HEADER
+        );
+        let file = file_with(&contents);
+        let point = inspect(file.path(), &csv_layout()).unwrap().unwrap();
+        assert_eq!(point.valid_len, contents.len() as u64);
+        assert!(point.last.is_some());
+    }
+
+    #[test]
+    fn test_drops_footer() {
+        let data = "2026-01-01T10:00:00+00:00,1,a\n";
+        let contents = format!(
+            "{}\n{}# integrity: rows=1 bytes={} sha256=00\n",
+            HEADER,
+            data,
+            data.len()
+        );
+        let file = file_with(&contents);
+        let point = inspect(file.path(), &csv_layout()).unwrap().unwrap();
+        assert_eq!(point.data_start, HEADER.len() as u64 + 1);
+        assert_eq!(point.valid_len, point.data_start + data.len() as u64);
+        assert!(point.last.is_some());
+
+        let footer = r#"{"Integrity":{"Rows":0,"Bytes":0,"Sha256":"00"}}"#;
+        let file = file_with(&format!("{}\n", footer));
+        let point = inspect(file.path(), &Layout::JsonLines).unwrap().unwrap();
+        assert_eq!((point.data_start, point.valid_len), (0, 0));
+        assert_eq!(point.last, None);
+    }
+
+    #[test]
+    fn test_incompatible_schema() {
+        let file = file_with("Timestamp,PID,Process Name,Scope\n");
+        let error = inspect(file.path(), &csv_layout()).unwrap_err();
+        assert!(error.to_string().contains("Cannot resume"));
+
+        let file = file_with(&format!("{}\n", HEADER));
+        assert!(inspect(file.path(), &Layout::JsonLines).is_err());
+
+        let file = file_with("{\"Timestamp\":1767261600000}\n");
+        let point = inspect(file.path(), &Layout::JsonLines).unwrap().unwrap();
+        assert_eq!(point.last.unwrap().timestamp_millis(), 1_767_261_600_000);
+        assert!(inspect(file.path(), &csv_layout()).is_err());
+        assert!(inspect(file.path(), &records()).is_err());
+
+        let file = file_with("{\"timestamp\":\"2026-01-01T10:00:00+00:00\",\"pid\":1}\n");
+        let point = inspect(file.path(), &records()).unwrap().unwrap();
+        assert_eq!(point.last.unwrap().timestamp_millis(), 1_767_261_600_000);
+        assert!(inspect(file.path(), &Layout::JsonLines).is_err());
+    }
+
+    #[test]
+    fn test_epoch_records() {
+        let contents =
+            "{\"timestamp\":1767261600,\"pid\":1}\n{\"timestamp\":1767261601,\"pid\":1}\n";
+        let file = file_with(contents);
+        let epoch = Layout::Records(TimestampFormat::new(Style::Epoch, false));
+        let point = inspect(file.path(), &epoch).unwrap().unwrap();
+        assert_eq!(point.last.unwrap().timestamp(), 1_767_261_601);
+        assert_eq!(point.valid_len, contents.len() as u64);
+        assert!(inspect(file.path(), &records()).is_err());
+
+        let file = file_with("{\"timestamp\":1767261600250,\"pid\":1}\n");
+        let millis = Layout::Records(TimestampFormat::new(Style::EpochMillis, true));
+        let point = inspect(file.path(), &millis).unwrap().unwrap();
+        assert_eq!(point.last.unwrap().timestamp_millis(), 1_767_261_600_250);
+    }
+
+    #[test]
+    fn test_moved_timestamp_column() {
+        let header = "Process Name,CPU Usage (%),PID,Timestamp,Memory Usage (%)";
+        let file = file_with(&format!(
+            "{}\n\"a,b\",1.00,1,1767261600000,2.00\n\"[resumed, again]\",,,1767261601000,\n",
+            header
+        ));
+        let layout = Layout::Csv(
+            header.to_string(),
+            TimestampFormat::new(Style::EpochMillis, false),
+        );
+        let point = inspect(file.path(), &layout).unwrap().unwrap();
+        assert_eq!(point.last.unwrap().timestamp_millis(), 1_767_261_601_000);
+
+        let header = "PID,Process Name";
+        let file = file_with(&format!("{}\n1,a\n", header));
+        let layout = Layout::Csv(header.to_string(), TimestampFormat::default());
+        assert_eq!(inspect(file.path(), &layout).unwrap().unwrap().last, None);
+    }
+}
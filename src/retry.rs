@@ -0,0 +1,259 @@
+use crate::backoff::Backoff;
+use log::warn;
+use std::{
+    error::Error,
+    fmt,
+    io::{self, ErrorKind, Write},
+    thread,
+    time::Duration,
+};
+
+/// No single wait between retries grows beyond this.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// How output writes that fail with a transient error are retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts made after the first one fails.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after it.
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 3,
+            delay: Duration::from_millis(500),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Likely to clear up on its own, e.g. a stalled network filesystem.
+    Transient,
+    /// Will fail the same way however often it is retried.
+    Permanent,
+    /// Neither; the write is not retried but the run carries on.
+    Other,
+}
+
+/// Sorts an output error into what retrying can and cannot fix. A full disk
+/// is retried because space may be freed, but is permanent once retries run
+/// out.
+pub fn classify(error: &io::Error) -> ErrorClass {
+    match error.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => return ErrorClass::Transient,
+        ErrorKind::PermissionDenied => return ErrorClass::Permanent,
+        _ => {}
+    }
+    #[cfg(unix)]
+    match error.raw_os_error() {
+        Some(libc::ESTALE | libc::ENOSPC | libc::EAGAIN | libc::ETIMEDOUT) => {
+            return ErrorClass::Transient
+        }
+        Some(libc::EACCES | libc::EPERM | libc::EROFS) => return ErrorClass::Permanent,
+        _ => {}
+    }
+    ErrorClass::Other
+}
+
+/// An output write that failed permanently or was still failing after every
+/// retry. It travels inside the returned [`io::Error`] so the run can tell it
+/// apart from errors that only cost a tick.
+#[derive(Debug)]
+pub struct WriteFailed {
+    attempts: u32,
+    source: io::Error,
+}
+
+impl fmt::Display for WriteFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.attempts {
+            1 => write!(f, "{}", self.source),
+            attempts => write!(f, "{} (gave up after {} attempts)", self.source, attempts),
+        }
+    }
+}
+
+impl Error for WriteFailed {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// True if `error` carries a [`WriteFailed`].
+pub fn is_write_failed(error: &io::Error) -> bool {
+    error
+        .get_ref()
+        .is_some_and(|inner| inner.is::<WriteFailed>())
+}
+
+/// Retries writes and flushes to the wrapped writer with exponential backoff
+/// while they fail with a [`ErrorClass::Transient`] error.
+pub struct Retrying<W> {
+    inner: W,
+    policy: RetryPolicy,
+    backoff: Backoff,
+}
+
+impl<W> Retrying<W> {
+    pub fn new(inner: W, policy: RetryPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            backoff: Backoff::new(policy.delay, MAX_RETRY_DELAY.max(policy.delay)),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    fn retry<T>(&mut self, mut op: impl FnMut(&mut W) -> io::Result<T>) -> io::Result<T> {
+        self.backoff.reset();
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let error = match op(&mut self.inner) {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+            let class = classify(&error);
+            if class == ErrorClass::Other {
+                return Err(error);
+            }
+            if class == ErrorClass::Permanent || attempts > self.policy.retries {
+                return Err(io::Error::new(
+                    error.kind(),
+                    WriteFailed {
+                        attempts,
+                        source: error,
+                    },
+                ));
+            }
+            let delay = self.backoff.next_delay();
+            warn!(
+                "Output write failed, retrying in {:?} ({} of {}): {}",
+                delay, attempts, self.policy.retries, error
+            );
+            thread::sleep(delay);
+        }
+    }
+}
+
+impl<W: Write> Write for Retrying<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.retry(|inner| inner.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.retry(|inner| inner.flush())
+    }
+}
+
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+
+    /// Fails the first `failures` writes and flushes with `kind`, then
+    /// accepts everything.
+    pub struct FlakyWriter {
+        pub failures: u32,
+        pub kind: ErrorKind,
+        pub calls: u32,
+        pub written: Vec<u8>,
+    }
+
+    impl FlakyWriter {
+        pub fn new(failures: u32, kind: ErrorKind) -> Self {
+            Self {
+                failures,
+                kind,
+                calls: 0,
+                written: Vec::new(),
+            }
+        }
+
+        fn fail(&mut self) -> io::Result<()> {
+            self.calls += 1;
+            if self.failures == 0 {
+                return Ok(());
+            }
+            self.failures -= 1;
+            Err(io::Error::from(self.kind))
+        }
+    }
+
+    impl Write for FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.fail()?;
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.fail()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::FlakyWriter;
+    use super::*;
+
+    fn policy(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            delay: Duration::from_millis(1),
+        }
+    }
+
+    #[test]
+    fn test_recovers_within_retries() {
+        let mut writer = Retrying::new(FlakyWriter::new(3, ErrorKind::TimedOut), policy(3));
+        writer.write_all(b"row\n").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.inner.written, b"row\n");
+        assert_eq!(writer.inner.calls, 5);
+    }
+
+    #[test]
+    fn test_gives_up_after_retries() {
+        let mut writer = Retrying::new(FlakyWriter::new(5, ErrorKind::WouldBlock), policy(2));
+        let error = writer.write_all(b"row\n").unwrap_err();
+        assert!(is_write_failed(&error));
+        assert!(error.to_string().contains("after 3 attempts"));
+        assert_eq!(writer.inner.calls, 3);
+    }
+
+    #[test]
+    fn test_permanent_error_is_not_retried() {
+        let mut writer = Retrying::new(FlakyWriter::new(1, ErrorKind::PermissionDenied), policy(5));
+        let error = writer.write_all(b"row\n").unwrap_err();
+        assert!(is_write_failed(&error));
+        assert_eq!(writer.inner.calls, 1);
+    }
+
+    #[test]
+    fn test_other_error_is_passed_through() {
+        let mut writer = Retrying::new(FlakyWriter::new(1, ErrorKind::Other), policy(5));
+        let error = writer.flush().unwrap_err();
+        assert!(!is_write_failed(&error));
+        assert_eq!(writer.inner.calls, 1);
+        writer.flush().unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_classify_errno() {
+        let errno = io::Error::from_raw_os_error;
+        assert_eq!(classify(&errno(libc::ESTALE)), ErrorClass::Transient);
+        assert_eq!(classify(&errno(libc::ENOSPC)), ErrorClass::Transient);
+        assert_eq!(classify(&errno(libc::EAGAIN)), ErrorClass::Transient);
+        assert_eq!(classify(&errno(libc::EACCES)), ErrorClass::Permanent);
+        assert_eq!(classify(&errno(libc::EIO)), ErrorClass::Other);
+    }
+}
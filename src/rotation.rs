@@ -0,0 +1,206 @@
+use log::{info, warn};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// When `--rotate-size` and `--rotate-every` move the output on to a new
+/// file, and how many files `--max-files` keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Policy {
+    pub max_bytes: Option<u64>,
+    pub every: Option<Duration>,
+    pub max_files: Option<u64>,
+}
+
+impl Policy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_bytes.is_some() || self.every.is_some()
+    }
+}
+
+/// Tracks which file of a rotated capture is being written: the output
+/// itself first, then `<stem>.1.<ext>`, `<stem>.2.<ext>` and so on.
+#[derive(Debug)]
+pub struct Rotation {
+    policy: Policy,
+    base: PathBuf,
+    index: u64,
+    opened: Instant,
+    /// Ticks written to the current file, which is never left without one.
+    ticks: u64,
+}
+
+impl Rotation {
+    pub fn new(base: &Path, policy: Policy) -> Self {
+        Self {
+            policy,
+            base: base.to_path_buf(),
+            index: 0,
+            opened: Instant::now(),
+            ticks: 0,
+        }
+    }
+
+    /// The file being written.
+    pub fn current(&self) -> PathBuf {
+        path_for(&self.base, self.index)
+    }
+
+    /// Notes that a tick was written to the current file.
+    pub fn record_tick(&mut self) {
+        self.ticks += 1;
+    }
+
+    /// Whether the current file has reached the size or age to rotate at.
+    pub fn is_due(&self) -> bool {
+        if self.ticks == 0 {
+            return false;
+        }
+        if let Some(every) = self.policy.every {
+            if self.opened.elapsed() >= every {
+                return true;
+            }
+        }
+        let Some(max_bytes) = self.policy.max_bytes else {
+            return false;
+        };
+        match fs::metadata(self.current()) {
+            Ok(metadata) => metadata.len() >= max_bytes,
+            Err(e) => {
+                warn!("Failed to check the size of the output file: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Moves on to the next file and returns its path. The caller must have
+    /// closed the current one.
+    pub fn advance(&mut self) -> PathBuf {
+        self.index += 1;
+        self.opened = Instant::now();
+        self.ticks = 0;
+        self.current()
+    }
+
+    /// Deletes the file that fell out of the `--max-files` most recent
+    /// ones when the current file was opened.
+    pub fn prune(&self) -> io::Result<()> {
+        let Some(max_files) = self.policy.max_files else {
+            return Ok(());
+        };
+        let Some(oldest) = self.index.checked_sub(max_files) else {
+            return Ok(());
+        };
+        let path = path_for(&self.base, oldest);
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                info!("Deleted old output file {}", path.display());
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// The path of file `index` of a capture rotated from `base`: `base`
+/// itself for 0, `foo.<index>.csv` for `foo.csv` otherwise.
+pub fn path_for(base: &Path, index: u64) -> PathBuf {
+    if index == 0 {
+        return base.to_path_buf();
+    }
+    let stem = base.file_stem().unwrap_or(base.as_os_str());
+    let mut name = stem.to_os_string();
+    name.push(format!(".{}", index));
+    if let Some(extension) = base.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    base.with_file_name(name)
+}
+
+/// Parses sizes such as `500000`, `64K`, `100M` or `2G`, in bytes with
+/// binary multiples.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size: {:?}", value))?;
+    let multiple: u64 = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(format!("Invalid size unit in {:?}", value)),
+    };
+    match number.checked_mul(multiple) {
+        Some(0) => Err("The size must be greater than 0".to_string()),
+        Some(bytes) => Ok(bytes),
+        None => Err(format!("Size too large: {:?}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_for() {
+        let base = Path::new("/var/log/process_usage.csv");
+        assert_eq!(path_for(base, 0), base);
+        assert_eq!(
+            path_for(base, 1),
+            PathBuf::from("/var/log/process_usage.1.csv")
+        );
+        assert_eq!(
+            path_for(Path::new("capture"), 12),
+            PathBuf::from("capture.12")
+        );
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("500000"), Ok(500_000));
+        assert_eq!(parse_size("64K"), Ok(64 * 1024));
+        assert_eq!(parse_size("100mb"), Ok(100 * 1024 * 1024));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("10T").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn test_prunes_beyond_max_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let base = dir.path().join("capture.csv");
+        let mut rotation = Rotation::new(
+            &base,
+            Policy {
+                max_bytes: Some(1),
+                every: None,
+                max_files: Some(2),
+            },
+        );
+        fs::write(rotation.current(), "x").unwrap();
+        assert!(!rotation.is_due(), "Rotating a file without ticks");
+        rotation.record_tick();
+        assert!(rotation.is_due());
+        for _ in 0..3 {
+            let path = rotation.advance();
+            fs::write(&path, "x").unwrap();
+            rotation.prune().unwrap();
+        }
+        let mut names: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["capture.2.csv", "capture.3.csv"]);
+    }
+}
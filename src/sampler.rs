@@ -0,0 +1,612 @@
+//! Sampling the process table from code instead of the command line.
+//!
+//! A [`Sampler`] is configured with a [`SamplerConfig`], much like the
+//! `--pid`, `--name`, `--exact`, `--top` and `--normalize-cpu` options, and
+//! returns each tick as a list of [`ProcessRecord`]s. Records can be written
+//! out with a [`RecordSink`] such as [`CsvSink`] or [`JsonlSink`], in the
+//! same layout as the command line writes them. [`LoopConfig`] runs the
+//! sampling loop itself, from any [`RecordSource`] into a sink, paced like
+//! `--interval` and `--duration`.
+
+use crate::{
+    csv_header, filter, jsonl, numfmt::MemoryUnit, schedule::Schedule, source,
+    timestamp::TimestampFormat, write_csv_rows, CsvLayout, OutputFormat, ProcessLogger,
+    ProcessSample, RowBuffers, SortKey,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use std::{
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use sysinfo::{Pid, PidExt};
+
+/// How often a loop waiting for its next tick checks whether to stop.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The usage of one process at one tick.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessRecord {
+    /// When the process table was read.
+    pub timestamp: DateTime<Local>,
+    pub pid: u32,
+    pub name: String,
+    /// CPU usage in percent of one CPU, or of all of them with
+    /// [`SamplerConfig::normalize_cpu`].
+    pub cpu_percent: f32,
+    /// Resident memory in percent of the physical memory.
+    pub memory_percent: f64,
+}
+
+impl ProcessRecord {
+    fn to_sample(&self) -> ProcessSample {
+        ProcessSample {
+            pid: Some(Pid::from_u32(self.pid)),
+            name: self.name.clone(),
+            cpu_usage: self.cpu_percent,
+            memory_usage: self.memory_percent,
+            extras: Vec::new(),
+        }
+    }
+}
+
+/// Which processes a [`Sampler`] reports and how.
+///
+/// ```
+/// use chronologger::{SamplerConfig, SortKey};
+///
+/// // The three busiest processes whose name contains "postgres".
+/// let config = SamplerConfig::new()
+///     .name("postgres")
+///     .top(3, SortKey::Cpu);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SamplerConfig {
+    pids: Vec<u32>,
+    names: Vec<String>,
+    exact: bool,
+    top: Option<(usize, SortKey)>,
+    normalize_cpu: bool,
+}
+
+impl SamplerConfig {
+    /// Reports every process.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reports the process `pid`, like `--pid`. With names or other PIDs,
+    /// a process matching any of them is reported.
+    pub fn pid(mut self, pid: u32) -> Self {
+        self.pids.push(pid);
+        self
+    }
+
+    /// Reports processes whose name contains `name`, or matches it as a
+    /// glob or `re:` regex, like `--name`.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.names.push(name.into());
+        self
+    }
+
+    /// Matches names exactly instead of as substrings, like `--exact`.
+    pub fn exact(mut self, exact: bool) -> Self {
+        self.exact = exact;
+        self
+    }
+
+    /// Reports only the `count` processes with the highest `key` on each
+    /// tick, after any filter, like `--top` and `--sort-by`.
+    pub fn top(mut self, count: usize, key: SortKey) -> Self {
+        self.top = Some((count, key));
+        self
+    }
+
+    /// Reports CPU usage as a share of all CPUs rather than of one, like
+    /// `--normalize-cpu`.
+    pub fn normalize_cpu(mut self, normalize: bool) -> Self {
+        self.normalize_cpu = normalize;
+        self
+    }
+
+    /// Creates a sampler of the local system with this configuration.
+    pub fn build(self) -> Result<Sampler> {
+        Sampler::new(self)
+    }
+}
+
+/// Reads the process table on demand.
+///
+/// ```no_run
+/// use chronologger::{Sampler, SamplerConfig};
+///
+/// let mut sampler = Sampler::new(SamplerConfig::new())?;
+/// for record in sampler.sample()? {
+///     println!("{} {} {:.1}%", record.pid, record.name, record.cpu_percent);
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct Sampler {
+    logger: ProcessLogger,
+}
+
+impl Sampler {
+    /// Creates a sampler of the local system. This takes a fraction of a
+    /// second, so that the first sample measures CPU usage against a recent
+    /// reading instead of reporting 0 for every process.
+    pub fn new(config: SamplerConfig) -> Result<Self> {
+        let mut sampler = Self::with_source(config, Box::new(source::SysinfoSource::new()))?;
+        sampler.logger.prime();
+        Ok(sampler)
+    }
+
+    fn with_source(config: SamplerConfig, source: Box<dyn source::SystemSource>) -> Result<Self> {
+        if config.top.is_some_and(|(count, _)| count == 0) {
+            anyhow::bail!("The number of processes to keep must be greater than 0");
+        }
+        let mut logger = ProcessLogger::discarding("", OutputFormat::Csv, source);
+        if !config.pids.is_empty() || !config.names.is_empty() {
+            logger.set_filter(filter::ProcessFilter::new(
+                config.pids.into_iter().map(Pid::from_u32).collect(),
+                config.names,
+                config.exact,
+            )?);
+        }
+        if let Some((count, key)) = config.top {
+            logger.set_top(count, key);
+        }
+        if config.normalize_cpu {
+            logger.set_normalize_cpu();
+        }
+        Ok(Self { logger })
+    }
+
+    /// Reads the process table and returns a record for every process the
+    /// configuration selects, all with the same timestamp. CPU usage is
+    /// measured since the previous call.
+    pub fn sample(&mut self) -> Result<Vec<ProcessRecord>> {
+        let logger = &mut self.logger;
+        logger.source.refresh();
+        logger.skipped_processes += logger.source.take_read_errors();
+        let timestamp = Local::now();
+        logger.collect_samples();
+        logger.keep_top();
+        Ok(logger
+            .samples
+            .iter()
+            .map(|sample| ProcessRecord {
+                timestamp,
+                pid: sample
+                    .pid
+                    .expect("The sampler does not group processes")
+                    .as_u32(),
+                name: sample.name.clone(),
+                cpu_percent: sample.cpu_usage,
+                memory_percent: sample.memory_usage,
+            })
+            .collect())
+    }
+
+    /// How many processes could not be read, e.g. because they exited
+    /// while the table was read, over all samples so far.
+    pub fn skipped_processes(&self) -> u64 {
+        self.logger.skipped_processes
+    }
+}
+
+/// Something that produces the records of one tick at a time, such as a
+/// [`Sampler`]. Implement it to drive [`LoopConfig::run`] with records from
+/// elsewhere, e.g. in a harness's own tests.
+pub trait RecordSource {
+    /// Returns the records of one tick.
+    fn sample(&mut self) -> Result<Vec<ProcessRecord>>;
+}
+
+impl RecordSource for Sampler {
+    fn sample(&mut self) -> Result<Vec<ProcessRecord>> {
+        Sampler::sample(self)
+    }
+}
+
+/// How [`LoopConfig::run`] paces a sampling loop and when it ends, like
+/// `--interval`, `--duration` and Ctrl-C do for the command line.
+///
+/// ```no_run
+/// use chronologger::{JsonlSink, LoopConfig, SamplerConfig};
+/// use std::time::Duration;
+///
+/// let mut sampler = SamplerConfig::new().name("postgres").build()?;
+/// let mut sink = JsonlSink::new(std::fs::File::create("postgres.jsonl")?);
+/// let ticks = LoopConfig::new(Duration::from_millis(500))
+///     .duration(Duration::from_secs(60))
+///     .run(&mut sampler, &mut sink)?;
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct LoopConfig {
+    interval: Duration,
+    duration: Option<Duration>,
+    ticks: Option<u64>,
+    stop: Option<Arc<AtomicBool>>,
+}
+
+impl LoopConfig {
+    /// Samples every `interval`, from the start, until stopped.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            duration: None,
+            ticks: None,
+            stop: None,
+        }
+    }
+
+    /// Takes only the ticks due before `duration` has passed, like
+    /// `--duration`.
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Ends after `count` ticks.
+    pub fn ticks(mut self, count: u64) -> Self {
+        self.ticks = Some(count);
+        self
+    }
+
+    /// Ends the loop once `stop` is set, between ticks, e.g. from another
+    /// thread when the benchmark being measured is done.
+    pub fn until(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Samples `source` into `sink` until the loop ends, then flushes the
+    /// sink and returns how many ticks were taken. Ticks are due at
+    /// multiples of the interval from the start, so they do not drift; one
+    /// that a slow tick overran is skipped rather than taken late.
+    pub fn run(&self, source: &mut dyn RecordSource, sink: &mut dyn RecordSink) -> Result<u64> {
+        if self.interval.is_zero() {
+            anyhow::bail!("The interval must be greater than 0");
+        }
+        let stopped = || {
+            self.stop
+                .as_ref()
+                .is_some_and(|stop| stop.load(Ordering::SeqCst))
+        };
+        let mut schedule = Schedule::new(self.interval, None);
+        let start = Instant::now();
+        let mut ticks = 0;
+        loop {
+            if self.ticks.is_some_and(|limit| ticks >= limit) {
+                break;
+            }
+            let (due, _) = schedule.next(start.elapsed());
+            if self.duration.is_some_and(|duration| due >= duration) {
+                break;
+            }
+            while !stopped() {
+                let Some(wait) = due.checked_sub(start.elapsed()) else {
+                    break;
+                };
+                thread::sleep(wait.min(STOP_POLL_INTERVAL));
+            }
+            if stopped() {
+                break;
+            }
+            sink.write(&source.sample()?)?;
+            ticks += 1;
+        }
+        sink.flush()?;
+        Ok(ticks)
+    }
+}
+
+/// Somewhere to write [`ProcessRecord`]s to.
+pub trait RecordSink {
+    /// Writes the records of one or more ticks, in order.
+    fn write(&mut self, records: &[ProcessRecord]) -> Result<()>;
+
+    /// Writes out anything still buffered.
+    fn flush(&mut self) -> Result<()>;
+}
+
+/// Writes records as CSV with the command line's default columns, header
+/// included.
+///
+/// ```
+/// use chronologger::{CsvSink, ProcessRecord, RecordSink};
+///
+/// let mut sink = CsvSink::new(Vec::new());
+/// sink.write(&[ProcessRecord {
+///     timestamp: chrono::Local::now(),
+///     pid: 42,
+///     name: "postgres".to_string(),
+///     cpu_percent: 12.5,
+///     memory_percent: 3.0,
+/// }])?;
+/// let csv = String::from_utf8(sink.into_inner()?)?;
+/// assert!(csv.starts_with("Timestamp,PID,Process Name,CPU Usage (%),Memory Usage (%)\n"));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct CsvSink<W: Write> {
+    writer: csv::Writer<W>,
+    buffers: RowBuffers,
+    header_written: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            buffers: RowBuffers::default(),
+            header_written: false,
+        }
+    }
+
+    /// Flushes the sink and returns the writer.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.flush()?;
+        self.writer
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .context("Failed to flush writer!")
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        if !self.header_written {
+            self.writer
+                .write_record(csv_header(MemoryUnit::Percent, &[], false, false, None))
+                .context("Failed to write header!")?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> RecordSink for CsvSink<W> {
+    fn write(&mut self, records: &[ProcessRecord]) -> Result<()> {
+        self.write_header()?;
+        for tick in records.chunk_by(|a, b| a.timestamp == b.timestamp) {
+            let samples: Vec<ProcessSample> = tick.iter().map(ProcessRecord::to_sample).collect();
+            self.buffers.timestamp.clear();
+            TimestampFormat::default().write(&mut self.buffers.timestamp, &tick[0].timestamp);
+            write_csv_rows(
+                &mut self.writer,
+                &mut self.buffers,
+                &samples,
+                &Default::default(),
+                CsvLayout::default(),
+                None,
+                None,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.write_header()?;
+        self.writer.flush().context("Failed to flush writer!")
+    }
+}
+
+/// Writes records as JSON Lines, one object per record as `--format jsonl`
+/// writes them.
+///
+/// ```
+/// use chronologger::{JsonlSink, ProcessRecord, RecordSink};
+///
+/// let mut sink = JsonlSink::new(Vec::new());
+/// sink.write(&[ProcessRecord {
+///     timestamp: chrono::Local::now(),
+///     pid: 42,
+///     name: "postgres".to_string(),
+///     cpu_percent: 12.5,
+///     memory_percent: 3.0,
+/// }])?;
+/// let line: serde_json::Value = serde_json::from_slice(&sink.into_inner()?)?;
+/// assert_eq!(line["pid"], 42);
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct JsonlSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonlSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Flushes the sink and returns the writer.
+    pub fn into_inner(mut self) -> Result<W> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> RecordSink for JsonlSink<W> {
+    fn write(&mut self, records: &[ProcessRecord]) -> Result<()> {
+        for record in records {
+            let timestamp = record.timestamp.to_rfc3339().into();
+            let value = jsonl::record(
+                &[],
+                &Default::default(),
+                &timestamp,
+                &record.to_sample(),
+                None,
+                None,
+            );
+            serde_json::to_writer(&mut self.writer, &value).context("Failed to write record!")?;
+            self.writer
+                .write_all(b"\n")
+                .context("Failed to write record!")?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush().context("Failed to flush writer!")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::testing::FakeSource;
+    use chrono::TimeZone;
+
+    fn fake_sampler(config: SamplerConfig) -> Sampler {
+        Sampler::with_source(config, Box::new(FakeSource::new(5))).unwrap()
+    }
+
+    fn record(seconds: i64, pid: u32, name: &str) -> ProcessRecord {
+        ProcessRecord {
+            timestamp: Local.timestamp_opt(1_767_225_600 + seconds, 0).unwrap(),
+            pid,
+            name: name.to_string(),
+            cpu_percent: 12.345,
+            memory_percent: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_sample_returns_every_process() {
+        let mut sampler = fake_sampler(SamplerConfig::new());
+        let records = sampler.sample().unwrap();
+        let pids: Vec<u32> = records.iter().map(|record| record.pid).collect();
+        assert_eq!(pids, [1, 2, 3, 4, 5]);
+        assert_eq!(records[1].name, "worker-2");
+        assert!(records
+            .iter()
+            .all(|record| record.timestamp == records[0].timestamp));
+        // The fake's CPU usage is (pid + ticks) % 100 / 3.
+        assert_eq!(records[1].cpu_percent, 1.0);
+    }
+
+    #[test]
+    fn test_sample_filters_and_ranks() {
+        let mut sampler = fake_sampler(
+            SamplerConfig::new()
+                .pid(1)
+                .name("worker-3")
+                .name("worker-4")
+                .exact(true)
+                .top(2, SortKey::Cpu),
+        );
+        let pids: Vec<u32> = sampler
+            .sample()
+            .unwrap()
+            .iter()
+            .map(|record| record.pid)
+            .collect();
+        assert_eq!(pids, [4, 3]);
+    }
+
+    #[test]
+    fn test_config_rejects_empty_top() {
+        let config = SamplerConfig::new().top(0, SortKey::Memory);
+        assert!(Sampler::with_source(config, Box::new(FakeSource::new(1))).is_err());
+    }
+
+    #[test]
+    fn test_csv_sink_matches_cli_rows() {
+        let mut sink = CsvSink::new(Vec::new());
+        sink.write(&[record(0, 1, "init"), record(0, 2, "a, b")])
+            .unwrap();
+        sink.write(&[record(5, 1, "init")]).unwrap();
+        let csv = String::from_utf8(sink.into_inner().unwrap()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "Timestamp,PID,Process Name,CPU Usage (%),Memory Usage (%)"
+        );
+        let first = Local.timestamp_opt(1_767_225_600, 0).unwrap().to_rfc3339();
+        assert_eq!(lines[2], format!("{},2,\"a, b\",12.35,0.50", first));
+        assert!(lines[3].ends_with(",1,init,12.35,0.50"));
+    }
+
+    #[test]
+    fn test_csv_sink_writes_header_without_records() {
+        let csv = CsvSink::new(Vec::new()).into_inner().unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "Timestamp,PID,Process Name,CPU Usage (%),Memory Usage (%)\n"
+        );
+    }
+
+    #[test]
+    fn test_jsonl_sink_writes_a_line_per_record() {
+        let mut sink = JsonlSink::new(Vec::new());
+        sink.write(&[record(0, 1, "init"), record(0, 2, "worker")])
+            .unwrap();
+        let output = sink.into_inner().unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["pid"], 2);
+        assert_eq!(lines[1]["name"], "worker");
+        assert_eq!(lines[1]["cpu_percent"], 12.35);
+    }
+
+    #[test]
+    fn test_loop_ends_after_ticks_or_duration() {
+        let mut sampler = fake_sampler(SamplerConfig::new().top(1, SortKey::Cpu));
+        let mut sink = JsonlSink::new(Vec::new());
+        let ticks = LoopConfig::new(Duration::from_millis(10))
+            .ticks(3)
+            .run(&mut sampler, &mut sink)
+            .unwrap();
+        assert_eq!(ticks, 3);
+        assert_eq!(
+            sink.into_inner().unwrap().split(|b| *b == b'\n').count(),
+            3 + 1
+        );
+
+        let started = Instant::now();
+        let ticks = LoopConfig::new(Duration::from_millis(40))
+            .duration(Duration::from_millis(100))
+            .run(&mut sampler, &mut JsonlSink::new(std::io::sink()))
+            .unwrap();
+        // Due at 0, 40 and 80 ms.
+        assert_eq!(ticks, 3);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_loop_stops_when_asked() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopper = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                stop.store(true, Ordering::SeqCst);
+            })
+        };
+        let started = Instant::now();
+        let ticks = LoopConfig::new(Duration::from_secs(3600))
+            .until(stop)
+            .run(
+                &mut fake_sampler(SamplerConfig::new()),
+                &mut JsonlSink::new(std::io::sink()),
+            )
+            .unwrap();
+        stopper.join().unwrap();
+        assert_eq!(ticks, 1);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(LoopConfig::new(Duration::ZERO)
+            .run(
+                &mut fake_sampler(SamplerConfig::new()),
+                &mut JsonlSink::new(std::io::sink())
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,256 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tier {
+    /// The whole process table.
+    Full,
+    /// Only the focused processes.
+    Focus,
+}
+
+/// Interleaves the full-table and focus cadences. Deadlines are multiples of
+/// each interval from the start, so neither cadence drifts however long a
+/// tick takes; a late tick runs immediately but missed ones are not replayed.
+pub struct Schedule {
+    full: Duration,
+    focus: Option<Duration>,
+    /// The full-table interval the run was started with, which
+    /// `speed_up` does not go below.
+    configured: Duration,
+    next_full: Duration,
+    next_focus: Duration,
+    /// Full-table deadlines dropped since the last `take_missed`.
+    missed: u64,
+}
+
+impl Schedule {
+    pub fn new(full: Duration, focus: Option<Duration>) -> Self {
+        Self {
+            full,
+            focus,
+            configured: full,
+            next_full: Duration::ZERO,
+            next_focus: Duration::ZERO,
+            missed: 0,
+        }
+    }
+
+    /// Returns how many full-table deadlines passed while an earlier tick
+    /// was still running since the last call, and resets the count.
+    pub fn take_missed(&mut self) -> u64 {
+        std::mem::take(&mut self.missed)
+    }
+
+    /// Doubles both intervals, returning the full-table one. The pending
+    /// deadlines move back by the old intervals, so the next ticks come the
+    /// new intervals after the last ones.
+    pub fn slow_down(&mut self) -> Duration {
+        self.next_full += self.full;
+        self.full *= 2;
+        if let Some(focus) = &mut self.focus {
+            self.next_focus += *focus;
+            *focus *= 2;
+        }
+        self.full
+    }
+
+    /// Halves both intervals, returning the full-table one, unless they are
+    /// back to the ones the run was started with. The pending deadlines move
+    /// forward by the intervals' new lengths, so the next ticks come the new
+    /// intervals after the last ones.
+    pub fn speed_up(&mut self) -> Option<Duration> {
+        if self.full <= self.configured {
+            return None;
+        }
+        self.full /= 2;
+        self.next_full -= self.full;
+        if let Some(focus) = &mut self.focus {
+            *focus /= 2;
+            self.next_focus -= *focus;
+        }
+        Some(self.full)
+    }
+
+    /// Returns the offset from the start at which the next tick is due and
+    /// which tier it samples. When both tiers are due at once only the full
+    /// table is sampled, since it covers the focused processes too.
+    pub fn next(&mut self, elapsed: Duration) -> (Duration, Tier) {
+        let (next_full, missed) = catch_up(self.next_full, self.full, elapsed);
+        self.next_full = next_full;
+        self.missed += missed;
+        let Some(focus) = self.focus else {
+            let due = self.next_full;
+            self.next_full += self.full;
+            return (due, Tier::Full);
+        };
+        self.next_focus = catch_up(self.next_focus, focus, elapsed).0;
+
+        if self.next_full <= self.next_focus {
+            let due = self.next_full;
+            self.next_full += self.full;
+            if self.next_focus == due {
+                self.next_focus += focus;
+            }
+            (due, Tier::Full)
+        } else {
+            let due = self.next_focus;
+            self.next_focus += focus;
+            (due, Tier::Focus)
+        }
+    }
+}
+
+/// Moves `deadline` forward past every interval that has fully elapsed, so a
+/// late tick still runs once but ticks it overran entirely are dropped.
+/// Also returns how many were dropped.
+fn catch_up(deadline: Duration, interval: Duration, elapsed: Duration) -> (Duration, u64) {
+    if deadline >= elapsed || interval.is_zero() {
+        return (deadline, 0);
+    }
+    let step = interval.as_nanos();
+    let skipped = (elapsed - deadline).as_nanos() / step;
+    (
+        deadline + Duration::from_nanos((skipped * step) as u64),
+        skipped as u64,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs the schedule against a simulated clock for `total`, charging
+    /// `cost` for every tick, and counts the ticks of each tier.
+    fn simulate(schedule: &mut Schedule, total: Duration, cost: Duration) -> (u32, u32) {
+        let (mut clock, mut full, mut focus) = (Duration::ZERO, 0, 0);
+        loop {
+            let (due, tier) = schedule.next(clock);
+            assert!(
+                clock.saturating_sub(due) < Duration::from_millis(100),
+                "Only the latest missed tick may run late"
+            );
+            if due >= total {
+                return (full, focus);
+            }
+            match tier {
+                Tier::Full => full += 1,
+                Tier::Focus => focus += 1,
+            }
+            clock = due.max(clock) + cost;
+        }
+    }
+
+    #[test]
+    fn test_interleaves_over_a_minute() {
+        let mut schedule = Schedule::new(Duration::from_secs(10), Some(Duration::from_millis(100)));
+        let (full, focus) = simulate(&mut schedule, Duration::from_secs(60), Duration::ZERO);
+        assert_eq!(full, 6);
+        // 600 focus deadlines, minus the 6 that coincide with a full tick.
+        assert_eq!(focus, 594);
+    }
+
+    #[test]
+    fn test_does_not_drift_with_tick_cost() {
+        let mut schedule = Schedule::new(Duration::from_secs(10), Some(Duration::from_millis(100)));
+        let (full, focus) = simulate(
+            &mut schedule,
+            Duration::from_secs(60),
+            Duration::from_millis(7),
+        );
+        assert_eq!((full, focus), (6, 594));
+    }
+
+    #[test]
+    fn test_skips_missed_deadlines() {
+        let mut schedule = Schedule::new(Duration::from_secs(1), Some(Duration::from_millis(100)));
+        assert_eq!(schedule.next(Duration::ZERO), (Duration::ZERO, Tier::Full));
+        // A full tick that took 350ms drops the focus ticks it overran and
+        // runs the latest one late.
+        assert_eq!(
+            schedule.next(Duration::from_millis(350)),
+            (Duration::from_millis(300), Tier::Focus)
+        );
+        assert_eq!(
+            schedule.next(Duration::from_millis(360)),
+            (Duration::from_millis(400), Tier::Focus)
+        );
+    }
+
+    #[test]
+    fn test_counts_missed_full_ticks() {
+        let mut schedule = Schedule::new(Duration::from_secs(1), None);
+        assert_eq!(schedule.next(Duration::ZERO).0, Duration::ZERO);
+        assert_eq!(
+            schedule.next(Duration::from_millis(500)).0,
+            Duration::from_secs(1)
+        );
+        assert_eq!(schedule.take_missed(), 0);
+        // The tick due at 1s took 2.5s: the deadline at 2s is dropped and
+        // the one at 3s runs late.
+        assert_eq!(
+            schedule.next(Duration::from_millis(3500)).0,
+            Duration::from_secs(3)
+        );
+        assert_eq!(schedule.take_missed(), 1);
+        assert_eq!(schedule.take_missed(), 0);
+    }
+
+    #[test]
+    fn test_slow_down() {
+        let mut schedule = Schedule::new(Duration::from_secs(1), Some(Duration::from_millis(100)));
+        assert_eq!(schedule.next(Duration::ZERO), (Duration::ZERO, Tier::Full));
+        assert_eq!(schedule.slow_down(), Duration::from_secs(2));
+        assert_eq!(
+            schedule.next(Duration::from_millis(10)),
+            (Duration::from_millis(200), Tier::Focus)
+        );
+        assert_eq!(
+            schedule.next(Duration::from_millis(210)),
+            (Duration::from_millis(400), Tier::Focus)
+        );
+        let (full, focus) = simulate(&mut schedule, Duration::from_secs(10), Duration::ZERO);
+        // Full ticks at 2s, 4s, 6s and 8s, focus ticks every 200ms between.
+        assert_eq!((full, focus), (4, 43));
+    }
+
+    #[test]
+    fn test_speed_up() {
+        let mut schedule = Schedule::new(Duration::from_secs(1), Some(Duration::from_millis(100)));
+        assert_eq!(schedule.speed_up(), None);
+        assert_eq!(schedule.next(Duration::ZERO), (Duration::ZERO, Tier::Full));
+        assert_eq!(schedule.slow_down(), Duration::from_secs(2));
+        assert_eq!(schedule.slow_down(), Duration::from_secs(4));
+        assert_eq!(
+            schedule.next(Duration::from_millis(10)),
+            (Duration::from_millis(400), Tier::Focus)
+        );
+
+        assert_eq!(schedule.speed_up(), Some(Duration::from_secs(2)));
+        // The next focus tick comes 200ms after the last one.
+        assert_eq!(
+            schedule.next(Duration::from_millis(410)),
+            (Duration::from_millis(600), Tier::Focus)
+        );
+        assert_eq!(schedule.speed_up(), Some(Duration::from_secs(1)));
+        // Back to the configured intervals, which it does not go below.
+        assert_eq!(schedule.speed_up(), None);
+        assert_eq!(
+            schedule.next(Duration::from_millis(610)),
+            (Duration::from_millis(700), Tier::Focus)
+        );
+        let (full, focus) = simulate(&mut schedule, Duration::from_secs(3), Duration::ZERO);
+        // Full ticks at 1s and 2s, focus ticks every 100ms from 800ms.
+        assert_eq!((full, focus), (2, 20));
+    }
+
+    #[test]
+    fn test_full_only() {
+        let mut schedule = Schedule::new(Duration::from_secs(1), None);
+        let (full, focus) = simulate(
+            &mut schedule,
+            Duration::from_secs(60),
+            Duration::from_millis(300),
+        );
+        assert_eq!((full, focus), (60, 0));
+    }
+}
@@ -0,0 +1,294 @@
+use crate::{columns::Extra, metadata::SCHEMA_VERSION, numfmt::MemoryUnit};
+use serde_json::{json, Value};
+use std::fmt::Write as _;
+
+/// Version of the `--print-schema json` document itself, bumped whenever
+/// its layout changes. Which columns it lists is versioned by
+/// [`SCHEMA_VERSION`].
+const DOCUMENT_VERSION: u32 = 1;
+
+/// Table name used by `--print-schema sql`, after the default output file.
+const SQL_TABLE: &str = "process_usage";
+
+/// Digits after the decimal point of the percentage columns.
+const PERCENT_SCALE: u32 = crate::numfmt::PERCENT_PRECISION as u32;
+
+/// How `--print-schema` writes the schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SchemaFormat {
+    /// A versioned JSON document listing the columns.
+    Json,
+    /// A PostgreSQL CREATE TABLE statement.
+    Sql,
+    /// An Apache Arrow schema in Arrow's JSON integration format.
+    Arrow,
+}
+
+/// What the values of a column are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// RFC 3339 with the local offset and nanoseconds.
+    Timestamp,
+    /// Unsigned 32-bit process ID.
+    Pid,
+    Text,
+    /// Fixed-point percentage with [`PERCENT_SCALE`] decimals.
+    Percent,
+    /// Unsigned 64-bit count.
+    Count,
+    /// Signed 64-bit integer.
+    Signed,
+    Uuid,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Timestamp => "timestamp",
+            Kind::Pid => "uint32",
+            Kind::Text => "string",
+            Kind::Percent => "decimal",
+            Kind::Count => "uint64",
+            Kind::Signed => "int64",
+            Kind::Uuid => "uuid",
+        }
+    }
+
+    fn sql(self) -> String {
+        match self {
+            Kind::Timestamp => "TIMESTAMPTZ".to_string(),
+            // PIDs stay far below 2^31 on every supported platform.
+            Kind::Pid => "INTEGER".to_string(),
+            Kind::Text => "TEXT".to_string(),
+            Kind::Percent => format!("NUMERIC(12, {})", PERCENT_SCALE),
+            Kind::Count | Kind::Signed => "BIGINT".to_string(),
+            Kind::Uuid => "UUID".to_string(),
+        }
+    }
+
+    fn arrow(self) -> Value {
+        match self {
+            Kind::Timestamp => {
+                json!({ "name": "timestamp", "unit": "NANOSECOND", "timezone": "UTC" })
+            }
+            Kind::Pid => json!({ "name": "int", "bitWidth": 32, "isSigned": false }),
+            Kind::Text | Kind::Uuid => json!({ "name": "utf8" }),
+            Kind::Percent => {
+                json!({ "name": "decimal", "precision": 12, "scale": PERCENT_SCALE, "bitWidth": 128 })
+            }
+            Kind::Count => json!({ "name": "int", "bitWidth": 64, "isSigned": false }),
+            Kind::Signed => json!({ "name": "int", "bitWidth": 64, "isSigned": true }),
+        }
+    }
+}
+
+/// One column of the output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Column {
+    /// Identifier for tables built from the schema.
+    pub id: &'static str,
+    /// The CSV header.
+    pub header: &'static str,
+    pub kind: Kind,
+    pub unit: Option<&'static str>,
+    /// Whether a row may leave the column empty, as markers and processes
+    /// whose value cannot be read do.
+    pub nullable: bool,
+}
+
+impl Column {
+    const fn new(id: &'static str, header: &'static str, kind: Kind, nullable: bool) -> Self {
+        Self {
+            id,
+            header,
+            kind,
+            unit: None,
+            nullable,
+        }
+    }
+
+    const fn with_unit(self, unit: &'static str) -> Self {
+        Self {
+            unit: Some(unit),
+            ..self
+        }
+    }
+}
+
+/// The columns written by default.
+const DEFAULT_COLUMNS: [Column; 5] = [
+    Column::new("timestamp", "Timestamp", Kind::Timestamp, false),
+    Column::new("pid", "PID", Kind::Pid, true),
+    Column::new("process_name", "Process Name", Kind::Text, false),
+    Column::new("cpu_usage", "CPU Usage (%)", Kind::Percent, true).with_unit("percent"),
+    Column::new("memory_usage", "Memory Usage (%)", Kind::Percent, true).with_unit("percent"),
+];
+
+/// The columns of the output in the order they are written: the defaults,
+/// with memory in `memory`, `extras`, then, with two-tier sampling, the
+/// scope and the run ID if it is written.
+pub fn columns(
+    memory: MemoryUnit,
+    extras: &[Extra],
+    with_scope: bool,
+    with_run_id: bool,
+) -> Vec<Column> {
+    let mut columns = DEFAULT_COLUMNS.to_vec();
+    if memory != MemoryUnit::Percent {
+        let kind = match memory {
+            MemoryUnit::Bytes => Kind::Count,
+            _ => Kind::Percent,
+        };
+        columns[4] =
+            Column::new("memory_usage", memory.header(), kind, true).with_unit(memory.unit());
+    }
+    columns.extend(extras.iter().map(|&extra| Column {
+        unit: extra.unit(),
+        ..Column::new(extra.id(), extra.header(), extra.kind(), true)
+    }));
+    if with_scope {
+        columns.push(Column::new("scope", "Scope", Kind::Text, false));
+    }
+    if with_run_id {
+        columns.push(Column::new("run_id", "Run ID", Kind::Uuid, false));
+    }
+    columns
+}
+
+/// Writes `columns` as `format`.
+pub fn render(format: SchemaFormat, columns: &[Column]) -> String {
+    match format {
+        SchemaFormat::Json => {
+            let columns: Vec<Value> = columns
+                .iter()
+                .map(|column| {
+                    let mut value = json!({
+                        "id": column.id,
+                        "header": column.header,
+                        "type": column.kind.name(),
+                        "nullable": column.nullable,
+                    });
+                    if let Some(unit) = column.unit {
+                        value["unit"] = unit.into();
+                    }
+                    if column.kind == Kind::Percent {
+                        value["scale"] = PERCENT_SCALE.into();
+                    }
+                    value
+                })
+                .collect();
+            let document = json!({
+                "format": "chronologger-schema",
+                "version": DOCUMENT_VERSION,
+                "schema_version": SCHEMA_VERSION,
+                "columns": columns,
+            });
+            serde_json::to_string_pretty(&document).expect("Schema is valid JSON") + "\n"
+        }
+        SchemaFormat::Sql => {
+            let definitions: Vec<String> = columns
+                .iter()
+                .map(|column| {
+                    let constraint = if column.nullable { "" } else { " NOT NULL" };
+                    format!("    \"{}\" {}{}", column.id, column.kind.sql(), constraint)
+                })
+                .collect();
+            let mut sql = format!(
+                "CREATE TABLE {} (\n{}\n",
+                SQL_TABLE,
+                definitions.join(",\n")
+            );
+            sql.push_str(");\n");
+            for column in columns {
+                let _ = writeln!(
+                    sql,
+                    "COMMENT ON COLUMN {}.\"{}\" IS '{}';",
+                    SQL_TABLE,
+                    column.id,
+                    column.header.replace('\'', "''")
+                );
+            }
+            sql
+        }
+        SchemaFormat::Arrow => {
+            let fields: Vec<Value> = columns
+                .iter()
+                .map(|column| {
+                    json!({
+                        "name": column.id,
+                        "nullable": column.nullable,
+                        "type": column.kind.arrow(),
+                        "children": [],
+                        "metadata": [{ "key": "header", "value": column.header }],
+                    })
+                })
+                .collect();
+            let schema = json!({
+                "fields": fields,
+                "metadata": [{ "key": "chronologger.schema_version", "value": SCHEMA_VERSION.to_string() }],
+            });
+            serde_json::to_string_pretty(&schema).expect("Schema is valid JSON") + "\n"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_document() {
+        let columns = columns(MemoryUnit::Percent, &[Extra::Pss], true, true);
+        let document: Value = serde_json::from_str(&render(SchemaFormat::Json, &columns)).unwrap();
+        assert_eq!(document["version"], DOCUMENT_VERSION);
+        assert_eq!(document["schema_version"], SCHEMA_VERSION);
+        let ids: Vec<&str> = document["columns"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|column| column["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                "timestamp",
+                "pid",
+                "process_name",
+                "cpu_usage",
+                "memory_usage",
+                "pss_bytes",
+                "scope",
+                "run_id"
+            ]
+        );
+        assert_eq!(document["columns"][3]["scale"], 2);
+        assert_eq!(document["columns"][5]["unit"], "bytes");
+        assert_eq!(document["columns"][5]["nullable"], true);
+        assert_eq!(document["columns"][7]["type"], "uuid");
+    }
+
+    #[test]
+    fn test_sql_and_arrow() {
+        let columns = columns(MemoryUnit::Percent, &[], false, false);
+        let sql = render(SchemaFormat::Sql, &columns);
+        assert!(sql.starts_with("CREATE TABLE process_usage (\n"));
+        assert!(sql.contains("    \"timestamp\" TIMESTAMPTZ NOT NULL,\n"));
+        assert!(sql.contains("    \"memory_usage\" NUMERIC(12, 2)\n);\n"));
+        assert!(sql.contains("COMMENT ON COLUMN process_usage.\"pid\" IS 'PID';"));
+
+        let arrow: Value = serde_json::from_str(&render(SchemaFormat::Arrow, &columns)).unwrap();
+        assert_eq!(arrow["fields"].as_array().unwrap().len(), 5);
+        assert_eq!(arrow["fields"][1]["type"]["bitWidth"], 32);
+        assert_eq!(arrow["fields"][0]["nullable"], false);
+    }
+
+    #[test]
+    fn test_text_extra() {
+        let columns = columns(MemoryUnit::Percent, &[Extra::Container], false, false);
+        let sql = render(SchemaFormat::Sql, &columns);
+        assert!(sql.contains("    \"container\" TEXT\n"));
+        let document: Value = serde_json::from_str(&render(SchemaFormat::Json, &columns)).unwrap();
+        assert_eq!(document["columns"][5]["type"], "string");
+        assert!(document["columns"][5].get("unit").is_none());
+    }
+}
@@ -0,0 +1,382 @@
+use crate::{shutdown, Config};
+use anyhow::{Context, Result};
+use log::{error, info};
+use std::{
+    ffi::{c_void, OsStr, OsString},
+    iter,
+    os::windows::ffi::OsStrExt,
+    path::PathBuf,
+    ptr,
+    sync::{
+        atomic::{AtomicIsize, Ordering},
+        Mutex,
+    },
+};
+use windows_sys::Win32::{
+    Foundation::{ERROR_CALL_NOT_IMPLEMENTED, ERROR_SERVICE_SPECIFIC_ERROR, NO_ERROR},
+    System::Services::{
+        CloseServiceHandle, CreateServiceW, DeleteService, OpenSCManagerW, OpenServiceW,
+        RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW, SC_HANDLE,
+        SC_MANAGER_CONNECT, SC_MANAGER_CREATE_SERVICE, SERVICE_ACCEPT_SHUTDOWN,
+        SERVICE_ACCEPT_STOP, SERVICE_ALL_ACCESS, SERVICE_AUTO_START, SERVICE_CONTROL_INTERROGATE,
+        SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_ERROR_NORMAL, SERVICE_RUNNING,
+        SERVICE_START_PENDING, SERVICE_STATUS, SERVICE_STATUS_CURRENT_STATE, SERVICE_STOPPED,
+        SERVICE_STOP_PENDING, SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS,
+    },
+};
+
+const SERVICE_NAME: &str = "Chronologger";
+const DISPLAY_NAME: &str = "Chronologger process logger";
+
+/// The log file a service writes to unless `--log-file` is given, next to
+/// the executable.
+const LOG_FILE: &str = "chronologger.log";
+
+/// What `--service` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Action {
+    /// Registers chronologger as a service started at boot, with the rest of
+    /// the command line.
+    Install,
+    /// Removes the service.
+    Uninstall,
+    /// Runs as the service; only the service control manager does this.
+    Run,
+}
+
+/// The configuration `service_main` runs with.
+static CONFIG: Mutex<Option<Config>> = Mutex::new(None);
+
+/// Where service status updates go, once the control handler is registered.
+static STATUS_HANDLE: AtomicIsize = AtomicIsize::new(0);
+
+/// Registers the service to run this executable with `--service run` and
+/// the rest of the current command line.
+pub fn install() -> Result<()> {
+    let executable = std::env::current_exe().context("Failed to locate chronologger")?;
+    let command_line = command_line(executable.as_os_str(), std::env::args_os().skip(1));
+
+    let manager = open_manager(SC_MANAGER_CREATE_SERVICE)?;
+    let (name, display_name, command_line) = (
+        wide(OsStr::new(SERVICE_NAME)),
+        wide(OsStr::new(DISPLAY_NAME)),
+        wide(&command_line),
+    );
+    // SAFETY: every string is NUL-terminated UTF-16 and outlives the call;
+    // the optional arguments are null.
+    let service = unsafe {
+        CreateServiceW(
+            manager.0,
+            name.as_ptr(),
+            display_name.as_ptr(),
+            SERVICE_ALL_ACCESS,
+            SERVICE_WIN32_OWN_PROCESS,
+            SERVICE_AUTO_START,
+            SERVICE_ERROR_NORMAL,
+            command_line.as_ptr(),
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null(),
+            ptr::null(),
+            ptr::null(),
+        )
+    };
+    if service == 0 {
+        return Err(std::io::Error::last_os_error())
+            .context(format!("Failed to install service '{}'", SERVICE_NAME));
+    }
+    let _service = Handle(service);
+    info!(
+        "Installed service '{}', logging to {}",
+        SERVICE_NAME,
+        default_log_file()?.display()
+    );
+    Ok(())
+}
+
+/// Removes the service. A running service is removed once it stops.
+pub fn uninstall() -> Result<()> {
+    let manager = open_manager(SC_MANAGER_CONNECT)?;
+    let name = wide(OsStr::new(SERVICE_NAME));
+    // SAFETY: `name` is NUL-terminated UTF-16 and outlives the call.
+    let service = unsafe { OpenServiceW(manager.0, name.as_ptr(), SERVICE_ALL_ACCESS) };
+    if service == 0 {
+        return Err(std::io::Error::last_os_error())
+            .context(format!("Failed to open service '{}'", SERVICE_NAME));
+    }
+    let service = Handle(service);
+    // SAFETY: `service` is an open service handle.
+    if unsafe { DeleteService(service.0) } == 0 {
+        return Err(std::io::Error::last_os_error())
+            .context(format!("Failed to remove service '{}'", SERVICE_NAME));
+    }
+    info!("Removed service '{}'", SERVICE_NAME);
+    Ok(())
+}
+
+/// Hands the thread to the service control manager, which starts the run
+/// with `config` on a thread of its own and returns once it has stopped.
+/// Relative paths are resolved next to the executable rather than in the
+/// system directory services start in.
+pub fn run(config: Config) -> Result<()> {
+    if let Some(dir) = executable_dir()? {
+        std::env::set_current_dir(&dir)
+            .with_context(|| format!("Failed to change to {}", dir.display()))?;
+    }
+    *CONFIG.lock().unwrap() = Some(config);
+
+    let mut name = wide(OsStr::new(SERVICE_NAME));
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: name.as_mut_ptr(),
+            lpServiceProc: Some(service_main),
+        },
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: ptr::null_mut(),
+            lpServiceProc: None,
+        },
+    ];
+    // SAFETY: the table ends with a null entry and, like `name`, outlives
+    // the dispatcher, which only returns once the service has stopped.
+    if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to connect to the service control manager; use --service install");
+    }
+    Ok(())
+}
+
+/// The log file a service writes to by default.
+pub fn default_log_file() -> Result<PathBuf> {
+    Ok(executable_dir()?.unwrap_or_default().join(LOG_FILE))
+}
+
+fn executable_dir() -> Result<Option<PathBuf>> {
+    let executable = std::env::current_exe().context("Failed to locate chronologger")?;
+    Ok(executable.parent().map(PathBuf::from))
+}
+
+/// Runs the capture for the service control manager, reporting its state.
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut *mut u16) {
+    let name = wide(OsStr::new(SERVICE_NAME));
+    // SAFETY: `name` is NUL-terminated UTF-16, and the handler only touches
+    // statics, so no context is passed.
+    let handle =
+        unsafe { RegisterServiceCtrlHandlerExW(name.as_ptr(), Some(control_handler), ptr::null()) };
+    if handle == 0 {
+        error!(
+            "Failed to register the service control handler: {}",
+            std::io::Error::last_os_error()
+        );
+        return;
+    }
+    STATUS_HANDLE.store(handle, Ordering::SeqCst);
+    report(SERVICE_START_PENDING, NO_ERROR);
+
+    let Some(config) = CONFIG.lock().unwrap().take() else {
+        report(SERVICE_STOPPED, ERROR_SERVICE_SPECIFIC_ERROR);
+        return;
+    };
+    report(SERVICE_RUNNING, NO_ERROR);
+    let exit_code = match crate::run(config) {
+        Ok(()) => NO_ERROR,
+        Err(e) => {
+            error!("{:#}", e);
+            ERROR_SERVICE_SPECIFIC_ERROR
+        }
+    };
+    report(SERVICE_STOPPED, exit_code);
+}
+
+/// Runs on the dispatcher thread for every control the service receives.
+/// Stop and shutdown end the run the same way a termination signal does.
+unsafe extern "system" fn control_handler(
+    control: u32,
+    _event_type: u32,
+    _event_data: *mut c_void,
+    _context: *mut c_void,
+) -> u32 {
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            info!("Service is stopping...");
+            report(SERVICE_STOP_PENDING, NO_ERROR);
+            shutdown::request_stop();
+            NO_ERROR
+        }
+        SERVICE_CONTROL_INTERROGATE => NO_ERROR,
+        _ => ERROR_CALL_NOT_IMPLEMENTED,
+    }
+}
+
+/// Tells the service control manager the service's state. Does nothing
+/// before the control handler is registered.
+fn report(state: SERVICE_STATUS_CURRENT_STATE, exit_code: u32) {
+    let handle = STATUS_HANDLE.load(Ordering::SeqCst);
+    if handle == 0 {
+        return;
+    }
+    let status = SERVICE_STATUS {
+        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+        dwCurrentState: state,
+        dwControlsAccepted: if state == SERVICE_RUNNING {
+            SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN
+        } else {
+            0
+        },
+        dwWin32ExitCode: exit_code,
+        dwServiceSpecificExitCode: u32::from(exit_code != NO_ERROR),
+        dwCheckPoint: 0,
+        // Finishing the run flushes the output, which may take a moment.
+        dwWaitHint: if state == SERVICE_STOP_PENDING {
+            10_000
+        } else {
+            0
+        },
+    };
+    // SAFETY: `handle` came from RegisterServiceCtrlHandlerExW and `status`
+    // outlives the call.
+    unsafe { SetServiceStatus(handle, &status) };
+}
+
+/// Closes a service control manager or service handle when dropped.
+struct Handle(SC_HANDLE);
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        // SAFETY: the handle was opened by the service control manager and is
+        // closed once.
+        unsafe { CloseServiceHandle(self.0) };
+    }
+}
+
+fn open_manager(access: u32) -> Result<Handle> {
+    // SAFETY: null selects the local machine's active services database.
+    let manager = unsafe { OpenSCManagerW(ptr::null(), ptr::null(), access) };
+    if manager == 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to open the service control manager; run as administrator");
+    }
+    Ok(Handle(manager))
+}
+
+/// The arguments the service runs with: everything but `--service` itself.
+fn service_args(args: impl Iterator<Item = OsString>) -> Vec<OsString> {
+    let mut kept = Vec::new();
+    let mut skip_value = false;
+    for arg in args {
+        if skip_value {
+            skip_value = false;
+        } else if arg == "--service" {
+            skip_value = true;
+        } else if !arg.to_string_lossy().starts_with("--service=") {
+            kept.push(arg);
+        }
+    }
+    kept
+}
+
+/// The command line the service is started with. `--service run` comes
+/// right after the executable, since `--exec` and `run` take every argument
+/// after them for the command they start.
+fn command_line(executable: &OsStr, args: impl Iterator<Item = OsString>) -> OsString {
+    let mut command_line = quote(executable);
+    command_line.push(" --service run");
+    for arg in service_args(args) {
+        command_line.push(" ");
+        command_line.push(quote(&arg));
+    }
+    command_line
+}
+
+/// Quotes an argument for the stored command line the way the C runtime
+/// splits it again: backslashes are only special before a quote.
+fn quote(arg: &OsStr) -> OsString {
+    let text = arg.to_string_lossy();
+    if !text.is_empty() && !text.contains([' ', '\t', '"']) {
+        return arg.to_os_string();
+    }
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in text.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            // The backslashes before a quote are doubled, and the quote
+            // itself escaped.
+            '"' => {
+                quoted.extend((0..=backslashes).map(|_| '\\'));
+                backslashes = 0;
+            }
+            _ => backslashes = 0,
+        }
+        quoted.push(c);
+    }
+    // As are those before the closing quote.
+    quoted.extend((0..backslashes).map(|_| '\\'));
+    quoted.push('"');
+    OsString::from(quoted)
+}
+
+fn wide(value: &OsStr) -> Vec<u16> {
+    value.encode_wide().chain(iter::once(0)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stop_control_requests_shutdown() {
+        // SAFETY: the handler only touches statics.
+        unsafe {
+            assert_eq!(
+                control_handler(
+                    SERVICE_CONTROL_INTERROGATE,
+                    0,
+                    ptr::null_mut(),
+                    ptr::null_mut()
+                ),
+                NO_ERROR
+            );
+            assert!(!shutdown::stop_requested());
+            assert_eq!(
+                control_handler(SERVICE_CONTROL_STOP, 0, ptr::null_mut(), ptr::null_mut()),
+                NO_ERROR
+            );
+        }
+        assert!(
+            shutdown::stop_requested(),
+            "Stop control did not reach the run"
+        );
+    }
+
+    #[test]
+    fn test_service_command_line() {
+        let args = [
+            "--output",
+            "C:\\logs\\out.csv",
+            "--service",
+            "install",
+            "--interval",
+            "5",
+        ]
+        .map(OsString::from);
+        assert_eq!(
+            service_args(args.into_iter()),
+            ["--output", "C:\\logs\\out.csv", "--interval", "5"].map(OsString::from)
+        );
+        let exec = ["--service", "install", "--exec", "job.exe", "--nightly"].map(OsString::from);
+        assert_eq!(
+            command_line(
+                OsStr::new("C:\\Program Files\\chronologger.exe"),
+                exec.into_iter()
+            ),
+            "\"C:\\Program Files\\chronologger.exe\" --service run --exec job.exe --nightly"
+        );
+        assert_eq!(quote(OsStr::new("plain")), "plain");
+        assert_eq!(
+            quote(OsStr::new("C:\\Program Files\\out dir\\")),
+            "\"C:\\Program Files\\out dir\\\\\""
+        );
+        assert_eq!(quote(OsStr::new("say \"hi\"")), "\"say \\\"hi\\\"\"");
+        assert_eq!(quote(OsStr::new("")), "\"\"");
+    }
+}
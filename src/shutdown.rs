@@ -0,0 +1,202 @@
+use anyhow::Result;
+use log::{info, warn};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Set once the run has been finished and its output flushed.
+static FINISHED: AtomicBool = AtomicBool::new(false);
+
+/// Set by a stop request that may arrive before the handler is installed.
+#[cfg(all(windows, feature = "service"))]
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Clears `running` when the process is asked to stop, so the logging loop
+/// ends and the run is finished normally: on SIGINT or SIGTERM on Unix, and
+/// on a console control event on Windows.
+#[cfg(unix)]
+pub fn install(running: Arc<AtomicBool>) -> Result<()> {
+    use signal_hook::{
+        consts::{SIGINT, SIGTERM},
+        iterator::Signals,
+    };
+
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            info!("Received termination signal, stopping...");
+            running.store(false, Ordering::SeqCst);
+        }
+    });
+    Ok(())
+}
+
+/// Runs `flush` whenever the process gets SIGHUP, which would otherwise
+/// end it, so that a copy of the output taken mid-run holds every tick
+/// written so far.
+#[cfg(unix)]
+pub fn on_hangup(flush: impl Fn() -> Result<()> + Send + 'static) -> Result<()> {
+    use signal_hook::{consts::SIGHUP, iterator::Signals};
+
+    let mut signals = Signals::new([SIGHUP])?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            match flush() {
+                Ok(()) => info!("Received SIGHUP, output flushed"),
+                Err(e) => warn!("Failed to flush the output on SIGHUP: {:#}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(windows)]
+pub fn install(running: Arc<AtomicBool>) -> Result<()> {
+    use anyhow::{bail, Context};
+    use windows_sys::Win32::System::Console::SetConsoleCtrlHandler;
+
+    if windows::RUNNING.set(running.clone()).is_err() {
+        bail!("Shutdown handler is already installed");
+    }
+    #[cfg(feature = "service")]
+    if STOP_REQUESTED.load(Ordering::SeqCst) {
+        running.store(false, Ordering::SeqCst);
+    }
+    // SAFETY: the handler is a plain function that lives as long as the
+    // process and only touches statics.
+    if unsafe { SetConsoleCtrlHandler(Some(windows::ctrl_handler), 1) } == 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to install the console control handler");
+    }
+    Ok(())
+}
+
+/// Stops the run as a termination signal would, e.g. when the Windows
+/// service is asked to stop.
+#[cfg(all(windows, feature = "service"))]
+pub fn request_stop() {
+    STOP_REQUESTED.store(true, Ordering::SeqCst);
+    if let Some(running) = windows::RUNNING.get() {
+        running.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(all(windows, feature = "service", test))]
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Tells a pending console close on Windows that it no longer has to wait
+/// for the run to finish.
+pub fn finished() {
+    FINISHED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::*;
+    use std::{
+        sync::OnceLock,
+        thread,
+        time::{Duration, Instant},
+    };
+    use windows_sys::Win32::{
+        Foundation::BOOL,
+        System::Console::{
+            CTRL_BREAK_EVENT, CTRL_CLOSE_EVENT, CTRL_C_EVENT, CTRL_LOGOFF_EVENT,
+            CTRL_SHUTDOWN_EVENT,
+        },
+    };
+
+    pub static RUNNING: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+    /// How long a close, logoff or shutdown event holds the process open for
+    /// the run to finish. Windows ends the process after about 5 seconds.
+    const CLOSE_WAIT: Duration = Duration::from_millis(4500);
+
+    /// Runs on a thread of its own for every console control event.
+    pub unsafe extern "system" fn ctrl_handler(event: u32) -> BOOL {
+        let Some(running) = RUNNING.get() else {
+            return 0;
+        };
+        match event {
+            CTRL_C_EVENT | CTRL_BREAK_EVENT => {
+                info!("Received console interrupt, stopping...");
+                running.store(false, Ordering::SeqCst);
+                1
+            }
+            CTRL_CLOSE_EVENT | CTRL_LOGOFF_EVENT | CTRL_SHUTDOWN_EVENT => {
+                info!("Console is closing, stopping...");
+                running.store(false, Ordering::SeqCst);
+                // The process ends as soon as this returns, so the run gets
+                // as long as Windows allows to flush its output.
+                let started = Instant::now();
+                while !FINISHED.load(Ordering::SeqCst) && started.elapsed() < CLOSE_WAIT {
+                    thread::sleep(Duration::from_millis(10));
+                }
+                1
+            }
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    fn wait_for_stop(running: &AtomicBool) {
+        let started = Instant::now();
+        while running.load(Ordering::SeqCst) {
+            assert!(
+                started.elapsed() < Duration::from_secs(5),
+                "Termination request was not handled"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_termination_request_stops_run() {
+        let running = Arc::new(AtomicBool::new(true));
+        install(running.clone()).expect("Failed to install shutdown handler");
+
+        #[cfg(unix)]
+        // SAFETY: raising a signal that the handler installed above catches.
+        unsafe {
+            libc::raise(libc::SIGTERM);
+        }
+        #[cfg(windows)]
+        // SAFETY: the handler only touches statics.
+        unsafe {
+            windows::ctrl_handler(windows_sys::Win32::System::Console::CTRL_C_EVENT);
+        }
+
+        wait_for_stop(&running);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_hangup_flushes_and_keeps_running() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let sender = std::sync::Mutex::new(sender);
+        on_hangup(move || {
+            sender.lock().unwrap().send(()).unwrap();
+            Ok(())
+        })
+        .expect("Failed to install hangup handler");
+
+        // SAFETY: raising a signal that the handler installed above catches.
+        unsafe {
+            libc::raise(libc::SIGHUP);
+        }
+        receiver
+            .recv_timeout(Duration::from_secs(5))
+            .expect("SIGHUP did not flush");
+    }
+}
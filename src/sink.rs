@@ -0,0 +1,19 @@
+use crate::{alerts::Alert, ProcessSample};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+
+/// Receives every tick's samples in addition to the CSV output.
+pub trait Sink: Send {
+    fn publish(&mut self, timestamp: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()>;
+
+    /// Called for every alert raised, before the tick that raised it is
+    /// published.
+    fn alert(&mut self, _alert: &Alert) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once when the run ends, on every shutdown path.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use std::{fs, io, path::Path};
+
+/// Memory of one process as the kernel accounts it per mapping, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Footprint {
+    /// Proportional set size: each shared page is divided among the
+    /// processes that map it.
+    pub pss: u64,
+    /// Unique set size: pages mapped by this process alone.
+    pub uss: u64,
+}
+
+/// Reads the footprint of `pid` from `/proc/<pid>/smaps_rollup`, or by adding
+/// up `/proc/<pid>/smaps` on kernels older than 4.14 that lack the rollup.
+pub fn read(pid: u32) -> io::Result<Footprint> {
+    read_from(Path::new("/proc"), pid)
+}
+
+fn read_from(proc: &Path, pid: u32) -> io::Result<Footprint> {
+    let dir = proc.join(pid.to_string());
+    let text = match fs::read_to_string(dir.join("smaps_rollup")) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound && dir.is_dir() => {
+            fs::read_to_string(dir.join("smaps"))?
+        }
+        result => result?,
+    };
+    Ok(parse(&text))
+}
+
+/// Adds up the `Pss` and `Private_*` lines. The rollup has one of
+/// each, the full file one per mapping.
+fn parse(text: &str) -> Footprint {
+    let mut footprint = Footprint::default();
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let field = match key {
+            "Pss" => &mut footprint.pss,
+            "Private_Clean" | "Private_Dirty" => &mut footprint.uss,
+            _ => continue,
+        };
+        let kb = value
+            .trim()
+            .trim_end_matches("kB")
+            .trim_end()
+            .parse::<u64>()
+            .unwrap_or(0);
+        *field += kb * 1024;
+    }
+    footprint
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const ROLLUP: &str = "\
+55d0c0a4e000-7ffd5a5f2000 ---p 00000000 00:00 0                          [rollup]
+Rss:                4096 kB
+Pss:                1536 kB
+Pss_Anon:            512 kB
+Shared_Clean:       2560 kB
+Shared_Dirty:          0 kB
+Private_Clean:       512 kB
+Private_Dirty:       1024 kB
+Referenced:         4096 kB
+";
+
+    const SMAPS: &str = "\
+55d0c0a4e000-55d0c0a50000 r--p 00000000 08:01 1234                       /usr/bin/app
+Size:                  8 kB
+Rss:                   8 kB
+Pss:                   4 kB
+Private_Clean:         0 kB
+Private_Dirty:         4 kB
+VmFlags: rd mr mw me dw sd
+7ffd5a5d1000-7ffd5a5f2000 rw-p 00000000 00:00 0                          [stack]
+Size:                132 kB
+Rss:                  12 kB
+Pss:                  12 kB
+Private_Clean:         0 kB
+Private_Dirty:        12 kB
+VmFlags: rd wr mr mw me gd ac
+";
+
+    #[test]
+    fn test_parse_rollup() {
+        assert_eq!(
+            parse(ROLLUP),
+            Footprint {
+                pss: 1536 * 1024,
+                uss: 1536 * 1024,
+            }
+        );
+    }
+
+    #[test]
+    fn test_falls_back_to_smaps() {
+        let proc = TempDir::new().unwrap();
+        fs::create_dir(proc.path().join("7")).unwrap();
+        fs::write(proc.path().join("7/smaps"), SMAPS).unwrap();
+        let footprint = read_from(proc.path(), 7).unwrap();
+        assert_eq!(footprint.pss, 16 * 1024);
+        assert_eq!(footprint.uss, 16 * 1024);
+
+        assert!(read_from(proc.path(), 8).is_err(), "Missing process");
+    }
+
+    #[test]
+    fn test_own_pss_within_rss() {
+        let footprint = read(std::process::id()).expect("Failed to read own smaps");
+        let status = fs::read_to_string("/proc/self/status").unwrap();
+        let rss_kb: u64 = status
+            .lines()
+            .find_map(|line| line.strip_prefix("VmRSS:"))
+            .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+            .expect("VmRSS missing");
+        assert!(footprint.pss > 0);
+        assert!(footprint.pss <= rss_kb * 1024);
+        assert!(footprint.uss <= footprint.pss);
+    }
+}
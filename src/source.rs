@@ -0,0 +1,419 @@
+use log::warn;
+use std::collections::BTreeMap;
+use sysinfo::{
+    Pid, Process, ProcessExt, ProcessRefreshKind, ProcessStatus, ProcessorExt, System, SystemExt,
+};
+
+/// One process as reported by a [`SystemSource`].
+pub struct ProcessInfo<'a> {
+    pub pid: Pid,
+    pub name: &'a str,
+    pub cpu_usage: f32,
+    /// Resident memory in KB.
+    pub memory: u64,
+    /// When the process started, in source-specific units. Together with the
+    /// PID it identifies a process across PID reuse.
+    pub start_time: u64,
+    /// The CPU time the process has used since it started, user and system
+    /// together, in milliseconds, if the source reads it.
+    pub cpu_time: Option<u64>,
+    /// The rest of what the source knows about the process, for the
+    /// optional columns that come from the process table.
+    pub details: Option<ProcessDetails<'a>>,
+}
+
+/// What a source reports about a process beyond the default columns.
+pub struct ProcessDetails<'a> {
+    /// Resident and virtual memory in bytes.
+    pub resident_bytes: u64,
+    pub virtual_bytes: u64,
+    /// Bytes read from and written to disk since the process started.
+    /// Another user's counters need root.
+    pub disk_read: Result<u64, Denied>,
+    pub disk_written: Result<u64, Denied>,
+    pub status: ProcessStatus,
+    pub parent: Option<Pid>,
+    /// When the process started, in seconds since the Unix epoch.
+    pub started: u64,
+    /// The command line, one argument per element. Another user's needs
+    /// root outside Linux.
+    pub cmd: Result<&'a [String], Denied>,
+    /// The user ID of the owner, where the OS has one.
+    pub uid: Option<u32>,
+}
+
+/// A value the OS does not let this user read, as opposed to one that does
+/// not exist for the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Denied;
+
+/// Whether this user may read what the OS keeps private to the owner of a
+/// process, such as its I/O counters: as root, or as the owner.
+#[cfg(unix)]
+pub fn may_read_private(owner: u32) -> bool {
+    // SAFETY: geteuid always succeeds.
+    let euid = unsafe { libc::geteuid() };
+    euid == 0 || euid == owner
+}
+
+#[cfg(not(unix))]
+pub fn may_read_private(_owner: u32) -> bool {
+    true
+}
+
+/// Whole-system figures as reported by a [`SystemSource`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SystemStats {
+    /// Share of all CPUs in use, from 0 to 100.
+    pub cpu_usage: f32,
+    /// Share of each logical CPU in use, from 0 to 100.
+    pub core_usage: Vec<f32>,
+    /// Memory and swap in KB.
+    pub used_memory: u64,
+    pub total_memory: u64,
+    pub used_swap: u64,
+    pub total_swap: u64,
+    /// Over 1, 5 and 15 minutes, where the OS keeps them.
+    pub load_average: Option<[f64; 3]>,
+    pub processes: usize,
+    /// Seconds since the system booted.
+    pub uptime: Option<u64>,
+}
+
+/// Where the logger reads the process table from. Tests substitute a
+/// synthetic table to exercise the per-tick path at arbitrary scale.
+pub trait SystemSource {
+    /// Refreshes the whole process table.
+    fn refresh(&mut self);
+    /// Total memory in KB.
+    fn total_memory(&self) -> u64;
+    fn hostname(&self) -> Option<String>;
+    /// When the system booted, in seconds since the Unix epoch.
+    fn boot_time(&self) -> Option<u64> {
+        None
+    }
+    /// How many logical CPUs the host has, if the source knows.
+    fn cpu_count(&self) -> Option<usize> {
+        None
+    }
+    fn processes(&self) -> Box<dyn Iterator<Item = ProcessInfo<'_>> + '_>;
+
+    /// Whole-system figures as of the last refresh of the full table, if
+    /// the source can tell.
+    fn system_stats(&self) -> Option<SystemStats> {
+        None
+    }
+
+    /// Narrows later refreshes to an explicit set of PIDs. Returns false if
+    /// the source keeps refreshing the whole table, in which case the caller
+    /// has to filter.
+    fn watch(&mut self, _pids: &[Pid]) -> bool {
+        false
+    }
+
+    /// Forgets the previous readings that rates are computed against, so
+    /// that the next refresh does not compute rates across a suspend.
+    fn reset_baselines(&mut self) {}
+
+    /// Returns how many processes were left out since the last call because
+    /// their entries could not be read, and resets the count.
+    fn take_read_errors(&mut self) -> u64 {
+        0
+    }
+}
+
+pub struct SysinfoSource {
+    system: System,
+    /// Set when only explicit PIDs are refreshed. Each has its own `System`
+    /// because sysinfo measures a process's CPU usage against the global CPU
+    /// time of the previous refresh, which one shared instance would reset
+    /// between the per-PID refreshes.
+    watched: Option<BTreeMap<Pid, System>>,
+    /// What is re-read of each process on every refresh.
+    refresh_kind: ProcessRefreshKind,
+    /// On Linux the process refresh updates only the global CPU usage, so
+    /// each CPU's is read by an instance of its own.
+    #[cfg(target_os = "linux")]
+    cores: System,
+}
+
+impl SysinfoSource {
+    pub fn new() -> Self {
+        Self {
+            system: System::new_all(),
+            watched: None,
+            refresh_kind: ProcessRefreshKind::new().with_cpu(),
+            #[cfg(target_os = "linux")]
+            cores: System::new(),
+        }
+    }
+
+    /// The system whose processors hold each CPU's usage.
+    fn cores(&self) -> &System {
+        #[cfg(target_os = "linux")]
+        return &self.cores;
+        #[cfg(not(target_os = "linux"))]
+        &self.system
+    }
+
+    /// Also re-reads disk usage on every refresh, for the disk columns.
+    pub fn with_disk_usage(mut self) -> Self {
+        self.refresh_kind = self.refresh_kind.with_disk_usage();
+        self
+    }
+}
+
+impl SystemSource for SysinfoSource {
+    fn refresh(&mut self) {
+        let kind = self.refresh_kind;
+        let Some(watched) = &mut self.watched else {
+            // Memory, names, command lines and parents are read with every
+            // process regardless of `kind`; disks, networks and sensors are
+            // not logged and left alone. On Linux the process refresh also
+            // updates the global CPU usage, which refreshing it separately
+            // would reset between the two.
+            self.system.refresh_memory();
+            #[cfg(target_os = "linux")]
+            self.cores.refresh_cpu();
+            #[cfg(not(target_os = "linux"))]
+            self.system.refresh_cpu();
+            self.system.refresh_processes_specifics(kind);
+            return;
+        };
+        // The filter logs watched processes that exit.
+        watched.retain(|pid, system| system.refresh_process_specifics(*pid, kind));
+    }
+
+    fn total_memory(&self) -> u64 {
+        self.system.total_memory()
+    }
+
+    fn hostname(&self) -> Option<String> {
+        self.system.host_name()
+    }
+
+    fn boot_time(&self) -> Option<u64> {
+        Some(self.system.boot_time())
+    }
+
+    fn cpu_count(&self) -> Option<usize> {
+        Some(self.system.processors().len()).filter(|count| *count > 0)
+    }
+
+    fn system_stats(&self) -> Option<SystemStats> {
+        let load = self.system.load_average();
+        Some(SystemStats {
+            cpu_usage: self.system.global_processor_info().cpu_usage(),
+            core_usage: self
+                .cores()
+                .processors()
+                .iter()
+                .map(|processor| processor.cpu_usage())
+                .collect(),
+            used_memory: self.system.used_memory(),
+            total_memory: self.system.total_memory(),
+            used_swap: self.system.used_swap(),
+            total_swap: self.system.total_swap(),
+            load_average: (!cfg!(windows)).then_some([load.one, load.five, load.fifteen]),
+            processes: self.system.processes().len(),
+            uptime: Some(self.system.uptime()),
+        })
+    }
+
+    fn processes(&self) -> Box<dyn Iterator<Item = ProcessInfo<'_>> + '_> {
+        match &self.watched {
+            Some(watched) => Box::new(watched.iter().filter_map(|(pid, system)| {
+                system
+                    .process(*pid)
+                    .map(|process| process_info(*pid, process))
+            })),
+            None => Box::new(
+                self.system
+                    .processes()
+                    .iter()
+                    .map(|(pid, process)| process_info(*pid, process)),
+            ),
+        }
+    }
+
+    fn watch(&mut self, pids: &[Pid]) -> bool {
+        let mut watched = BTreeMap::new();
+        for pid in pids {
+            if self.system.process(*pid).is_some() {
+                // A first reading for the next refresh to measure CPU
+                // usage against, which would otherwise report 0.
+                let mut system = System::new();
+                system.refresh_process_specifics(*pid, self.refresh_kind);
+                watched.insert(*pid, system);
+            } else {
+                warn!("Process {} not found, it will not be logged", pid);
+            }
+        }
+        self.watched = Some(watched);
+        true
+    }
+}
+
+/// Resident and virtual memory of `process` in bytes. sysinfo counts the
+/// resident size in KiB on Linux and in thousands of bytes elsewhere, and
+/// the virtual size in bytes on Linux.
+fn memory_bytes(process: &Process) -> (u64, u64) {
+    if cfg!(target_os = "linux") {
+        (process.memory() * 1024, process.virtual_memory())
+    } else {
+        (process.memory() * 1000, process.virtual_memory() * 1000)
+    }
+}
+
+fn process_info(pid: Pid, process: &Process) -> ProcessInfo<'_> {
+    #[cfg(unix)]
+    let uid = Some(process.uid);
+    #[cfg(not(unix))]
+    let uid = None;
+    let disk = process.disk_usage();
+    let (resident_bytes, virtual_bytes) = memory_bytes(process);
+    // sysinfo reports counters and command lines it may not read as zero
+    // and empty.
+    let private = uid.is_none_or(may_read_private);
+    let disk_denied = !private && disk.total_read_bytes == 0 && disk.total_written_bytes == 0;
+    let cmd_denied = !private && !cfg!(target_os = "linux") && process.cmd().is_empty();
+    let permitted = |value, denied: bool| if denied { Err(Denied) } else { Ok(value) };
+    ProcessInfo {
+        pid,
+        name: process.name(),
+        cpu_usage: process.cpu_usage(),
+        memory: process.memory(),
+        start_time: process.start_time(),
+        cpu_time: None,
+        details: Some(ProcessDetails {
+            resident_bytes,
+            virtual_bytes,
+            disk_read: permitted(disk.total_read_bytes, disk_denied),
+            disk_written: permitted(disk.total_written_bytes, disk_denied),
+            status: process.status(),
+            parent: process.parent(),
+            started: process.start_time(),
+            cmd: if cmd_denied {
+                Err(Denied)
+            } else {
+                Ok(process.cmd())
+            },
+            uid,
+        }),
+    }
+}
+
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use sysinfo::PidExt;
+
+    /// A synthetic process table of `count` processes whose CPU usage moves
+    /// on every refresh, or every `cpu_period` refreshes.
+    pub struct FakeSource {
+        names: Vec<String>,
+        tick: u32,
+        cpu_period: u32,
+        unreadable: usize,
+        read_errors: u64,
+        boot_time: u64,
+    }
+
+    /// When every fake system booted unless told otherwise:
+    /// 2026-01-01T00:00:00Z.
+    pub const BOOT_TIME: u64 = 1_767_225_600;
+
+    impl FakeSource {
+        pub fn new(count: u32) -> Self {
+            Self {
+                names: (1..=count).map(|pid| format!("worker-{}", pid)).collect(),
+                tick: 0,
+                cpu_period: 1,
+                unreadable: 0,
+                read_errors: 0,
+                boot_time: BOOT_TIME,
+            }
+        }
+
+        pub fn with_boot_time(mut self, boot_time: u64) -> Self {
+            self.boot_time = boot_time;
+            self
+        }
+
+        /// Moves CPU usage only on every `period`th refresh.
+        pub fn with_cpu_period(mut self, period: u32) -> Self {
+            self.cpu_period = period;
+            self
+        }
+
+        /// Names the processes, one per name, instead of numbering them.
+        pub fn with_names(mut self, names: &[&str]) -> Self {
+            self.names = names.iter().map(ToString::to_string).collect();
+            self
+        }
+
+        /// Makes the last `count` processes unreadable on every refresh.
+        pub fn with_unreadable(mut self, count: usize) -> Self {
+            self.unreadable = count;
+            self
+        }
+    }
+
+    impl SystemSource for FakeSource {
+        fn refresh(&mut self) {
+            self.tick += 1;
+            self.read_errors += self.unreadable as u64;
+        }
+
+        fn total_memory(&self) -> u64 {
+            64 * 1024 * 1024
+        }
+
+        fn hostname(&self) -> Option<String> {
+            Some("fake-host".to_string())
+        }
+
+        fn boot_time(&self) -> Option<u64> {
+            Some(self.boot_time)
+        }
+
+        fn processes(&self) -> Box<dyn Iterator<Item = ProcessInfo<'_>> + '_> {
+            let readable = self.names.len() - self.unreadable;
+            Box::new(
+                self.names[..readable]
+                    .iter()
+                    .enumerate()
+                    .map(|(index, name)| {
+                        let pid = index as u32 + 1;
+                        ProcessInfo {
+                            pid: Pid::from_u32(pid),
+                            name,
+                            cpu_usage: ((pid + self.tick / self.cpu_period) % 100) as f32 / 3.0,
+                            memory: pid as u64 * 16,
+                            start_time: 0,
+                            cpu_time: None,
+                            details: None,
+                        }
+                    }),
+            )
+        }
+
+        fn system_stats(&self) -> Option<SystemStats> {
+            Some(SystemStats {
+                cpu_usage: (self.tick % 100) as f32,
+                core_usage: vec![(self.tick % 100) as f32, 0.0],
+                used_memory: 1024 * 1024 + self.tick as u64,
+                total_memory: self.total_memory(),
+                used_swap: 0,
+                total_swap: 2 * 1024 * 1024,
+                load_average: Some([1.5, 1.25, 1.0]),
+                processes: self.names.len() - self.unreadable,
+                uptime: Some(3600 + self.tick as u64),
+            })
+        }
+
+        fn take_read_errors(&mut self) -> u64 {
+            std::mem::take(&mut self.read_errors)
+        }
+    }
+}
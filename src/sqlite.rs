@@ -0,0 +1,463 @@
+//! Writes samples into a SQLite database through `rusqlite`, with SQLite
+//! built into chronologger. Each run adds a row to `runs`; every process it sees gets one row
+//! in `processes` and every sample one row in `samples`, so runs can be
+//! queried with SQL directly.
+
+use crate::{lifecycle, metadata::Metadata, suspend, ProcessSample};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
+use rusqlite::{params, Connection};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use sysinfo::{Pid, PidExt};
+
+/// `--output` value of a database that is never written to disk.
+const IN_MEMORY: &str = ":memory:";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    run_id TEXT,
+    started_at TEXT NOT NULL,
+    ended_at TEXT
+);
+CREATE TABLE IF NOT EXISTS run_metadata (
+    run INTEGER NOT NULL REFERENCES runs (id),
+    key TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS processes (
+    id INTEGER PRIMARY KEY,
+    run INTEGER NOT NULL REFERENCES runs (id),
+    pid INTEGER NOT NULL,
+    name TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS processes_pid ON processes (pid);
+CREATE TABLE IF NOT EXISTS samples (
+    run INTEGER NOT NULL REFERENCES runs (id),
+    timestamp TEXT NOT NULL,
+    process INTEGER NOT NULL REFERENCES processes (id),
+    cpu_percent REAL NOT NULL,
+    memory_percent REAL NOT NULL,
+    scope TEXT
+);
+CREATE INDEX IF NOT EXISTS samples_timestamp ON samples (timestamp);
+CREATE TABLE IF NOT EXISTS events (
+    run INTEGER NOT NULL REFERENCES runs (id),
+    timestamp TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    since TEXT,
+    dropped_ticks INTEGER,
+    scope TEXT
+);
+CREATE TABLE IF NOT EXISTS process_events (
+    run INTEGER NOT NULL REFERENCES runs (id),
+    timestamp TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    process INTEGER NOT NULL REFERENCES processes (id),
+    parent_pid INTEGER,
+    last_seen TEXT,
+    lifetime_seconds INTEGER,
+    exit_code INTEGER,
+    scope TEXT
+);
+";
+
+const INSERT_SAMPLE: &str =
+    "INSERT INTO samples (run, timestamp, process, cpu_percent, memory_percent, scope) \
+     VALUES (?, ?, ?, ?, ?, ?)";
+const INSERT_PROCESS: &str = "INSERT INTO processes (run, pid, name) VALUES (?, ?, ?)";
+const INSERT_EVENT: &str =
+    "INSERT INTO events (run, timestamp, kind, since, dropped_ticks, scope) \
+     VALUES (?, ?, ?, ?, ?, ?)";
+const INSERT_PROCESS_EVENT: &str =
+    "INSERT INTO process_events (run, timestamp, kind, process, parent_pid, last_seen, \
+     lifetime_seconds, exit_code, scope) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)";
+
+/// Timestamps are stored as UTC RFC 3339 text, which sorts in time order
+/// and is understood by SQLite's date and time functions.
+fn timestamp(time: &DateTime<Local>) -> String {
+    time.with_timezone(&Utc)
+        .to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// A sample read back from a database by [`read_samples`].
+pub struct StoredSample {
+    /// UTC RFC 3339, as written.
+    pub timestamp: String,
+    pub pid: u32,
+    pub name: String,
+    pub cpu_usage: f64,
+    pub memory_usage: f64,
+}
+
+/// Calls `visit` with every full-table sample of every run in the database
+/// at `path`, in the order they were written.
+pub fn read_samples(path: &str, mut visit: impl FnMut(StoredSample)) -> Result<()> {
+    let connection = open(path)?;
+    let mut statement = connection.prepare(
+        "SELECT samples.timestamp, processes.pid, processes.name, samples.cpu_percent, \
+         samples.memory_percent FROM samples JOIN processes ON processes.id = samples.process \
+         WHERE samples.scope IS NULL OR samples.scope = 'full' ORDER BY samples.rowid",
+    )?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        visit(StoredSample {
+            timestamp: row.get::<_, Option<String>>(0)?.unwrap_or_default(),
+            pid: row.get(1)?,
+            name: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            cpu_usage: row.get(3)?,
+            memory_usage: row.get(4)?,
+        });
+    }
+    Ok(())
+}
+
+/// Opens the database at `path`, creating it if needed.
+fn open(path: &str) -> Result<Connection> {
+    Connection::open(path).with_context(|| format!("Failed to open SQLite database {}", path))
+}
+
+/// Writes one run into a SQLite database, one transaction per tick.
+pub struct SqliteOutput {
+    connection: Connection,
+    /// The `runs` row of this run.
+    run: i64,
+    /// The `processes` row of each PID and name seen so far.
+    processes: HashMap<(u32, String), i64>,
+    /// Adds the growth of the database to the output's written bytes.
+    written: Arc<AtomicU64>,
+    size: u64,
+}
+
+impl SqliteOutput {
+    /// Opens the database at `path`, or one in memory for `None`, and starts
+    /// a new run in it. A database already there keeps its earlier runs.
+    pub fn open(path: Option<&str>, written: Arc<AtomicU64>) -> Result<Self> {
+        let connection = open(path.unwrap_or(IN_MEMORY))?;
+        // A reader can query the database while the capture goes on.
+        connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))
+            .context("Failed to enable write-ahead logging")?;
+        connection
+            .execute_batch(SCHEMA)
+            .context("Failed to create the database schema")?;
+        connection
+            .execute(
+                "INSERT INTO runs (started_at) VALUES (?)",
+                [timestamp(&Local::now())],
+            )
+            .context("Failed to start the run")?;
+        let run = connection.last_insert_rowid();
+        let mut output = Self {
+            connection,
+            run,
+            processes: HashMap::new(),
+            written,
+            size: 0,
+        };
+        output.count_written()?;
+        Ok(output)
+    }
+
+    /// Records `run_id` as this run's ID.
+    pub fn set_run_id(&mut self, run_id: &str) -> Result<()> {
+        self.connection
+            .execute(
+                "UPDATE runs SET run_id = ? WHERE id = ?",
+                params![run_id, self.run],
+            )
+            .context("Failed to record the run ID")?;
+        Ok(())
+    }
+
+    /// Records `metadata` in `run_metadata`, one row per entry.
+    pub fn write_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        let mut insert = self
+            .connection
+            .prepare("INSERT INTO run_metadata (run, key, value) VALUES (?, ?, ?)")?;
+        for (key, value) in metadata.entries() {
+            insert
+                .execute(params![self.run, key, value])
+                .context("Failed to write metadata")?;
+        }
+        drop(insert);
+        self.count_written()
+    }
+
+    pub fn write_tick(
+        &mut self,
+        now: &DateTime<Local>,
+        samples: &[ProcessSample],
+        scope: Option<&str>,
+    ) -> Result<()> {
+        let timestamp = timestamp(now);
+        self.connection.execute_batch("BEGIN")?;
+        let result = samples.iter().try_for_each(|sample| {
+            // Groups, which have no PID, are not written to SQLite.
+            let process = self.process(sample.pid.map_or(0, Pid::as_u32), &sample.name)?;
+            self.connection
+                .prepare_cached(INSERT_SAMPLE)?
+                .execute(params![
+                    self.run,
+                    timestamp,
+                    process,
+                    sample.cpu_usage as f64,
+                    sample.memory_usage,
+                    scope,
+                ])?;
+            Ok(())
+        });
+        self.end_transaction(result)
+            .context("Failed to write record!")?;
+        self.count_written()
+    }
+
+    /// Records an `event` at `time`, e.g. a heartbeat, ticks dropped or the
+    /// end of an interruption that started at `since`.
+    pub fn write_event(
+        &mut self,
+        time: &DateTime<Local>,
+        kind: &str,
+        since: Option<&DateTime<Local>>,
+        dropped_ticks: Option<u64>,
+        scope: Option<&str>,
+    ) -> Result<()> {
+        self.connection
+            .prepare_cached(INSERT_EVENT)?
+            .execute(params![
+                self.run,
+                timestamp(time),
+                kind,
+                since.map(timestamp),
+                dropped_ticks.map(|ticks| ticks as i64),
+                scope,
+            ])
+            .context("Failed to write record!")?;
+        self.count_written()
+    }
+
+    /// Records the processes spawned and exited before `now`.
+    pub fn write_process_events(
+        &mut self,
+        now: &DateTime<Local>,
+        events: &[lifecycle::Event],
+        scope: Option<&str>,
+    ) -> Result<()> {
+        let time = timestamp(now);
+        self.connection.execute_batch("BEGIN")?;
+        let result = events.iter().try_for_each(|event| {
+            let process = self.process(event.pid.as_u32(), &event.name)?;
+            self.connection
+                .prepare_cached(INSERT_PROCESS_EVENT)?
+                .execute(params![
+                    self.run,
+                    time,
+                    event.kind.name().to_lowercase(),
+                    process,
+                    event.parent.map(|parent| parent.as_u32()),
+                    event.last_seen.map(|(last_seen, ..)| timestamp(&last_seen)),
+                    event.lifetime().map(|lifetime| lifetime.as_secs() as i64),
+                    event.exit_code.map(|code| code as i64),
+                    scope,
+                ])?;
+            Ok(())
+        });
+        self.end_transaction(result)
+    }
+
+    /// Commits the transaction of a tick if `result` is fine, and rolls it
+    /// back otherwise.
+    fn end_transaction(&mut self, result: Result<()>) -> Result<()> {
+        match result {
+            Ok(()) => self.connection.execute_batch("COMMIT").map_err(Into::into),
+            Err(e) => {
+                // Rows of processes first seen in this tick are rolled back.
+                self.processes.clear();
+                self.connection.execute_batch("ROLLBACK")?;
+                Err(e)
+            }
+        }
+        .context("Failed to write record!")?;
+        self.count_written()
+    }
+
+    /// Records an interruption of `kind` over `gap`.
+    pub fn write_interruption(
+        &mut self,
+        gap: &suspend::Gap,
+        kind: suspend::GapKind,
+        scope: Option<&str>,
+    ) -> Result<()> {
+        let kind = match kind {
+            suspend::GapKind::Suspend => "suspended",
+            suspend::GapKind::Resume => "resumed",
+            suspend::GapKind::Reboot => "rebooted",
+        };
+        self.write_event(&gap.end, kind, Some(&gap.start), None, scope)
+    }
+
+    /// Records when the run ended and moves everything written into the
+    /// database file itself.
+    pub fn end(&mut self) -> Result<()> {
+        self.connection
+            .execute(
+                "UPDATE runs SET ended_at = ? WHERE id = ?",
+                params![timestamp(&Local::now()), self.run],
+            )
+            .context("Failed to record the end of the run")?;
+        self.connection
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+            .context("Failed to checkpoint the database")
+    }
+
+    /// The `processes` row of `pid` running `name`, added on first sight.
+    fn process(&mut self, pid: u32, name: &str) -> Result<i64> {
+        if let Some(&id) = self.processes.get(&(pid, name.to_string())) {
+            return Ok(id);
+        }
+        self.connection
+            .prepare_cached(INSERT_PROCESS)?
+            .execute(params![self.run, pid, name])?;
+        let id = self.connection.last_insert_rowid();
+        self.processes.insert((pid, name.to_string()), id);
+        Ok(id)
+    }
+
+    /// Adds how much the database has grown to the written bytes.
+    fn count_written(&mut self) -> Result<()> {
+        let pragma = |name: &str| {
+            self.connection
+                .query_row(&format!("PRAGMA {}", name), [], |row| row.get::<_, i64>(0))
+        };
+        let size = (pragma("page_count")? * pragma("page_size")?) as u64;
+        self.written
+            .fetch_add(size.saturating_sub(self.size), Ordering::Relaxed);
+        self.size = self.size.max(size);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+    use sysinfo::Pid;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_writes_normalized_runs() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("capture.sqlite");
+        let path = path.to_str().unwrap();
+        let now = Local::now();
+        for run_id in ["first", "second"] {
+            let written = Arc::new(AtomicU64::new(0));
+            let mut output = SqliteOutput::open(Some(path), written.clone()).unwrap();
+            output.set_run_id(run_id).unwrap();
+            let samples = [sample(1, "init", 0.5, 1.5), sample(42, "worker", 12.0, 1.5)];
+            output.write_tick(&now, &samples, None).unwrap();
+            output.write_tick(&now, &samples[1..], None).unwrap();
+            output
+                .write_event(&now, "heartbeat", None, None, None)
+                .unwrap();
+            let exited = lifecycle::Event {
+                kind: lifecycle::Kind::Exited,
+                pid: Pid::from_u32(1),
+                name: "init".to_string(),
+                started: None,
+                parent: None,
+                last_seen: Some((now, 0.5, 1.5)),
+                exit_code: Some(0),
+            };
+            output.write_process_events(&now, &[exited], None).unwrap();
+            output.end().unwrap();
+            assert!(written.load(Ordering::Relaxed) > 0);
+        }
+
+        let connection = open(path).unwrap();
+        let count = |sql: &str| -> i64 { connection.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM runs"), 2);
+        assert_eq!(
+            count("SELECT COUNT(*) FROM runs WHERE ended_at IS NOT NULL"),
+            2
+        );
+        // Each run has its own processes, but sees each one once.
+        assert_eq!(count("SELECT COUNT(*) FROM processes"), 4);
+        assert_eq!(count("SELECT COUNT(*) FROM samples"), 6);
+        let mut statement = connection
+            .prepare(
+                "SELECT runs.run_id, samples.timestamp, SUM(samples.cpu_percent) \
+                 FROM samples JOIN processes ON processes.id = samples.process \
+                 JOIN runs ON runs.id = samples.run \
+                 WHERE processes.pid = 42 GROUP BY runs.id ORDER BY runs.id",
+            )
+            .unwrap();
+        let rows: Vec<(String, String, f64)> = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                ("first".to_string(), timestamp(&now), 24.0),
+                ("second".to_string(), timestamp(&now), 24.0),
+            ]
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM events WHERE kind = 'heartbeat'"),
+            2
+        );
+        // Events refer to the processes rows their samples do.
+        assert_eq!(
+            count(
+                "SELECT COUNT(*) FROM process_events \
+                     JOIN processes ON processes.id = process_events.process \
+                     WHERE kind = 'exited' AND processes.pid = 1 AND exit_code = 0"
+            ),
+            2
+        );
+    }
+
+    #[test]
+    fn test_read_samples() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("capture.sqlite");
+        let path = path.to_str().unwrap();
+        let now = Local::now();
+        let mut output = SqliteOutput::open(Some(path), Arc::default()).unwrap();
+        output
+            .write_tick(&now, &[sample(1, "init", 0.5, 1.5)], Some("full"))
+            .unwrap();
+        output
+            .write_tick(&now, &[sample(1, "init", 9.0, 1.5)], Some("focus"))
+            .unwrap();
+        output.end().unwrap();
+
+        let mut samples = Vec::new();
+        read_samples(path, |sample| samples.push(sample)).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].timestamp, timestamp(&now));
+        assert_eq!((samples[0].pid, samples[0].name.as_str()), (1, "init"));
+        assert_eq!(samples[0].cpu_usage, 0.5);
+        assert_eq!(samples[0].memory_usage, 1.5);
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let written = Arc::new(AtomicU64::new(0));
+        let mut output = SqliteOutput::open(None, written.clone()).unwrap();
+        let before = written.load(Ordering::Relaxed);
+        let samples: Vec<_> = (0..500)
+            .map(|pid| sample(pid, "process", 1.0, 1.5))
+            .collect();
+        output.write_tick(&Local::now(), &samples, None).unwrap();
+        assert!(written.load(Ordering::Relaxed) > before);
+    }
+}
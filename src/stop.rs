@@ -0,0 +1,164 @@
+//! Conditions besides `--duration` that end a run: `--until-exit`, once a
+//! process is gone, and `--until-idle`, once the logged processes have been
+//! quiet for a while.
+
+use crate::ProcessSample;
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+use sysinfo::PidExt;
+
+/// Ends a run on what the full-table ticks sample. It sees each tick's
+/// samples after the filters and before `--top` leaves any out, like the
+/// alerts.
+pub trait StopCondition: Send {
+    /// Looks at the samples of a tick taken at `now`, telling why the run
+    /// should stop if it should.
+    fn check(&mut self, now: Instant, samples: &[ProcessSample]) -> Option<String>;
+}
+
+/// The process `--until-exit` waits for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Pid(u32),
+    /// Every process with this exact name.
+    Name(String),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Pid(pid) => write!(f, "PID {}", pid),
+            Target::Name(name) => write!(f, "{:?}", name),
+        }
+    }
+}
+
+/// Parses `--until-exit`: a PID, or else a process name.
+pub fn parse_target(value: &str) -> Result<Target, String> {
+    if value.is_empty() {
+        return Err("Expected a PID or process name".to_string());
+    }
+    Ok(match value.parse::<u32>() {
+        Ok(pid) => Target::Pid(pid),
+        Err(_) => Target::Name(value.to_string()),
+    })
+}
+
+/// Stops once no logged process is the target, including on the first tick
+/// if it is not running then.
+pub struct UntilExit {
+    target: Target,
+    seen: bool,
+}
+
+impl UntilExit {
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            seen: false,
+        }
+    }
+}
+
+impl StopCondition for UntilExit {
+    fn check(&mut self, _now: Instant, samples: &[ProcessSample]) -> Option<String> {
+        let running = samples.iter().any(|sample| match &self.target {
+            Target::Pid(pid) => sample.pid.is_some_and(|sample| sample.as_u32() == *pid),
+            Target::Name(name) => sample.name == *name,
+        });
+        if running {
+            self.seen = true;
+            return None;
+        }
+        if self.seen {
+            Some(format!("{} exited", self.target))
+        } else {
+            Some(format!("{} is not running", self.target))
+        }
+    }
+}
+
+/// Stops once the summed CPU usage of the logged processes has stayed
+/// below a threshold for a period.
+pub struct UntilIdle {
+    /// CPU usage in percent, as the CPU column has it.
+    threshold: f32,
+    period: Duration,
+    /// When the current quiet stretch began.
+    since: Option<Instant>,
+}
+
+impl UntilIdle {
+    pub fn new(threshold: f32, period: Duration) -> Self {
+        Self {
+            threshold,
+            period,
+            since: None,
+        }
+    }
+}
+
+impl StopCondition for UntilIdle {
+    fn check(&mut self, now: Instant, samples: &[ProcessSample]) -> Option<String> {
+        let usage: f32 = samples.iter().map(|sample| sample.cpu_usage).sum();
+        if usage >= self.threshold {
+            self.since = None;
+            return None;
+        }
+        let since = *self.since.get_or_insert(now);
+        (now.duration_since(since) >= self.period).then(|| {
+            format!(
+                "CPU usage stayed below {}% for {:?}",
+                self.threshold, self.period
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+
+    #[test]
+    fn test_until_exit() {
+        assert_eq!(parse_target("42"), Ok(Target::Pid(42)));
+        assert_eq!(parse_target("nginx"), Ok(Target::Name("nginx".to_string())));
+        assert!(parse_target("").is_err());
+
+        let now = Instant::now();
+        let mut condition = UntilExit::new(parse_target("make").unwrap());
+        assert_eq!(condition.check(now, &[sample(1, "make", 1.0, 0.0)]), None);
+        assert_eq!(
+            condition.check(now, &[sample(2, "make-helper", 1.0, 0.0)]),
+            Some("\"make\" exited".to_string())
+        );
+
+        let mut condition = UntilExit::new(Target::Pid(7));
+        assert_eq!(
+            condition.check(now, &[]),
+            Some("PID 7 is not running".to_string())
+        );
+    }
+
+    #[test]
+    fn test_until_idle() {
+        let start = Instant::now();
+        let mut condition = UntilIdle::new(5.0, Duration::from_secs(10));
+        let idle = [sample(1, "a", 1.0, 0.0), sample(2, "b", 2.0, 0.0)];
+        assert_eq!(condition.check(start, &idle), None);
+        assert_eq!(condition.check(start + Duration::from_secs(6), &idle), None);
+        // A busy tick starts the quiet stretch over.
+        let busy = [sample(1, "a", 4.0, 0.0), sample(2, "b", 2.0, 0.0)];
+        assert_eq!(condition.check(start + Duration::from_secs(8), &busy), None);
+        assert_eq!(
+            condition.check(start + Duration::from_secs(12), &idle),
+            None
+        );
+        assert!(condition
+            .check(start + Duration::from_secs(22), &idle)
+            .is_some());
+    }
+}
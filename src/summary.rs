@@ -0,0 +1,274 @@
+use crate::{numfmt, ProcessSample, STDOUT_PATH};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::info;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+use sysinfo::{Pid, PidExt};
+
+/// Columns of the CSV written by `--summary`.
+pub const HEADER: [&str; 9] = [
+    "PID",
+    "Process Name",
+    "Samples",
+    "Mean CPU Usage (%)",
+    "Max CPU Usage (%)",
+    "Mean Memory Usage (%)",
+    "Max Memory Usage (%)",
+    "First Seen",
+    "Last Seen",
+];
+
+/// A process, told apart from earlier and later ones with the same PID by
+/// its name and start time, or a group, which has no PID, by its name.
+type Key = (Option<Pid>, String, u64);
+
+/// What one process used over the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcStats {
+    pub samples: u64,
+    pub cpu_total: f64,
+    pub cpu_max: f32,
+    pub memory_total: f64,
+    pub memory_max: f64,
+    pub first_seen: DateTime<Local>,
+    pub last_seen: DateTime<Local>,
+}
+
+impl ProcStats {
+    pub fn new(now: &DateTime<Local>) -> Self {
+        Self {
+            samples: 0,
+            cpu_total: 0.0,
+            cpu_max: 0.0,
+            memory_total: 0.0,
+            memory_max: 0.0,
+            first_seen: *now,
+            last_seen: *now,
+        }
+    }
+
+    pub fn add(&mut self, now: &DateTime<Local>, sample: &ProcessSample) {
+        self.samples += 1;
+        self.cpu_total += sample.cpu_usage as f64;
+        self.cpu_max = self.cpu_max.max(sample.cpu_usage);
+        self.memory_total += sample.memory_usage;
+        self.memory_max = self.memory_max.max(sample.memory_usage);
+        self.last_seen = *now;
+    }
+
+    pub fn cpu_mean(&self) -> f64 {
+        self.cpu_total / self.samples as f64
+    }
+
+    pub fn memory_mean(&self) -> f64 {
+        self.memory_total / self.samples as f64
+    }
+}
+
+/// Accumulates per-process statistics over the full-table ticks of a run.
+#[derive(Debug, Default)]
+pub struct Accumulator {
+    stats: HashMap<Key, ProcStats>,
+    /// Start times of the processes read this tick, which samples do not
+    /// carry.
+    start_times: HashMap<Pid, u64>,
+}
+
+impl Accumulator {
+    /// Notes when the process `pid` read this tick started.
+    pub fn note_start(&mut self, pid: Pid, start_time: u64) {
+        self.start_times.insert(pid, start_time);
+    }
+
+    /// Adds the samples of the tick taken at `now`.
+    pub fn record(&mut self, now: &DateTime<Local>, samples: &[ProcessSample]) {
+        for sample in samples {
+            let start_time = sample
+                .pid
+                .and_then(|pid| self.start_times.get(&pid).copied())
+                .unwrap_or(0);
+            let key = (sample.pid, sample.name.clone(), start_time);
+            self.stats
+                .entry(key)
+                .or_insert_with(|| ProcStats::new(now))
+                .add(now, sample);
+        }
+        self.start_times.clear();
+    }
+
+    /// Every process seen, by highest max CPU usage first and then by PID
+    /// and first sample.
+    pub fn sorted(&self) -> Vec<(&Key, &ProcStats)> {
+        let mut rows: Vec<_> = self.stats.iter().collect();
+        rows.sort_unstable_by(|(a_key, a), (b_key, b)| {
+            b.cpu_max
+                .total_cmp(&a.cpu_max)
+                .then(a_key.0.cmp(&b_key.0))
+                .then(a.first_seen.cmp(&b.first_seen))
+        });
+        rows
+    }
+
+    /// Describes the peak usage of the `limit` processes with the highest
+    /// max CPU usage, one line each, for the end-of-run report. A last line
+    /// counts the processes left out.
+    pub fn peak_lines(&self, limit: usize) -> Vec<String> {
+        let rows = self.sorted();
+        let mut lines: Vec<String> = rows
+            .iter()
+            .take(limit)
+            .map(|((pid, name, _), stats)| {
+                let pid = pid.map_or_else(String::new, |pid| format!(" ({})", pid.as_u32()));
+                format!(
+                    "{}{}: peak CPU {:.2}%, peak memory {:.2}% over {} samples",
+                    name, pid, stats.cpu_max, stats.memory_max, stats.samples
+                )
+            })
+            .collect();
+        if rows.len() > limit {
+            lines.push(format!("and {} more processes", rows.len() - limit));
+        }
+        lines
+    }
+
+    /// Writes the summary CSV to `path`, or to stdout for `-`.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if path == Path::new(STDOUT_PATH) {
+            return self.write_to(std::io::stdout().lock());
+        }
+        info!("Writing the per-process summary to {}", path.display());
+        let file = File::create(path).context("Failed to create summary file!")?;
+        self.write_to(BufWriter::new(file))
+    }
+
+    fn write_to<W: Write>(&self, writer: W) -> Result<()> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer
+            .write_record(HEADER)
+            .context("Failed to write summary header!")?;
+        let mut fields: [String; HEADER.len()] = Default::default();
+        for ((pid, name, _), stats) in self.sorted() {
+            for field in &mut fields {
+                field.clear();
+            }
+            let [pid_text, name_text, samples, cpu_mean, cpu_max, memory_mean, memory_max, first, last] =
+                &mut fields;
+            if let Some(pid) = pid {
+                numfmt::write_int(pid_text, pid.as_u32());
+            }
+            name_text.push_str(name);
+            numfmt::write_int(samples, stats.samples);
+            numfmt::write_fixed(cpu_mean, stats.cpu_mean(), numfmt::PERCENT_PRECISION);
+            numfmt::write_fixed(cpu_max, stats.cpu_max as f64, numfmt::PERCENT_PRECISION);
+            numfmt::write_fixed(memory_mean, stats.memory_mean(), numfmt::PERCENT_PRECISION);
+            numfmt::write_fixed(memory_max, stats.memory_max, numfmt::PERCENT_PRECISION);
+            first.push_str(&stats.first_seen.to_rfc3339());
+            last.push_str(&stats.last_seen.to_rfc3339());
+            writer
+                .write_record(&fields)
+                .context("Failed to write summary record!")?;
+        }
+        writer.flush().context("Failed to flush summary!")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_accumulates_and_sorts_by_max_cpu() {
+        let start = Local.timestamp_opt(1_767_225_600, 0).unwrap();
+        let mut accumulator = Accumulator::default();
+        let ticks = [
+            vec![sample(1, "init", 1.0, 2.0), sample(2, "worker", 10.0, 5.0)],
+            vec![sample(1, "init", 3.0, 4.0), sample(2, "worker", 30.0, 1.0)],
+            // PID 2 was reused by another program.
+            vec![sample(1, "init", 5.0, 3.0), sample(2, "shell", 50.0, 1.0)],
+        ];
+        for (seconds, samples) in ticks.iter().enumerate() {
+            let now = start + chrono::Duration::seconds(seconds as i64);
+            accumulator.record(&now, samples);
+        }
+
+        let rows = accumulator.sorted();
+        let names: Vec<&str> = rows.iter().map(|((_, name, _), _)| name.as_str()).collect();
+        assert_eq!(names, ["shell", "worker", "init"]);
+        let (_, worker) = rows[1];
+        assert_eq!(worker.samples, 2);
+        assert_eq!(worker.cpu_mean(), 20.0);
+        assert_eq!(worker.cpu_max, 30.0);
+        assert_eq!(worker.memory_max, 5.0);
+        assert_eq!(worker.last_seen, start + chrono::Duration::seconds(1));
+        let (_, init) = rows[2];
+        assert_eq!(init.samples, 3);
+        assert_eq!(init.memory_mean(), 3.0);
+        assert_eq!(init.first_seen, start);
+    }
+
+    #[test]
+    fn test_start_time_tells_reused_pids_apart() {
+        let now = Local::now();
+        let mut accumulator = Accumulator::default();
+        for start_time in [100, 100, 200] {
+            accumulator.note_start(Pid::from_u32(7), start_time);
+            accumulator.record(&now, &[sample(7, "worker", 1.0, 1.0)]);
+        }
+        let mut samples: Vec<u64> = accumulator
+            .sorted()
+            .iter()
+            .map(|(_, stats)| stats.samples)
+            .collect();
+        samples.sort_unstable();
+        assert_eq!(samples, [1, 2]);
+    }
+
+    #[test]
+    fn test_peak_lines_keep_the_heaviest() {
+        let mut accumulator = Accumulator::default();
+        let now = Local::now();
+        accumulator.record(
+            &now,
+            &[sample(1, "init", 1.0, 2.0), sample(2, "worker", 10.0, 5.0)],
+        );
+        accumulator.record(
+            &now,
+            &[sample(1, "init", 3.0, 4.0), sample(3, "shell", 0.5, 1.0)],
+        );
+        assert_eq!(
+            accumulator.peak_lines(2),
+            [
+                "worker (2): peak CPU 10.00%, peak memory 5.00% over 1 samples",
+                "init (1): peak CPU 3.00%, peak memory 4.00% over 2 samples",
+                "and 1 more processes",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_writes_summary_csv() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("summary.csv");
+        let mut accumulator = Accumulator::default();
+        let now = Local::now();
+        accumulator.record(&now, &[sample(3, "a, b", 12.345, 0.5)]);
+        accumulator.write(&path).unwrap();
+
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        assert_eq!(reader.headers().unwrap(), HEADER.as_slice());
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0].iter().take(7).collect::<Vec<_>>(),
+            ["3", "a, b", "1", "12.35", "12.35", "0.50", "0.50"]
+        );
+        assert_eq!(&rows[0][7], now.to_rfc3339());
+    }
+}
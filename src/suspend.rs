@@ -0,0 +1,151 @@
+use chrono::{DateTime, Local};
+use log::warn;
+use std::time::{Duration, Instant};
+
+/// Wall-clock jumps shorter than this are never taken for a suspend, so
+/// that small clock adjustments do not produce gap markers.
+const MIN_SUSPEND: Duration = Duration::from_secs(10);
+
+/// A stretch of wall-clock time during which the system was suspended and
+/// nothing could be sampled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gap {
+    /// Wall-clock time of the last tick before the suspend.
+    pub start: DateTime<Local>,
+    /// Wall-clock time of the first tick after it.
+    pub end: DateTime<Local>,
+    /// How long the system was suspended.
+    pub length: Duration,
+}
+
+/// Why nothing was sampled during a [`Gap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GapKind {
+    /// The system was suspended.
+    Suspend,
+    /// The capture was interrupted and later continued with `--resume`.
+    Resume,
+    /// Likewise, but the host rebooted in between.
+    Reboot,
+}
+
+/// Detects system suspend by comparing the wall clock with the monotonic
+/// clock between ticks. The monotonic clock stops while the system sleeps
+/// but the wall clock does not, so after a resume the wall clock has moved
+/// on by much more than the monotonic clock.
+pub struct SuspendDetector {
+    threshold: Duration,
+    last: Option<(Instant, DateTime<Local>)>,
+    gaps: u64,
+}
+
+impl SuspendDetector {
+    /// Creates a detector for ticks taken every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            threshold: (interval * 3).max(MIN_SUSPEND),
+            last: None,
+            gaps: 0,
+        }
+    }
+
+    /// Records a tick taken at `monotonic` and `wall`, returning the gap if
+    /// the system was suspended since the previous tick.
+    pub fn check(&mut self, monotonic: Instant, wall: DateTime<Local>) -> Option<Gap> {
+        let previous = self.last.replace((monotonic, wall));
+        let (last_monotonic, last_wall) = previous?;
+        let wall_elapsed = (wall - last_wall).to_std().ok()?;
+        let unaccounted = wall_elapsed.checked_sub(monotonic - last_monotonic)?;
+        if unaccounted < self.threshold {
+            return None;
+        }
+        self.gaps += 1;
+        warn!(
+            "System was suspended for about {:?} ({} to {})",
+            Duration::from_secs(unaccounted.as_secs()),
+            last_wall.to_rfc3339(),
+            wall.to_rfc3339()
+        );
+        Some(Gap {
+            start: last_wall,
+            end: wall,
+            length: unaccounted,
+        })
+    }
+
+    /// Suspend gaps detected so far.
+    pub fn gaps(&self) -> u64 {
+        self.gaps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clock whose wall time can be moved on without the monotonic time,
+    /// as happens across a suspend.
+    struct MockClock {
+        monotonic: Instant,
+        wall: DateTime<Local>,
+    }
+
+    impl MockClock {
+        fn new() -> Self {
+            Self {
+                monotonic: Instant::now(),
+                wall: Local::now(),
+            }
+        }
+
+        fn advance(&mut self, elapsed: Duration) {
+            self.monotonic += elapsed;
+            self.wall += chrono::Duration::from_std(elapsed).unwrap();
+        }
+
+        fn suspend(&mut self, length: Duration) {
+            self.wall += chrono::Duration::from_std(length).unwrap();
+        }
+
+        fn tick(&self, detector: &mut SuspendDetector) -> Option<Gap> {
+            detector.check(self.monotonic, self.wall)
+        }
+    }
+
+    #[test]
+    fn test_two_hour_suspend() {
+        let mut clock = MockClock::new();
+        let mut detector = SuspendDetector::new(Duration::from_secs(1));
+        for _ in 0..5 {
+            assert_eq!(clock.tick(&mut detector), None);
+            clock.advance(Duration::from_secs(1));
+        }
+        let before = clock.wall - chrono::Duration::seconds(1);
+        clock.suspend(Duration::from_secs(2 * 3600));
+
+        let gap = clock.tick(&mut detector).expect("Suspend not detected");
+        assert_eq!(gap.start, before);
+        assert_eq!(gap.end, clock.wall);
+        assert_eq!(gap.length, Duration::from_secs(2 * 3600));
+        assert_eq!(detector.gaps(), 1);
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(clock.tick(&mut detector), None);
+    }
+
+    #[test]
+    fn test_ignores_slow_ticks_and_clock_steps() {
+        let mut clock = MockClock::new();
+        let mut detector = SuspendDetector::new(Duration::from_secs(1));
+        clock.tick(&mut detector);
+        // A tick that took long by the monotonic clock is not a suspend.
+        clock.advance(Duration::from_secs(120));
+        assert_eq!(clock.tick(&mut detector), None);
+        // Neither is a small wall-clock step or a step backwards.
+        clock.suspend(Duration::from_secs(2));
+        assert_eq!(clock.tick(&mut detector), None);
+        clock.wall -= chrono::Duration::seconds(3600);
+        assert_eq!(clock.tick(&mut detector), None);
+        assert_eq!(detector.gaps(), 0);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{
+    numfmt,
+    overhead::Counted,
+    retry::{RetryPolicy, Retrying},
+    source::SystemStats,
+    timestamp::TimestampFormat,
+    STDOUT_PATH,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::info;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicU64, Arc},
+};
+
+/// Columns of the system CSV written by `--mode system` and `--mode both`,
+/// followed by one per CPU, see [`core_column`].
+pub const HEADER: [&str; 11] = [
+    "Timestamp",
+    "CPU Usage (%)",
+    "Used Memory (kB)",
+    "Total Memory (kB)",
+    "Used Swap (kB)",
+    "Total Swap (kB)",
+    "Load Average 1m",
+    "Load Average 5m",
+    "Load Average 15m",
+    "Processes",
+    "Uptime (s)",
+];
+
+/// The column of logical CPU `index` in the system CSV.
+pub fn core_column(index: usize) -> String {
+    format!("CPU {} Usage (%)", index)
+}
+
+/// Where `--mode both` writes the system rows next to `output`, e.g.
+/// `foo.system.csv` for `foo.csv`.
+pub fn path_for(output: &str) -> PathBuf {
+    let output = Path::new(output);
+    let stem = output.file_stem().unwrap_or(output.as_os_str());
+    let mut name = stem.to_os_string();
+    name.push(".system.csv");
+    output.with_file_name(name)
+}
+
+/// Writes one row of whole-system figures per full-table tick.
+pub struct Writer {
+    writer: csv::Writer<BufWriter<Counted<Box<dyn Write + Send>>>>,
+    fields: Vec<String>,
+}
+
+impl Writer {
+    /// Creates the system CSV at `path`, or writes it to stdout for `-`,
+    /// adding every byte it writes to `written`.
+    pub fn create(path: &Path, retry: RetryPolicy, written: Arc<AtomicU64>) -> Result<Self> {
+        let destination: Box<dyn Write + Send> = if path == Path::new(STDOUT_PATH) {
+            info!("Writing system rows to stdout");
+            Box::new(std::io::stdout())
+        } else {
+            info!("Creating system output file: {}", path.display());
+            let file = File::create(path).context("Failed to create system output file!")?;
+            Box::new(Retrying::new(file, retry))
+        };
+        Ok(Self {
+            writer: csv::Writer::from_writer(BufWriter::new(Counted::new(destination, written))),
+            fields: vec![String::new(); HEADER.len()],
+        })
+    }
+
+    /// Writes the header, with a column for each of `cores` CPUs.
+    pub fn write_header(&mut self, cores: usize) -> Result<()> {
+        self.fields.resize(HEADER.len() + cores, String::new());
+        let header = HEADER
+            .iter()
+            .map(|column| column.to_string())
+            .chain((0..cores).map(core_column));
+        self.writer
+            .write_record(header)
+            .context("Failed to write system header!")?;
+        self.flush()
+    }
+
+    /// Writes and flushes the row of the tick sampled at `now`, stamped as
+    /// `timestamps` says.
+    pub fn write(
+        &mut self,
+        now: &DateTime<Local>,
+        timestamps: &TimestampFormat,
+        stats: &SystemStats,
+    ) -> Result<()> {
+        for field in &mut self.fields {
+            field.clear();
+        }
+        let (fields, cores) = self.fields.split_at_mut(HEADER.len());
+        let [timestamp, cpu, used_memory, total_memory, used_swap, total_swap, load_1, load_5, load_15, processes, uptime] =
+            fields
+        else {
+            unreachable!("the system row has a field per column");
+        };
+        timestamps.write(timestamp, now);
+        numfmt::write_fixed(cpu, stats.cpu_usage as f64, numfmt::PERCENT_PRECISION);
+        numfmt::write_int(used_memory, stats.used_memory);
+        numfmt::write_int(total_memory, stats.total_memory);
+        numfmt::write_int(used_swap, stats.used_swap);
+        numfmt::write_int(total_swap, stats.total_swap);
+        // Left empty where the OS has no load average, i.e. on Windows.
+        if let Some(load) = stats.load_average {
+            for (text, value) in [load_1, load_5, load_15].into_iter().zip(load) {
+                numfmt::write_fixed(text, value, 2);
+            }
+        }
+        numfmt::write_int(processes, stats.processes as u64);
+        if let Some(seconds) = stats.uptime {
+            numfmt::write_int(uptime, seconds);
+        }
+        // A CPU the header has no column for, e.g. one brought online
+        // during the run, is left out.
+        for (text, usage) in cores.iter_mut().zip(&stats.core_usage) {
+            numfmt::write_fixed(text, *usage as f64, numfmt::PERCENT_PRECISION);
+        }
+        self.writer
+            .write_record(&self.fields)
+            .context("Failed to write system record!")?;
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer
+            .flush()
+            .context("Failed to flush system output!")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_for() {
+        assert_eq!(path_for("foo.csv"), PathBuf::from("foo.system.csv"));
+        assert_eq!(
+            path_for("/var/log/run.jsonl"),
+            PathBuf::from("/var/log/run.system.csv")
+        );
+        assert_eq!(path_for("capture"), PathBuf::from("capture.system.csv"));
+    }
+}
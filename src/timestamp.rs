@@ -0,0 +1,180 @@
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Local, TimeZone, Utc,
+};
+use serde_json::Value;
+use std::fmt::Write;
+
+/// How the timestamps of CSV and JSON output are written, resolved
+/// once from `--timestamp-format` and `--utc`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TimestampFormat {
+    style: Style,
+    utc: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Style {
+    /// RFC 3339 with the UTC offset, e.g. `2026-01-01T10:00:00.123+02:00`.
+    #[default]
+    Rfc3339,
+    /// Whole seconds since the Unix epoch.
+    Epoch,
+    /// Milliseconds since the Unix epoch.
+    EpochMillis,
+    /// A chrono strftime pattern, parsed when the option is.
+    Strftime(Vec<Item<'static>>),
+}
+
+impl TimestampFormat {
+    pub fn new(style: Style, utc: bool) -> Self {
+        Self { style, utc }
+    }
+
+    /// Whether this is the default, RFC 3339 in local time.
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Appends `time` to `out`.
+    pub fn write(&self, out: &mut String, time: &DateTime<Local>) {
+        let _ = match &self.style {
+            Style::Rfc3339 if self.utc => write!(out, "{}", time.with_timezone(&Utc).to_rfc3339()),
+            Style::Rfc3339 => write!(out, "{}", time.to_rfc3339()),
+            Style::Epoch => write!(out, "{}", time.timestamp()),
+            Style::EpochMillis => write!(out, "{}", time.timestamp_millis()),
+            Style::Strftime(items) if self.utc => write!(
+                out,
+                "{}",
+                time.with_timezone(&Utc).format_with_items(items.iter())
+            ),
+            Style::Strftime(items) => write!(out, "{}", time.format_with_items(items.iter())),
+        };
+    }
+
+    /// `time` as a string.
+    pub fn format(&self, time: &DateTime<Local>) -> String {
+        let mut out = String::new();
+        self.write(&mut out, time);
+        out
+    }
+
+    /// Reads back a timestamp written in this format, for `--resume`. A
+    /// strftime pattern need not carry the date or offset, so it is never
+    /// read back.
+    pub fn parse(&self, text: &str) -> Option<DateTime<Local>> {
+        match self.style {
+            Style::Rfc3339 => DateTime::parse_from_rfc3339(text)
+                .ok()
+                .map(|time| time.with_timezone(&Local)),
+            Style::Epoch => Local.timestamp_opt(text.parse().ok()?, 0).single(),
+            Style::EpochMillis => Local.timestamp_millis_opt(text.parse().ok()?).single(),
+            Style::Strftime(_) => None,
+        }
+    }
+
+    /// Whether [`parse`](Self::parse) can read this format back.
+    pub fn is_readable(&self) -> bool {
+        !matches!(self.style, Style::Strftime(_))
+    }
+
+    /// `time` as a JSON value: a number for the epoch styles, else a string.
+    pub fn to_json(&self, time: &DateTime<Local>) -> Value {
+        match self.style {
+            Style::Epoch => time.timestamp().into(),
+            Style::EpochMillis => time.timestamp_millis().into(),
+            _ => self.format(time).into(),
+        }
+    }
+}
+
+/// Parses a `--timestamp-format` value: `rfc3339`, `epoch`, `epoch-ms` or
+/// `strftime:<FMT>`.
+pub fn parse_style(value: &str) -> Result<Style, String> {
+    match value {
+        "rfc3339" => Ok(Style::Rfc3339),
+        "epoch" => Ok(Style::Epoch),
+        "epoch-ms" => Ok(Style::EpochMillis),
+        _ => {
+            let Some(pattern) = value.strip_prefix("strftime:") else {
+                return Err(format!(
+                    "Unknown timestamp format {:?}, expected rfc3339, epoch, epoch-ms or \
+                     strftime:<FMT>",
+                    value
+                ));
+            };
+            if pattern.is_empty() {
+                return Err("The strftime pattern is empty".to_string());
+            }
+            StrftimeItems::new(pattern)
+                .parse_to_owned()
+                .map(Style::Strftime)
+                .map_err(|_| format!("Invalid strftime pattern {:?}", pattern))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, TimeZone};
+
+    fn time() -> DateTime<Local> {
+        FixedOffset::east_opt(2 * 3600)
+            .unwrap()
+            .timestamp_millis_opt(1_767_261_600_250)
+            .unwrap()
+            .with_timezone(&Local)
+    }
+
+    #[test]
+    fn test_formats() {
+        let time = time();
+        let format = |value: &str, utc: bool| {
+            TimestampFormat::new(parse_style(value).unwrap(), utc).format(&time)
+        };
+        assert_eq!(format("rfc3339", false), time.to_rfc3339());
+        assert_eq!(format("rfc3339", true), "2026-01-01T10:00:00.250+00:00");
+        assert_eq!(format("epoch", false), "1767261600");
+        assert_eq!(format("epoch-ms", true), "1767261600250");
+        assert_eq!(
+            format("strftime:%Y-%m-%d %H:%M:%S%.3f %z", true),
+            "2026-01-01 10:00:00.250 +0000"
+        );
+        assert!(TimestampFormat::default().is_default());
+        assert!(!TimestampFormat::new(Style::Rfc3339, true).is_default());
+    }
+
+    #[test]
+    fn test_parses_what_it_writes() {
+        let time = time();
+        for value in ["rfc3339", "epoch-ms"] {
+            let format = TimestampFormat::new(parse_style(value).unwrap(), true);
+            assert_eq!(format.parse(&format.format(&time)), Some(time), "{}", value);
+        }
+        let format = TimestampFormat::new(Style::Epoch, false);
+        assert_eq!(
+            format.parse(&format.format(&time)).unwrap().timestamp(),
+            time.timestamp()
+        );
+        assert_eq!(format.parse("2026-01-01T10:00:00+00:00"), None);
+        let format = TimestampFormat::new(parse_style("strftime:%s").unwrap(), false);
+        assert!(!format.is_readable());
+        assert_eq!(format.parse(&format.format(&time)), None);
+    }
+
+    #[test]
+    fn test_json_epoch_is_numeric() {
+        let format = TimestampFormat::new(Style::EpochMillis, false);
+        assert_eq!(format.to_json(&time()), Value::from(1_767_261_600_250i64));
+        let format = TimestampFormat::new(Style::Rfc3339, true);
+        assert!(format.to_json(&time()).is_string());
+    }
+
+    #[test]
+    fn test_rejects_invalid_formats() {
+        for invalid in ["iso", "strftime:", "strftime:%Q", "strftime:%Y-%"] {
+            assert!(parse_style(invalid).is_err(), "{} accepted", invalid);
+        }
+    }
+}
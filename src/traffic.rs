@@ -0,0 +1,357 @@
+//! Per-process TCP traffic, from the kernel's socket diagnostics.
+//!
+//! Linux keeps byte counters per TCP socket, not per process. Each tick the
+//! counters of every TCP socket are dumped over a `NETLINK_SOCK_DIAG`
+//! socket, as `ss -ti` does, and a process is credited with what its
+//! sockets, found through `/proc/<pid>/fd`, carried since the previous
+//! tick. Traffic of sockets that were opened and closed between two ticks
+//! is missed, and a socket shared by several processes is counted for each.
+
+use log::warn;
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+};
+
+#[cfg(target_os = "linux")]
+const NETLINK_SOCK_DIAG: libc::c_int = 4;
+const SOCK_DIAG_BY_FAMILY: u16 = 20;
+#[cfg(target_os = "linux")]
+const NLM_F_REQUEST: u16 = 0x1;
+#[cfg(target_os = "linux")]
+const NLM_F_DUMP: u16 = 0x300;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+/// Asks for `struct tcp_info` with each socket.
+const INET_DIAG_INFO: u16 = 2;
+
+/// `struct nlmsghdr`.
+const HEADER_LEN: usize = 16;
+/// `struct inet_diag_req_v2`.
+#[cfg(target_os = "linux")]
+const REQUEST_LEN: usize = 56;
+/// Offset of `idiag_inode` in `struct inet_diag_msg`.
+const INODE_OFFSET: usize = 68;
+/// Length of `struct inet_diag_msg`, after which its attributes start.
+const MESSAGE_LEN: usize = 72;
+/// Offsets of `tcpi_bytes_acked` and `tcpi_bytes_received` in
+/// `struct tcp_info`, which has them since Linux 4.2.
+const BYTES_ACKED_OFFSET: usize = 120;
+const BYTES_RECEIVED_OFFSET: usize = 128;
+
+/// Bytes a socket or process has sent and received.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bytes {
+    pub sent: u64,
+    pub received: u64,
+}
+
+/// Adds up the TCP traffic of each process over the run.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    /// Counters of every socket at the previous tick, `None` before the
+    /// first, whose traffic is the baseline rather than counted.
+    previous: Option<HashMap<u64, Bytes>>,
+    /// Counters of every socket this tick, dumped on the first read.
+    current: Option<HashMap<u64, Bytes>>,
+    /// What each process has sent and received since it was first read.
+    totals: HashMap<u32, Bytes>,
+    /// Processes read this tick; the totals of the others are dropped.
+    seen: HashSet<u32>,
+    warned: bool,
+}
+
+impl Traffic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a tick, whose reads count traffic since the previous one.
+    pub fn begin_tick(&mut self) {
+        if let Some(current) = self.current.take() {
+            self.previous = Some(current);
+            let seen = std::mem::take(&mut self.seen);
+            self.totals.retain(|pid, _| seen.contains(pid));
+        }
+    }
+
+    /// Forgets the socket counters read so far, so that the next tick takes
+    /// them as its baseline rather than counting traffic across a suspend.
+    pub fn reset(&mut self) {
+        self.previous = None;
+        self.current = None;
+    }
+
+    /// What `pid` has sent and received over TCP since it was first read,
+    /// or `None` if its sockets cannot be listed, e.g. because it belongs
+    /// to another user.
+    pub fn read(&mut self, pid: u32) -> Option<Bytes> {
+        let inodes = socket_inodes(pid)?;
+        let current = self.current.get_or_insert_with(|| {
+            dump().unwrap_or_else(|e| {
+                if !std::mem::replace(&mut self.warned, true) {
+                    warn!("Failed to read TCP socket statistics: {}", e);
+                }
+                HashMap::new()
+            })
+        });
+        self.seen.insert(pid);
+        let total = self.totals.entry(pid).or_default();
+        let Some(previous) = &self.previous else {
+            return Some(*total);
+        };
+        for inode in inodes {
+            let Some(now) = current.get(&inode) else {
+                continue;
+            };
+            // Sockets opened since the previous tick count from zero.
+            let before = previous.get(&inode).copied().unwrap_or_default();
+            total.sent += now.sent.saturating_sub(before.sent);
+            total.received += now.received.saturating_sub(before.received);
+        }
+        Some(*total)
+    }
+}
+
+/// The inodes of the sockets `pid` has open.
+#[cfg(target_os = "linux")]
+fn socket_inodes(pid: u32) -> Option<Vec<u64>> {
+    let entries = std::fs::read_dir(format!("/proc/{}/fd", pid)).ok()?;
+    let inodes = entries
+        .filter_map(|entry| std::fs::read_link(entry.ok()?.path()).ok())
+        .filter_map(|target| {
+            let target = target.to_str()?;
+            target
+                .strip_prefix("socket:[")?
+                .strip_suffix(']')?
+                .parse()
+                .ok()
+        })
+        .collect();
+    Some(inodes)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn socket_inodes(_pid: u32) -> Option<Vec<u64>> {
+    None
+}
+
+/// The counters of every IPv4 and IPv6 TCP socket, by inode.
+#[cfg(not(target_os = "linux"))]
+fn dump() -> io::Result<HashMap<u64, Bytes>> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// The counters of every IPv4 and IPv6 TCP socket, by inode.
+#[cfg(target_os = "linux")]
+fn dump() -> io::Result<HashMap<u64, Bytes>> {
+    // SAFETY: plain socket creation; the descriptor is owned below.
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            NETLINK_SOCK_DIAG,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` was just opened and nothing else owns it.
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut sockets = HashMap::new();
+    for family in [libc::AF_INET, libc::AF_INET6] {
+        send_request(&socket, family as u8)?;
+        receive(&socket, &mut sockets)?;
+    }
+    Ok(sockets)
+}
+
+/// Asks for every TCP socket of `family`, in any state, with its
+/// `tcp_info`.
+#[cfg(target_os = "linux")]
+fn send_request(socket: &OwnedFd, family: u8) -> io::Result<()> {
+    let mut request = [0u8; HEADER_LEN + REQUEST_LEN];
+    request[0..4].copy_from_slice(&((HEADER_LEN + REQUEST_LEN) as u32).to_ne_bytes());
+    request[4..6].copy_from_slice(&SOCK_DIAG_BY_FAMILY.to_ne_bytes());
+    request[6..8].copy_from_slice(&(NLM_F_REQUEST | NLM_F_DUMP).to_ne_bytes());
+    request[HEADER_LEN] = family;
+    request[HEADER_LEN + 1] = libc::IPPROTO_TCP as u8;
+    request[HEADER_LEN + 2] = 1 << (INET_DIAG_INFO - 1);
+    request[HEADER_LEN + 4..HEADER_LEN + 8].copy_from_slice(&u32::MAX.to_ne_bytes());
+    // SAFETY: the buffer is valid for its length; the kernel is the
+    // default destination of a netlink socket.
+    let sent = unsafe {
+        libc::send(
+            socket.as_raw_fd(),
+            request.as_ptr() as *const libc::c_void,
+            request.len(),
+            0,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reads the replies to a dump request into `sockets` until the dump ends.
+#[cfg(target_os = "linux")]
+fn receive(socket: &OwnedFd, sockets: &mut HashMap<u64, Bytes>) -> io::Result<()> {
+    let mut buffer = vec![0u8; 32 * 1024];
+    loop {
+        // SAFETY: the buffer is valid for its length.
+        let received = unsafe {
+            libc::recv(
+                socket.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if parse_messages(&buffer[..received as usize], sockets)? {
+            return Ok(());
+        }
+    }
+}
+
+/// Adds the sockets in one datagram of netlink messages to `sockets`.
+/// Returns whether the dump is done.
+fn parse_messages(mut data: &[u8], sockets: &mut HashMap<u64, Bytes>) -> io::Result<bool> {
+    while data.len() >= HEADER_LEN {
+        let len = u32_at(data, 0) as usize;
+        let kind = u16::from_ne_bytes([data[4], data[5]]);
+        if len < HEADER_LEN || len > data.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated netlink message",
+            ));
+        }
+        match kind {
+            NLMSG_DONE => return Ok(true),
+            NLMSG_ERROR => {
+                let code = i32::from_ne_bytes(data[16..20].try_into().unwrap());
+                return Err(io::Error::from_raw_os_error(-code));
+            }
+            SOCK_DIAG_BY_FAMILY => {
+                if let Some((inode, bytes)) = parse_socket(&data[HEADER_LEN..len]) {
+                    sockets.insert(inode, bytes);
+                }
+            }
+            _ => {}
+        }
+        data = &data[align(len).min(data.len())..];
+    }
+    Ok(false)
+}
+
+/// The inode and counters of the socket an `inet_diag_msg` describes, if
+/// it carries `tcp_info` with them.
+fn parse_socket(message: &[u8]) -> Option<(u64, Bytes)> {
+    let inode = u32_at(message.get(..MESSAGE_LEN)?, INODE_OFFSET) as u64;
+    let mut attributes = &message[MESSAGE_LEN..];
+    while attributes.len() >= 4 {
+        let len = u16::from_ne_bytes([attributes[0], attributes[1]]) as usize;
+        let kind = u16::from_ne_bytes([attributes[2], attributes[3]]);
+        if len < 4 || len > attributes.len() {
+            return None;
+        }
+        if kind == INET_DIAG_INFO {
+            let info = &attributes[4..len];
+            let bytes = Bytes {
+                sent: u64_at(info.get(..BYTES_ACKED_OFFSET + 8)?, BYTES_ACKED_OFFSET),
+                received: u64_at(
+                    info.get(..BYTES_RECEIVED_OFFSET + 8)?,
+                    BYTES_RECEIVED_OFFSET,
+                ),
+            };
+            return Some((inode, bytes));
+        }
+        attributes = &attributes[align(len).min(attributes.len())..];
+    }
+    None
+}
+
+/// Netlink messages and attributes are aligned to 4 bytes.
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_ne_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_counts_own_traffic() {
+        use std::{
+            io::{Read, Write},
+            net::{TcpListener, TcpStream},
+        };
+
+        let pid = std::process::id();
+        let mut traffic = Traffic::new();
+        traffic.begin_tick();
+        assert_eq!(traffic.read(pid), Some(Bytes::default()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        client.write_all(&[7; 5000]).unwrap();
+        server.read_exact(&mut [0; 5000]).unwrap();
+        server.write_all(&[7; 300]).unwrap();
+        client.read_exact(&mut [0; 300]).unwrap();
+
+        traffic.begin_tick();
+        let bytes = traffic.read(pid).unwrap();
+        // Both ends are ours.
+        assert!(bytes.sent >= 5300, "{:?}", bytes);
+        assert!(bytes.received >= 5300, "{:?}", bytes);
+        traffic.begin_tick();
+        assert_eq!(traffic.read(pid), Some(bytes), "Nothing sent since");
+        assert_eq!(traffic.read(99_999_999), None);
+    }
+
+    #[test]
+    fn test_parse_socket() {
+        let mut message = vec![0u8; MESSAGE_LEN];
+        message[INODE_OFFSET..INODE_OFFSET + 4].copy_from_slice(&42u32.to_ne_bytes());
+        // An attribute that is not `tcp_info` comes first.
+        message.extend_from_slice(&6u16.to_ne_bytes());
+        message.extend_from_slice(&1u16.to_ne_bytes());
+        message.extend_from_slice(&[0, 0, 0, 0]);
+        let mut info = vec![0u8; 232];
+        info[BYTES_ACKED_OFFSET..BYTES_ACKED_OFFSET + 8].copy_from_slice(&10u64.to_ne_bytes());
+        info[BYTES_RECEIVED_OFFSET..BYTES_RECEIVED_OFFSET + 8]
+            .copy_from_slice(&20u64.to_ne_bytes());
+        message.extend_from_slice(&(info.len() as u16 + 4).to_ne_bytes());
+        message.extend_from_slice(&INET_DIAG_INFO.to_ne_bytes());
+        message.extend_from_slice(&info);
+        assert_eq!(
+            parse_socket(&message),
+            Some((
+                42,
+                Bytes {
+                    sent: 10,
+                    received: 20
+                }
+            ))
+        );
+        // Kernels before 4.2 have no byte counters.
+        message.truncate(MESSAGE_LEN + 8 + 4 + 100);
+        message[MESSAGE_LEN + 8..MESSAGE_LEN + 10].copy_from_slice(&104u16.to_ne_bytes());
+        assert_eq!(parse_socket(&message), None);
+    }
+}
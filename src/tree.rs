@@ -0,0 +1,355 @@
+use crate::{source::ProcessInfo, ProcessSample};
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    process::{Child, Command, ExitStatus, Stdio},
+};
+use sysinfo::{Pid, PidExt, ProcessStatus};
+
+/// The process followed by `--follow-children` or started by `--exec` or
+/// `run`, and every process descended from it.
+pub struct ProcessTree {
+    root: Pid,
+    /// When the root started, once it has been seen, to tell it apart from a
+    /// later process given its PID.
+    root_start: Option<u64>,
+    root_exited: bool,
+    /// Whether the run ends once the root has exited.
+    stop_with_root: bool,
+    /// Whether the root's descendants are followed, or only the root.
+    descendants: bool,
+    /// Whether each process's usage includes that of its descendants.
+    aggregate: bool,
+    /// The command started by `--exec`, and how it exited once it has.
+    child: Option<Child>,
+    status: Option<ExitStatus>,
+    /// The processes followed as of the last update.
+    members: HashSet<Pid>,
+    /// Scratch space for updates, kept to reuse its allocations.
+    children: HashMap<Pid, Vec<Pid>>,
+    running: HashSet<Pid>,
+    next: HashSet<Pid>,
+    stack: Vec<Pid>,
+    usage: HashMap<Pid, (f32, f64)>,
+}
+
+impl ProcessTree {
+    /// Follows the running process `root`.
+    pub fn new(root: Pid, stop_with_root: bool) -> Self {
+        Self {
+            root,
+            root_start: None,
+            root_exited: false,
+            stop_with_root,
+            descendants: true,
+            aggregate: false,
+            child: None,
+            status: None,
+            members: HashSet::new(),
+            children: HashMap::new(),
+            running: HashSet::new(),
+            next: HashSet::new(),
+            stack: Vec::new(),
+            usage: HashMap::new(),
+        }
+    }
+
+    /// Starts `command` and follows it. Its output goes to stderr if
+    /// `quiet_stdout`, so that it cannot mix with output written to stdout.
+    pub fn spawn(command: &[OsString], stop_with_root: bool, quiet_stdout: bool) -> Result<Self> {
+        let (program, args) = command.split_first().context("No command to run")?;
+        let mut process = Command::new(program);
+        process.args(args);
+        if quiet_stdout {
+            process.stdout(Stdio::from(std::io::stderr()));
+        }
+        let child = process
+            .spawn()
+            .with_context(|| format!("Failed to run {}", program.to_string_lossy()))?;
+        let root = Pid::from_u32(child.id());
+        info!("Started {} as PID {}", program.to_string_lossy(), root);
+        let mut tree = Self::new(root, stop_with_root);
+        tree.child = Some(child);
+        Ok(tree)
+    }
+
+    /// Follows only the root, not the processes it starts.
+    pub fn without_descendants(mut self) -> Self {
+        self.descendants = false;
+        self
+    }
+
+    /// Adds the usage of each followed process's descendants to its own,
+    /// see [`ProcessTree::aggregate`].
+    pub fn aggregating(mut self) -> Self {
+        self.aggregate = true;
+        self
+    }
+
+    /// Whether `pid` is the root or one of its descendants.
+    pub fn contains(&self, pid: Pid) -> bool {
+        self.members.contains(&pid)
+    }
+
+    /// Whether the root has exited and the run should end with it.
+    pub fn should_stop(&self) -> bool {
+        self.stop_with_root && self.root_exited
+    }
+
+    /// Finds the descendants of the root in this tick's process table.
+    /// Processes stay followed after their parent exits and they are
+    /// adopted elsewhere, for as long as they run.
+    pub fn update<'a>(&mut self, processes: impl Iterator<Item = ProcessInfo<'a>>) {
+        // A command that exited is reaped here, so that it leaves the table
+        // rather than lingering as a zombie.
+        self.try_wait();
+        for children in self.children.values_mut() {
+            children.clear();
+        }
+        self.running.clear();
+        let mut root_running = false;
+        for process in processes {
+            let details = process.details.as_ref();
+            if details.is_some_and(|details| details.status == ProcessStatus::Zombie) {
+                continue;
+            }
+            if process.pid == self.root {
+                let start = *self.root_start.get_or_insert(process.start_time);
+                root_running = start == process.start_time && !self.root_exited;
+            }
+            self.running.insert(process.pid);
+            if let Some(parent) = details.and_then(|details| details.parent) {
+                self.children.entry(parent).or_default().push(process.pid);
+            }
+        }
+        self.children.retain(|_, children| !children.is_empty());
+        if !root_running && !self.root_exited {
+            match self.root_start {
+                Some(_) => info!("Followed process {} exited", self.root),
+                None => warn!("Process {} not found, it will not be logged", self.root),
+            }
+            self.root_exited = true;
+        }
+
+        self.stack.clear();
+        if root_running {
+            self.stack.push(self.root);
+        }
+        let running = &self.running;
+        self.stack.extend(
+            self.members
+                .iter()
+                .filter(|pid| **pid != self.root && running.contains(pid)),
+        );
+        self.next.clear();
+        while let Some(pid) = self.stack.pop() {
+            if self.next.insert(pid) && self.descendants {
+                if let Some(children) = self.children.get(&pid) {
+                    self.stack.extend(children);
+                }
+            }
+        }
+        std::mem::swap(&mut self.members, &mut self.next);
+    }
+
+    /// Adds the CPU and memory usage of the followed descendants of each
+    /// followed process in `samples` to its own, so that the root's sample
+    /// totals the tree, if aggregating. Must follow [`ProcessTree::update`]
+    /// for the same tick. Samples of other processes are left as they are.
+    pub fn aggregate(&mut self, samples: &mut [ProcessSample]) {
+        if !self.aggregate {
+            return;
+        }
+        self.usage.clear();
+        self.usage.extend(
+            samples
+                .iter()
+                .filter_map(|sample| Some((sample.pid?, sample)))
+                .filter(|(pid, _)| self.members.contains(pid))
+                .map(|(pid, sample)| (pid, (sample.cpu_usage, sample.memory_usage))),
+        );
+        for sample in samples {
+            let Some(root) = sample.pid.filter(|pid| self.usage.contains_key(pid)) else {
+                continue;
+            };
+            // A reused PID can make a parent look like its own descendant.
+            self.next.clear();
+            self.next.insert(root);
+            self.stack.clear();
+            self.stack
+                .extend(self.children.get(&root).into_iter().flatten());
+            while let Some(pid) = self.stack.pop() {
+                if !self.next.insert(pid) {
+                    continue;
+                }
+                if let Some((cpu, memory)) = self.usage.get(&pid) {
+                    sample.cpu_usage += cpu;
+                    sample.memory_usage += memory;
+                }
+                if let Some(children) = self.children.get(&pid) {
+                    self.stack.extend(children);
+                }
+            }
+        }
+    }
+
+    /// How `pid` exited, if it is the command started by `--exec` and has
+    /// exited.
+    pub fn exit_status(&self, pid: Pid) -> Option<ExitStatus> {
+        self.status.filter(|_| pid == self.root)
+    }
+
+    /// How the command started by `--exec` exited, or `None` if it is still
+    /// running or there is none. A command still running is left to run.
+    pub fn finish(&mut self) -> Option<ExitStatus> {
+        self.try_wait();
+        if self.child.is_some() && self.status.is_none() {
+            warn!(
+                "Process {} is still running, chronologger stops following it",
+                self.root
+            );
+        }
+        self.status
+    }
+
+    fn try_wait(&mut self) {
+        let Some(child) = self.child.as_mut().filter(|_| self.status.is_none()) else {
+            return;
+        };
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                info!("Command exited with {}", status);
+                self.status = Some(status);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to check whether the command exited: {}", e),
+        }
+    }
+}
+
+/// The exit code that passes on how a command exited: its own, or 128 plus
+/// the number of the signal that killed it, as shells report it.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::ProcessDetails;
+
+    /// A process table of `(pid, parent)` pairs.
+    fn table(entries: &[(u32, u32)]) -> Vec<ProcessInfo<'static>> {
+        entries
+            .iter()
+            .map(|&(pid, parent)| ProcessInfo {
+                pid: Pid::from_u32(pid),
+                name: "worker",
+                cpu_usage: 0.0,
+                memory: 0,
+                start_time: 0,
+                cpu_time: None,
+                details: Some(ProcessDetails {
+                    resident_bytes: 0,
+                    virtual_bytes: 0,
+                    disk_read: Ok(0),
+                    disk_written: Ok(0),
+                    status: ProcessStatus::Run,
+                    parent: Some(Pid::from_u32(parent)),
+                    started: 0,
+                    cmd: Ok(&[]),
+                    uid: None,
+                }),
+            })
+            .collect()
+    }
+
+    fn members(tree: &ProcessTree) -> Vec<u32> {
+        let mut pids: Vec<u32> = tree.members.iter().map(|pid| pid.as_u32()).collect();
+        pids.sort_unstable();
+        pids
+    }
+
+    #[test]
+    fn test_follows_descendants() {
+        let mut tree = ProcessTree::new(Pid::from_u32(10), true);
+        // 10 -> 11 -> 12 and 10 -> 13, with 20 unrelated.
+        tree.update(table(&[(1, 0), (10, 1), (11, 10), (12, 11), (13, 10), (20, 1)]).into_iter());
+        assert_eq!(members(&tree), [10, 11, 12, 13]);
+        assert!(!tree.contains(Pid::from_u32(20)));
+
+        // A child started since is picked up.
+        tree.update(table(&[(1, 0), (10, 1), (11, 10), (14, 11), (20, 1)]).into_iter());
+        assert_eq!(members(&tree), [10, 11, 14]);
+        assert!(!tree.should_stop());
+    }
+
+    #[test]
+    fn test_keeps_orphans_after_root_exits() {
+        let mut tree = ProcessTree::new(Pid::from_u32(10), true);
+        tree.update(table(&[(1, 0), (10, 1), (11, 10), (12, 11)]).into_iter());
+        // The root and 11 exit, and 12 is adopted by init.
+        tree.update(table(&[(1, 0), (12, 1), (15, 12)]).into_iter());
+        assert_eq!(members(&tree), [12, 15]);
+        assert!(tree.should_stop());
+    }
+
+    #[test]
+    fn test_without_descendants() {
+        let mut tree = ProcessTree::new(Pid::from_u32(10), true).without_descendants();
+        tree.update(table(&[(1, 0), (10, 1), (11, 10), (12, 11)]).into_iter());
+        assert_eq!(members(&tree), [10]);
+        tree.update(table(&[(1, 0), (11, 1)]).into_iter());
+        assert!(members(&tree).is_empty());
+        assert!(tree.should_stop());
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let mut tree = ProcessTree::new(Pid::from_u32(10), true).aggregating();
+        // 10 -> 11 -> 12 and 10 -> 13, with 20 unrelated.
+        tree.update(table(&[(1, 0), (10, 1), (11, 10), (12, 11), (13, 10), (20, 1)]).into_iter());
+        let mut samples: Vec<ProcessSample> = [10, 11, 12, 13, 20]
+            .iter()
+            .map(|&pid| ProcessSample {
+                pid: Some(Pid::from_u32(pid)),
+                name: pid.to_string(),
+                cpu_usage: pid as f32,
+                memory_usage: 1.0,
+                extras: Vec::new(),
+            })
+            .collect();
+        tree.aggregate(&mut samples);
+        let totals: Vec<(f32, f64)> = samples
+            .iter()
+            .map(|sample| (sample.cpu_usage, sample.memory_usage))
+            .collect();
+        assert_eq!(
+            totals,
+            [
+                (46.0, 4.0),
+                (23.0, 2.0),
+                (12.0, 1.0),
+                (13.0, 1.0),
+                (20.0, 1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reused_root_pid_is_not_followed() {
+        let mut tree = ProcessTree::new(Pid::from_u32(10), false);
+        tree.update(table(&[(1, 0), (10, 1)]).into_iter());
+        let mut reused = table(&[(1, 0), (10, 1)]);
+        reused[1].start_time = 99;
+        tree.update(reused.into_iter());
+        assert!(members(&tree).is_empty());
+        assert!(!tree.should_stop());
+    }
+}
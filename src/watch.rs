@@ -0,0 +1,460 @@
+//! The live dashboard of `chronologger watch`: a table of the processes of
+//! the latest tick and sparklines of their total CPU and memory usage, drawn
+//! on the terminal with ratatui while the capture is written.
+
+use crate::{sink::Sink, ProcessSample};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use log::LevelFilter;
+use ratatui::{
+    backend::{Backend, CrosstermBackend},
+    crossterm::{
+        cursor,
+        event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+        execute, terminal,
+    },
+    layout::{Constraint, Layout},
+    text::Line,
+    widgets::{Row as TableRow, Sparkline, Table},
+    Frame,
+};
+use std::{
+    collections::VecDeque,
+    io::{IsTerminal, Stdout},
+    panic,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Once,
+    },
+    thread,
+    time::Duration,
+};
+use sysinfo::{Pid, PidExt};
+
+/// How often the key thread checks whether the dashboard is still shown.
+const KEY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Whether the dashboard holds the terminal, for the panic hook to give it
+/// back.
+static SHOWN: AtomicBool = AtomicBool::new(false);
+
+/// Options of `chronologger watch`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Ticks of history the sparklines show.
+    pub history: usize,
+    /// Whether log messages are held back while the dashboard is shown,
+    /// because they would go to the same terminal.
+    pub hide_logs: bool,
+}
+
+/// What the table is sorted by, chosen with a key while it is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortBy {
+    Cpu,
+    Memory,
+    Pid,
+    Name,
+}
+
+impl SortBy {
+    fn from_key(key: char) -> Option<Self> {
+        match key.to_ascii_lowercase() {
+            'c' => Some(SortBy::Cpu),
+            'm' => Some(SortBy::Memory),
+            'p' => Some(SortBy::Pid),
+            'n' => Some(SortBy::Name),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SortBy::Cpu => "CPU",
+            SortBy::Memory => "memory",
+            SortBy::Pid => "PID",
+            SortBy::Name => "name",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    /// Empty for a `--group-by` group.
+    pid: Option<u32>,
+    name: String,
+    cpu: f32,
+    memory: f64,
+}
+
+/// Everything the dashboard shows.
+struct Screen {
+    output: String,
+    history: usize,
+    sort_by: SortBy,
+    time: Option<DateTime<Local>>,
+    rows: Vec<Row>,
+    /// Total CPU and memory usage of the latest ticks, oldest first.
+    cpu: VecDeque<f64>,
+    memory: VecDeque<f64>,
+}
+
+impl Screen {
+    fn new(output: &str, history: usize) -> Self {
+        Self {
+            output: output.to_string(),
+            history,
+            sort_by: SortBy::Cpu,
+            time: None,
+            rows: Vec::new(),
+            cpu: VecDeque::with_capacity(history),
+            memory: VecDeque::with_capacity(history),
+        }
+    }
+
+    fn update(&mut self, time: &DateTime<Local>, samples: &[ProcessSample]) {
+        self.time = Some(*time);
+        self.rows = samples
+            .iter()
+            .map(|sample| Row {
+                pid: sample.pid.map(Pid::as_u32),
+                name: sample.name.clone(),
+                cpu: sample.cpu_usage,
+                memory: sample.memory_usage,
+            })
+            .collect();
+        self.sort();
+        for (history, total) in [
+            (
+                &mut self.cpu,
+                samples.iter().map(|s| s.cpu_usage as f64).sum(),
+            ),
+            (
+                &mut self.memory,
+                samples.iter().map(|s| s.memory_usage).sum(),
+            ),
+        ] {
+            if history.len() == self.history {
+                history.pop_front();
+            }
+            history.push_back(total);
+        }
+    }
+
+    fn sort_by(&mut self, sort_by: SortBy) {
+        self.sort_by = sort_by;
+        self.sort();
+    }
+
+    /// Sorts usage highest first and PIDs and names in ascending order,
+    /// breaking ties by PID, and groups, which have none, by name.
+    fn sort(&mut self) {
+        let sort_by = self.sort_by;
+        self.rows.sort_by(|a, b| {
+            match sort_by {
+                SortBy::Cpu => b.cpu.total_cmp(&a.cpu),
+                SortBy::Memory => b.memory.total_cmp(&a.memory),
+                SortBy::Pid => std::cmp::Ordering::Equal,
+                SortBy::Name => a.name.cmp(&b.name),
+            }
+            .then(a.pid.cmp(&b.pid))
+            .then_with(|| a.name.cmp(&b.name))
+        });
+    }
+
+    /// Draws the screen on `frame`.
+    fn render(&self, frame: &mut Frame) {
+        let [title, cpu, memory, help, table] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Min(0),
+        ])
+        .areas(frame.area());
+        frame.render_widget(
+            Line::from(format!(
+                "chronologger watch  {}  {} processes  writing {}",
+                self.time.map_or_else(
+                    || "--:--:--".to_string(),
+                    |time| time.format("%H:%M:%S").to_string()
+                ),
+                self.rows.len(),
+                self.output
+            )),
+            title,
+        );
+        for (label, history, area) in [("CPU", &self.cpu, cpu), ("Mem", &self.memory, memory)] {
+            let [label_area, spark_area, value_area] = Layout::horizontal([
+                Constraint::Length(5),
+                Constraint::Min(0),
+                Constraint::Length(9),
+            ])
+            .areas(area);
+            frame.render_widget(Line::from(label), label_area);
+            frame.render_widget(
+                Sparkline::default().data(sparkline(history, spark_area.width as usize)),
+                spark_area,
+            );
+            frame.render_widget(
+                Line::from(format!(
+                    "{:>8.1}%",
+                    history.back().copied().unwrap_or_default()
+                )),
+                value_area,
+            );
+        }
+        frame.render_widget(
+            Line::from(format!(
+                "Sorted by {}: [c]pu [m]emory [p]id [n]ame, [q]uit",
+                self.sort_by.name()
+            )),
+            help,
+        );
+        let rows = self.rows.iter().map(|row| {
+            TableRow::new([
+                format!(
+                    "{:>8}",
+                    row.pid.map_or_else(String::new, |pid| pid.to_string())
+                ),
+                format!("{:>7.1}", row.cpu),
+                format!("{:>7.2}", row.memory),
+                row.name.clone(),
+            ])
+        });
+        let widths = [
+            Constraint::Length(8),
+            Constraint::Length(7),
+            Constraint::Length(7),
+            Constraint::Min(0),
+        ];
+        let header = TableRow::new([
+            format!("{:>8}", "PID"),
+            format!("{:>7}", "CPU %"),
+            format!("{:>7}", "MEM %"),
+            "NAME".to_string(),
+        ]);
+        frame.render_widget(Table::new(rows, widths).header(header), table);
+    }
+}
+
+/// The latest `width` values, in tenths so that the bars keep a decimal of
+/// precision. The sparkline scales them to the highest one shown.
+fn sparkline(values: &VecDeque<f64>, width: usize) -> Vec<u64> {
+    values
+        .iter()
+        .skip(values.len().saturating_sub(width))
+        .map(|value| (value * 10.0).round() as u64)
+        .collect()
+}
+
+/// What the tick and key threads share: the screen and the terminal it is
+/// drawn on.
+struct Shown<B: Backend> {
+    screen: Screen,
+    terminal: ratatui::Terminal<B>,
+}
+
+impl<B: Backend> Shown<B> {
+    fn draw(&mut self) {
+        let screen = &self.screen;
+        // A terminal that has gone away has no one left to show anything to.
+        let _ = self.terminal.draw(|frame| screen.render(frame));
+    }
+}
+
+/// The dashboard, as a sink receiving every tick. A thread reads the keys
+/// that sort the table or quit the run.
+pub struct Dashboard {
+    shown: Arc<Mutex<Shown<CrosstermBackend<Stdout>>>>,
+    /// The log level to restore once the dashboard is gone.
+    log_level: Option<LevelFilter>,
+}
+
+impl Dashboard {
+    /// Takes over the terminal for a capture writing to `output`. `q`
+    /// clears `running`, as does Ctrl-C, which raw mode turns into a key.
+    pub fn new(output: &str, options: &Options, running: Arc<AtomicBool>) -> Result<Self> {
+        if !std::io::stdout().is_terminal() {
+            bail!("watch needs a terminal to draw on");
+        }
+        let terminal = enter()?;
+        let log_level = options.hide_logs.then(|| {
+            let level = log::max_level();
+            log::set_max_level(LevelFilter::Off);
+            level
+        });
+        let mut shown = Shown {
+            screen: Screen::new(output, options.history),
+            terminal,
+        };
+        shown.draw();
+        let shown = Arc::new(Mutex::new(shown));
+        let keys = shown.clone();
+        thread::Builder::new()
+            .name("watch-keys".to_string())
+            .spawn(move || read_keys(&keys, &running))?;
+        Ok(Self { shown, log_level })
+    }
+}
+
+impl Sink for Dashboard {
+    fn publish(&mut self, timestamp: &DateTime<Local>, samples: &[ProcessSample]) -> Result<()> {
+        let mut shown = self.shown.lock().unwrap();
+        shown.screen.update(timestamp, samples);
+        shown.draw();
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        leave();
+        if let Some(level) = self.log_level.take() {
+            log::set_max_level(level);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        leave();
+    }
+}
+
+/// Sorts the table as the keys typed ask and redraws it when the terminal
+/// is resized, until `q` or Ctrl-C stops the run or the dashboard is gone.
+fn read_keys<B: Backend>(shown: &Mutex<Shown<B>>, running: &AtomicBool) {
+    while SHOWN.load(Ordering::SeqCst) {
+        match event::poll(KEY_POLL_INTERVAL) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(_) => return,
+        }
+        let Ok(event) = event::read() else {
+            return;
+        };
+        let mut shown = shown.lock().unwrap();
+        match event {
+            Event::Key(KeyEvent {
+                code: KeyCode::Char(key),
+                modifiers,
+                kind: KeyEventKind::Press,
+                ..
+            }) => {
+                let interrupt = key == 'c' && modifiers.contains(KeyModifiers::CONTROL);
+                if interrupt || key.eq_ignore_ascii_case(&'q') {
+                    running.store(false, Ordering::SeqCst);
+                    return;
+                }
+                if let Some(sort_by) = SortBy::from_key(key) {
+                    shown.screen.sort_by(sort_by);
+                    shown.draw();
+                }
+            }
+            Event::Resize(..) => {
+                let _ = shown.terminal.autoresize();
+                shown.draw();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Puts the terminal in raw mode on the alternate screen with the cursor
+/// hidden. A panic gives the terminal back before its message is printed.
+fn enter() -> Result<ratatui::Terminal<CrosstermBackend<Stdout>>> {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            leave();
+            previous(info);
+        }));
+    });
+    terminal::enable_raw_mode().context("Failed to put the terminal in raw mode")?;
+    SHOWN.store(true, Ordering::SeqCst);
+    let mut stdout = std::io::stdout();
+    execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)
+        .context("Failed to switch to the alternate screen")?;
+    ratatui::Terminal::new(CrosstermBackend::new(stdout)).context("Failed to set up the terminal")
+}
+
+/// Gives the terminal back as it was, if the dashboard holds it.
+fn leave() {
+    if !SHOWN.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    let _ = execute!(
+        std::io::stdout(),
+        cursor::Show,
+        terminal::LeaveAlternateScreen
+    );
+    let _ = terminal::disable_raw_mode();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::sample;
+
+    #[test]
+    fn test_sparkline() {
+        let values: VecDeque<f64> = [0.0, 50.0, 100.05].into_iter().collect();
+        assert_eq!(sparkline(&values, 5), [0, 500, 1001]);
+        assert_eq!(sparkline(&values, 2), [500, 1001]);
+    }
+
+    #[test]
+    fn test_screen_sorts_and_keeps_history() {
+        let mut screen = Screen::new("capture.csv", 2);
+        let now = Local::now();
+        for cpu in [10.0, 20.0, 30.0] {
+            screen.update(
+                &now,
+                &[
+                    sample(7, "zsh", cpu, 1.0),
+                    sample(3, "bash", 5.0, 9.0),
+                    sample(5, "init", 5.0, 0.5),
+                ],
+            );
+        }
+        assert_eq!(Vec::from(screen.cpu.clone()), [30.0, 40.0]);
+        let pids = |screen: &Screen| {
+            screen
+                .rows
+                .iter()
+                .map(|row| row.pid.unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pids(&screen), [7, 3, 5]);
+        screen.sort_by(SortBy::Memory);
+        assert_eq!(pids(&screen), [3, 7, 5]);
+        screen.sort_by(SortBy::Pid);
+        assert_eq!(pids(&screen), [3, 5, 7]);
+        screen.sort_by(SortBy::Name);
+        assert_eq!(pids(&screen), [3, 5, 7]);
+    }
+
+    #[test]
+    fn test_render_fits_terminal() {
+        use ratatui::backend::TestBackend;
+
+        let mut shown = Shown {
+            screen: Screen::new("capture.csv", 60),
+            terminal: ratatui::Terminal::new(TestBackend::new(40, 10)).unwrap(),
+        };
+        let samples: Vec<_> = (1..=50)
+            .map(|pid| sample(pid, "a-process-with-a-rather-long-name", pid as f32, 1.0))
+            .collect();
+        shown.screen.update(&Local::now(), &samples);
+        shown.draw();
+        let buffer = shown.terminal.backend().buffer();
+        let lines: Vec<String> = (0..10)
+            .map(|y| (0..40).map(|x| buffer[(x, y)].symbol()).collect())
+            .collect();
+        assert!(lines[0].starts_with("chronologger watch"), "{:?}", lines);
+        assert!(lines[4].contains("PID"), "{:?}", lines[4]);
+        assert!(lines[5].trim_start().starts_with("50 "), "{:?}", lines[5]);
+        assert!(lines[9].trim_start().starts_with("46 "), "{:?}", lines[9]);
+    }
+}
@@ -0,0 +1,29 @@
+use std::process::Command;
+
+#[test]
+fn test_analyze_capture() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = dir.path().join("capture.csv");
+    let capture = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--interval", "1", "--duration", "2", "--output"])
+        .arg(&output)
+        .status()
+        .expect("Failed to start chronologger");
+    assert!(capture.success(), "Exited with {}", capture);
+
+    let analyzed = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["analyze", "--top", "3", "--json"])
+        .arg(&output)
+        .output()
+        .expect("Failed to start chronologger");
+    assert!(analyzed.status.success(), "Exited with {}", analyzed.status);
+    let stats: serde_json::Value = serde_json::from_slice(&analyzed.stdout).unwrap();
+    let stats = stats.as_array().unwrap();
+    assert_eq!(stats.len(), 3);
+    for process in stats {
+        assert!(process["samples"].as_u64().unwrap() >= 1);
+        let cpu = &process["cpu_percent"];
+        assert!(cpu["min"].as_f64() <= cpu["p95"].as_f64());
+        assert!(cpu["p95"].as_f64() <= cpu["max"].as_f64());
+    }
+}
@@ -0,0 +1,40 @@
+#![cfg(unix)]
+
+use std::{
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// `chronologger -o - | head -3` must stop at the next tick after `head`
+/// exits, with status 0 and without waiting out `--duration`.
+#[test]
+fn test_reader_exiting_early_ends_run_cleanly() {
+    let started = Instant::now();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--output", "-", "--interval", "1", "--duration", "30"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start chronologger");
+
+    let stdout = child.stdout.take().unwrap();
+    let lines: Vec<String> = BufReader::new(stdout)
+        .lines()
+        .take(3)
+        .map(|line| line.expect("Failed to read line"))
+        .collect();
+    assert_eq!(
+        lines[0],
+        "Timestamp,PID,Process Name,CPU Usage (%),Memory Usage (%)"
+    );
+    assert_eq!(lines.len(), 3);
+
+    let status = child.wait().expect("Failed to wait for chronologger");
+    assert!(status.success(), "Exited with {}", status);
+    assert!(
+        started.elapsed() < Duration::from_secs(15),
+        "Kept running for {:?} after the reader exited",
+        started.elapsed()
+    );
+}
@@ -0,0 +1,52 @@
+use chronologger::{CsvSink, RecordSink, Sampler, SamplerConfig};
+
+#[test]
+fn test_samples_own_process_into_csv() {
+    let pid = std::process::id();
+    let mut sampler = Sampler::new(SamplerConfig::new().pid(pid)).unwrap();
+    let records = sampler.sample().unwrap();
+    assert_eq!(
+        records.len(),
+        1,
+        "Expected only this process: {:?}",
+        records
+    );
+    assert_eq!(records[0].pid, pid);
+    assert!(records[0].memory_percent > 0.0);
+
+    let mut sink = CsvSink::new(Vec::new());
+    sink.write(&records).unwrap();
+    let csv = String::from_utf8(sink.into_inner().unwrap()).unwrap();
+    let row = csv.lines().nth(1).expect("Missing row");
+    assert!(
+        row.contains(&format!(",{},", pid)),
+        "Unexpected row {}",
+        row
+    );
+}
+
+#[test]
+fn test_runs_capture_in_process() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = dir.path().join("capture.jsonl");
+    let config = chronologger::Config::try_parse_from([
+        "chronologger".as_ref(),
+        "--once".as_ref(),
+        "--no-config".as_ref(),
+        "--format".as_ref(),
+        "jsonl".as_ref(),
+        "--pid".as_ref(),
+        std::process::id().to_string().as_ref(),
+        "--output".as_ref(),
+        output.as_os_str(),
+    ])
+    .unwrap();
+    chronologger::run(config).unwrap();
+
+    let contents = std::fs::read_to_string(&output).unwrap();
+    let record: serde_json::Value = serde_json::from_str(contents.lines().next().unwrap()).unwrap();
+    assert_eq!(record["pid"], std::process::id());
+
+    let verify = chronologger::Config::try_parse_from(["chronologger", "verify", "capture.csv"]);
+    assert!(verify.is_err());
+}
@@ -0,0 +1,680 @@
+use std::{
+    collections::HashSet,
+    fs,
+    process::Command,
+    time::{Duration, Instant},
+};
+
+/// A short capture must produce the header, rows for the running system, the
+/// integrity footer and the sidecar, on every platform chronologger builds
+/// for.
+#[test]
+fn test_short_capture_writes_header_and_rows() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = dir.path().join("capture.csv");
+    let status = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--interval", "1", "--duration", "2", "--output"])
+        .arg(&output)
+        .status()
+        .expect("Failed to start chronologger");
+    assert!(status.success(), "Exited with {}", status);
+
+    let contents = fs::read_to_string(&output).unwrap();
+    let mut lines = contents.lines();
+    assert_eq!(
+        lines.next(),
+        Some("Timestamp,PID,Process Name,CPU Usage (%),Memory Usage (%)")
+    );
+    let mut lines: Vec<&str> = lines.collect();
+    let footer = lines.pop().unwrap_or_default();
+    assert!(
+        footer.starts_with("# integrity: "),
+        "No footer: {:?}",
+        footer
+    );
+    let rows: Vec<Vec<&str>> = lines.iter().map(|line| line.split(',').collect()).collect();
+    assert!(rows.len() > 1, "No processes were logged");
+    for row in &rows {
+        assert!(row.len() >= 5, "Short row {:?}", row);
+        assert!(row[1].parse::<u32>().is_ok(), "Bad PID in {:?}", row);
+        let memory = row[row.len() - 1].parse::<f64>().expect("Bad memory usage");
+        assert!(
+            (0.0..=100.0).contains(&memory),
+            "Memory out of range in {:?}",
+            row
+        );
+    }
+
+    let sidecar = dir.path().join("capture.csv.meta.json");
+    let sidecar: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(sidecar).unwrap()).unwrap();
+    assert_eq!(sidecar["options"]["duration"], "2");
+    assert_eq!(sidecar["termination_reason"], "completed");
+    assert_eq!(sidecar["rows"], rows.len());
+}
+
+/// `--once` writes a single tick to stdout and exits without waiting out a
+/// duration.
+#[test]
+fn test_once_writes_one_tick() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let started = Instant::now();
+    let output = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--once", "--output", "-"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to start chronologger");
+    assert!(output.status.success(), "Exited with {}", output.status);
+    assert!(
+        started.elapsed() < Duration::from_secs(10),
+        "Took {:?}",
+        started.elapsed()
+    );
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut lines = stdout.lines();
+    assert_eq!(
+        lines.next(),
+        Some("Timestamp,PID,Process Name,CPU Usage (%),Memory Usage (%)")
+    );
+    let timestamps: HashSet<&str> = lines
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    assert_eq!(timestamps.len(), 1, "Ticks at {:?}", timestamps);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("summary"), "Summary in {:?}", stderr);
+}
+
+/// `--format json` written to stdout is one JSON array, closed when the
+/// capture ends.
+#[test]
+fn test_json_to_stdout_is_one_document() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--once", "--format", "json", "--output", "-"])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to start chronologger");
+    assert!(output.status.success(), "Exited with {}", output.status);
+
+    let document: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("Output is not one JSON document");
+    let records = document.as_array().unwrap();
+    assert!(!records.is_empty(), "No processes were logged");
+    for record in records {
+        assert!(record["pid"].is_u64(), "Bad PID in {}", record);
+        assert!(
+            record["memory_percent"].is_f64(),
+            "Bad memory in {}",
+            record
+        );
+    }
+}
+
+/// `--top` keeps only the heaviest processes of a tick, highest first.
+#[test]
+fn test_top_keeps_heaviest_processes() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args([
+            "--once",
+            "--top",
+            "3",
+            "--sort-by",
+            "memory",
+            "--output",
+            "-",
+        ])
+        .current_dir(dir.path())
+        .output()
+        .expect("Failed to start chronologger");
+    assert!(output.status.success(), "Exited with {}", output.status);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let memory: Vec<f64> = stdout
+        .lines()
+        .skip(1)
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.rsplit(',').next().unwrap().parse().unwrap())
+        .collect();
+    assert!(
+        (1..=3).contains(&memory.len()),
+        "{} rows logged",
+        memory.len()
+    );
+    assert!(
+        memory.windows(2).all(|pair| pair[0] >= pair[1]),
+        "Not highest first: {:?}",
+        memory
+    );
+}
+
+/// `--columns` writes a child's parent PID and its command line, quoting an
+/// argument with spaces, commas and quotes so that it reads back intact, and
+/// leaves out the memory column it does not list.
+#[test]
+#[cfg(unix)]
+fn test_columns_quote_command_line() {
+    // The trailing `:` keeps the shell from replacing itself with `sleep`.
+    let mut child = Command::new("sh")
+        .args(["-c", "sleep 30; :", "label, with \"quotes\""])
+        .spawn()
+        .expect("Failed to start sh");
+    let output = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--once", "--output", "-", "--pid"])
+        .arg(child.id().to_string())
+        .args(["--columns", "timestamp,pid,name,cpu,ppid,status,cmdline"])
+        .output()
+        .expect("Failed to start chronologger");
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(output.status.success(), "Exited with {}", output.status);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(output.stdout.as_slice());
+    let headers = reader.headers().unwrap().clone();
+    assert_eq!(
+        headers.iter().skip(3).collect::<Vec<_>>(),
+        ["CPU Usage (%)", "Parent PID", "Status", "Command Line"]
+    );
+    let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+    assert_eq!(rows.len(), 1, "Rows: {:?}", rows);
+    assert_eq!(&rows[0][1], child.id().to_string());
+    assert_eq!(&rows[0][4], std::process::id().to_string());
+    assert!(!rows[0][5].is_empty());
+    assert_eq!(&rows[0][6], "sh -c sleep 30; : label, with \"quotes\"");
+}
+
+/// `--daemon` returns to the shell at once and leaves the capture running
+/// in the background, with its PID in the `--pid-file` until it ends.
+#[test]
+#[cfg(unix)]
+fn test_daemon_detaches_and_writes_pid_file() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = dir.path().join("capture.csv");
+    let pid_file = dir.path().join("chronologger.pid");
+    let started = Instant::now();
+    let status = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args([
+            "--daemon",
+            "--interval",
+            "200ms",
+            "--duration",
+            "2",
+            "--output",
+        ])
+        .arg(&output)
+        .arg("--pid-file")
+        .arg(&pid_file)
+        .status()
+        .expect("Failed to start chronologger");
+    assert!(status.success(), "Exited with {}", status);
+    assert!(started.elapsed() < Duration::from_secs(2), "Did not detach");
+
+    let pid: u32 = fs::read_to_string(&pid_file)
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    assert_ne!(pid, std::process::id());
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while pid_file.exists() {
+        assert!(Instant::now() < deadline, "The daemon did not end");
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    let contents = fs::read_to_string(&output).unwrap();
+    assert!(
+        contents
+            .lines()
+            .last()
+            .unwrap()
+            .starts_with("# integrity: "),
+        "No footer"
+    );
+}
+
+/// SIGHUP flushes a running capture without stopping it, and SIGTERM ends
+/// it with every row complete and the integrity footer last.
+#[test]
+#[cfg(unix)]
+fn test_signals_leave_complete_rows() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = dir.path().join("capture.csv");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--interval", "100ms", "--duration", "60", "--output"])
+        .arg(&output)
+        .spawn()
+        .expect("Failed to start chronologger");
+    let pid = child.id() as libc::pid_t;
+    let rows = |contents: &str| -> usize {
+        let mut reader = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .from_reader(contents.as_bytes());
+        reader
+            .records()
+            .map(|record| {
+                let record = record.expect("Incomplete row");
+                assert_eq!(record.len(), 5, "Short row {:?}", record);
+            })
+            .count()
+    };
+
+    std::thread::sleep(Duration::from_millis(1000));
+    // SAFETY: signalling the child started above.
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGHUP) }, 0);
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(child.try_wait().unwrap(), None, "SIGHUP ended the run");
+    let flushed = fs::read_to_string(&output).unwrap();
+    assert!(flushed.ends_with('\n'), "Cut short: {:?}", flushed);
+    assert!(rows(&flushed) > 0, "Nothing flushed");
+
+    // SAFETY: as above.
+    assert_eq!(unsafe { libc::kill(pid, libc::SIGTERM) }, 0);
+    let status = child.wait().unwrap();
+    assert!(status.success(), "Exited with {}", status);
+    let contents = fs::read_to_string(&output).unwrap();
+    assert!(
+        contents
+            .lines()
+            .last()
+            .unwrap()
+            .starts_with("# integrity: "),
+        "No footer"
+    );
+    assert!(rows(&contents) >= rows(&flushed));
+}
+
+/// A busy child goes over `--alert-cpu`, which is recorded in the alerts
+/// file and, with `--fail-on-alert`, fails the run. Its single alert per
+/// period keeps the file to one row.
+#[test]
+#[cfg(unix)]
+fn test_alert_on_busy_process() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let mut child = Command::new("sh")
+        .args(["-c", "while :; do :; done"])
+        .spawn()
+        .expect("Failed to start sh");
+    let run = |name: &str, fail: bool| {
+        let output = dir.path().join(name);
+        let mut command = Command::new(env!("CARGO_BIN_EXE_chronologger"));
+        command
+            .args([
+                "--interval",
+                "500ms",
+                "--duration",
+                "2",
+                "--alert-cpu",
+                "20",
+            ])
+            .args(["--pid", &child.id().to_string(), "--output"])
+            .arg(&output);
+        if fail {
+            command.arg("--fail-on-alert");
+        }
+        let status = command.status().expect("Failed to start chronologger");
+        let alerts = fs::read_to_string(dir.path().join(name.replace(".csv", ".alerts.csv")))
+            .expect("No alerts file");
+        (status, alerts)
+    };
+    let (passed, alerts) = run("passing.csv", false);
+    let (failed, _) = run("failing.csv", true);
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert!(passed.success(), "Exited with {}", passed);
+    assert!(!failed.success(), "Alert did not fail the run");
+    let rows: Vec<&str> = alerts.lines().skip(1).collect();
+    assert_eq!(rows.len(), 1, "Alerts: {}", alerts);
+    assert!(
+        rows[0].contains(&format!(",{},sh,cpu,", child.id())),
+        "Unexpected alert {}",
+        rows[0]
+    );
+}
+
+/// A run stopped by SIGINT still writes `--summary`, with one row for the
+/// watched child covering every tick it was sampled in.
+#[test]
+#[cfg(unix)]
+fn test_summary_written_on_interrupt() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let summary = dir.path().join("summary.csv");
+    let mut child = Command::new("sh")
+        .args(["-c", "while :; do :; done"])
+        .spawn()
+        .expect("Failed to start sh");
+    let mut logger = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--interval", "200ms", "--duration", "60", "--pid"])
+        .arg(child.id().to_string())
+        .arg("--output")
+        .arg(dir.path().join("capture.csv"))
+        .arg("--summary")
+        .arg(&summary)
+        .spawn()
+        .expect("Failed to start chronologger");
+    std::thread::sleep(Duration::from_millis(1500));
+    // SAFETY: signalling the child started above.
+    assert_eq!(
+        unsafe { libc::kill(logger.id() as libc::pid_t, libc::SIGINT) },
+        0
+    );
+    let status = logger.wait().unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(status.success(), "Exited with {}", status);
+
+    let mut reader = csv::Reader::from_path(&summary).expect("No summary");
+    let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+    assert_eq!(rows.len(), 1, "Summary: {:?}", rows);
+    let row = &rows[0];
+    assert_eq!(&row[0], child.id().to_string());
+    let samples: u64 = row[2].parse().unwrap();
+    assert!(samples >= 3, "Only {} samples", samples);
+    let value = |column: usize| -> f64 { row[column].parse().unwrap() };
+    assert!(value(4) >= value(3), "CPU max below mean: {:?}", row);
+    assert!(value(6) >= value(5), "Memory max below mean: {:?}", row);
+    assert!(row[8] > row[7], "Last seen not after first seen: {:?}", row);
+}
+
+/// A `.gz` output is a gzip stream the system's gzip reads back, whose
+/// contents have the header, rows and a footer that verifies.
+#[test]
+#[cfg(unix)]
+fn test_gzip_output_decompresses() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = dir.path().join("capture.csv.gz");
+    let status = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--interval", "500ms", "--duration", "2", "--output"])
+        .arg(&output)
+        .status()
+        .expect("Failed to start chronologger");
+    assert!(status.success(), "Exited with {}", status);
+
+    let decompressed = Command::new("gzip")
+        .arg("-dk")
+        .arg(&output)
+        .status()
+        .expect("Failed to start gzip");
+    assert!(decompressed.success(), "gzip exited with {}", decompressed);
+    let csv_path = dir.path().join("capture.csv");
+    let contents = fs::read_to_string(&csv_path).unwrap();
+    let mut reader = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(contents.as_bytes());
+    assert_eq!(
+        reader.headers().unwrap(),
+        vec![
+            "Timestamp",
+            "PID",
+            "Process Name",
+            "CPU Usage (%)",
+            "Memory Usage (%)"
+        ]
+    );
+    let timestamps: HashSet<String> = reader
+        .records()
+        .map(|record| record.unwrap()[0].to_string())
+        .collect();
+    assert!(timestamps.len() >= 3, "Only {} ticks", timestamps.len());
+    assert!(fs::metadata(&output).unwrap().len() * 3 < contents.len() as u64);
+
+    let verified = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .arg("verify")
+        .arg(&csv_path)
+        .status()
+        .expect("Failed to start chronologger");
+    assert!(verified.success(), "Verify exited with {}", verified);
+    let refused = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .arg("verify")
+        .arg(&output)
+        .output()
+        .expect("Failed to start chronologger");
+    assert!(!refused.status.success());
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("gzip-compressed"));
+}
+
+/// `--stdout` streams each tick as it is taken, with the log kept on stderr,
+/// and a reader that goes away ends the run cleanly.
+#[test]
+#[cfg(unix)]
+fn test_stdout_streams_until_reader_closes() {
+    use std::io::{BufRead, BufReader, Read};
+    use std::process::Stdio;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--stdout", "--interval", "500ms", "--duration", "60"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start chronologger");
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    assert_eq!(
+        lines.next().unwrap().unwrap(),
+        "Timestamp,PID,Process Name,CPU Usage (%),Memory Usage (%)"
+    );
+    let mut timestamps = HashSet::new();
+    while timestamps.len() < 2 {
+        let line = lines.next().expect("Output ended early").unwrap();
+        let row: Vec<&str> = line.split(',').collect();
+        assert!(row.len() >= 5, "Short row {:?}", row);
+        assert!(row[1].parse::<u32>().is_ok(), "Bad PID in {:?}", row);
+        timestamps.insert(row[0].to_string());
+    }
+    // The second tick arrived well before the run's duration is up.
+    assert!(
+        child.try_wait().unwrap().is_none(),
+        "Exited before streaming"
+    );
+
+    drop(lines);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            child.kill().unwrap();
+            panic!("Still running after its reader closed");
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    assert!(status.success(), "Exited with {}", status);
+    let mut stderr = String::new();
+    child
+        .stderr
+        .take()
+        .unwrap()
+        .read_to_string(&mut stderr)
+        .unwrap();
+    assert!(stderr.contains("Output closed by its reader"), "{}", stderr);
+    assert!(!stderr.contains("Timestamp,PID"), "Data in the log");
+}
+
+/// `--follow-children` logs a shell and the children it forked, but not an
+/// unrelated process.
+#[test]
+#[cfg(unix)]
+fn test_follow_children_logs_descendants() {
+    let mut shell = Command::new("sh")
+        .args(["-c", "sleep 30 & sleep 30 & wait"])
+        .spawn()
+        .expect("Failed to start sh");
+    let mut unrelated = Command::new("sleep")
+        .arg("30")
+        .spawn()
+        .expect("Failed to start sleep");
+    // Gives the shell time to fork.
+    std::thread::sleep(Duration::from_millis(500));
+    let output = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--once", "--output", "-", "--follow-children"])
+        .arg(shell.id().to_string())
+        .output()
+        .expect("Failed to start chronologger");
+    for child in [&mut shell, &mut unrelated] {
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+    assert!(output.status.success(), "Exited with {}", output.status);
+
+    let mut reader = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(output.stdout.as_slice());
+    let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+    let pids: HashSet<String> = rows.iter().map(|row| row[1].to_string()).collect();
+    let mut names: Vec<&str> = rows.iter().map(|row| &row[2]).collect();
+    names.sort_unstable();
+    assert_eq!(names, ["sh", "sleep", "sleep"], "Rows: {:?}", rows);
+    assert!(pids.contains(&shell.id().to_string()));
+    assert!(!pids.contains(&unrelated.id().to_string()));
+}
+
+/// `--exec` logs the command's children and ends with the command, passing
+/// on its exit code.
+#[test]
+#[cfg(unix)]
+fn test_exec_stops_with_command() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = dir.path().join("capture.csv");
+    let started = Instant::now();
+    let status = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--interval", "250ms", "--output"])
+        .arg(&output)
+        .args(["--exec", "--", "sh", "-c", "sleep 1; exit 7"])
+        .status()
+        .expect("Failed to start chronologger");
+    assert_eq!(status.code(), Some(7));
+    assert!(
+        started.elapsed() < Duration::from_secs(20),
+        "Took {:?}",
+        started.elapsed()
+    );
+
+    let mut reader = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_path(&output)
+        .unwrap();
+    let names: HashSet<String> = reader
+        .records()
+        .map(|record| record.unwrap()[2].to_string())
+        .collect();
+    assert_eq!(
+        names,
+        HashSet::from(["sh".to_string(), "sleep".to_string()])
+    );
+    let contents = fs::read_to_string(&output).unwrap();
+    assert!(contents
+        .lines()
+        .last()
+        .unwrap()
+        .starts_with("# integrity: "));
+}
+
+/// `run` logs only the command it starts, without `--children`, for as long
+/// as it runs, and exits with its exit code.
+#[test]
+#[cfg(unix)]
+fn test_run_logs_command_until_exit() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = dir.path().join("capture.csv");
+    let status = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(["--interval", "250ms", "--output"])
+        .arg(&output)
+        .args(["run", "--", "sh", "-c", "sleep 1; exit 5"])
+        .status()
+        .expect("Failed to start chronologger");
+    assert_eq!(status.code(), Some(5));
+
+    let mut reader = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_path(&output)
+        .unwrap();
+    let names: HashSet<String> = reader
+        .records()
+        .map(|record| record.unwrap()[2].to_string())
+        .collect();
+    assert_eq!(names, HashSet::from(["sh".to_string()]));
+}
+
+/// `--listen` serves the filtered samples as Prometheus metrics while
+/// `--output none` writes nothing.
+#[test]
+fn test_listen_serves_metrics() {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::process::Stdio;
+
+    let dir = tempfile::TempDir::new().unwrap();
+    let pid = std::process::id().to_string();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .current_dir(dir.path())
+        .args(["--output", "none", "--listen", "127.0.0.1:0"])
+        .args(["--interval", "250ms", "--duration", "60", "--pid", &pid])
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start chronologger");
+    // The port the OS picked is only in the log.
+    let mut log = BufReader::new(child.stderr.take().unwrap()).lines();
+    let address = loop {
+        let line = log.next().expect("Log ended early").unwrap();
+        if let Some((_, rest)) = line.split_once("Serving Prometheus metrics on http://") {
+            break rest.trim_end_matches("/metrics").to_string();
+        }
+    };
+    std::thread::spawn(move || log.for_each(drop));
+
+    let scrape = || {
+        let mut stream = TcpStream::connect(&address).unwrap();
+        write!(
+            stream,
+            "GET /metrics HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            address
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let response = loop {
+        let response = scrape();
+        if !response.contains("chronologger_samples_total 0\n") {
+            break response;
+        }
+        assert!(Instant::now() < deadline, "No tick published: {}", response);
+        std::thread::sleep(Duration::from_millis(100));
+    };
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    for family in [
+        "# TYPE chronologger_process_cpu_percent gauge",
+        "# TYPE chronologger_process_memory_percent gauge",
+        "# TYPE chronologger_samples_total counter",
+    ] {
+        assert!(
+            response.contains(family),
+            "{} missing: {}",
+            family,
+            response
+        );
+    }
+    let series: Vec<&str> = response
+        .lines()
+        .filter(|line| line.starts_with("chronologger_process_cpu_percent{"))
+        .collect();
+    assert_eq!(series.len(), 1, "{:?}", series);
+    assert!(
+        series[0].contains(&format!("pid=\"{}\"", pid)),
+        "{:?}",
+        series
+    );
+    assert_eq!(
+        fs::read_dir(dir.path()).unwrap().count(),
+        0,
+        "Output written"
+    );
+}
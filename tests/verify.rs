@@ -0,0 +1,124 @@
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+fn chronologger(args: &[&str], output: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_chronologger"))
+        .args(args)
+        .arg(output)
+        .output()
+        .expect("Failed to start chronologger")
+}
+
+/// Takes a short capture in `format` at `output`.
+fn capture(format: &str, output: &Path) {
+    let args = [
+        "--interval",
+        "1",
+        "--duration",
+        "2",
+        "--format",
+        format,
+        "--output",
+    ];
+    let result = chronologger(&args, output);
+    assert!(result.status.success(), "Exited with {}", result.status);
+}
+
+fn verify(output: &Path) -> (bool, String) {
+    let result = chronologger(&["verify"], output);
+    let text = String::from_utf8_lossy(&result.stdout).into_owned()
+        + &String::from_utf8_lossy(&result.stderr);
+    (result.status.success(), text)
+}
+
+#[test]
+fn test_clean_capture_verifies() {
+    let dir = tempfile::TempDir::new().unwrap();
+    for (format, name) in [("csv", "capture.csv"), ("emf", "capture.jsonl")] {
+        let output = dir.path().join(name);
+        capture(format, &output);
+        let (ok, text) = verify(&output);
+        assert!(ok, "{} failed to verify: {}", format, text);
+        assert!(text.contains(": OK, "), "{}", text);
+    }
+}
+
+#[test]
+fn test_truncated_capture_fails() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = dir.path().join("capture.csv");
+    capture("csv", &output);
+    let contents = fs::read(&output).unwrap();
+    fs::write(&output, &contents[..contents.len() * 2 / 3]).unwrap();
+
+    let (ok, text) = verify(&output);
+    assert!(!ok, "Verified a truncated capture");
+    assert!(text.contains("no integrity footer"), "{}", text);
+
+    // Dropping a whole row but keeping the footer is caught as well.
+    let text = String::from_utf8(contents).unwrap();
+    let mut lines: Vec<&str> = text.lines().collect();
+    lines.remove(1);
+    fs::write(&output, lines.join("\n") + "\n").unwrap();
+    let (ok, text) = verify(&output);
+    assert!(!ok, "Verified a capture missing a row");
+    assert!(
+        text.contains("does not match its integrity footer"),
+        "{}",
+        text
+    );
+}
+
+#[test]
+fn test_bit_flipped_capture_fails() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = dir.path().join("capture.jsonl");
+    capture("emf", &output);
+    let mut contents = fs::read(&output).unwrap();
+    // A digit in the first record's timestamp.
+    let digit = contents.iter().position(u8::is_ascii_digit).unwrap();
+    contents[digit] ^= 0x01;
+    fs::write(&output, &contents).unwrap();
+
+    let (ok, text) = verify(&output);
+    assert!(!ok, "Verified a corrupted capture");
+    assert!(text.contains("SHA-256"), "{}", text);
+    assert!(!text.contains(" rows, not "), "{}", text);
+}
+
+#[test]
+fn test_future_schema_version_fails() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let output = dir.path().join("capture.jsonl");
+    let args = [
+        "--duration",
+        "1",
+        "--format",
+        "emf",
+        "--metadata",
+        "--output",
+    ];
+    assert!(chronologger(&args, &output).status.success());
+    let sidecar = dir.path().join("capture.jsonl.meta.json");
+    let metadata: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&sidecar).unwrap()).unwrap();
+    let version: u32 = metadata["schema_version"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(version >= 1);
+    assert!(verify(&output).0, "Failed to verify the capture as written");
+
+    let future = metadata.to_string().replace(
+        &format!("\"schema_version\":\"{}\"", version),
+        &format!("\"schema_version\":\"{}\"", version + 1),
+    );
+    fs::write(&sidecar, future).unwrap();
+    let (ok, text) = verify(&output);
+    assert!(!ok, "Verified a capture with a newer schema");
+    assert!(text.contains("only understands up to version"), "{}", text);
+}